@@ -127,8 +127,7 @@
                 partitionType = "count";
                 # TODO also run integration tests
                 # this needs --all-features to enable io,_danger_local_https features
-                # unfortunately this can't yet work because running docker inside the nix sandbox is not possible,
-                # which precludes use of the redis test container
+                # the directory no longer needs a redis test container, but bitcoind must be provided
                 # cargoExtraArgs = "--locked --all-features";
                 # buildInputs = [ pkgs.bitcoind ]; # not verified to work
               });
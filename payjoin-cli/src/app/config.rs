@@ -8,6 +8,7 @@ use payjoin::bitcoin::FeeRate;
 use serde::Deserialize;
 use url::Url;
 
+use super::qr::QrFormat;
 use crate::db;
 
 type Builder = config::builder::ConfigBuilder<DefaultState>;
@@ -32,8 +33,18 @@ pub struct V1Config {
 pub struct V2Config {
     #[serde(deserialize_with = "deserialize_ohttp_keys_from_path")]
     pub ohttp_keys: Option<payjoin::OhttpKeys>,
-    pub ohttp_relay: Url,
+    #[serde(rename = "ohttp_relay", deserialize_with = "deserialize_one_or_many_urls")]
+    pub ohttp_relays: Vec<Url>,
     pub pj_directory: Url,
+    /// Further directories a receive session publishes to in case `pj_directory` goes down
+    #[serde(default)]
+    pub alternative_pj_directories: Vec<Url>,
+    /// Encrypts stored sessions when set. Also read from `PAYJOIN_SESSION_PASSPHRASE`
+    #[serde(default)]
+    pub session_passphrase: Option<String>,
+    /// Use OHTTP keys that couldn't be confirmed to be served to everyone
+    #[serde(default)]
+    pub skip_ohttp_key_check: bool,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -52,6 +63,12 @@ pub enum VersionConfig {
 pub struct Config {
     pub db_path: PathBuf,
     pub max_fee_rate: Option<FeeRate>,
+    /// Where to save the receive URI as an SVG or PNG QR code
+    pub qr_file: Option<PathBuf>,
+    /// Seconds to wait for a payjoin proposal before broadcasting the original transaction
+    pub send_timeout: Option<u64>,
+    /// SOCKS5 proxy, such as Tor, that all outgoing requests are sent through
+    pub socks5_proxy: Option<Url>,
     pub bitcoind: BitcoindConfig,
     #[serde(skip)]
     pub version: Option<VersionConfig>,
@@ -134,6 +151,9 @@ impl Config {
         let mut config = Config {
             db_path: built_config.get("db_path")?,
             max_fee_rate: built_config.get("max_fee_rate").ok(),
+            qr_file: built_config.get("qr_file").ok(),
+            send_timeout: built_config.get("send_timeout").ok(),
+            socks5_proxy: built_config.get("socks5_proxy").ok(),
             bitcoind: built_config.get("bitcoind")?,
             version: None,
         };
@@ -180,6 +200,11 @@ impl Config {
             ));
         }
 
+        // Catch a bad QR file name before a receive session is created for it
+        if let Some(path) = &config.qr_file {
+            QrFormat::from_path(path).map_err(|e| ConfigError::Message(e.to_string()))?;
+        }
+
         log::debug!("App config: {:?}", config);
         Ok(config)
     }
@@ -232,7 +257,11 @@ fn add_bitcoind_defaults(builder: Builder, matches: &ArgMatches) -> Result<Build
 fn add_common_defaults(builder: Builder, matches: &ArgMatches) -> Result<Builder, ConfigError> {
     builder
         .set_default("db_path", db::DB_PATH)?
-        .set_override_option("db_path", matches.get_one::<String>("db_path").map(|s| s.as_str()))
+        .set_override_option("db_path", matches.get_one::<String>("db_path").map(|s| s.as_str()))?
+        .set_override_option(
+            "socks5_proxy",
+            matches.get_one::<Url>("socks5_proxy").map(|s| s.as_str()),
+        )
 }
 
 /// Set up default values for v1-specific settings when v2 is not enabled
@@ -249,20 +278,40 @@ fn add_v2_defaults(builder: Builder, matches: &ArgMatches) -> Result<Builder, Co
     builder
         .set_override_option(
             "v2.ohttp_relay",
-            matches.get_one::<Url>("ohttp_relay").map(|s| s.as_str()),
+            matches
+                .get_many::<Url>("ohttp_relay")
+                .map(|relays| relays.map(|r| r.as_str()).collect::<Vec<_>>()),
         )?
         .set_default("v2.pj_directory", "https://payjo.in")?
-        .set_default("v2.ohttp_keys", None::<String>)
+        .set_default("v2.ohttp_keys", None::<String>)?
+        .set_override_option(
+            "v2.skip_ohttp_key_check",
+            matches.get_flag("skip_ohttp_key_check").then_some(true),
+        )?
+        .set_override_option(
+            "v2.session_passphrase",
+            std::env::var(SESSION_PASSPHRASE_ENV).ok().filter(|p| !p.is_empty()),
+        )
 }
 
+/// Environment variable the session passphrase can be supplied through instead of `config.toml`
+#[cfg(feature = "v2")]
+const SESSION_PASSPHRASE_ENV: &str = "PAYJOIN_SESSION_PASSPHRASE";
+
 /// Handles configuration overrides based on CLI subcommands
 fn handle_subcommands(builder: Builder, matches: &ArgMatches) -> Result<Builder, ConfigError> {
     match matches.subcommand() {
-        Some(("send", _)) => Ok(builder),
+        Some(("send", matches)) => builder
+            .set_override_option("send_timeout", matches.get_one::<u64>("send_timeout").copied()),
         Some(("receive", matches)) => {
             let builder = handle_receive_command(builder, matches)?;
             let max_fee_rate = matches.get_one::<FeeRate>("max_fee_rate");
-            builder.set_override_option("max_fee_rate", max_fee_rate.map(|f| f.to_string()))
+            builder
+                .set_override_option("max_fee_rate", max_fee_rate.map(|f| f.to_string()))?
+                .set_override_option(
+                    "qr_file",
+                    matches.get_one::<String>("qr_file").map(|s| s.as_str()),
+                )
         }
         #[cfg(feature = "v2")]
         Some(("resume", _)) => Ok(builder),
@@ -296,6 +345,12 @@ fn handle_receive_command(builder: Builder, matches: &ArgMatches) -> Result<Buil
                 "v2.ohttp_keys",
                 matches.get_one::<String>("ohttp_keys").map(|s| s.as_str()),
             )?
+            .set_override_option(
+                "v2.alternative_pj_directories",
+                matches
+                    .get_many::<Url>("alternative_pj_directory")
+                    .map(|dirs| dirs.map(|d| d.as_str()).collect::<Vec<_>>()),
+            )?
     };
 
     Ok(builder)
@@ -322,3 +377,24 @@ where
             .map(Some),
     }
 }
+
+/// Accept either a single relay URL or a list of them
+#[cfg(feature = "v2")]
+fn deserialize_one_or_many_urls<'de, D>(deserializer: D) -> Result<Vec<Url>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(Url),
+        Many(Vec<Url>),
+    }
+
+    match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(url) => Ok(vec![url]),
+        OneOrMany::Many(urls) if urls.is_empty() =>
+            Err(serde::de::Error::custom("At least one ohttp_relay is required")),
+        OneOrMany::Many(urls) => Ok(urls),
+    }
+}
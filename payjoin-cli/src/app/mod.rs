@@ -4,11 +4,14 @@ use anyhow::{anyhow, Result};
 use bitcoincore_rpc::bitcoin::Amount;
 use payjoin::bitcoin::psbt::Psbt;
 use payjoin::bitcoin::FeeRate;
+use payjoin::send::{FallbackRequired, ProposalDiff};
 use payjoin::{bitcoin, PjUri};
 use tokio::signal;
 use tokio::sync::watch;
+use url::Url;
 
 pub mod config;
+mod qr;
 pub mod wallet;
 use crate::app::config::Config;
 use crate::app::wallet::BitcoindWallet;
@@ -42,8 +45,17 @@ pub trait App: Send + Sync {
         self.wallet().create_psbt(outputs, fee_rate, true)
     }
 
-    fn process_pj_response(&self, psbt: Psbt) -> Result<bitcoin::Txid> {
+    /// Complete the payment without payjoin by broadcasting the original transaction
+    fn broadcast_fallback(&self, fallback: FallbackRequired) -> Result<bitcoin::Txid> {
+        println!("{}", fallback);
+        let txid = self.wallet().broadcast_tx(fallback.fallback_tx())?;
+        println!("Fallback transaction broadcast. TXID: {}", txid);
+        Ok(txid)
+    }
+
+    fn process_pj_response(&self, psbt: Psbt, diff: ProposalDiff) -> Result<bitcoin::Txid> {
         log::debug!("Proposed psbt: {:#?}", psbt);
+        print_proposal_diff(&diff);
 
         let signed = self.wallet().process_psbt(&psbt)?;
         let tx = self.wallet().finalize_psbt(&signed)?;
@@ -55,11 +67,71 @@ pub trait App: Send + Sync {
     }
 }
 
+fn print_proposal_diff(diff: &ProposalDiff) {
+    println!(
+        "Receiver added {} input(s) worth {}",
+        diff.receiver_inputs().len(),
+        diff.receiver_input_value()
+    );
+    for (original, proposed) in diff.substituted_outputs() {
+        println!(
+            "Output {} {} substituted by {} {}",
+            original.script_pubkey, original.value, proposed.script_pubkey, proposed.value
+        );
+    }
+    for output in diff.receiver_outputs() {
+        println!("Receiver added output {} {}", output.script_pubkey, output.value);
+    }
+    println!(
+        "Fee increased by {} to {}, of which we contribute {}",
+        diff.fee_delta(),
+        diff.proposed_fee(),
+        diff.sender_fee_contribution()
+    );
+}
+
 #[cfg(feature = "_danger-local-https")]
-fn http_agent() -> Result<reqwest::Client> { Ok(http_agent_builder()?.build()?) }
+fn http_agent(config: &Config) -> Result<reqwest::Client> {
+    Ok(with_socks5_proxy(http_agent_builder()?, config)?.build()?)
+}
 
 #[cfg(not(feature = "_danger-local-https"))]
-fn http_agent() -> Result<reqwest::Client> { Ok(reqwest::Client::new()) }
+fn http_agent(config: &Config) -> Result<reqwest::Client> {
+    Ok(with_socks5_proxy(reqwest::Client::builder(), config)?.build()?)
+}
+
+/// Send every request through the configured SOCKS5 proxy, if any
+///
+/// Host names are resolved by the proxy so that `.onion` endpoints work and lookups don't leak to
+/// the local resolver.
+fn with_socks5_proxy(
+    builder: reqwest::ClientBuilder,
+    config: &Config,
+) -> Result<reqwest::ClientBuilder> {
+    match &config.socks5_proxy {
+        Some(proxy) => Ok(builder.proxy(socks5_proxy(proxy)?)),
+        None => Ok(builder),
+    }
+}
+
+#[cfg(feature = "v2")]
+fn socks5_proxy(proxy: &Url) -> Result<reqwest::Proxy> {
+    Ok(payjoin::io::socks5_proxy(proxy.clone())?)
+}
+
+/// Mirrors `payjoin::io::socks5_proxy`, which needs the v2 feature
+#[cfg(not(feature = "v2"))]
+fn socks5_proxy(proxy: &Url) -> Result<reqwest::Proxy> {
+    let mut proxy = proxy.clone();
+    match proxy.scheme() {
+        "socks5h" => {}
+        "socks5" => proxy
+            .set_scheme("socks5h")
+            .map_err(|_| anyhow!("Cannot resolve host names through SOCKS5 proxy {}", proxy))?,
+        scheme => return Err(anyhow!("Unsupported SOCKS5 proxy scheme {}", scheme)),
+    }
+    Ok(reqwest::Proxy::all(proxy.as_str())?)
+}
 
 #[cfg(feature = "_danger-local-https")]
 fn http_agent_builder() -> Result<reqwest::ClientBuilder> {
@@ -0,0 +1,105 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use payjoin::PjUri;
+use qrcode::render::{svg, unicode};
+use qrcode::QrCode;
+
+/// Serialize `uri` for a QR code.
+///
+/// BIP 21 permits an uppercase scheme and bech32 address, and the payjoin endpoint is already
+/// uppercased where it may be, including the `RK1`, `OH1` and `EX1` fragment parameters. That
+/// lets all but the short parameter names be encoded in the denser alphanumeric mode.
+pub(crate) fn qr_uri_string(uri: &PjUri) -> String {
+    format!("{:#}", uri).replacen("bitcoin:", "BITCOIN:", 1)
+}
+
+/// Print `uri` as a QR code to the terminal and, if `path` is given, save it as an SVG or PNG
+/// file depending on the extension.
+pub(crate) fn display_qr(uri: &PjUri, path: Option<&Path>) -> Result<()> {
+    // QrCode::new picks the densest encoding mode for each segment of the data
+    let code = QrCode::new(qr_uri_string(uri)).context("Failed to encode URI as a QR code")?;
+    let terminal = code
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build();
+    println!("{}", terminal);
+
+    if let Some(path) = path {
+        save_qr(&code, path)?;
+        println!("Saved QR code to {}", path.display());
+    }
+    Ok(())
+}
+
+/// The image formats a QR code can be saved in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum QrFormat {
+    Svg,
+    Png,
+}
+
+impl QrFormat {
+    /// Pick the format from the extension of `path`
+    pub(crate) fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_ascii_lowercase()) {
+            Some(ext) if ext == "svg" => Ok(QrFormat::Svg),
+            Some(ext) if ext == "png" => Ok(QrFormat::Png),
+            _ => Err(anyhow!("QR code file must end in .svg or .png: {}", path.display())),
+        }
+    }
+}
+
+fn save_qr(code: &QrCode, path: &Path) -> Result<()> {
+    match QrFormat::from_path(path)? {
+        QrFormat::Svg => {
+            let image = code.render::<svg::Color>().min_dimensions(256, 256).build();
+            std::fs::write(path, image)
+                .with_context(|| format!("Failed to write {}", path.display()))
+        }
+        QrFormat::Png => code
+            .render::<image::Luma<u8>>()
+            .min_dimensions(256, 256)
+            .build()
+            .save(path)
+            .with_context(|| format!("Failed to write {}", path.display())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use payjoin::{PjUri, Uri, UriExt};
+
+    use super::{qr_uri_string, QrFormat};
+
+    #[test]
+    fn qr_uri_string_round_trips() {
+        let uri = Uri::try_from(
+            "bitcoin:tb1qqypqxpq9qcrsszg2pvxq6rs0zqg3yyc5r7fxez?amount=0.01&pj=https://example.com/pj",
+        )
+        .unwrap()
+        .assume_checked()
+        .check_pj_supported()
+        .unwrap();
+
+        let qr = qr_uri_string(&uri);
+        assert!(qr.starts_with("BITCOIN:TB1QQYPQXPQ9QCRSSZG2PVXQ6RS0ZQG3YYC5R7FXEZ?"));
+
+        let parsed: PjUri =
+            Uri::try_from(qr.as_str()).unwrap().assume_checked().check_pj_supported().unwrap();
+        assert_eq!(parsed.address, uri.address);
+        assert_eq!(parsed.amount, uri.amount);
+        assert_eq!(parsed.extras.endpoint(), uri.extras.endpoint());
+    }
+
+    #[test]
+    fn qr_format_from_extension() {
+        use std::path::Path;
+
+        assert_eq!(QrFormat::from_path(Path::new("pj.svg")).unwrap(), QrFormat::Svg);
+        assert_eq!(QrFormat::from_path(Path::new("PJ.PNG")).unwrap(), QrFormat::Png);
+        assert!(QrFormat::from_path(Path::new("pj.jpg")).is_err());
+        assert!(QrFormat::from_path(Path::new("pj")).is_err());
+    }
+}
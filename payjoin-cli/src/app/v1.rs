@@ -2,6 +2,7 @@ use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{anyhow, Context, Result};
 use bitcoincore_rpc::bitcoin::Amount;
@@ -18,11 +19,13 @@ use payjoin::receive::v1::{PayjoinProposal, UncheckedProposal};
 use payjoin::receive::ImplementationError;
 use payjoin::receive::ReplyableError::{self, Implementation, V1};
 use payjoin::send::v1::SenderBuilder;
-use payjoin::{Uri, UriExt};
+use payjoin::send::ResponseError;
+use payjoin::{PjUri, Uri, UriExt};
 use tokio::net::TcpListener;
 use tokio::sync::watch;
 
 use super::config::Config;
+use super::qr::display_qr;
 use super::wallet::BitcoindWallet;
 use super::App as AppTrait;
 use crate::app::{handle_interrupt, http_agent};
@@ -67,20 +70,29 @@ impl AppTrait for App {
         let uri = uri.assume_checked();
         let uri = uri.check_pj_supported().map_err(|_| anyhow!("URI does not support Payjoin"))?;
         let psbt = self.create_original_psbt(&uri, fee_rate)?;
-        let (req, ctx) = SenderBuilder::new(psbt, uri.clone())
+        let timeout = self.config.send_timeout.map(Duration::from_secs);
+        let mut builder = SenderBuilder::new(psbt, uri.clone());
+        if let Some(timeout) = timeout {
+            builder = builder.deadline(SystemTime::now() + timeout);
+        }
+        let (req, ctx) = builder
             .build_recommended(fee_rate)
             .with_context(|| "Failed to build payjoin request")?
             .extract_v1()?;
-        let http = http_agent()?;
+        let http = http_agent(&self.config)?;
         let body = String::from_utf8(req.body.clone()).unwrap();
         println!("Sending fallback request to {}", &req.url);
-        let response = http
-            .post(req.url)
-            .header("Content-Type", req.content_type)
-            .body(body.clone())
-            .send()
-            .await
-            .with_context(|| "HTTP request failed")?;
+        let mut request =
+            http.post(req.url).header("Content-Type", req.content_type).body(body.clone());
+        if let Some(timeout) = timeout {
+            request = request.timeout(timeout);
+        }
+        let response = match request.send().await {
+            Ok(response) => response.bytes().await?.to_vec(),
+            // The deadline has passed, so processing yields the fallback
+            Err(e) if e.is_timeout() => Vec::new(),
+            Err(e) => return Err(e).with_context(|| "HTTP request failed"),
+        };
         let fallback_tx = Psbt::from_str(&body)
             .map_err(|e| anyhow!("Failed to load PSBT from base64: {}", e))?
             .extract_tx()?;
@@ -89,25 +101,30 @@ impl AppTrait for App {
             "Sent fallback transaction hex: {:#}",
             payjoin::bitcoin::consensus::encode::serialize_hex(&fallback_tx)
         );
-        let psbt = ctx.process_response(&mut response.bytes().await?.to_vec().as_slice()).map_err(
-            |e| {
+        match ctx.process_response(&mut response.as_slice()) {
+            Ok((psbt, diff)) => {
+                self.process_pj_response(psbt, diff)?;
+            }
+            Err(ResponseError::FallbackRequired(fallback)) => {
+                self.broadcast_fallback(fallback)?;
+            }
+            Err(e) => {
                 log::debug!("Error processing response: {:?}", e);
-                anyhow!("Failed to process response {}", e)
-            },
-        )?;
-
-        self.process_pj_response(psbt)?;
+                return Err(anyhow!("Failed to process response {}", e));
+            }
+        }
         Ok(())
     }
 
     #[allow(clippy::incompatible_msrv)]
     async fn receive_payjoin(&self, amount: Amount) -> Result<()> {
-        let pj_uri_string = self.construct_payjoin_uri(amount, None)?;
+        let pj_uri = self.construct_payjoin_uri(amount, None)?;
         println!(
             "Listening at {}. Configured to accept payjoin at BIP 21 Payjoin Uri:",
             self.config.v1()?.port
         );
-        println!("{}", pj_uri_string);
+        println!("{}", pj_uri);
+        display_qr(&pj_uri, self.config.qr_file.as_deref())?;
 
         let mut interrupt = self.interrupt.clone();
         tokio::select! {
@@ -130,7 +147,7 @@ impl App {
         &self,
         amount: Amount,
         fallback_target: Option<&str>,
-    ) -> Result<String> {
+    ) -> Result<PjUri<'static>> {
         let pj_receiver_address = self.wallet.get_new_address()?;
         let pj_part = match fallback_target {
             Some(target) => target,
@@ -139,11 +156,7 @@ impl App {
         let pj_part = payjoin::Url::parse(pj_part)
             .map_err(|e| anyhow!("Failed to parse pj_endpoint: {}", e))?;
 
-        let mut pj_uri =
-            payjoin::receive::v1::build_v1_pj_uri(&pj_receiver_address, &pj_part, false)?;
-        pj_uri.amount = Some(amount);
-
-        Ok(pj_uri.to_string())
+        Ok(payjoin::PjUriBuilder::new(pj_receiver_address, pj_part).amount(amount).build()?)
     }
 
     async fn start_http_server(&self) -> Result<()> {
@@ -282,7 +295,7 @@ impl App {
 
         let payjoin_proposal = self.process_v1_proposal(proposal)?;
         let psbt = payjoin_proposal.psbt();
-        let body = psbt.to_string();
+        let body = payjoin_proposal.psbt_base64();
         println!(
             "Responded with Payjoin proposal {}",
             psbt.clone().extract_tx_unchecked_fee_rate().compute_txid()
@@ -1,37 +1,56 @@
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
 use anyhow::{anyhow, Context, Result};
 use payjoin::bitcoin::consensus::encode::serialize_hex;
 use payjoin::bitcoin::psbt::Psbt;
+use payjoin::bitcoin::secp256k1::rand;
 use payjoin::bitcoin::{Amount, FeeRate};
 use payjoin::receive::v2::{Receiver, UncheckedProposal};
 use payjoin::receive::{Error, ImplementationError, ReplyableError};
-use payjoin::send::v2::{Sender, SenderBuilder};
-use payjoin::Uri;
+use payjoin::relay::RelayPool;
+use payjoin::send::v2::{EncapsulationError, Sender, SenderBuilder, V2GetContext};
+use payjoin::send::{FallbackRequired, ProposalDiff, ResponseError};
+use payjoin::{OhttpKeysStale, Uri, Url};
 use tokio::sync::watch;
 
 use super::config::Config;
+use super::qr::display_qr;
 use super::wallet::BitcoindWallet;
 use super::App as AppTrait;
 use crate::app::{handle_interrupt, http_agent};
 use crate::db::Database;
 
+/// How many messages a large Original PSBT may be split into
+const MAX_ORIGINAL_PSBT_MESSAGES: u8 = 16;
+
 #[derive(Clone)]
 pub(crate) struct App {
     config: Config,
     db: Arc<Database>,
     wallet: BitcoindWallet,
+    relays: Arc<Mutex<RelayPool>>,
     interrupt: watch::Receiver<()>,
 }
 
 #[async_trait::async_trait]
 impl AppTrait for App {
     fn new(config: Config) -> Result<Self> {
-        let db = Arc::new(Database::create(&config.db_path)?);
+        let mut db = Database::create(&config.db_path)?;
+        if let Some(passphrase) = &config.v2()?.session_passphrase {
+            db = db.with_session_passphrase(passphrase)?;
+        }
+        let db = Arc::new(db);
         let (interrupt_tx, interrupt_rx) = watch::channel(());
         tokio::spawn(handle_interrupt(interrupt_tx));
         let wallet = BitcoindWallet::new(&config.bitcoind)?;
-        let app = Self { config, db, wallet, interrupt: interrupt_rx };
+        let mut relays = RelayPool::new(config.v2()?.ohttp_relays.clone())?;
+        for (relay, health) in db.get_relay_health()? {
+            relays.set_health(&relay, health);
+        }
+        let relays = Arc::new(Mutex::new(relays));
+        let app = Self { config, db, wallet, relays, interrupt: interrupt_rx };
         app.wallet()
             .network()
             .context("Failed to connect to bitcoind. Check config RPC connection.")?;
@@ -52,9 +71,18 @@ impl AppTrait for App {
             Some(send_session) => send_session,
             None => {
                 let psbt = self.create_original_psbt(&uri, fee_rate)?;
-                let mut req_ctx = SenderBuilder::new(psbt, uri.clone())
+                let mut builder = SenderBuilder::new(psbt, uri.clone());
+                if let Some(timeout) = self.config.send_timeout {
+                    builder = builder.deadline(SystemTime::now() + Duration::from_secs(timeout));
+                }
+                let mut req_ctx = builder
                     .build_recommended(fee_rate)
                     .with_context(|| "Failed to build payjoin request")?;
+                if let Some(ohttp_keys) = req_ctx.ohttp_keys() {
+                    let directory = req_ctx.endpoint().join("/")?;
+                    let ohttp_keys = self.check_ohttp_keys(&directory, ohttp_keys).await?;
+                    req_ctx.update_ohttp_keys(ohttp_keys);
+                }
                 self.db.insert_send_session(&mut req_ctx, url)?;
                 req_ctx
             }
@@ -64,13 +92,22 @@ impl AppTrait for App {
 
     async fn receive_payjoin(&self, amount: Amount) -> Result<()> {
         let address = self.wallet().get_new_address()?;
-        let ohttp_keys = unwrap_ohttp_keys_or_else_fetch(&self.config).await?;
-        let session = Receiver::new(
+        let ohttp_keys = self.unwrap_ohttp_keys_or_else_fetch().await?;
+        let ohttp_keys = self.check_ohttp_keys(&self.config.v2()?.pj_directory, ohttp_keys).await?;
+        let mut session = Receiver::new(
             address,
             self.config.v2()?.pj_directory.clone(),
             ohttp_keys.clone(),
             None,
-        )?;
+        )?
+        .with_max_messages(MAX_ORIGINAL_PSBT_MESSAGES);
+        for directory in &self.config.v2()?.alternative_pj_directories {
+            match self.fetch_ohttp_keys(directory).await {
+                Ok(ohttp_keys) =>
+                    session = session.with_alternative_directory(directory.clone(), ohttp_keys)?,
+                Err(e) => println!("Skipping alternative directory {}: {}", directory, e),
+            }
+        }
         self.db.insert_recv_session(session.clone())?;
         self.spawn_payjoin_receiver(session, Some(amount)).await
     }
@@ -122,7 +159,10 @@ impl App {
         let mut interrupt = self.interrupt.clone();
         tokio::select! {
             res = self.long_poll_post(&mut req_ctx) => {
-                self.process_pj_response(res?)?;
+                match res {
+                    Ok((psbt, diff)) => self.process_pj_response(psbt, diff)?,
+                    Err(e) => self.broadcast_fallback(e.downcast::<FallbackRequired>()?)?,
+                };
                 self.db.clear_send_session(req_ctx.endpoint())?;
             }
             _ = interrupt.changed() => {
@@ -139,39 +179,66 @@ impl App {
         amount: Option<Amount>,
     ) -> Result<()> {
         println!("Receive session established");
-        let mut pj_uri = session.pj_uri();
-        pj_uri.amount = amount;
+        let mut pj_uri = session.pj_uri_builder();
+        if let Some(amount) = amount {
+            pj_uri = pj_uri.amount(amount);
+        }
+        let pj_uri = pj_uri.build()?;
         println!("Request Payjoin by sharing this Payjoin Uri:");
         println!("{}", pj_uri);
+        display_qr(&pj_uri, self.config.qr_file.as_deref())?;
 
         let mut interrupt = self.interrupt.clone();
-        let receiver = tokio::select! {
+        let polled = tokio::select! {
             res = self.long_poll_fallback(&mut session) => res,
             _ = interrupt.changed() => {
                 println!("Interrupted. Call the `resume` command to resume all sessions.");
                 return Ok(());
             }
-        }?;
+        };
+        let (directory, receiver) = match polled {
+            Ok(polled) => polled,
+            Err(e) if is_cancelled(&e) => {
+                println!("The sender cancelled the payjoin.");
+                self.db.clear_recv_session()?;
+                return Ok(());
+            }
+            Err(e) if is_expired(&e) => {
+                println!("The session expired.");
+                self.db.clear_recv_session()?;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
 
         println!("Fallback transaction received. Consider broadcasting this to get paid if the Payjoin fails:");
         println!("{}", serialize_hex(&receiver.extract_tx_to_schedule_broadcast()));
         let mut payjoin_proposal = match self.process_v2_proposal(receiver.clone()) {
             Ok(proposal) => proposal,
             Err(Error::ReplyToSender(e)) => {
-                return Err(
-                    handle_recoverable_error(e, receiver, &self.config.v2()?.ohttp_relay).await
-                );
+                return Err(self.handle_recoverable_error(e, receiver).await);
             }
             Err(e) => return Err(e.into()),
         };
-        let (req, ohttp_ctx) = payjoin_proposal
-            .extract_v2_req(&self.config.v2()?.ohttp_relay)
-            .map_err(|e| anyhow!("v2 req extraction failed {}", e))?;
         println!("Got a request from the sender. Responding with a Payjoin proposal.");
-        let res = post_request(req).await?;
-        payjoin_proposal
-            .process_res(&res.bytes().await?, ohttp_ctx)
-            .map_err(|e| anyhow!("Failed to deserialize response {}", e))?;
+        let mut refreshed = false;
+        loop {
+            let (res, ohttp_ctx) = self
+                .post_via_relay(|relay| {
+                    payjoin_proposal
+                        .extract_v2_req(relay)
+                        .map_err(|e| anyhow!("v2 req extraction failed {}", e))
+                })
+                .await?;
+            match payjoin_proposal.process_res(&res.bytes().await?, ohttp_ctx) {
+                Ok(()) => break,
+                Err(Error::V2(e)) if !refreshed && e.ohttp_keys_stale().is_some() => {
+                    payjoin_proposal.update_ohttp_keys(self.refresh_ohttp_keys(&directory).await?);
+                    refreshed = true;
+                }
+                Err(e) => return Err(anyhow!("Failed to deserialize response {}", e)),
+            }
+        }
         let payjoin_psbt = payjoin_proposal.psbt().clone();
         println!(
             "Response successful. Watch mempool for successful Payjoin. TXID: {}",
@@ -181,65 +248,205 @@ impl App {
         Ok(())
     }
 
-    async fn long_poll_post(&self, req_ctx: &mut Sender) -> Result<Psbt> {
-        match req_ctx.extract_v2(self.config.v2()?.ohttp_relay.clone()) {
-            Ok((req, ctx)) => {
-                println!("Posting Original PSBT Payload request...");
-                let response = post_request(req).await?;
-                println!("Sent fallback transaction");
-                let v2_ctx = Arc::new(ctx.process_response(&response.bytes().await?)?);
-                loop {
-                    let (req, ohttp_ctx) =
-                        v2_ctx.extract_req(self.config.v2()?.ohttp_relay.clone())?;
-                    let response = post_request(req).await?;
-                    match v2_ctx.process_response(&response.bytes().await?, ohttp_ctx) {
-                        Ok(Some(psbt)) => return Ok(psbt),
-                        Ok(None) => {
-                            println!("No response yet.");
-                        }
-                        Err(re) => {
-                            println!("{}", re);
-                            log::debug!("{:?}", re);
-                            return Err(anyhow!("Response error").context(re));
-                        }
+    /// Post the Original PSBT to the receiver's directory, falling back to the alternative
+    /// directories it advertised in order
+    ///
+    /// Returns the context to poll for the proposal with and the endpoint it was posted to.
+    async fn post_original_psbt(&self, req_ctx: &Sender) -> Result<(V2GetContext, Url)> {
+        let mut last_err = None;
+        for mut sender in std::iter::once(req_ctx.clone()).chain(req_ctx.alternatives()) {
+            let mut res = self.try_post_original_psbt(&sender).await;
+            if res.as_ref().err().and_then(ohttp_keys_stale).is_some() {
+                match self.refresh_ohttp_keys(sender.endpoint()).await {
+                    Ok(ohttp_keys) => {
+                        sender.update_ohttp_keys(ohttp_keys);
+                        res = self.try_post_original_psbt(&sender).await;
                     }
+                    Err(e) => res = Err(e),
                 }
             }
-            Err(_) => {
-                let (req, v1_ctx) = req_ctx.extract_v1()?;
-                println!("Posting Original PSBT Payload request...");
-                let response = post_request(req).await?;
-                println!("Sent fallback transaction");
-                match v1_ctx.process_response(&mut response.bytes().await?.to_vec().as_slice()) {
-                    Ok(psbt) => Ok(psbt),
+            match res {
+                Ok(v2_ctx) => return Ok((v2_ctx, sender.endpoint().clone())),
+                Err(e) => {
+                    println!("Failed to post to {}: {}", sender.endpoint(), e);
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| anyhow!("No payjoin directory to post to")))
+    }
+
+    async fn try_post_original_psbt(&self, sender: &Sender) -> Result<V2GetContext> {
+        // Post the parts of a large Original PSBT before the message announcing them
+        let relay = self.relays.lock().expect("relay pool lock poisoned").choose();
+        let parts = sender.extract_v2_continuations(relay)?.len();
+        for index in 0..parts {
+            let (response, ctx) = self
+                .post_via_relay(|relay| {
+                    Ok(sender.extract_v2_continuations(relay.clone())?.swap_remove(index))
+                })
+                .await?;
+            ctx.process_response(&response.bytes().await?)?;
+        }
+        let (response, ctx) =
+            self.post_via_relay(|relay| Ok(sender.extract_v2(relay.clone())?)).await?;
+        Ok(ctx.process_response(&response.bytes().await?)?)
+    }
+
+    async fn long_poll_post(&self, req_ctx: &mut Sender) -> Result<(Psbt, ProposalDiff)> {
+        if req_ctx.is_v2() {
+            println!("Posting Original PSBT Payload request...");
+            let (v2_ctx, endpoint) = self.post_original_psbt(req_ctx).await?;
+            let mut v2_ctx = Arc::new(v2_ctx);
+            println!("Sent fallback transaction");
+            let mut refreshed = false;
+            loop {
+                let (response, ohttp_ctx) =
+                    self.post_via_relay(|relay| Ok(v2_ctx.extract_req(relay.clone())?)).await?;
+                match v2_ctx.process_response(&response.bytes().await?, ohttp_ctx) {
+                    Ok(Some(proposal)) => {
+                        self.send_feedback(&v2_ctx, None).await;
+                        return Ok(proposal);
+                    }
+                    Ok(None) => {
+                        println!("No response yet.");
+                    }
+                    Err(ResponseError::Validation(e))
+                        if !refreshed && e.ohttp_keys_stale().is_some() =>
+                    {
+                        let ohttp_keys = self.refresh_ohttp_keys(&endpoint).await?;
+                        Arc::make_mut(&mut v2_ctx).update_ohttp_keys(ohttp_keys);
+                        refreshed = true;
+                    }
                     Err(re) => {
+                        self.send_feedback(&v2_ctx, Some(&re)).await;
+                        if let ResponseError::FallbackRequired(fallback) = re {
+                            return Err(fallback.into());
+                        }
                         println!("{}", re);
                         log::debug!("{:?}", re);
-                        Err(anyhow!("Response error").context(re))
+                        return Err(anyhow!("Response error").context(re));
                     }
                 }
             }
+        } else {
+            let (req, v1_ctx) = req_ctx.extract_v1()?;
+            println!("Posting Original PSBT Payload request...");
+            let response = post_request(&self.config, req).await?;
+            println!("Sent fallback transaction");
+            match v1_ctx.process_response(&mut response.bytes().await?.to_vec().as_slice()) {
+                Ok(proposal) => Ok(proposal),
+                Err(ResponseError::FallbackRequired(fallback)) => Err(fallback.into()),
+                Err(re) => {
+                    println!("{}", re);
+                    log::debug!("{:?}", re);
+                    Err(anyhow!("Response error").context(re))
+                }
+            }
         }
     }
 
+    /// Tell the receiver whether its proposal was accepted so it can stop waiting on us.
+    ///
+    /// Feedback is a courtesy, so failing to deliver it is not an error.
+    async fn send_feedback(&self, v2_ctx: &V2GetContext, rejection: Option<&ResponseError>) {
+        let res = async {
+            let (response, ctx) = self
+                .post_via_relay(|relay| {
+                    Ok(match rejection {
+                        Some(e) => v2_ctx.extract_nack_req(relay.clone(), e)?,
+                        None => v2_ctx.extract_ack_req(relay.clone())?,
+                    })
+                })
+                .await?;
+            Ok::<_, anyhow::Error>(ctx.process_response(&response.bytes().await?)?)
+        }
+        .await;
+        if let Err(e) = res {
+            log::warn!("Failed to send feedback to the receiver: {}", e);
+        }
+    }
+
+    /// Poll every directory of the session concurrently until one of them holds a proposal
+    ///
+    /// A directory that fails is skipped for the round, so the session survives any single
+    /// directory going down.
     async fn long_poll_fallback(
         &self,
         session: &mut payjoin::receive::v2::Receiver,
-    ) -> Result<payjoin::receive::v2::UncheckedProposal> {
+    ) -> Result<(Url, payjoin::receive::v2::UncheckedProposal)> {
+        // Directories whose keys were refreshed and have yet to be accepted
+        let mut refreshed = HashSet::new();
         loop {
-            let (req, context) = session.extract_req(&self.config.v2()?.ohttp_relay)?;
             println!("Polling receive request...");
-            let ohttp_response = post_request(req).await?;
-            let proposal = session
-                .process_res(ohttp_response.bytes().await?.to_vec().as_slice(), context)
-                .map_err(|_| anyhow!("GET fallback failed"))?;
-            log::debug!("got response");
-            if let Some(proposal) = proposal {
-                break Ok(proposal);
+            let mut polls = tokio::task::JoinSet::new();
+            for directory in session.directories() {
+                let app = self.clone();
+                let mut session = session.clone();
+                polls.spawn(async move {
+                    let res = app.poll_directory(&mut session, &directory).await;
+                    (directory, session, res)
+                });
+            }
+            let mut last_err = None;
+            let mut any_ok = false;
+            while let Some(joined) = polls.join_next().await {
+                match joined? {
+                    (directory, _, Ok(Some(proposal))) => return Ok((directory, proposal)),
+                    (directory, polled, Ok(None)) => {
+                        refreshed.remove(&directory);
+                        // Keep the parts of a large Original PSBT received so far
+                        if polled != *session {
+                            *session = polled;
+                            self.db.insert_recv_session(session.clone())?;
+                        }
+                        any_ok = true;
+                    }
+                    (_, _, Err(e)) if is_cancelled(&e) || is_expired(&e) => return Err(e),
+                    // Poll with fresh keys in the next round
+                    (directory, _, Err(e)) if ohttp_keys_stale(&e).is_some() => {
+                        if !refreshed.insert(directory.clone()) {
+                            return Err(e.context(format!(
+                                "{} rejected the OHTTP keys it just served",
+                                directory
+                            )));
+                        }
+                        let ohttp_keys = self.refresh_ohttp_keys(&directory).await?;
+                        session.update_ohttp_keys(&directory, ohttp_keys)?;
+                        self.db.insert_recv_session(session.clone())?;
+                        any_ok = true;
+                    }
+                    (directory, _, Err(e)) => {
+                        log::warn!("Polling {} failed: {}", directory, e);
+                        last_err = Some(e);
+                    }
+                }
+            }
+            if let (false, Some(e)) = (any_ok, last_err) {
+                return Err(e);
             }
         }
     }
 
+    async fn poll_directory(
+        &self,
+        session: &mut payjoin::receive::v2::Receiver,
+        directory: &Url,
+    ) -> Result<Option<payjoin::receive::v2::UncheckedProposal>> {
+        let (ohttp_response, context) =
+            self.post_via_relay(|relay| Ok(session.extract_req_from(directory, relay)?)).await?;
+        let proposal = match session.process_res_from(
+            directory,
+            ohttp_response.bytes().await?.to_vec().as_slice(),
+            context,
+        ) {
+            Err(e @ (Error::Cancelled | Error::V2(_))) => return Err(e.into()),
+            res => res.map_err(|_| anyhow!("GET fallback failed"))?,
+        };
+        log::debug!("got response");
+        Ok(proposal)
+    }
+
     fn process_v2_proposal(
         &self,
         proposal: payjoin::receive::v2::UncheckedProposal,
@@ -279,34 +486,216 @@ impl App {
         log::debug!("Receiver's Payjoin proposal PSBT Rsponse: {:#?}", payjoin_proposal_psbt);
         Ok(payjoin_proposal)
     }
-}
 
-/// Handle request error by sending an error response over the directory
-async fn handle_recoverable_error(
-    e: ReplyableError,
-    mut receiver: UncheckedProposal,
-    ohttp_relay: &payjoin::Url,
-) -> anyhow::Error {
-    let (err_req, err_ctx) = match receiver.extract_err_req(&e, ohttp_relay) {
-        Ok(req_ctx) => req_ctx,
-        Err(e) => return anyhow!("Failed to extract error request: {}", e),
-    };
-
-    let err_response = match post_request(err_req).await {
-        Ok(response) => response,
-        Err(e) => return anyhow!("Failed to post error request: {}", e),
-    };
-
-    let err_bytes = match err_response.bytes().await {
-        Ok(bytes) => bytes,
-        Err(e) => return anyhow!("Failed to get error response bytes: {}", e),
-    };
-
-    if let Err(e) = receiver.process_err_res(&err_bytes, err_ctx) {
-        return anyhow!("Failed to process error response: {}", e);
+    /// Handle request error by sending an error response over the directory
+    async fn handle_recoverable_error(
+        &self,
+        e: ReplyableError,
+        mut receiver: UncheckedProposal,
+    ) -> anyhow::Error {
+        let (err_response, err_ctx) = match self
+            .post_via_relay(|relay| {
+                receiver
+                    .extract_err_req(&e, relay)
+                    .map_err(|e| anyhow!("Failed to extract error request: {}", e))
+            })
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => return anyhow!("Failed to post error request: {}", e),
+        };
+
+        let err_bytes = match err_response.bytes().await {
+            Ok(bytes) => bytes,
+            Err(e) => return anyhow!("Failed to get error response bytes: {}", e),
+        };
+
+        if let Err(e) = receiver.process_err_res(&err_bytes, err_ctx) {
+            return anyhow!("Failed to process error response: {}", e);
+        }
+
+        e.into()
+    }
+
+    async fn unwrap_ohttp_keys_or_else_fetch(&self) -> Result<payjoin::OhttpKeys> {
+        if let Some(keys) = self.config.v2()?.ohttp_keys.clone() {
+            println!("Using OHTTP Keys from config");
+            return Ok(keys);
+        }
+        println!("Bootstrapping private network transport over Oblivious HTTP");
+        self.fetch_ohttp_keys(&self.config.v2()?.pj_directory).await
+    }
+
+    /// Make sure the directory serves `ohttp_keys` to everyone, not just to us
+    ///
+    /// Returns the keys to use, which are the directory's current ones if it has rotated away from
+    /// `ohttp_keys`. Keys that can't be confirmed are refused unless the check is skipped in the
+    /// config. The keys are fetched through each relay, or through isolated Tor circuits when
+    /// requests go through a SOCKS5 proxy, which can't be chained with a relay's tunnel.
+    async fn check_ohttp_keys(
+        &self,
+        payjoin_directory: &Url,
+        ohttp_keys: payjoin::OhttpKeys,
+    ) -> Result<payjoin::OhttpKeys> {
+        if self.config.v2()?.skip_ohttp_key_check {
+            log::warn!("Skipping the OHTTP key consistency check");
+            return Ok(ohttp_keys);
+        }
+        let clients = match &self.config.socks5_proxy {
+            Some(proxy) => self.isolated_circuits(proxy)?,
+            None => {
+                let relays = &self.config.v2()?.ohttp_relays;
+                let mut clients = Vec::with_capacity(relays.len());
+                for relay in relays {
+                    clients.push((relay.clone(), tunnel_agent(relay)?));
+                }
+                clients
+            }
+        };
+        match payjoin::io::check_ohttp_keys_with_clients(
+            &clients,
+            payjoin_directory.clone(),
+            &ohttp_keys,
+        )
+        .await
+        {
+            Ok(()) => Ok(ohttp_keys),
+            Err(e) if e.mismatched_relay().is_some() =>
+                Err(anyhow!("Refusing to use OHTTP keys that may target us").context(e)),
+            Err(e) => match e.rotated_keys() {
+                Some(rotated) => {
+                    println!("The directory has rotated its OHTTP keys. Using the current ones");
+                    Ok(rotated.clone())
+                }
+                None => Err(anyhow!(
+                    "Could not confirm the OHTTP keys are served to everyone. Configure more \
+                     relays, or pass --skip-ohttp-key-check to use them anyway"
+                )
+                .context(e)),
+            },
+        }
+    }
+
+    /// Clients that each reach the SOCKS5 proxy with their own credentials
+    ///
+    /// Tor isolates streams by SOCKS credentials, so each client gets its own circuit and exit.
+    /// The circuits are labelled with distinct hosts so that each counts as an independent relay.
+    fn isolated_circuits(&self, proxy: &Url) -> Result<Vec<(Url, reqwest::Client)>> {
+        let mut clients = Vec::with_capacity(payjoin::io::MIN_INDEPENDENT_RELAYS);
+        for circuit in 0..payjoin::io::MIN_INDEPENDENT_RELAYS {
+            let mut isolated = proxy.clone();
+            let username = format!("payjoin-{:016x}", rand::random::<u64>());
+            isolated
+                .set_username(&username)
+                .and_then(|_| isolated.set_password(Some("payjoin")))
+                .map_err(|_| anyhow!("Cannot set credentials on SOCKS5 proxy {}", proxy))?;
+            let mut config = self.config.clone();
+            config.socks5_proxy = Some(isolated);
+            let label = Url::parse(&format!("socks5h://tor-circuit-{}.invalid", circuit))?;
+            clients.push((label, http_agent(&config)?));
+        }
+        Ok(clients)
+    }
+
+    /// Fetch the current OHTTP keys of the directory at `endpoint` after it rejected ours
+    async fn refresh_ohttp_keys(&self, endpoint: &Url) -> Result<payjoin::OhttpKeys> {
+        println!("The directory rejected our OHTTP keys as stale. Fetching fresh ones...");
+        self.fetch_ohttp_keys(endpoint).await
+    }
+
+    async fn fetch_ohttp_keys(&self, payjoin_directory: &Url) -> Result<payjoin::OhttpKeys> {
+        if self.config.socks5_proxy.is_some() {
+            // The SOCKS5 proxy already keeps our IP address from the directory
+            let http = http_agent(&self.config)?;
+            return Ok(payjoin::io::fetch_ohttp_keys_with_client(&http, payjoin_directory).await?);
+        }
+        let relays = self.relays.lock().expect("relay pool lock poisoned").failover_order();
+        let mut last_err = None;
+        for ohttp_relay in relays {
+            #[cfg(feature = "_danger-local-https")]
+            let res = {
+                let http = tunnel_agent(&ohttp_relay)?;
+                payjoin::io::fetch_ohttp_keys_with_client(&http, payjoin_directory).await
+            };
+            #[cfg(not(feature = "_danger-local-https"))]
+            let res =
+                payjoin::io::fetch_ohttp_keys(ohttp_relay.clone(), payjoin_directory.clone()).await;
+            match res {
+                Ok(ohttp_keys) => {
+                    self.record_relay_outcome(&ohttp_relay, true)?;
+                    return Ok(ohttp_keys);
+                }
+                Err(e) => {
+                    log::warn!("Failed to fetch OHTTP keys through {}: {}", ohttp_relay, e);
+                    self.record_relay_outcome(&ohttp_relay, false)?;
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err.map(Into::into).unwrap_or_else(|| anyhow!("No OHTTP relay configured")))
+    }
+
+    /// Send a request through a randomly chosen OHTTP relay, failing over to the
+    /// remaining relays when one cannot be reached.
+    ///
+    /// The request is extracted anew for each relay attempted.
+    async fn post_via_relay<T>(
+        &self,
+        mut extract_req: impl FnMut(&Url) -> Result<(payjoin::Request, T)>,
+    ) -> Result<(reqwest::Response, T)> {
+        let http = http_agent(&self.config)?;
+        let relays = self.relays.lock().expect("relay pool lock poisoned").failover_order();
+        let mut last_err = None;
+        for relay in relays {
+            let (req, ctx) = extract_req(&relay)?;
+            match send_request(&http, req).await {
+                Ok(response) => {
+                    self.record_relay_outcome(&relay, true)?;
+                    return Ok((response, ctx));
+                }
+                Err(e) => {
+                    log::warn!("OHTTP relay {} failed: {}", relay, e);
+                    self.record_relay_outcome(&relay, false)?;
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err.map(map_reqwest_err).unwrap_or_else(|| anyhow!("No OHTTP relay configured")))
+    }
+
+    /// Update the relay pool and persist the relay's health for future runs
+    fn record_relay_outcome(&self, relay: &Url, success: bool) -> Result<()> {
+        let health = {
+            let mut relays = self.relays.lock().expect("relay pool lock poisoned");
+            if success {
+                relays.record_success(relay);
+            } else {
+                relays.record_failure(relay);
+            }
+            relays.health(relay).cloned()
+        };
+        if let Some(health) = health {
+            self.db.update_relay_health(relay, &health)?;
+        }
+        Ok(())
     }
+}
+
+fn is_cancelled(e: &anyhow::Error) -> bool {
+    matches!(e.downcast_ref::<Error>(), Some(Error::Cancelled))
+}
+
+/// Whether the receive session expired, so polling it further is pointless
+fn is_expired(e: &anyhow::Error) -> bool {
+    matches!(e.downcast_ref::<Error>(), Some(Error::V2(e)) if e.is_expired())
+}
 
-    e.into()
+/// Whether a directory's OHTTP gateway rejected the OHTTP keys a request was made with
+fn ohttp_keys_stale(e: &anyhow::Error) -> Option<&OhttpKeysStale> {
+    match e.downcast_ref::<Error>() {
+        Some(Error::V2(e)) => e.ohttp_keys_stale(),
+        _ => e.downcast_ref::<EncapsulationError>()?.ohttp_keys_stale(),
+    }
 }
 
 fn try_contributing_inputs(
@@ -324,34 +713,25 @@ fn try_contributing_inputs(
         .commit_inputs())
 }
 
-async fn unwrap_ohttp_keys_or_else_fetch(config: &Config) -> Result<payjoin::OhttpKeys> {
-    if let Some(keys) = config.v2()?.ohttp_keys.clone() {
-        println!("Using OHTTP Keys from config");
-        Ok(keys)
-    } else {
-        println!("Bootstrapping private network transport over Oblivious HTTP");
-        let ohttp_relay = config.v2()?.ohttp_relay.clone();
-        let payjoin_directory = config.v2()?.pj_directory.clone();
-        #[cfg(feature = "_danger-local-https")]
-        let ohttp_keys = {
-            let cert_der = crate::app::read_local_cert()?;
-            payjoin::io::fetch_ohttp_keys_with_cert(ohttp_relay, payjoin_directory, cert_der)
-                .await?
-        };
-        #[cfg(not(feature = "_danger-local-https"))]
-        let ohttp_keys = payjoin::io::fetch_ohttp_keys(ohttp_relay, payjoin_directory).await?;
-        Ok(ohttp_keys)
-    }
+/// A client tunneling through `ohttp_relay` with HTTP CONNECT
+fn tunnel_agent(ohttp_relay: &Url) -> Result<reqwest::Client> {
+    #[cfg(feature = "_danger-local-https")]
+    let builder = crate::app::http_agent_builder()?;
+    #[cfg(not(feature = "_danger-local-https"))]
+    let builder = reqwest::Client::builder();
+    Ok(builder.proxy(reqwest::Proxy::all(ohttp_relay.as_str())?).build()?)
 }
 
-async fn post_request(req: payjoin::Request) -> Result<reqwest::Response> {
-    let http = http_agent()?;
-    http.post(req.url)
-        .header("Content-Type", req.content_type)
-        .body(req.body)
-        .send()
-        .await
-        .map_err(map_reqwest_err)
+async fn post_request(config: &Config, req: payjoin::Request) -> Result<reqwest::Response> {
+    let http = http_agent(config)?;
+    send_request(&http, req).await.map_err(map_reqwest_err)
+}
+
+async fn send_request(
+    http: &reqwest::Client,
+    req: payjoin::Request,
+) -> reqwest::Result<reqwest::Response> {
+    http.post(req.url).header("Content-Type", req.content_type).body(req.body).send().await
 }
 
 fn map_reqwest_err(e: reqwest::Error) -> anyhow::Error {
@@ -13,6 +13,10 @@ pub(crate) enum Error {
     Serialize(serde_json::Error),
     #[cfg(feature = "v2")]
     Deserialize(serde_json::Error),
+    #[cfg(feature = "v2")]
+    Encryption(payjoin::encrypted::EncryptionError),
+    #[cfg(feature = "v2")]
+    WrongPassphrase,
 }
 
 impl fmt::Display for Error {
@@ -23,6 +27,13 @@ impl fmt::Display for Error {
             Error::Serialize(e) => write!(f, "Serialization failed: {}", e),
             #[cfg(feature = "v2")]
             Error::Deserialize(e) => write!(f, "Deserialization failed: {}", e),
+            #[cfg(feature = "v2")]
+            Error::Encryption(e) => write!(f, "Session encryption failed: {}", e),
+            #[cfg(feature = "v2")]
+            Error::WrongPassphrase => write!(
+                f,
+                "The session passphrase does not match the one the database was created with"
+            ),
         }
     }
 }
@@ -9,19 +9,28 @@ use error::*;
 
 pub(crate) const DB_PATH: &str = "payjoin.sled";
 
-pub(crate) struct Database(sled::Db);
+pub(crate) struct Database {
+    db: sled::Db,
+    /// Key the stored sessions are encrypted under, if any
+    #[cfg(feature = "v2")]
+    session_key: Option<payjoin::encrypted::SessionKey>,
+}
 
 impl Database {
     pub(crate) fn create(path: impl AsRef<Path>) -> Result<Self> {
         let db = sled::open(path)?;
-        Ok(Self(db))
+        Ok(Self {
+            db,
+            #[cfg(feature = "v2")]
+            session_key: None,
+        })
     }
 
     /// Inserts the input and returns true if the input was seen before, false otherwise.
     pub(crate) fn insert_input_seen_before(&self, input: OutPoint) -> Result<bool> {
         let key = serialize(&input);
-        let was_seen_before = self.0.insert(key.as_slice(), IVec::from(vec![]))?.is_some();
-        self.0.flush()?;
+        let was_seen_before = self.db.insert(key.as_slice(), IVec::from(vec![]))?.is_some();
+        self.db.flush()?;
         Ok(was_seen_before)
     }
 }
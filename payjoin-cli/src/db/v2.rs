@@ -1,62 +1,130 @@
 use bitcoincore_rpc::jsonrpc::serde_json;
+use payjoin::bitcoin::secp256k1::rand;
+use payjoin::encrypted::SessionKey;
 use payjoin::receive::v2::Receiver;
+use payjoin::relay::RelayHealth;
 use payjoin::send::v2::Sender;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use sled::{IVec, Tree};
 use url::Url;
 
 use super::*;
 
+/// A known value sealed under the session key, checked to reject a wrong passphrase early
+const PASSPHRASE_CHECK: &str = "payjoin-cli session passphrase check";
+
 impl Database {
+    /// Encrypt stored sessions under a key derived from `passphrase`
+    ///
+    /// The salt and a check value are created the first time a passphrase is used and kept in the
+    /// database, so later runs must supply the same passphrase. Sessions stored in plaintext
+    /// before then are encrypted at that point.
+    pub(crate) fn with_session_passphrase(mut self, passphrase: &str) -> Result<Self> {
+        let meta_tree = self.db.open_tree("meta")?;
+        let key = SessionKey::from_passphrase(passphrase, &session_salt(&meta_tree)?);
+        match meta_tree.get("session_check")? {
+            Some(check) => {
+                let check: String = key.open(&check).map_err(|_| Error::WrongPassphrase)?;
+                if check != PASSPHRASE_CHECK {
+                    return Err(Error::WrongPassphrase);
+                }
+            }
+            None => {
+                // The check is written last so that an interrupted migration is resumed on the
+                // next run, under the same salt
+                self.seal_plaintext_sessions(&key)?;
+                meta_tree.insert(
+                    "session_check",
+                    key.seal(&PASSPHRASE_CHECK).map_err(Error::Encryption)?,
+                )?;
+                meta_tree.flush()?;
+            }
+        }
+        self.session_key = Some(key);
+        Ok(self)
+    }
+
+    /// Encrypt the sessions that are still stored as plaintext JSON
+    fn seal_plaintext_sessions(&self, key: &SessionKey) -> Result<()> {
+        for tree in ["recv_sessions", "send_sessions"] {
+            let tree = self.db.open_tree(tree)?;
+            for item in tree.iter() {
+                let (id, value) = item?;
+                // Sealed sessions start with a version byte and never parse as JSON
+                if let Ok(session) = serde_json::from_slice::<serde_json::Value>(&value) {
+                    tree.insert(id, key.seal(&session).map_err(Error::Encryption)?)?;
+                }
+            }
+            tree.flush()?;
+        }
+        Ok(())
+    }
+
+    fn serialize_session<T: Serialize>(&self, session: &T) -> Result<IVec> {
+        match &self.session_key {
+            Some(key) => Ok(key.seal(session).map_err(Error::Encryption)?.into()),
+            None => Ok(serde_json::to_vec(session).map_err(Error::Serialize)?.into()),
+        }
+    }
+
+    fn deserialize_session<T: DeserializeOwned>(&self, value: &[u8]) -> Result<T> {
+        match &self.session_key {
+            Some(key) => key.open(value).map_err(Error::Encryption),
+            None => serde_json::from_slice(value).map_err(Error::Deserialize),
+        }
+    }
+
     pub(crate) fn insert_recv_session(&self, session: Receiver) -> Result<()> {
-        let recv_tree = self.0.open_tree("recv_sessions")?;
+        let recv_tree = self.db.open_tree("recv_sessions")?;
         let key = &session.id();
-        let value = serde_json::to_string(&session).map_err(Error::Serialize)?;
-        recv_tree.insert(key.as_slice(), IVec::from(value.as_str()))?;
+        let value = self.serialize_session(&session)?;
+        recv_tree.insert(key.as_slice(), value)?;
         recv_tree.flush()?;
         Ok(())
     }
 
     pub(crate) fn get_recv_sessions(&self) -> Result<Vec<Receiver>> {
-        let recv_tree = self.0.open_tree("recv_sessions")?;
+        let recv_tree = self.db.open_tree("recv_sessions")?;
         let mut sessions = Vec::new();
         for item in recv_tree.iter() {
             let (_, value) = item?;
-            let session: Receiver = serde_json::from_slice(&value).map_err(Error::Deserialize)?;
+            let session: Receiver = self.deserialize_session(&value)?;
             sessions.push(session);
         }
         Ok(sessions)
     }
 
     pub(crate) fn clear_recv_session(&self) -> Result<()> {
-        let recv_tree: Tree = self.0.open_tree("recv_sessions")?;
+        let recv_tree: Tree = self.db.open_tree("recv_sessions")?;
         recv_tree.clear()?;
         recv_tree.flush()?;
         Ok(())
     }
 
     pub(crate) fn insert_send_session(&self, session: &mut Sender, pj_url: &Url) -> Result<()> {
-        let send_tree: Tree = self.0.open_tree("send_sessions")?;
-        let value = serde_json::to_string(session).map_err(Error::Serialize)?;
-        send_tree.insert(pj_url.to_string(), IVec::from(value.as_str()))?;
+        let send_tree: Tree = self.db.open_tree("send_sessions")?;
+        let value = self.serialize_session(session)?;
+        send_tree.insert(pj_url.to_string(), value)?;
         send_tree.flush()?;
         Ok(())
     }
 
     pub(crate) fn get_send_sessions(&self) -> Result<Vec<Sender>> {
-        let send_tree: Tree = self.0.open_tree("send_sessions")?;
+        let send_tree: Tree = self.db.open_tree("send_sessions")?;
         let mut sessions = Vec::new();
         for item in send_tree.iter() {
             let (_, value) = item?;
-            let session: Sender = serde_json::from_slice(&value).map_err(Error::Deserialize)?;
+            let session: Sender = self.deserialize_session(&value)?;
             sessions.push(session);
         }
         Ok(sessions)
     }
 
     pub(crate) fn get_send_session(&self, pj_url: &Url) -> Result<Option<Sender>> {
-        let send_tree = self.0.open_tree("send_sessions")?;
+        let send_tree = self.db.open_tree("send_sessions")?;
         if let Some(val) = send_tree.get(pj_url.to_string())? {
-            let session: Sender = serde_json::from_slice(&val).map_err(Error::Deserialize)?;
+            let session: Sender = self.deserialize_session(&val)?;
             Ok(Some(session))
         } else {
             Ok(None)
@@ -64,9 +132,77 @@ impl Database {
     }
 
     pub(crate) fn clear_send_session(&self, pj_url: &Url) -> Result<()> {
-        let send_tree: Tree = self.0.open_tree("send_sessions")?;
+        let send_tree: Tree = self.db.open_tree("send_sessions")?;
         send_tree.remove(pj_url.to_string())?;
         send_tree.flush()?;
         Ok(())
     }
+
+    pub(crate) fn get_relay_health(&self) -> Result<Vec<(Url, RelayHealth)>> {
+        let relay_tree: Tree = self.db.open_tree("relay_health")?;
+        let mut health = Vec::new();
+        for item in relay_tree.iter() {
+            let (key, value) = item?;
+            let relay = match std::str::from_utf8(&key).ok().and_then(|s| Url::parse(s).ok()) {
+                Some(relay) => relay,
+                None => continue,
+            };
+            let relay_health: RelayHealth =
+                serde_json::from_slice(&value).map_err(Error::Deserialize)?;
+            health.push((relay, relay_health));
+        }
+        Ok(health)
+    }
+
+    pub(crate) fn update_relay_health(&self, relay: &Url, health: &RelayHealth) -> Result<()> {
+        let relay_tree: Tree = self.db.open_tree("relay_health")?;
+        let value = serde_json::to_string(health).map_err(Error::Serialize)?;
+        relay_tree.insert(relay.as_str(), IVec::from(value.as_str()))?;
+        relay_tree.flush()?;
+        Ok(())
+    }
+}
+
+/// The salt session keys are derived with, created and persisted on first use
+fn session_salt(meta_tree: &Tree) -> Result<Vec<u8>> {
+    if let Some(salt) = meta_tree.get("session_salt")? {
+        return Ok(salt.to_vec());
+    }
+    let salt = rand::random::<[u8; 16]>();
+    meta_tree.insert("session_salt", &salt)?;
+    meta_tree.flush()?;
+    Ok(salt.to_vec())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PASSPHRASE: &str = "correct horse battery staple";
+
+    #[test]
+    fn interrupted_session_migration_resumes() -> Result<()> {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let path = dir.path().join("db");
+        let session = serde_json::json!({ "session": "plaintext" });
+        let db = Database::create(&path)?;
+        let recv_tree = db.db.open_tree("recv_sessions")?;
+        recv_tree.insert("sealed", serde_json::to_vec(&session).map_err(Error::Serialize)?)?;
+
+        // Seal a session, then stop before the check value is written
+        let key =
+            SessionKey::from_passphrase(PASSPHRASE, &session_salt(&db.db.open_tree("meta")?)?);
+        db.seal_plaintext_sessions(&key)?;
+        recv_tree.insert("plaintext", serde_json::to_vec(&session).map_err(Error::Serialize)?)?;
+        drop((recv_tree, db));
+
+        let db = Database::create(&path)?.with_session_passphrase(PASSPHRASE)?;
+        let recv_tree = db.db.open_tree("recv_sessions")?;
+        for id in ["sealed", "plaintext"] {
+            let value = recv_tree.get(id)?.expect("session is stored");
+            let opened: serde_json::Value = db.deserialize_session(&value)?;
+            assert_eq!(opened, session);
+        }
+        Ok(())
+    }
 }
@@ -130,6 +130,13 @@ fn cli() -> ArgMatches {
                 .help("The password for the bitcoin node"),
         )
         .arg(Arg::new("db_path").short('d').long("db-path").help("Sets a custom database path"))
+        .arg(
+            Arg::new("socks5_proxy")
+                .long("socks5-proxy")
+                .num_args(1)
+                .help("Send all requests through a SOCKS5 proxy such as Tor, e.g. socks5h://127.0.0.1:9050")
+                .value_parser(value_parser!(Url)),
+        )
         .subcommand_required(true);
 
     // Conditional arguments based on features
@@ -138,9 +145,16 @@ fn cli() -> ArgMatches {
         cmd = cmd.arg(
             Arg::new("ohttp_relay")
                 .long("ohttp-relay")
-                .help("The ohttp relay url")
+                .help("An ohttp relay url. May be repeated to rotate requests across relays")
+                .action(clap::ArgAction::Append)
                 .value_parser(value_parser!(Url)),
         );
+        cmd = cmd.arg(
+            Arg::new("skip_ohttp_key_check")
+                .long("skip-ohttp-key-check")
+                .help("Use OHTTP keys even if too few relays could confirm they are served to everyone")
+                .action(clap::ArgAction::SetTrue),
+        );
     }
 
     cmd = cmd.subcommand(
@@ -154,6 +168,13 @@ fn cli() -> ArgMatches {
                     .value_name("FEE_SAT_PER_VB")
                     .help("Fee rate in sat/vB")
                     .value_parser(parse_fee_rate_in_sat_per_vb),
+            )
+            .arg(
+                Arg::new("send_timeout")
+                    .long("timeout")
+                    .value_name("SECONDS")
+                    .help("Broadcast the original transaction if the receiver does not respond in time")
+                    .value_parser(value_parser!(u64)),
             ),
     );
 
@@ -173,6 +194,12 @@ fn cli() -> ArgMatches {
             .help("The maximum effective fee rate the receiver is willing to pay (in sat/vB)")
             .value_parser(parse_fee_rate_in_sat_per_vb),
     );
+    receive_cmd = receive_cmd.arg(
+        Arg::new("qr_file")
+            .long("qr-file")
+            .num_args(1)
+            .help("Also save the payjoin URI QR code to this .svg or .png file"),
+    );
     #[cfg(feature = "v1")]
     {
         receive_cmd = receive_cmd.arg(
@@ -201,6 +228,13 @@ fn cli() -> ArgMatches {
                 .help("The directory to store payjoin requests")
                 .value_parser(value_parser!(Url)),
         );
+        receive_cmd = receive_cmd.arg(
+            Arg::new("alternative_pj_directory")
+                .long("alternative-pj-directory")
+                .help("A further directory to publish the session to. May be repeated")
+                .action(clap::ArgAction::Append)
+                .value_parser(value_parser!(Url)),
+        );
         receive_cmd = receive_cmd
             .arg(Arg::new("ohttp_keys").long("ohttp-keys").help("The ohttp key config file path"));
     }
@@ -200,6 +200,7 @@ mod e2e {
                 .arg(&receiver_db_path)
                 .arg("--ohttp-relay")
                 .arg(mock_ohttp_relay)
+                .arg("--skip-ohttp-key-check")
                 .arg("receive")
                 .arg(RECEIVE_SATS)
                 .arg("--pj-directory")
@@ -220,6 +221,7 @@ mod e2e {
                 .arg(&sender_db_path)
                 .arg("--ohttp-relay")
                 .arg(mock_ohttp_relay)
+                .arg("--skip-ohttp-key-check")
                 .arg("send")
                 .arg(&bip21)
                 .arg("--fee-rate")
@@ -239,6 +241,7 @@ mod e2e {
                 .arg(&receiver_db_path)
                 .arg("--ohttp-relay")
                 .arg(mock_ohttp_relay)
+                .arg("--skip-ohttp-key-check")
                 .arg("resume")
                 .stdout(Stdio::piped())
                 .stderr(Stdio::inherit())
@@ -255,6 +258,7 @@ mod e2e {
                 .arg(&sender_db_path)
                 .arg("--ohttp-relay")
                 .arg(mock_ohttp_relay)
+                .arg("--skip-ohttp-key-check")
                 .arg("send")
                 .arg(&bip21)
                 .arg("--fee-rate")
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::watch;
+
+use super::{expiry, unix_millis, Error, MailboxStore, Result, StoreFuture};
+
+/// Mailboxes kept in process memory
+///
+/// Nothing survives a restart, which suits tests and directories that would rather lose
+/// in-flight sessions than keep payloads on disk.
+#[derive(Debug)]
+pub struct MemoryStore {
+    mailboxes: Mutex<HashMap<Vec<u8>, Mailbox>>,
+    /// Signalled on every push so waiting readers check their mailbox again
+    updated: watch::Sender<()>,
+}
+
+#[derive(Debug)]
+struct Mailbox {
+    data: Vec<u8>,
+    expires_at: u64,
+    forget_at: u64,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self { mailboxes: Mutex::new(HashMap::new()), updated: watch::channel(()).0 }
+    }
+}
+
+impl MemoryStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let mailboxes = self.mailboxes.lock().expect("mailbox lock poisoned");
+        match mailboxes.get(key) {
+            Some(mailbox) if mailbox.expires_at <= unix_millis(SystemTime::now()) =>
+                Err(Error::Expired),
+            Some(mailbox) if !mailbox.data.is_empty() => Ok(Some(mailbox.data.clone())),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl MailboxStore for MemoryStore {
+    fn push<'a>(&'a self, key: &'a [u8], data: Vec<u8>, ttl: Duration) -> StoreFuture<'a, ()> {
+        let (expires_at, forget_at) = expiry(SystemTime::now(), ttl);
+        self.mailboxes
+            .lock()
+            .expect("mailbox lock poisoned")
+            .insert(key.to_vec(), Mailbox { data, expires_at, forget_at });
+        self.updated.send_replace(());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn peek<'a>(&'a self, key: &'a [u8]) -> StoreFuture<'a, Vec<u8>> {
+        Box::pin(async move {
+            // Subscribe before the first read so a push in between isn't missed
+            let mut updated = self.updated.subscribe();
+            loop {
+                if let Some(data) = self.get(key)? {
+                    return Ok(data);
+                }
+                // The sender lives as long as the store, so this never fails
+                let _ = updated.changed().await;
+            }
+        })
+    }
+
+    fn sweep(&self) -> StoreFuture<'_, ()> {
+        let now = unix_millis(SystemTime::now());
+        let mut mailboxes = self.mailboxes.lock().expect("mailbox lock poisoned");
+        mailboxes.retain(|_, mailbox| mailbox.forget_at > now);
+        for mailbox in mailboxes.values_mut().filter(|mailbox| mailbox.expires_at <= now) {
+            mailbox.data = Vec::new();
+        }
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test::SHORT_TTL;
+
+    #[tokio::test]
+    async fn test_sweep_erases_expired_payload() {
+        let store = MemoryStore::default();
+        store.push(b"key", b"payload".to_vec(), SHORT_TTL).await.unwrap();
+        store.sweep().await.unwrap();
+        assert_eq!(store.mailboxes.lock().unwrap()[&b"key"[..]].data, b"payload");
+
+        tokio::time::sleep(SHORT_TTL).await;
+        store.sweep().await.unwrap();
+        assert!(store.mailboxes.lock().unwrap()[&b"key"[..]].data.is_empty());
+    }
+}
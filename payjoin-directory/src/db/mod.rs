@@ -0,0 +1,287 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use payjoin::directory::ShortId;
+
+mod memory;
+mod redis;
+mod sled;
+
+pub use self::memory::MemoryStore;
+pub use self::redis::RedisStore;
+pub use self::sled::SledStore;
+
+const DEFAULT_COLUMN: &str = "";
+const PJ_V1_COLUMN: &str = "pjv1";
+
+/// A boxed future returned by [`MailboxStore`] methods
+pub type StoreFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// Storage for the payloads the directory forwards between peers.
+///
+/// Each mailbox holds the latest payload pushed to its key until its time to live runs out. An
+/// expired mailbox is remembered for as long again, so that readers can tell it apart from one
+/// that was never written. Implementations only need to store payloads and wake waiting readers;
+/// the directory applies its own timeout to [`peek`].
+///
+/// [`peek`]: MailboxStore::peek
+pub trait MailboxStore: Send + Sync + 'static {
+    /// Replace the contents of the mailbox at `key` with `data`, expiring after `ttl`, and wake
+    /// any waiting readers
+    fn push<'a>(&'a self, key: &'a [u8], data: Vec<u8>, ttl: Duration) -> StoreFuture<'a, ()>;
+
+    /// Read the mailbox at `key`, waiting until it holds a non-empty payload
+    ///
+    /// Fails with [`Error::Expired`] if the mailbox has expired.
+    fn peek<'a>(&'a self, key: &'a [u8]) -> StoreFuture<'a, Vec<u8>>;
+
+    /// Erase expired payloads and forget old expired mailboxes
+    ///
+    /// The directory calls this periodically. Backends that expire keys natively need not
+    /// implement it.
+    fn sweep(&self) -> StoreFuture<'_, ()> { Box::pin(async { Ok(()) }) }
+}
+
+/// When a mailbox pushed at `now` with `ttl` expires, and when it is forgotten, in milliseconds
+/// since the unix epoch
+fn expiry(now: SystemTime, ttl: Duration) -> (u64, u64) {
+    let expires_at = unix_millis(now + ttl);
+    (expires_at, expires_at.saturating_add(ttl.as_millis() as u64))
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// A mailbox storage backend selected at startup
+#[derive(Debug, Clone)]
+pub enum DbBackend {
+    /// A Redis server at `host:port`, shared by any number of directory instances
+    Redis(String),
+    /// An embedded sled database at the given path, for single binary deployments
+    Sled(std::path::PathBuf),
+    /// Process memory. Mailboxes are lost when the directory stops
+    Memory,
+}
+
+impl DbBackend {
+    /// Open a store for this backend
+    pub fn open(&self) -> Result<Arc<dyn MailboxStore>> {
+        Ok(match self {
+            DbBackend::Redis(host) => Arc::new(RedisStore::new(host)?),
+            DbBackend::Sled(path) => Arc::new(SledStore::open(path)?),
+            DbBackend::Memory => Arc::new(MemoryStore::default()),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct DbPool {
+    store: Arc<dyn MailboxStore>,
+    timeout: Duration,
+    ttl: Duration,
+}
+
+/// Errors pertaining to [`MailboxStore`]s
+#[derive(Debug)]
+pub enum Error {
+    Store(Box<dyn std::error::Error + Send + Sync>),
+    Timeout(tokio::time::error::Elapsed),
+    /// The mailbox outlived its time to live
+    Expired,
+}
+
+impl Error {
+    /// Wrap an error from a [`MailboxStore`] implementation
+    pub fn store<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> Self {
+        Error::Store(error.into())
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Error::*;
+
+        match &self {
+            Store(error) => write!(f, "Storage error: {}", error),
+            Timeout(timeout) => write!(f, "Timeout: {}", timeout),
+            Expired => write!(f, "Mailbox expired"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Store(e) => Some(e.as_ref()),
+            Error::Timeout(e) => Some(e),
+            Error::Expired => None,
+        }
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl DbPool {
+    pub fn new(timeout: Duration, ttl: Duration, store: Arc<dyn MailboxStore>) -> Self {
+        Self { store, timeout, ttl }
+    }
+
+    /// Peek using [`DEFAULT_COLUMN`] as the channel type.
+    pub async fn push_default(&self, subdirectory_id: &ShortId, data: Vec<u8>) -> Result<()> {
+        self.push(subdirectory_id, DEFAULT_COLUMN, data).await
+    }
+
+    pub async fn peek_default(&self, subdirectory_id: &ShortId) -> Result<Vec<u8>> {
+        self.peek_with_timeout(subdirectory_id, DEFAULT_COLUMN).await
+    }
+
+    pub async fn push_v1(&self, subdirectory_id: &ShortId, data: Vec<u8>) -> Result<()> {
+        self.push(subdirectory_id, PJ_V1_COLUMN, data).await
+    }
+
+    /// Peek using [`PJ_V1_COLUMN`] as the channel type.
+    pub async fn peek_v1(&self, subdirectory_id: &ShortId) -> Result<Vec<u8>> {
+        self.peek_with_timeout(subdirectory_id, PJ_V1_COLUMN).await
+    }
+
+    async fn push(
+        &self,
+        subdirectory_id: &ShortId,
+        channel_type: &str,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        self.store.push(&channel_name(subdirectory_id, channel_type), data, self.ttl).await
+    }
+
+    pub async fn sweep(&self) -> Result<()> { self.store.sweep().await }
+
+    async fn peek_with_timeout(
+        &self,
+        subdirectory_id: &ShortId,
+        channel_type: &str,
+    ) -> Result<Vec<u8>> {
+        let key = channel_name(subdirectory_id, channel_type);
+        tokio::time::timeout(self.timeout, self.store.peek(&key)).await.map_err(Error::Timeout)?
+    }
+}
+
+fn channel_name(subdirectory_id: &ShortId, channel_type: &str) -> Vec<u8> {
+    (subdirectory_id.to_string() + channel_type).into_bytes()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(200);
+    const TTL: Duration = Duration::from_secs(60);
+    pub(super) const SHORT_TTL: Duration = Duration::from_millis(100);
+
+    fn id(byte: u8) -> ShortId { ShortId([byte; 8]) }
+
+    async fn push_then_peek(store: Arc<dyn MailboxStore>) {
+        let pool = DbPool::new(TIMEOUT, TTL, store);
+        pool.push_default(&id(1), b"first".to_vec()).await.unwrap();
+        assert_eq!(pool.peek_default(&id(1)).await.unwrap(), b"first");
+
+        // A push replaces the mailbox contents
+        pool.push_default(&id(1), b"second".to_vec()).await.unwrap();
+        assert_eq!(pool.peek_default(&id(1)).await.unwrap(), b"second");
+        assert!(matches!(pool.peek_default(&id(2)).await, Err(Error::Timeout(_))));
+    }
+
+    async fn push_wakes_waiting_reader(store: Arc<dyn MailboxStore>) {
+        let pool = DbPool::new(Duration::from_secs(5), TTL, store);
+        let reader = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.peek_default(&id(1)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        pool.push_default(&id(1), b"payload".to_vec()).await.unwrap();
+        assert_eq!(reader.await.unwrap().unwrap(), b"payload");
+    }
+
+    /// The v1 column key extends the default one, so a reader of one must ignore the other
+    async fn columns_are_separate(store: Arc<dyn MailboxStore>) {
+        let pool = DbPool::new(Duration::from_secs(5), TTL, store);
+        let reader = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.peek_default(&id(1)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        pool.push_v1(&id(1), b"v1".to_vec()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!reader.is_finished());
+
+        pool.push_default(&id(1), b"default".to_vec()).await.unwrap();
+        assert_eq!(reader.await.unwrap().unwrap(), b"default");
+        assert_eq!(pool.peek_v1(&id(1)).await.unwrap(), b"v1");
+    }
+
+    /// A mailbox expires after its TTL and is forgotten by a sweep after as long again
+    async fn mailbox_expires_and_is_swept(store: Arc<dyn MailboxStore>) {
+        let pool = DbPool::new(TIMEOUT, SHORT_TTL, store);
+        pool.push_default(&id(1), b"payload".to_vec()).await.unwrap();
+        assert_eq!(pool.peek_default(&id(1)).await.unwrap(), b"payload");
+
+        tokio::time::sleep(SHORT_TTL + Duration::from_millis(20)).await;
+        assert!(matches!(pool.peek_default(&id(1)).await, Err(Error::Expired)));
+        pool.sweep().await.unwrap();
+        assert!(matches!(pool.peek_default(&id(1)).await, Err(Error::Expired)));
+
+        tokio::time::sleep(SHORT_TTL).await;
+        pool.sweep().await.unwrap();
+        assert!(matches!(pool.peek_default(&id(1)).await, Err(Error::Timeout(_))));
+    }
+
+    fn sled_store() -> (tempfile::TempDir, Arc<dyn MailboxStore>) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SledStore::open(dir.path().join("db")).unwrap();
+        (dir, Arc::new(store))
+    }
+
+    #[tokio::test]
+    async fn test_memory_push_then_peek() { push_then_peek(Arc::new(MemoryStore::default())).await }
+
+    #[tokio::test]
+    async fn test_memory_push_wakes_waiting_reader() {
+        push_wakes_waiting_reader(Arc::new(MemoryStore::default())).await
+    }
+
+    #[tokio::test]
+    async fn test_memory_columns_are_separate() {
+        columns_are_separate(Arc::new(MemoryStore::default())).await
+    }
+
+    #[tokio::test]
+    async fn test_memory_mailbox_expires_and_is_swept() {
+        mailbox_expires_and_is_swept(Arc::new(MemoryStore::default())).await
+    }
+
+    #[tokio::test]
+    async fn test_sled_push_then_peek() {
+        let (_dir, store) = sled_store();
+        push_then_peek(store).await
+    }
+
+    #[tokio::test]
+    async fn test_sled_push_wakes_waiting_reader() {
+        let (_dir, store) = sled_store();
+        push_wakes_waiting_reader(store).await
+    }
+
+    #[tokio::test]
+    async fn test_sled_columns_are_separate() {
+        let (_dir, store) = sled_store();
+        columns_are_separate(store).await
+    }
+
+    #[tokio::test]
+    async fn test_sled_mailbox_expires_and_is_swept() {
+        let (_dir, store) = sled_store();
+        mailbox_expires_and_is_swept(store).await
+    }
+}
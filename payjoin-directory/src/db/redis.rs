@@ -0,0 +1,108 @@
+use std::time::{Duration, SystemTime};
+
+use futures::StreamExt;
+use redis::{AsyncCommands, Client, ErrorKind, RedisError, RedisResult};
+use tracing::debug;
+
+use super::{expiry, unix_millis, Error, MailboxStore, Result, StoreFuture};
+
+/// Mailboxes kept in Redis, with readers woken through pub/sub
+///
+/// Redis expires payloads natively. Each mailbox also has an expiry marker key that outlives the
+/// payload, so that an expired mailbox can be told apart from one that was never written.
+#[derive(Debug, Clone)]
+pub struct RedisStore {
+    client: Client,
+}
+
+impl From<RedisError> for Error {
+    fn from(value: RedisError) -> Self { Error::store(value) }
+}
+
+impl RedisStore {
+    /// Connect to the Redis server at `db_host`, given as `host:port`
+    pub fn new(db_host: &str) -> Result<Self> {
+        let client = Client::open(format!("redis://{}", db_host))?;
+        Ok(Self { client })
+    }
+
+    async fn push(&self, key: &[u8], data: Vec<u8>, ttl: Duration) -> RedisResult<()> {
+        let mut conn = self.client.get_async_connection().await?;
+        let (expires_at, _) = expiry(SystemTime::now(), ttl);
+        let ttl_millis = ttl.as_millis() as usize;
+        () = redis::pipe()
+            .atomic()
+            .pset_ex(key, data, ttl_millis)
+            .ignore()
+            .pset_ex(expiry_marker(key), expires_at, ttl_millis.saturating_mul(2))
+            .ignore()
+            .query_async(&mut conn)
+            .await?;
+        () = conn.publish(key, "updated").await?;
+        Ok(())
+    }
+
+    async fn peek(&self, key: &[u8]) -> Result<Vec<u8>> {
+        let mut conn = self.client.get_async_connection().await?;
+        if let Some(expires_at) = conn.get::<_, Option<u64>>(expiry_marker(key)).await? {
+            if expires_at <= unix_millis(SystemTime::now()) {
+                return Err(Error::Expired);
+            }
+        }
+
+        // Attempt to fetch existing content for the given key
+        if let Ok(data) = conn.get::<_, Vec<u8>>(key).await {
+            if !data.is_empty() {
+                return Ok(data);
+            }
+        }
+        debug!("Failed to fetch content initially");
+
+        // Set up a temporary listener for changes
+        let mut pubsub_conn = self.client.get_async_connection().await?.into_pubsub();
+        pubsub_conn.subscribe(key).await?;
+
+        // Use a block to limit the scope of the mutable borrow
+        let data = {
+            let mut message_stream = pubsub_conn.on_message();
+
+            loop {
+                match message_stream.next().await {
+                    Some(msg) => {
+                        () = msg.get_payload()?; // Notification received
+                                                 // Try fetching the data again
+                        if let Some(data) = conn.get::<_, Option<Vec<u8>>>(key).await? {
+                            if !data.is_empty() {
+                                break data; // Exit the block, returning the data
+                            }
+                        }
+                    }
+                    None =>
+                        return Err(RedisError::from((
+                            ErrorKind::IoError,
+                            "PubSub connection closed",
+                        ))
+                        .into()),
+                }
+            }
+        };
+
+        // Since the stream is dropped here, we can now unsubscribe
+        pubsub_conn.unsubscribe(key).await?;
+
+        Ok(data)
+    }
+}
+
+impl MailboxStore for RedisStore {
+    fn push<'a>(&'a self, key: &'a [u8], data: Vec<u8>, ttl: Duration) -> StoreFuture<'a, ()> {
+        Box::pin(async move { Ok(RedisStore::push(self, key, data, ttl).await?) })
+    }
+
+    fn peek<'a>(&'a self, key: &'a [u8]) -> StoreFuture<'a, Vec<u8>> {
+        Box::pin(RedisStore::peek(self, key))
+    }
+}
+
+/// The key holding the expiry timestamp of the mailbox at `key`
+fn expiry_marker(key: &[u8]) -> Vec<u8> { [key, b":expires"].concat() }
@@ -0,0 +1,131 @@
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use sled::{Db, Event};
+
+use super::{expiry, unix_millis, Error, MailboxStore, Result, StoreFuture};
+
+/// The length of the expiry header stored before each payload
+const HEADER_LEN: usize = 16;
+
+/// Mailboxes kept in an embedded sled database, so a directory can run as a single binary
+///
+/// Each value is the big endian millisecond timestamps at which the mailbox expires and is
+/// forgotten, followed by the payload.
+#[derive(Debug, Clone)]
+pub struct SledStore {
+    db: Db,
+}
+
+impl From<sled::Error> for Error {
+    fn from(value: sled::Error) -> Self { Error::store(value) }
+}
+
+impl SledStore {
+    /// Open or create the database at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> { Ok(Self { db: sled::open(path)? }) }
+}
+
+fn encode(expires_at: u64, forget_at: u64, data: &[u8]) -> Vec<u8> {
+    let mut value = Vec::with_capacity(HEADER_LEN + data.len());
+    value.extend_from_slice(&expires_at.to_be_bytes());
+    value.extend_from_slice(&forget_at.to_be_bytes());
+    value.extend_from_slice(data);
+    value
+}
+
+/// Split a stored value into its expiry timestamps and payload
+fn decode(value: &[u8]) -> Result<(u64, u64, &[u8])> {
+    if value.len() < HEADER_LEN {
+        return Err(Error::store("mailbox value too short"));
+    }
+    let (expires_at, rest) = value.split_at(8);
+    let (forget_at, data) = rest.split_at(8);
+    let expires_at = u64::from_be_bytes(expires_at.try_into().expect("8 bytes"));
+    let forget_at = u64::from_be_bytes(forget_at.try_into().expect("8 bytes"));
+    Ok((expires_at, forget_at, data))
+}
+
+/// The payload of a stored value, if it has one and hasn't expired
+fn payload(value: &[u8]) -> Result<Option<Vec<u8>>> {
+    let (expires_at, _, data) = decode(value)?;
+    if expires_at <= unix_millis(SystemTime::now()) {
+        return Err(Error::Expired);
+    }
+    Ok(Some(data.to_vec()).filter(|data| !data.is_empty()))
+}
+
+impl MailboxStore for SledStore {
+    fn push<'a>(&'a self, key: &'a [u8], data: Vec<u8>, ttl: Duration) -> StoreFuture<'a, ()> {
+        Box::pin(async move {
+            let (expires_at, forget_at) = expiry(SystemTime::now(), ttl);
+            self.db.insert(key, encode(expires_at, forget_at, &data))?;
+            Ok(())
+        })
+    }
+
+    fn peek<'a>(&'a self, key: &'a [u8]) -> StoreFuture<'a, Vec<u8>> {
+        Box::pin(async move {
+            // Subscribe before the first read so an insert in between isn't missed
+            let mut subscriber = self.db.watch_prefix(key);
+            if let Some(value) = self.db.get(key)? {
+                if let Some(data) = payload(&value)? {
+                    return Ok(data);
+                }
+            }
+            while let Some(event) = (&mut subscriber).await {
+                if let Event::Insert { key: inserted, value } = event {
+                    if inserted == key {
+                        if let Some(data) = payload(&value)? {
+                            return Ok(data);
+                        }
+                    }
+                }
+            }
+            Err(Error::store("sled database closed"))
+        })
+    }
+
+    fn sweep(&self) -> StoreFuture<'_, ()> {
+        Box::pin(async move {
+            let now = unix_millis(SystemTime::now());
+            for entry in self.db.iter() {
+                let (key, value) = entry?;
+                let (expires_at, forget_at, data) = decode(&value)?;
+                let swept = if forget_at <= now {
+                    None
+                } else if expires_at <= now && !data.is_empty() {
+                    // Keep the expiry so readers can still tell the mailbox expired
+                    Some(encode(expires_at, forget_at, &[]))
+                } else {
+                    continue;
+                };
+                // The swap fails if a push replaced the value since it was read, which leaves
+                // the freshly pushed mailbox alone
+                let _ = self.db.compare_and_swap(key, Some(value), swept)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test::SHORT_TTL;
+
+    #[tokio::test]
+    async fn test_sweep_erases_expired_payload() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SledStore::open(dir.path().join("db")).unwrap();
+        store.push(b"key", b"payload".to_vec(), SHORT_TTL).await.unwrap();
+        store.sweep().await.unwrap();
+        let value = store.db.get(b"key").unwrap().unwrap();
+        assert_eq!(decode(&value).unwrap().2, b"payload");
+
+        tokio::time::sleep(SHORT_TTL).await;
+        store.sweep().await.unwrap();
+        let value = store.db.get(b"key").unwrap().unwrap();
+        assert!(decode(&value).unwrap().2.is_empty());
+    }
+}
@@ -1,7 +1,7 @@
 use std::net::{IpAddr, Ipv6Addr, SocketAddr};
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use anyhow::Result;
 use http_body_util::combinators::BoxBody;
@@ -15,13 +15,17 @@ use hyper_util::rt::TokioIo;
 use payjoin::directory::{ShortId, ShortIdError, ENCAPSULATED_MESSAGE_BYTES};
 use tokio::net::TcpListener;
 use tokio::sync::Mutex;
-use tracing::{debug, error, info, trace};
+use tracing::{debug, error, trace};
 
-use crate::db::DbPool;
+use crate::db::{DbPool, MailboxStore};
 
 pub const DEFAULT_DIR_PORT: u16 = 8080;
 pub const DEFAULT_DB_HOST: &str = "localhost:6379";
+pub const DEFAULT_DB_PATH: &str = "payjoin-directory-db";
 pub const DEFAULT_TIMEOUT_SECS: u64 = 30;
+/// Matches the default BIP77 session expiry, after which no payload in a mailbox is useful
+pub const DEFAULT_MAILBOX_TTL_SECS: u64 = 60 * 60 * 24;
+const MAILBOX_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
 
 const CHACHA20_POLY1305_NONCE_LEN: usize = 32; // chacha20poly1305 n_k
 const POLY1305_TAG_SIZE: usize = 16;
@@ -33,21 +37,34 @@ const V1_REJECT_RES_JSON: &str =
     r#"{{"errorCode": "original-psbt-rejected ", "message": "Body is not a string"}}"#;
 const V1_UNAVAILABLE_RES_JSON: &str = r#"{{"errorCode": "unavailable", "message": "V2 receiver offline. V1 sends require synchronous communications."}}"#;
 
-mod db;
+pub mod db;
+mod ohttp_keys;
+
+pub use crate::ohttp_keys::{KeyRotation, OhttpKeyStore};
 
 #[cfg(feature = "_danger-local-https")]
 type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
 #[cfg(feature = "_danger-local-https")]
 pub async fn listen_tcp_with_tls_on_free_port(
-    db_host: String,
+    store: Arc<dyn MailboxStore>,
     timeout: Duration,
+    mailbox_ttl: Duration,
+    ohttp_keys: OhttpKeyStore,
     cert_key: (Vec<u8>, Vec<u8>),
 ) -> Result<(u16, tokio::task::JoinHandle<Result<(), BoxError>>), BoxError> {
     let listener = tokio::net::TcpListener::bind("[::]:0").await?;
     let port = listener.local_addr()?.port();
     println!("Directory server binding to port {}", listener.local_addr()?);
-    let handle = listen_tcp_with_tls_on_listener(listener, db_host, timeout, cert_key).await?;
+    let handle = listen_tcp_with_tls_on_listener(
+        listener,
+        store,
+        timeout,
+        mailbox_ttl,
+        ohttp_keys,
+        cert_key,
+    )
+    .await?;
     Ok((port, handle))
 }
 
@@ -55,12 +72,16 @@ pub async fn listen_tcp_with_tls_on_free_port(
 #[cfg(feature = "_danger-local-https")]
 async fn listen_tcp_with_tls_on_listener(
     listener: tokio::net::TcpListener,
-    db_host: String,
+    store: Arc<dyn MailboxStore>,
     timeout: Duration,
+    mailbox_ttl: Duration,
+    ohttp_keys: OhttpKeyStore,
     tls_config: (Vec<u8>, Vec<u8>),
 ) -> Result<tokio::task::JoinHandle<Result<(), BoxError>>, BoxError> {
-    let pool = DbPool::new(timeout, db_host).await?;
-    let ohttp = Arc::new(Mutex::new(init_ohttp()?));
+    let pool = DbPool::new(timeout, mailbox_ttl, store);
+    tokio::spawn(sweep_mailboxes(pool.clone()));
+    let ohttp = Arc::new(Mutex::new(ohttp_keys));
+    tokio::spawn(rotate_ohttp_keys(ohttp.clone()));
     let tls_acceptor = init_tls_acceptor(tls_config)?;
     // Spawn the connection handling loop in a separate task
     let handle = tokio::spawn(async move {
@@ -98,11 +119,15 @@ async fn listen_tcp_with_tls_on_listener(
 // Modify existing listen_tcp_with_tls to use the new helper
 pub async fn listen_tcp(
     port: u16,
-    db_host: String,
+    store: Arc<dyn MailboxStore>,
     timeout: Duration,
+    mailbox_ttl: Duration,
+    ohttp_keys: OhttpKeyStore,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let pool = DbPool::new(timeout, db_host).await?;
-    let ohttp = Arc::new(Mutex::new(init_ohttp()?));
+    let pool = DbPool::new(timeout, mailbox_ttl, store);
+    tokio::spawn(sweep_mailboxes(pool.clone()));
+    let ohttp = Arc::new(Mutex::new(ohttp_keys));
+    tokio::spawn(rotate_ohttp_keys(ohttp.clone()));
     let bind_addr = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port);
     let listener = TcpListener::bind(bind_addr).await?;
     while let Ok((stream, _)) = listener.accept().await {
@@ -131,13 +156,16 @@ pub async fn listen_tcp(
 #[cfg(feature = "_danger-local-https")]
 pub async fn listen_tcp_with_tls(
     port: u16,
-    db_host: String,
+    store: Arc<dyn MailboxStore>,
     timeout: Duration,
+    mailbox_ttl: Duration,
+    ohttp_keys: OhttpKeyStore,
     cert_key: (Vec<u8>, Vec<u8>),
 ) -> Result<tokio::task::JoinHandle<Result<(), BoxError>>, BoxError> {
     let addr = format!("0.0.0.0:{}", port);
     let listener = tokio::net::TcpListener::bind(&addr).await?;
-    listen_tcp_with_tls_on_listener(listener, db_host, timeout, cert_key).await
+    listen_tcp_with_tls_on_listener(listener, store, timeout, mailbox_ttl, ohttp_keys, cert_key)
+        .await
 }
 
 #[cfg(feature = "_danger-local-https")]
@@ -158,25 +186,39 @@ fn init_tls_acceptor(cert_key: (Vec<u8>, Vec<u8>)) -> Result<tokio_rustls::TlsAc
     Ok(TlsAcceptor::from(Arc::new(server_config)))
 }
 
-fn init_ohttp() -> Result<ohttp::Server> {
-    use ohttp::hpke::{Aead, Kdf, Kem};
-    use ohttp::{KeyId, SymmetricSuite};
+/// Periodically erase expired payloads from stores without native expiry
+async fn sweep_mailboxes(pool: DbPool) {
+    let mut interval = tokio::time::interval(MAILBOX_SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = pool.sweep().await {
+            error!("Failed to sweep expired mailboxes: {}", e);
+        }
+    }
+}
 
-    const KEY_ID: KeyId = 1;
-    const KEM: Kem = Kem::K256Sha256;
-    const SYMMETRIC: &[SymmetricSuite] =
-        &[SymmetricSuite::new(Kdf::HkdfSha256, Aead::ChaCha20Poly1305)];
+/// Rotate the OHTTP keys whenever the schedule calls for it
+async fn rotate_ohttp_keys(ohttp: Arc<Mutex<OhttpKeyStore>>) {
+    const RETRY_DELAY: Duration = Duration::from_secs(60);
 
-    // create or read from file
-    let server_config = ohttp::KeyConfig::new(KEY_ID, KEM, Vec::from(SYMMETRIC))?;
-    info!("Initialized a new OHTTP Key Configuration. GET /ohttp-keys to fetch it.");
-    Ok(ohttp::Server::new(server_config)?)
+    loop {
+        let next_rotation = match ohttp.lock().await.next_rotation() {
+            Some(next_rotation) => next_rotation,
+            None => return,
+        };
+        let delay = next_rotation.duration_since(SystemTime::now()).unwrap_or_default();
+        tokio::time::sleep(delay).await;
+        if let Err(e) = ohttp.lock().await.rotate(SystemTime::now()) {
+            error!("Failed to rotate OHTTP keys: {}", e);
+            tokio::time::sleep(RETRY_DELAY).await;
+        }
+    }
 }
 
 async fn serve_payjoin_directory(
     req: Request<Incoming>,
     pool: DbPool,
-    ohttp: Arc<Mutex<ohttp::Server>>,
+    ohttp: Arc<Mutex<OhttpKeyStore>>,
 ) -> Result<Response<BoxBody<Bytes, hyper::Error>>> {
     let path = req.uri().path().to_string();
     let query = req.uri().query().unwrap_or_default().to_string();
@@ -187,6 +229,8 @@ async fn serve_payjoin_directory(
     let mut response = match (parts.method, path_segments.as_slice()) {
         (Method::POST, ["", ""]) => handle_ohttp_gateway(body, pool, ohttp).await,
         (Method::GET, ["", "ohttp-keys"]) => get_ohttp_keys(&ohttp).await,
+        // RFC 9540 location for relays to fetch their gateway's keys from
+        (Method::GET, ["", ".well-known", "ohttp-gateway"]) => get_ohttp_keys(&ohttp).await,
         (Method::POST, ["", id]) => post_fallback_v1(id, query, body, pool).await,
         (Method::GET, ["", "health"]) => health_check().await,
         _ => Ok(not_found()),
@@ -202,7 +246,7 @@ async fn serve_payjoin_directory(
 async fn handle_ohttp_gateway(
     body: Incoming,
     pool: DbPool,
-    ohttp: Arc<Mutex<ohttp::Server>>,
+    ohttp: Arc<Mutex<OhttpKeyStore>>,
 ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, HandlerError> {
     // decapsulate
     let ohttp_body =
@@ -324,11 +368,12 @@ fn handle_peek(
     match result {
         Ok(buffered_req) => Ok(Response::new(full(buffered_req))),
         Err(e) => match e {
-            db::Error::Redis(re) => {
-                error!("Redis error: {}", re);
+            db::Error::Store(se) => {
+                error!("Storage error: {}", se);
                 Err(HandlerError::InternalServerError(anyhow::Error::msg("Internal server error")))
             }
             db::Error::Timeout(_) => Ok(timeout_response),
+            db::Error::Expired => Ok(gone()),
         },
     }
 }
@@ -417,6 +462,13 @@ async fn get_subdir(
     handle_peek(pool.peek_default(&id).await, timeout_response)
 }
 
+/// The response to reading an expired mailbox, distinct from one that is merely still empty
+fn gone() -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut res = Response::new(empty());
+    *res.status_mut() = StatusCode::GONE;
+    res
+}
+
 fn not_found() -> Response<BoxBody<Bytes, hyper::Error>> {
     let mut res = Response::default();
     *res.status_mut() = StatusCode::NOT_FOUND;
@@ -424,12 +476,12 @@ fn not_found() -> Response<BoxBody<Bytes, hyper::Error>> {
 }
 
 async fn get_ohttp_keys(
-    ohttp: &Arc<Mutex<ohttp::Server>>,
+    ohttp: &Arc<Mutex<OhttpKeyStore>>,
 ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, HandlerError> {
     let ohttp_keys = ohttp
         .lock()
         .await
-        .config()
+        .advertised()
         .encode()
         .map_err(|e| HandlerError::InternalServerError(e.into()))?;
     let mut res = Response::new(full(ohttp_keys));
@@ -444,3 +496,28 @@ fn empty() -> BoxBody<Bytes, hyper::Error> {
 fn full<T: Into<Bytes>>(chunk: T) -> BoxBody<Bytes, hyper::Error> {
     Full::new(chunk.into()).map_err(|never| match never {}).boxed()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::MemoryStore;
+
+    #[tokio::test]
+    async fn test_expired_mailbox_is_gone() {
+        let ttl = Duration::from_millis(100);
+        let pool = DbPool::new(Duration::from_millis(200), ttl, Arc::new(MemoryStore::default()));
+        let path = format!("/{}", ShortId([1; 8]));
+        let request = |method: Method, body: BoxBody<Bytes, hyper::Error>| {
+            Request::builder().method(method).uri(path.as_str()).body(body).unwrap()
+        };
+
+        let res = handle_v2(pool.clone(), request(Method::POST, full("payload"))).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = handle_v2(pool.clone(), request(Method::GET, empty())).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        tokio::time::sleep(ttl).await;
+        let res = handle_v2(pool, request(Method::GET, empty())).await.unwrap();
+        assert_eq!(res.status(), StatusCode::GONE);
+    }
+}
@@ -1,4 +1,6 @@
 use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
 
 use payjoin_directory::*;
 use tracing_subscriber::filter::LevelFilter;
@@ -13,11 +15,35 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let timeout_env = env::var("PJ_DIR_TIMEOUT_SECS")
         .map_or(DEFAULT_TIMEOUT_SECS, |s| s.parse().expect("Invalid timeout"));
-    let timeout = std::time::Duration::from_secs(timeout_env);
-
-    let db_host = env::var("PJ_DB_HOST").unwrap_or_else(|_| DEFAULT_DB_HOST.to_string());
-
-    payjoin_directory::listen_tcp(dir_port, db_host, timeout).await
+    let timeout = Duration::from_secs(timeout_env);
+
+    let mailbox_ttl = Duration::from_secs(
+        env::var("PJ_MAILBOX_TTL_SECS")
+            .map_or(DEFAULT_MAILBOX_TTL_SECS, |s| s.parse().expect("Invalid mailbox TTL")),
+    );
+
+    let db_backend = match env::var("PJ_DB_BACKEND").as_deref() {
+        Ok("redis") | Err(_) => db::DbBackend::Redis(
+            env::var("PJ_DB_HOST").unwrap_or_else(|_| DEFAULT_DB_HOST.to_string()),
+        ),
+        Ok("sled") => db::DbBackend::Sled(
+            env::var("PJ_DB_PATH").map_or_else(|_| PathBuf::from(DEFAULT_DB_PATH), PathBuf::from),
+        ),
+        Ok("memory") => db::DbBackend::Memory,
+        Ok(other) => panic!("Invalid database backend: {}", other),
+    };
+    let store = db_backend.open()?;
+
+    let ohttp_key_dir = env::var("PJ_OHTTP_KEY_DIR").ok().map(PathBuf::from);
+    let ohttp_key_rotation = env::var("PJ_OHTTP_KEY_ROTATION_SECS").ok().map(|s| {
+        let interval = Duration::from_secs(s.parse().expect("Invalid rotation interval"));
+        let grace_period = env::var("PJ_OHTTP_KEY_GRACE_SECS")
+            .map_or(interval, |s| Duration::from_secs(s.parse().expect("Invalid grace period")));
+        KeyRotation { interval, grace_period }
+    });
+    let ohttp_keys = OhttpKeyStore::new(ohttp_key_dir.as_deref(), ohttp_key_rotation)?;
+
+    payjoin_directory::listen_tcp(dir_port, store, timeout, mailbox_ttl, ohttp_keys).await
 }
 
 fn init_logging() {
@@ -0,0 +1,319 @@
+//! OHTTP key configurations that survive restarts and rotate on a schedule
+//!
+//! Receivers pin the directory's OHTTP key in the `ohttp` parameter of their payjoin URIs, so
+//! replacing the key breaks every URI still outstanding. Keys persisted to a directory are
+//! loaded again at startup, and a rotated key keeps decapsulating requests for a grace period
+//! while `/ohttp-keys` advertises its successor.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use bitcoin::secp256k1::rand::{thread_rng, RngCore};
+use ohttp::hpke::{Aead, Kdf, Kem};
+use ohttp::{KeyId, SymmetricSuite};
+use tracing::info;
+
+const KEM: Kem = Kem::K256Sha256;
+const SYMMETRIC: &[SymmetricSuite] =
+    &[SymmetricSuite::new(Kdf::HkdfSha256, Aead::ChaCha20Poly1305)];
+
+/// The key ID of the first key configuration
+const FIRST_KEY_ID: KeyId = 1;
+
+/// Length of the input keying material each key pair is derived from
+const IKM_LEN: usize = 32;
+
+/// Key files are named after their key ID with this extension
+const KEY_FILE_EXTENSION: &str = "ikm";
+
+/// Key IDs are a single byte, so at most this many keys can be live at once
+const MAX_LIVE_KEYS: u128 = KeyId::MAX as u128 + 1;
+
+/// How often the advertised OHTTP key is replaced and how long replaced keys remain usable
+#[derive(Debug, Clone, Copy)]
+pub struct KeyRotation {
+    /// Age at which the advertised key is replaced
+    pub interval: Duration,
+    /// How long a replaced key keeps decapsulating requests
+    pub grace_period: Duration,
+}
+
+impl KeyRotation {
+    /// Reject rotations that would rotate continuously or need more live keys than there are
+    /// key IDs
+    fn validate(&self) -> Result<()> {
+        if self.interval.is_zero() {
+            return Err(anyhow!("OHTTP key rotation interval must be greater than zero"));
+        }
+        // The advertised key, the keys it replaced that are still in their grace period, and
+        // one more while a late rotation has yet to retire the oldest
+        let live_keys = self.grace_period.as_nanos() / self.interval.as_nanos() + 2;
+        if live_keys > MAX_LIVE_KEYS {
+            return Err(anyhow!(
+                "OHTTP key grace period {:?} is too long for rotation interval {:?}",
+                self.grace_period,
+                self.interval
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Key {
+    key_id: KeyId,
+    server: ohttp::Server,
+    created: SystemTime,
+}
+
+/// The OHTTP key configurations the directory decapsulates requests with
+#[derive(Debug)]
+pub struct OhttpKeyStore {
+    dir: Option<PathBuf>,
+    rotation: Option<KeyRotation>,
+    /// Oldest first. The last key is the one advertised.
+    keys: Vec<Key>,
+}
+
+impl OhttpKeyStore {
+    /// Load the keys persisted in `dir`, or create the first one.
+    ///
+    /// Without `dir` a fresh key is created that is lost on restart. Without `rotation` the
+    /// advertised key is never replaced.
+    pub fn new(dir: Option<&Path>, rotation: Option<KeyRotation>) -> Result<Self> {
+        if let Some(rotation) = rotation {
+            rotation.validate()?;
+        }
+        let mut store = Self { dir: dir.map(Path::to_path_buf), rotation, keys: Vec::new() };
+        if let Some(dir) = dir {
+            fs::create_dir_all(dir)?;
+            store.keys = load_keys(dir)?;
+        }
+        if store.keys.is_empty() {
+            store.add_key(FIRST_KEY_ID, SystemTime::now())?;
+        } else {
+            info!("Loaded {} OHTTP Key Configurations.", store.keys.len());
+        }
+        store.rotate(SystemTime::now())?;
+        Ok(store)
+    }
+
+    /// The key configuration advertised at `/ohttp-keys`
+    pub(crate) fn advertised(&self) -> &ohttp::KeyConfig {
+        self.keys.last().expect("the store always holds a key").server.config()
+    }
+
+    /// Decapsulate a request with whichever live key it was encapsulated to
+    pub(crate) fn decapsulate(
+        &self,
+        enc_request: &[u8],
+    ) -> std::result::Result<(Vec<u8>, ohttp::ServerResponse), ohttp::Error> {
+        let key_id = *enc_request.first().ok_or(ohttp::Error::Truncated)?;
+        let key = self.keys.iter().find(|key| key.key_id == key_id).ok_or(ohttp::Error::KeyId)?;
+        key.server.decapsulate(enc_request)
+    }
+
+    /// Replace the advertised key if it is due and retire keys whose grace period is over
+    pub(crate) fn rotate(&mut self, now: SystemTime) -> Result<()> {
+        let rotation = match self.rotation {
+            Some(rotation) => rotation,
+            None => return Ok(()),
+        };
+
+        // A key is retired once its successor is created. Retire before adding so that the
+        // new key can reuse a freed key ID.
+        while self.keys.len() > 1 && now >= self.keys[1].created + rotation.grace_period {
+            let key = self.keys.remove(0);
+            if let Some(dir) = &self.dir {
+                fs::remove_file(key_path(dir, key.key_id))?;
+            }
+            info!("Retired OHTTP Key Configuration {}.", key.key_id);
+        }
+
+        let newest = self.keys.last().expect("the store always holds a key");
+        if now >= newest.created + rotation.interval {
+            let key_id = (1..=KeyId::MAX)
+                .map(|offset| newest.key_id.wrapping_add(offset))
+                .find(|key_id| self.keys.iter().all(|key| key.key_id != *key_id))
+                .ok_or_else(|| anyhow!("every OHTTP key ID is in use"))?;
+            self.add_key(key_id, now)?;
+        }
+        Ok(())
+    }
+
+    /// When [`Self::rotate`] next has work to do, if ever
+    pub(crate) fn next_rotation(&self) -> Option<SystemTime> {
+        let rotation = self.rotation?;
+        let newest = self.keys.last().expect("the store always holds a key");
+        let next_key = newest.created + rotation.interval;
+        let next_retirement = self.keys.get(1).map(|key| key.created + rotation.grace_period);
+        Some(next_retirement.map_or(next_key, |retirement| retirement.min(next_key)))
+    }
+
+    fn add_key(&mut self, key_id: KeyId, created: SystemTime) -> Result<()> {
+        let mut ikm = [0u8; IKM_LEN];
+        thread_rng().fill_bytes(&mut ikm);
+        if let Some(dir) = &self.dir {
+            persist_key(dir, key_id, created, &ikm)?;
+        }
+        self.keys.push(Key { key_id, server: derive_server(key_id, &ikm)?, created });
+        info!("Initialized a new OHTTP Key Configuration {}. GET /ohttp-keys to fetch it.", key_id);
+        Ok(())
+    }
+}
+
+fn derive_server(key_id: KeyId, ikm: &[u8]) -> Result<ohttp::Server> {
+    let config = ohttp::KeyConfig::derive(key_id, KEM, Vec::from(SYMMETRIC), ikm)?;
+    Ok(ohttp::Server::new(config)?)
+}
+
+fn key_path(dir: &Path, key_id: KeyId) -> PathBuf {
+    dir.join(key_id.to_string()).with_extension(KEY_FILE_EXTENSION)
+}
+
+/// Key files hold the creation time in big-endian seconds since the epoch followed by the
+/// input keying material.
+fn persist_key(dir: &Path, key_id: KeyId, created: SystemTime, ikm: &[u8]) -> Result<()> {
+    let created = created.duration_since(UNIX_EPOCH)?.as_secs();
+    // Write to a temporary file first so that a crash never leaves a truncated key behind
+    let tmp_path = key_path(dir, key_id).with_extension("tmp");
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp_path)?;
+    file.write_all(&created.to_be_bytes())?;
+    file.write_all(ikm)?;
+    file.sync_all()?;
+    fs::rename(tmp_path, key_path(dir, key_id))?;
+    Ok(())
+}
+
+fn load_keys(dir: &Path) -> Result<Vec<Key>> {
+    let mut keys = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() != Some(OsStr::new(KEY_FILE_EXTENSION)) {
+            continue;
+        }
+        let key_id: KeyId = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok())
+            .ok_or_else(|| anyhow!("invalid OHTTP key file name {}", path.display()))?;
+        let contents = fs::read(&path)?;
+        if contents.len() != 8 + IKM_LEN {
+            return Err(anyhow!("invalid OHTTP key file {}", path.display()));
+        }
+        let (created, ikm) = contents.split_at(8);
+        let created = u64::from_be_bytes(created.try_into().expect("split at 8 bytes"));
+        let created = UNIX_EPOCH + Duration::from_secs(created);
+        keys.push(Key { key_id, server: derive_server(key_id, ikm)?, created });
+    }
+    keys.sort_by_key(|key| key.created);
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ROTATION: KeyRotation =
+        KeyRotation { interval: Duration::from_secs(3600), grace_period: Duration::from_secs(600) };
+
+    /// Encapsulate a request to the key the store currently advertises
+    fn encapsulate_to(store: &OhttpKeyStore) -> Vec<u8> {
+        let config = store.advertised().encode().expect("encode key config");
+        let client = ohttp::ClientRequest::from_encoded_config(&config).expect("valid key config");
+        client.encapsulate(b"request").expect("encapsulate").0
+    }
+
+    #[test]
+    fn test_keys_load_after_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = OhttpKeyStore::new(Some(dir.path()), None).unwrap();
+        let enc_request = encapsulate_to(&store);
+        drop(store);
+
+        let reloaded = OhttpKeyStore::new(Some(dir.path()), None).unwrap();
+        assert_eq!(reloaded.keys.len(), 1);
+        let (request, _) = reloaded.decapsulate(&enc_request).unwrap();
+        assert_eq!(request, b"request");
+    }
+
+    #[test]
+    fn test_key_rotates_at_interval() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = OhttpKeyStore::new(Some(dir.path()), Some(ROTATION)).unwrap();
+        let created = store.keys[0].created;
+        assert_eq!(store.next_rotation(), Some(created + ROTATION.interval));
+
+        store.rotate(created + ROTATION.interval - Duration::from_secs(1)).unwrap();
+        assert_eq!(store.keys.len(), 1);
+
+        store.rotate(created + ROTATION.interval).unwrap();
+        assert_eq!(store.keys.len(), 2);
+        assert_eq!(store.keys[1].key_id, FIRST_KEY_ID + 1);
+        assert!(key_path(dir.path(), FIRST_KEY_ID + 1).exists());
+        assert_eq!(
+            store.next_rotation(),
+            Some(created + ROTATION.interval + ROTATION.grace_period)
+        );
+    }
+
+    #[test]
+    fn test_key_retires_after_grace_period() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = OhttpKeyStore::new(Some(dir.path()), Some(ROTATION)).unwrap();
+        let rotated = store.keys[0].created + ROTATION.interval;
+        let enc_request = encapsulate_to(&store);
+        store.rotate(rotated).unwrap();
+
+        // The replaced key still decapsulates during the grace period
+        store.rotate(rotated + ROTATION.grace_period - Duration::from_secs(1)).unwrap();
+        assert_eq!(store.keys.len(), 2);
+        assert!(store.decapsulate(&enc_request).is_ok());
+
+        store.rotate(rotated + ROTATION.grace_period).unwrap();
+        assert_eq!(store.keys.len(), 1);
+        assert!(!key_path(dir.path(), FIRST_KEY_ID).exists());
+        assert!(matches!(store.decapsulate(&enc_request), Err(ohttp::Error::KeyId)));
+
+        let reloaded = OhttpKeyStore::new(Some(dir.path()), None).unwrap();
+        assert!(matches!(reloaded.decapsulate(&enc_request), Err(ohttp::Error::KeyId)));
+    }
+
+    #[test]
+    fn test_rotation_rejects_zero_interval() {
+        let rotation = KeyRotation { interval: Duration::ZERO, grace_period: Duration::ZERO };
+        assert!(OhttpKeyStore::new(None, Some(rotation)).is_err());
+    }
+
+    #[test]
+    fn test_rotation_rejects_too_many_live_keys() {
+        let interval = Duration::from_secs(1);
+        let rotation = KeyRotation { interval, grace_period: interval * 254 };
+        assert!(OhttpKeyStore::new(None, Some(rotation)).is_ok());
+        let rotation = KeyRotation { interval, grace_period: interval * 255 };
+        assert!(OhttpKeyStore::new(None, Some(rotation)).is_err());
+    }
+
+    #[test]
+    fn test_rotate_errors_when_key_ids_exhausted() {
+        let mut store = OhttpKeyStore::new(None, None).unwrap();
+        let created = store.keys[0].created;
+        for key_id in FIRST_KEY_ID + 1..=KeyId::MAX {
+            store.add_key(key_id, created).unwrap();
+        }
+        store.add_key(0, created).unwrap();
+        // A grace period long enough that none of the keys retire
+        let rotation = KeyRotation { grace_period: ROTATION.interval * 2, ..ROTATION };
+        store.rotation = Some(rotation);
+        assert!(store.rotate(created + rotation.interval).is_err());
+        assert_eq!(store.keys.len(), MAX_LIVE_KEYS as usize);
+    }
+}
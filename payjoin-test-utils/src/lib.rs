@@ -12,8 +12,16 @@ use log::{log_enabled, Level};
 use once_cell::sync::OnceCell;
 use payjoin::io::{fetch_ohttp_keys_with_cert, Error as IOError};
 use payjoin::OhttpKeys;
+use payjoin_directory::db::MailboxStore;
+#[cfg(not(feature = "redis"))]
+use payjoin_directory::db::MemoryStore;
+#[cfg(feature = "redis")]
+use payjoin_directory::db::RedisStore;
+use payjoin_directory::OhttpKeyStore;
 use reqwest::{Client, ClientBuilder};
+#[cfg(feature = "redis")]
 use testcontainers::{clients, Container};
+#[cfg(feature = "redis")]
 use testcontainers_modules::redis::{Redis, REDIS_PORT};
 use tokio::task::JoinHandle;
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
@@ -38,7 +46,8 @@ pub fn init_tracing() {
 
 pub struct TestServices {
     cert_key: (Vec<u8>, Vec<u8>),
-    /// redis is an implicit dependency of the directory service
+    /// redis backs the directory service when the `redis` feature is enabled
+    #[cfg(feature = "redis")]
     #[allow(dead_code)]
     redis: (u16, Container<'static, Redis>),
     directory: (u16, Option<JoinHandle<Result<(), BoxSendSyncError>>>),
@@ -49,14 +58,20 @@ pub struct TestServices {
 impl TestServices {
     pub async fn initialize() -> Result<Self, BoxSendSyncError> {
         let cert_key = local_cert_key();
+        #[cfg(feature = "redis")]
         let redis = init_redis();
-        let db_host = format!("127.0.0.1:{}", redis.0);
-        let directory = init_directory(db_host, cert_key.clone()).await?;
+        #[cfg(feature = "redis")]
+        let store: Arc<dyn MailboxStore> =
+            Arc::new(RedisStore::new(&format!("127.0.0.1:{}", redis.0))?);
+        #[cfg(not(feature = "redis"))]
+        let store: Arc<dyn MailboxStore> = Arc::new(MemoryStore::default());
+        let directory = init_directory(store, cert_key.clone()).await?;
         let gateway_origin = Uri::from_str(&format!("https://localhost:{}", directory.0))?;
         let ohttp_relay = ohttp_relay::listen_tcp_on_free_port(gateway_origin).await?;
         let http_agent: Arc<Client> = Arc::new(http_agent(cert_key.0.clone())?);
         Ok(Self {
             cert_key,
+            #[cfg(feature = "redis")]
             redis,
             directory: (directory.0, Some(directory.1)),
             ohttp_relay: (ohttp_relay.0, Some(ohttp_relay.1)),
@@ -95,6 +110,7 @@ impl TestServices {
     }
 }
 
+#[cfg(feature = "redis")]
 pub fn init_redis() -> (u16, Container<'static, Redis>) {
     let docker = Box::leak(Box::new(clients::Cli::default()));
     let redis_instance = docker.run(Redis);
@@ -103,15 +119,23 @@ pub fn init_redis() -> (u16, Container<'static, Redis>) {
 }
 
 pub async fn init_directory(
-    db_host: String,
+    store: Arc<dyn MailboxStore>,
     local_cert_key: (Vec<u8>, Vec<u8>),
 ) -> std::result::Result<
     (u16, tokio::task::JoinHandle<std::result::Result<(), BoxSendSyncError>>),
     BoxSendSyncError,
 > {
-    println!("Database running on {}", db_host);
     let timeout = Duration::from_secs(2);
-    payjoin_directory::listen_tcp_with_tls_on_free_port(db_host, timeout, local_cert_key).await
+    let mailbox_ttl = Duration::from_secs(payjoin_directory::DEFAULT_MAILBOX_TTL_SECS);
+    let ohttp_keys = OhttpKeyStore::new(None, None)?;
+    payjoin_directory::listen_tcp_with_tls_on_free_port(
+        store,
+        timeout,
+        mailbox_ttl,
+        ohttp_keys,
+        local_cert_key,
+    )
+    .await
 }
 
 /// generate or get a DER encoded localhost cert and key.
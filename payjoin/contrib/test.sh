@@ -3,3 +3,6 @@ set -e
 
 cargo test --locked --package payjoin --verbose --all-features --lib
 cargo test --locked --package payjoin --verbose --all-features --test integration
+# Each HTTP client backend must build on its own
+cargo build --locked --package payjoin --verbose --no-default-features --features io-hyper
+cargo build --locked --package payjoin --verbose --no-default-features --features io-blocking
@@ -0,0 +1,186 @@
+//! Encrypted-at-rest serialization of sessions
+//!
+//! A serialized [`crate::receive::v2::Receiver`] or [`crate::send::v2::Sender`] contains HPKE
+//! secret keys. [`SessionKey::seal`] wraps the serde output in ChaCha20-Poly1305 so the session
+//! can be persisted without writing those keys in the clear, and [`SessionKey::open`] reverses it.
+//!
+//! A sealed session is `version || nonce || ciphertext`.
+
+use std::{error, fmt};
+
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hmac::Hmac;
+use hpke::rand_core::{OsRng, RngCore};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::Sha256;
+use zeroize::{Zeroize, Zeroizing};
+
+const VERSION: u8 = 1;
+const NONCE_LEN: usize = 12;
+
+/// PBKDF2-HMAC-SHA256 rounds used to derive a [`SessionKey`] from a passphrase
+pub const PBKDF2_ROUNDS: u32 = 600_000;
+
+/// A symmetric key sessions are sealed under
+///
+/// The key is overwritten with zeroes when dropped.
+#[derive(Clone)]
+pub struct SessionKey([u8; 32]);
+
+impl SessionKey {
+    /// Use existing key material as the session key
+    pub fn from_bytes(bytes: [u8; 32]) -> Self { Self(bytes) }
+
+    /// Derive the session key from a passphrase with PBKDF2-HMAC-SHA256
+    ///
+    /// The `salt` should be random, at least 16 bytes, and stored alongside the sealed sessions.
+    pub fn from_passphrase(passphrase: &str, salt: &[u8]) -> Self {
+        let mut key = [0u8; 32];
+        pbkdf2::pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+        Self(key)
+    }
+
+    /// Serialize `session` to JSON and encrypt it
+    pub fn seal<T: Serialize>(&self, session: &T) -> Result<Vec<u8>, EncryptionError> {
+        let plaintext = Zeroizing::new(
+            serde_json::to_vec(session).map_err(InternalEncryptionError::Serialize)?,
+        );
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext = self
+            .cipher()
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+            .map_err(|_| InternalEncryptionError::Encrypt)?;
+
+        let mut sealed = Vec::with_capacity(1 + NONCE_LEN + ciphertext.len());
+        sealed.push(VERSION);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypt a session produced by [`SessionKey::seal`] and deserialize it
+    pub fn open<T: DeserializeOwned>(&self, sealed: &[u8]) -> Result<T, EncryptionError> {
+        let (version, rest) = sealed.split_first().ok_or(InternalEncryptionError::Truncated)?;
+        if *version != VERSION {
+            return Err(InternalEncryptionError::UnsupportedVersion(*version).into());
+        }
+        if rest.len() < NONCE_LEN {
+            return Err(InternalEncryptionError::Truncated.into());
+        }
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let plaintext = Zeroizing::new(
+            self.cipher()
+                .decrypt(Nonce::from_slice(nonce), ciphertext)
+                .map_err(|_| InternalEncryptionError::Decrypt)?,
+        );
+        Ok(serde_json::from_slice(&plaintext).map_err(InternalEncryptionError::Deserialize)?)
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 { ChaCha20Poly1305::new(Key::from_slice(&self.0)) }
+}
+
+impl Drop for SessionKey {
+    fn drop(&mut self) { self.0.zeroize() }
+}
+
+impl zeroize::ZeroizeOnDrop for SessionKey {}
+
+impl fmt::Debug for SessionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "SessionKey([REDACTED])") }
+}
+
+/// Error sealing or opening an encrypted session
+#[derive(Debug)]
+pub struct EncryptionError(InternalEncryptionError);
+
+#[derive(Debug)]
+pub(crate) enum InternalEncryptionError {
+    Serialize(serde_json::Error),
+    Deserialize(serde_json::Error),
+    Encrypt,
+    /// Wrong key, or the sealed session was tampered with
+    Decrypt,
+    UnsupportedVersion(u8),
+    Truncated,
+}
+
+impl From<InternalEncryptionError> for EncryptionError {
+    fn from(value: InternalEncryptionError) -> Self { EncryptionError(value) }
+}
+
+impl fmt::Display for EncryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use InternalEncryptionError::*;
+
+        match &self.0 {
+            Serialize(e) => write!(f, "Failed to serialize session: {}", e),
+            Deserialize(e) => write!(f, "Failed to deserialize session: {}", e),
+            Encrypt => write!(f, "Failed to encrypt session"),
+            Decrypt => write!(f, "Failed to decrypt session: wrong key or corrupted data"),
+            UnsupportedVersion(v) => write!(f, "Unsupported encrypted session version {}", v),
+            Truncated => write!(f, "Encrypted session is truncated"),
+        }
+    }
+}
+
+impl error::Error for EncryptionError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use InternalEncryptionError::*;
+
+        match &self.0 {
+            Serialize(e) => Some(e),
+            Deserialize(e) => Some(e),
+            Encrypt => None,
+            Decrypt => None,
+            UnsupportedVersion(_) => None,
+            Truncated => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::HpkeKeyPair;
+
+    #[test]
+    fn seal_and_open() {
+        let key = SessionKey::from_bytes([7; 32]);
+        let keypair = HpkeKeyPair::gen_keypair();
+        let sealed = key.seal(keypair.secret_key()).expect("sealing should succeed");
+        assert_eq!(sealed[0], VERSION);
+        let secret_bytes = serde_json::to_vec(keypair.secret_key()).unwrap();
+        assert!(!sealed.windows(secret_bytes.len()).any(|w| w == secret_bytes.as_slice()));
+
+        let opened: crate::hpke::HpkeSecretKey = key.open(&sealed).expect("opening should succeed");
+        assert_eq!(&opened, keypair.secret_key());
+    }
+
+    #[test]
+    fn open_rejects_wrong_key_and_tampering() {
+        let key = SessionKey::from_bytes([7; 32]);
+        let mut sealed = key.seal(&"session").unwrap();
+        assert!(matches!(
+            SessionKey::from_bytes([8; 32]).open::<String>(&sealed),
+            Err(EncryptionError(InternalEncryptionError::Decrypt))
+        ));
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(matches!(
+            key.open::<String>(&sealed),
+            Err(EncryptionError(InternalEncryptionError::Decrypt))
+        ));
+        assert!(matches!(
+            key.open::<String>(&sealed[..NONCE_LEN]),
+            Err(EncryptionError(InternalEncryptionError::Truncated))
+        ));
+        sealed[0] = 0;
+        assert!(matches!(
+            key.open::<String>(&sealed),
+            Err(EncryptionError(InternalEncryptionError::UnsupportedVersion(0)))
+        ));
+    }
+}
@@ -1,7 +1,7 @@
 use std::ops::Deref;
 use std::{error, fmt};
 
-use bitcoin::key::constants::{ELLSWIFT_ENCODING_SIZE, PUBLIC_KEY_SIZE};
+use bitcoin::key::constants::{ELLSWIFT_ENCODING_SIZE, PUBLIC_KEY_SIZE, SECRET_KEY_SIZE};
 use bitcoin::secp256k1;
 use bitcoin::secp256k1::ellswift::ElligatorSwift;
 use hpke::aead::ChaCha20Poly1305;
@@ -10,6 +10,7 @@ use hpke::kem::SecpK256HkdfSha256;
 use hpke::rand_core::OsRng;
 use hpke::{Deserializable, OpModeR, OpModeS, Serializable};
 use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
 
 pub const PADDED_MESSAGE_BYTES: usize = 7168;
 pub const PADDED_PLAINTEXT_A_LENGTH: usize =
@@ -19,6 +20,8 @@ pub const PADDED_PLAINTEXT_B_LENGTH: usize =
 pub const POLY1305_TAG_SIZE: usize = 16; // FIXME there is a U16 defined for poly1305, should bitcoin hpke re-export it?
 pub const INFO_A: &[u8; 8] = b"PjV2MsgA";
 pub const INFO_B: &[u8; 8] = b"PjV2MsgB";
+/// Message A payload retracting an Original PSBT posted with the same reply key
+pub const CANCEL_PAYLOAD: &[u8] = b"cancel";
 
 pub type SecretKey = <SecpK256HkdfSha256 as hpke::Kem>::PrivateKey;
 pub type PublicKey = <SecpK256HkdfSha256 as hpke::Kem>::PublicKey;
@@ -33,13 +36,13 @@ impl From<HpkeKeyPair> for (HpkeSecretKey, HpkePublicKey) {
 
 impl HpkeKeyPair {
     pub fn from_secret_key(secret_key: &HpkeSecretKey) -> Self {
-        let public_key = <SecpK256HkdfSha256 as hpke::Kem>::sk_to_pk(&secret_key.0);
+        let public_key = <SecpK256HkdfSha256 as hpke::Kem>::sk_to_pk(&secret_key.key());
         Self(secret_key.clone(), HpkePublicKey(public_key))
     }
 
     pub fn gen_keypair() -> Self {
         let (sk, pk) = <SecpK256HkdfSha256 as hpke::Kem>::gen_keypair(&mut OsRng);
-        Self(HpkeSecretKey(sk), HpkePublicKey(pk))
+        Self(HpkeSecretKey::from_key(&sk), HpkePublicKey(pk))
     }
     pub fn secret_key(&self) -> &HpkeSecretKey { &self.0 }
     pub fn public_key(&self) -> &HpkePublicKey { &self.1 }
@@ -79,13 +82,23 @@ fn ellswift_bytes_from_encapped_key(
     Ok(ellswift.to_array())
 }
 
+/// The bytes of a secret key, overwritten with zeroes when dropped
+///
+/// The HPKE key is rebuilt from them for each use since bitcoin-hpke's own key type can't be
+/// erased.
 #[derive(Clone, PartialEq, Eq)]
-pub struct HpkeSecretKey(pub SecretKey);
+pub struct HpkeSecretKey(Zeroizing<[u8; SECRET_KEY_SIZE]>);
 
-impl Deref for HpkeSecretKey {
-    type Target = SecretKey;
+impl HpkeSecretKey {
+    fn from_key(key: &SecretKey) -> Self {
+        let mut bytes = Zeroizing::new([0u8; SECRET_KEY_SIZE]);
+        bytes.copy_from_slice(&key.to_bytes());
+        Self(bytes)
+    }
 
-    fn deref(&self) -> &Self::Target { &self.0 }
+    fn key(&self) -> SecretKey {
+        SecretKey::from_bytes(&self.0[..]).expect("bytes of a valid secret key")
+    }
 }
 
 impl core::fmt::Debug for HpkeSecretKey {
@@ -94,12 +107,15 @@ impl core::fmt::Debug for HpkeSecretKey {
     }
 }
 
+/// Secret keys are overwritten with zeroes when dropped
+impl zeroize::ZeroizeOnDrop for HpkeSecretKey {}
+
 impl serde::Serialize for HpkeSecretKey {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        serializer.serialize_bytes(&self.0.to_bytes())
+        serializer.serialize_bytes(&self.0[..])
     }
 }
 
@@ -109,10 +125,10 @@ impl<'de> serde::Deserialize<'de> for HpkeSecretKey {
         D: serde::Deserializer<'de>,
     {
         let bytes = Vec::<u8>::deserialize(deserializer)?;
-        Ok(HpkeSecretKey(
-            SecretKey::from_bytes(&bytes)
-                .map_err(|_| serde::de::Error::custom("Invalid secret key"))?,
-        ))
+        let bytes = Zeroizing::new(bytes);
+        let key = SecretKey::from_bytes(&bytes)
+            .map_err(|_| serde::de::Error::custom("Invalid secret key"))?;
+        Ok(HpkeSecretKey::from_key(&key))
     }
 }
 
@@ -207,7 +223,7 @@ pub fn decrypt_message_a(
         ChaCha20Poly1305,
         HkdfSha256,
         SecpK256HkdfSha256,
-    >(&OpModeR::Base, &receiver_sk.0, &enc, INFO_A)?;
+    >(&OpModeR::Base, &receiver_sk.key(), &enc, INFO_A)?;
 
     let mut ciphertext = Vec::new();
     cursor.read_to_end(&mut ciphertext).map_err(|_| HpkeError::PayloadTooShort)?;
@@ -229,7 +245,7 @@ pub fn encrypt_message_b(
     let (encapsulated_key, mut encryption_context) =
         hpke::setup_sender::<ChaCha20Poly1305, HkdfSha256, SecpK256HkdfSha256, _>(
             &OpModeS::Auth((
-                receiver_keypair.secret_key().0.clone(),
+                receiver_keypair.secret_key().key(),
                 receiver_keypair.public_key().0.clone(),
             )),
             &sender_pk.0,
@@ -254,7 +270,7 @@ pub fn decrypt_message_b(
         ChaCha20Poly1305,
         HkdfSha256,
         SecpK256HkdfSha256,
-    >(&OpModeR::Auth(receiver_pk.0), &sender_sk.0, &enc, INFO_B)?;
+    >(&OpModeR::Auth(receiver_pk.0), &sender_sk.key(), &enc, INFO_B)?;
     let plaintext = decryption_ctx
         .open(message_b.get(ELLSWIFT_ENCODING_SIZE..).ok_or(HpkeError::PayloadTooShort)?, &[])?;
     Ok(plaintext)
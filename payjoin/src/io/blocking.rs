@@ -0,0 +1,164 @@
+//! Blocking counterparts of the io functions, for callers without an async runtime.
+//!
+//! Requests are made with a [`ureq::Agent`], which carries any proxy and TLS configuration the
+//! caller already has.
+
+use std::future::Future;
+use std::io::Read;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+use ureq::{Agent, AgentBuilder, Proxy};
+use url::Url;
+
+use super::{Error, HttpClient, HttpClientError, HttpFuture, HttpResponse, InternalError};
+use crate::into_url::IntoUrl;
+use crate::{OhttpKeys, Request};
+
+/// Adapts a [`ureq::Agent`] to [`HttpClient`] for the functions in this module.
+///
+/// Each request blocks the thread that polls its future. The adapter is private so that it never
+/// reaches the async io functions, where it would stall the runtime.
+struct BlockingClient<'a>(&'a Agent);
+
+impl HttpClient for BlockingClient<'_> {
+    fn get<'a>(&'a self, url: &'a Url) -> HttpFuture<'a> {
+        Box::pin(async move { into_response(self.0.get(url.as_str()).call()) })
+    }
+
+    fn post<'a>(&'a self, request: &'a Request) -> HttpFuture<'a> {
+        Box::pin(async move {
+            into_response(
+                self.0
+                    .post(request.url.as_str())
+                    .set("Content-Type", request.content_type)
+                    .send_bytes(&request.body),
+            )
+        })
+    }
+}
+
+fn into_response(
+    res: Result<ureq::Response, ureq::Error>,
+) -> Result<HttpResponse, HttpClientError> {
+    let res = match res {
+        Ok(res) => res,
+        // Error statuses are for the io functions to interpret
+        Err(ureq::Error::Status(_, res)) => res,
+        Err(e) => return Err(HttpClientError::new(e)),
+    };
+    let status = res.status();
+    let mut body = Vec::new();
+    res.into_reader().read_to_end(&mut body).map_err(HttpClientError::new)?;
+    Ok(HttpResponse { status, body })
+}
+
+/// Fetch the ohttp keys from the specified payjoin directory via proxy.
+///
+/// * `ohttp_relay`: The http CONNNECT method proxy to request the ohttp keys from a payjoin
+///   directory.  Proxying requests for ohttp keys ensures a client IP address is never revealed to
+///   the payjoin directory.
+///
+/// * `payjoin_directory`: The payjoin directory from which to fetch the ohttp keys.  This
+///   directory stores and forwards payjoin client payloads.
+pub fn fetch_ohttp_keys(
+    ohttp_relay: impl IntoUrl,
+    payjoin_directory: impl IntoUrl,
+) -> Result<OhttpKeys, Error> {
+    let proxy = Proxy::new(ohttp_relay.into_url()?.as_str())
+        .map_err(|e| InternalError::Http(HttpClientError::new(e)))?;
+    let agent = AgentBuilder::new().proxy(proxy).build();
+    fetch_ohttp_keys_with_client(&agent, payjoin_directory)
+}
+
+/// Fetch the ohttp keys from the gateway an OHTTP relay forwards to, for relays without HTTP
+/// CONNECT support.
+///
+/// See [`super::fetch_ohttp_keys_from_gateway_with_client`].
+pub fn fetch_ohttp_keys_from_gateway(
+    ohttp_relay: impl IntoUrl,
+    payjoin_directory: impl IntoUrl,
+) -> Result<OhttpKeys, Error> {
+    block_on(super::fetch_ohttp_keys_from_gateway_with_client(
+        &BlockingClient(&Agent::new()),
+        ohttp_relay,
+        payjoin_directory,
+    ))
+}
+
+/// Fetch the ohttp keys from the specified payjoin directory with `agent`.
+///
+/// See [`super::fetch_ohttp_keys_with_client`].
+pub fn fetch_ohttp_keys_with_client(
+    agent: &Agent,
+    payjoin_directory: impl IntoUrl,
+) -> Result<OhttpKeys, Error> {
+    block_on(super::fetch_ohttp_keys_with_client(&BlockingClient(agent), payjoin_directory))
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) { self.0.unpark() }
+}
+
+/// Poll `future` to completion on the calling thread
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    use super::*;
+
+    /// Answer a single HTTP request on a local port with `status` and `body`
+    fn serve_once(status: &'static str, body: Vec<u8>) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n", status, body.len())
+                .unwrap();
+            stream.write_all(&body).unwrap();
+        });
+        url
+    }
+
+    #[test]
+    fn fetch_ohttp_keys_with_agent() {
+        let keys = OhttpKeys(
+            ohttp::KeyConfig::new(
+                1,
+                ohttp::hpke::Kem::K256Sha256,
+                vec![ohttp::SymmetricSuite::new(
+                    ohttp::hpke::Kdf::HkdfSha256,
+                    ohttp::hpke::Aead::ChaCha20Poly1305,
+                )],
+            )
+            .unwrap(),
+        );
+        let directory = serve_once("200 OK", keys.encode().unwrap());
+        let fetched = fetch_ohttp_keys_with_client(&Agent::new(), directory).unwrap();
+        assert_eq!(fetched, keys);
+
+        let directory = serve_once("503 Service Unavailable", vec![]);
+        assert!(matches!(
+            fetch_ohttp_keys_with_client(&Agent::new(), directory),
+            Err(Error(InternalError::UnexpectedStatusCode(503)))
+        ));
+    }
+}
@@ -0,0 +1,273 @@
+//! Checking that a directory serves everyone the same OHTTP keys.
+//!
+//! A directory that hands each client unique keys can link the requests encapsulated to them.
+//! Fetching the keys through independent relays and comparing them with the keys a client was
+//! given, in a payjoin URI or its configuration, makes such targeting detectable.
+
+use std::collections::HashSet;
+use std::{error, fmt};
+
+use url::Url;
+
+use super::{fetch_ohttp_keys_with_client, Error, HttpClient};
+use crate::into_url::IntoUrl;
+use crate::OhttpKeys;
+
+/// The number of independent relays the keys must be fetched through
+pub const MIN_INDEPENDENT_RELAYS: usize = 2;
+
+/// Check `expected` against the keys the directory serves through each of `clients`.
+///
+/// * `clients`: Pairs of an OHTTP relay and a client that tunnels through it with HTTP CONNECT.
+///   Relays on the same host count once.
+///
+/// * `payjoin_directory`: The payjoin directory the keys are for.
+///
+/// * `expected`: The keys to check, e.g. from the `OH1` parameter of a payjoin URI.
+///
+/// Fails on the first relay that sees different keys than the others, or a different public key
+/// under the key ID of `expected`. Relays that can't be reached are skipped, but the keys must be
+/// confirmed through at least [`MIN_INDEPENDENT_RELAYS`] distinct hosts.
+///
+/// A directory that rotates its keys advertises only the newest, while still accepting the
+/// previous key for a grace period. If every relay sees the same keys under another key ID, the
+/// check fails with the confirmed keys in [`KeyConsistencyError::rotated_keys`]. Those were
+/// served to everyone and can be used in place of `expected`.
+pub async fn check_ohttp_keys_with_clients<C: HttpClient>(
+    clients: &[(Url, C)],
+    payjoin_directory: impl IntoUrl,
+    expected: &OhttpKeys,
+) -> Result<(), KeyConsistencyError> {
+    let payjoin_directory = payjoin_directory.into_url().map_err(Error::from)?;
+    let mut served: Option<OhttpKeys> = None;
+    let mut confirmed_hosts = HashSet::new();
+    let mut errors = Vec::new();
+    for (relay, client) in clients {
+        match fetch_ohttp_keys_with_client(client, payjoin_directory.clone()).await {
+            Ok(keys) => {
+                let consistent = match &served {
+                    Some(served) => &keys == served,
+                    None => &keys == expected || key_id(&keys) != key_id(expected),
+                };
+                if !consistent {
+                    return Err(InternalKeyConsistencyError::Mismatch(relay.clone()).into());
+                }
+                served = Some(keys);
+                confirmed_hosts.insert(relay.host_str().unwrap_or_default().to_owned());
+            }
+            Err(e) => errors.push((relay.clone(), e)),
+        }
+    }
+    if confirmed_hosts.len() < MIN_INDEPENDENT_RELAYS {
+        return Err(InternalKeyConsistencyError::Unconfirmed {
+            confirmed: confirmed_hosts.len(),
+            errors,
+        }
+        .into());
+    }
+    match served {
+        Some(keys) if &keys != expected => Err(InternalKeyConsistencyError::Rotated(keys).into()),
+        _ => Ok(()),
+    }
+}
+
+/// The key ID that leads the encoding of a key configuration
+fn key_id(keys: &OhttpKeys) -> Option<u8> { keys.encode().ok()?.first().copied() }
+
+/// Error checking OHTTP keys for consistency
+#[derive(Debug)]
+pub struct KeyConsistencyError(InternalKeyConsistencyError);
+
+#[derive(Debug)]
+pub(crate) enum InternalKeyConsistencyError {
+    /// The directory served different keys through this relay
+    Mismatch(Url),
+    /// Every relay saw these keys, under another key ID than the expected ones
+    Rotated(OhttpKeys),
+    /// Too few independent relays confirmed the keys
+    Unconfirmed {
+        confirmed: usize,
+        errors: Vec<(Url, Error)>,
+    },
+    Io(Error),
+}
+
+impl KeyConsistencyError {
+    /// The relay through which the directory served different keys, if that's why the check
+    /// failed
+    ///
+    /// A mismatch means the keys may target this client. Don't send a payload encapsulated to them.
+    pub fn mismatched_relay(&self) -> Option<&Url> {
+        match &self.0 {
+            InternalKeyConsistencyError::Mismatch(relay) => Some(relay),
+            _ => None,
+        }
+    }
+
+    /// The keys the directory served through every relay, if it has rotated away from the
+    /// expected ones
+    ///
+    /// The expected keys may still be accepted during the directory's grace period, but these
+    /// were confirmed to be served to everyone. Use them instead.
+    pub fn rotated_keys(&self) -> Option<&OhttpKeys> {
+        match &self.0 {
+            InternalKeyConsistencyError::Rotated(keys) => Some(keys),
+            _ => None,
+        }
+    }
+}
+
+impl From<InternalKeyConsistencyError> for KeyConsistencyError {
+    fn from(value: InternalKeyConsistencyError) -> Self { Self(value) }
+}
+
+impl From<Error> for KeyConsistencyError {
+    fn from(value: Error) -> Self { Self(InternalKeyConsistencyError::Io(value)) }
+}
+
+impl fmt::Display for KeyConsistencyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use InternalKeyConsistencyError::*;
+
+        match &self.0 {
+            Mismatch(relay) => write!(
+                f,
+                "The directory served different ohttp keys through relay {}. The keys may target this client",
+                relay
+            ),
+            Rotated(_) => write!(f, "The directory has rotated its ohttp keys"),
+            Unconfirmed { confirmed, errors } => {
+                write!(
+                    f,
+                    "The ohttp keys were confirmed through {} independent relays, at least {} are required",
+                    confirmed, MIN_INDEPENDENT_RELAYS
+                )?;
+                for (relay, e) in errors {
+                    write!(f, "; {}: {}", relay, e)?;
+                }
+                Ok(())
+            }
+            Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for KeyConsistencyError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use InternalKeyConsistencyError::*;
+
+        match &self.0 {
+            Mismatch(_) | Rotated(_) => None,
+            Unconfirmed { errors, .. } => errors.first().map(|(_, e)| e as _),
+            Io(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::{HttpClientError, HttpFuture, HttpResponse};
+    use crate::Request;
+
+    /// Serves fixed keys, or fails every request when `None`
+    struct KeysClient(Option<OhttpKeys>);
+
+    impl HttpClient for KeysClient {
+        fn get<'a>(&'a self, _: &'a Url) -> HttpFuture<'a> {
+            let res = match &self.0 {
+                Some(keys) => Ok(HttpResponse { status: 200, body: keys.encode().unwrap() }),
+                None => Err(HttpClientError::new("relay unreachable")),
+            };
+            Box::pin(async move { res })
+        }
+
+        fn post<'a>(&'a self, _: &'a Request) -> HttpFuture<'a> {
+            Box::pin(async { Err(HttpClientError::new("unexpected POST")) })
+        }
+    }
+
+    fn keys(key_id: u8) -> OhttpKeys {
+        OhttpKeys(
+            ohttp::KeyConfig::new(
+                key_id,
+                ohttp::hpke::Kem::K256Sha256,
+                vec![ohttp::SymmetricSuite::new(
+                    ohttp::hpke::Kdf::HkdfSha256,
+                    ohttp::hpke::Aead::ChaCha20Poly1305,
+                )],
+            )
+            .unwrap(),
+        )
+    }
+
+    fn relay(url: &str) -> Url { Url::parse(url).unwrap() }
+
+    #[tokio::test]
+    async fn check_ohttp_keys_through_independent_relays() {
+        let expected = keys(1);
+        let directory = "https://directory.example/";
+
+        let consistent = [
+            (relay("https://relay-a.example"), KeysClient(Some(expected.clone()))),
+            (relay("https://relay-b.example"), KeysClient(Some(expected.clone()))),
+            (relay("https://relay-c.example"), KeysClient(None)),
+        ];
+        check_ohttp_keys_with_clients(&consistent, directory, &expected)
+            .await
+            .expect("two relays confirmed the keys");
+
+        let targeted = [
+            (relay("https://relay-a.example"), KeysClient(Some(expected.clone()))),
+            (relay("https://relay-b.example"), KeysClient(Some(keys(2)))),
+        ];
+        let err = check_ohttp_keys_with_clients(&targeted, directory, &expected).await.unwrap_err();
+        assert_eq!(err.mismatched_relay(), Some(&relay("https://relay-b.example")));
+
+        let same_host = [
+            (relay("https://relay-a.example"), KeysClient(Some(expected.clone()))),
+            (relay("https://relay-a.example:8443"), KeysClient(Some(expected.clone()))),
+        ];
+        let err =
+            check_ohttp_keys_with_clients(&same_host, directory, &expected).await.unwrap_err();
+        assert!(matches!(
+            err,
+            KeyConsistencyError(InternalKeyConsistencyError::Unconfirmed { confirmed: 1, .. })
+        ));
+        assert_eq!(err.mismatched_relay(), None);
+    }
+
+    #[tokio::test]
+    async fn check_ohttp_keys_after_rotation() {
+        let previous = keys(1);
+        let current = keys(2);
+        let directory = "https://directory.example/";
+
+        let rotated = [
+            (relay("https://relay-a.example"), KeysClient(Some(current.clone()))),
+            (relay("https://relay-b.example"), KeysClient(Some(current.clone()))),
+        ];
+        let err = check_ohttp_keys_with_clients(&rotated, directory, &previous).await.unwrap_err();
+        assert_eq!(err.mismatched_relay(), None);
+        assert_eq!(err.rotated_keys(), Some(&current));
+
+        // Only an agreement of enough relays confirms the rotation
+        let unconfirmed = [
+            (relay("https://relay-a.example"), KeysClient(Some(current.clone()))),
+            (relay("https://relay-b.example"), KeysClient(None)),
+        ];
+        let err =
+            check_ohttp_keys_with_clients(&unconfirmed, directory, &previous).await.unwrap_err();
+        assert_eq!(err.rotated_keys(), None);
+
+        // A rotation never reuses the key ID of a live key
+        let reused_key_id = keys(1);
+        let targeted = [
+            (relay("https://relay-a.example"), KeysClient(Some(reused_key_id.clone()))),
+            (relay("https://relay-b.example"), KeysClient(Some(reused_key_id))),
+        ];
+        let err = check_ohttp_keys_with_clients(&targeted, directory, &previous).await.unwrap_err();
+        assert_eq!(err.mismatched_relay(), Some(&relay("https://relay-a.example")));
+        assert_eq!(err.rotated_keys(), None);
+    }
+}
@@ -0,0 +1,47 @@
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper_util::client::legacy::connect::Connect;
+use hyper_util::client::legacy::Client;
+use url::Url;
+
+use super::{HttpClient, HttpClientError, HttpFuture, HttpResponse};
+use crate::Request;
+
+/// Requests are made with whatever connector the client was built with, which is where TLS and
+/// any CONNECT proxying are configured.
+impl<C> HttpClient for Client<C, Full<Bytes>>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    fn get<'a>(&'a self, url: &'a Url) -> HttpFuture<'a> {
+        Box::pin(async move {
+            let req = http::Request::get(url.as_str())
+                .body(Full::default())
+                .map_err(HttpClientError::new)?;
+            send(self, req).await
+        })
+    }
+
+    fn post<'a>(&'a self, request: &'a Request) -> HttpFuture<'a> {
+        Box::pin(async move {
+            let req = http::Request::post(request.url.as_str())
+                .header(http::header::CONTENT_TYPE, request.content_type)
+                .body(Full::new(Bytes::from(request.body.clone())))
+                .map_err(HttpClientError::new)?;
+            send(self, req).await
+        })
+    }
+}
+
+async fn send<C>(
+    client: &Client<C, Full<Bytes>>,
+    req: http::Request<Full<Bytes>>,
+) -> Result<HttpResponse, HttpClientError>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    let res = client.request(req).await.map_err(HttpClientError::new)?;
+    let status = res.status().as_u16();
+    let body = res.into_body().collect().await.map_err(HttpClientError::new)?.to_bytes().to_vec();
+    Ok(HttpResponse { status, body })
+}
@@ -0,0 +1,369 @@
+//! IO-related types and functions. Specifically, fetching OHTTP keys from a payjoin directory.
+//!
+//! Requests go through an [`HttpClient`] so an embedder can reuse the client, proxy settings and
+//! TLS roots it already has. Implementations are provided for `reqwest::Client` with the `io`
+//! feature and hyper's legacy client with `io-hyper`. The `blocking` functions of the
+//! `io-blocking` feature take a `ureq::Agent` instead.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::{error, fmt};
+
+use url::Url;
+
+use crate::into_url::IntoUrl;
+use crate::uri::UrlExt;
+use crate::{OhttpKeys, Request};
+
+#[cfg(feature = "io-blocking")]
+#[cfg_attr(docsrs, doc(cfg(feature = "io-blocking")))]
+pub mod blocking;
+mod consistency;
+#[cfg(feature = "io-hyper")]
+mod hyper;
+#[cfg(feature = "io")]
+mod reqwest;
+
+pub use self::consistency::{
+    check_ohttp_keys_with_clients, KeyConsistencyError, MIN_INDEPENDENT_RELAYS,
+};
+#[cfg(feature = "_danger-local-https")]
+pub use self::reqwest::fetch_ohttp_keys_with_cert;
+#[cfg(feature = "io")]
+pub use self::reqwest::{check_ohttp_keys, fetch_ohttp_keys, fetch_ohttp_keys_from_gateway};
+#[cfg(feature = "socks")]
+#[cfg_attr(docsrs, doc(cfg(feature = "socks")))]
+pub use self::reqwest::{fetch_ohttp_keys_via_socks5, socks5_proxy};
+
+/// The future an [`HttpClient`] returns
+pub type HttpFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<HttpResponse, HttpClientError>> + Send + 'a>>;
+
+/// An HTTP client the io functions send their requests with
+///
+/// Any proxying and TLS configuration belongs to the client. To fetch OHTTP keys without revealing
+/// the client's IP address to the directory, the client must tunnel through an OHTTP relay with
+/// HTTP CONNECT.
+pub trait HttpClient {
+    /// Send a GET request to `url`
+    fn get<'a>(&'a self, url: &'a Url) -> HttpFuture<'a>;
+
+    /// Send a POST request as described by `request`
+    fn post<'a>(&'a self, request: &'a Request) -> HttpFuture<'a>;
+}
+
+impl<T: HttpClient + ?Sized> HttpClient for &T {
+    fn get<'a>(&'a self, url: &'a Url) -> HttpFuture<'a> { (**self).get(url) }
+
+    fn post<'a>(&'a self, request: &'a Request) -> HttpFuture<'a> { (**self).post(request) }
+}
+
+/// The status and body of a response to an [`HttpClient`] request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// Whether the status code is in the 2xx range
+    pub fn is_success(&self) -> bool { (200..300).contains(&self.status) }
+}
+
+/// Error returned by an [`HttpClient`] implementation
+#[derive(Debug)]
+pub struct HttpClientError(Box<dyn error::Error + Send + Sync>);
+
+impl HttpClientError {
+    /// Wrap the error of the underlying HTTP library
+    pub fn new(error: impl Into<Box<dyn error::Error + Send + Sync>>) -> Self { Self(error.into()) }
+}
+
+impl fmt::Display for HttpClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { self.0.fmt(f) }
+}
+
+impl error::Error for HttpClientError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> { Some(self.0.as_ref()) }
+}
+
+/// Fetch the ohttp keys from the specified payjoin directory with `client`.
+///
+/// * `client`: The client to send the request with. It should tunnel through an OHTTP relay with
+///   HTTP CONNECT, which ensures a client IP address is never revealed to the payjoin directory.
+///
+/// * `payjoin_directory`: The payjoin directory from which to fetch the ohttp keys.  This
+///   directory stores and forwards payjoin client payloads.
+pub async fn fetch_ohttp_keys_with_client(
+    client: &impl HttpClient,
+    payjoin_directory: impl IntoUrl,
+) -> Result<OhttpKeys, Error> {
+    let ohttp_keys_url = payjoin_directory.into_url()?.join("/ohttp-keys")?;
+    let res = client.get(&ohttp_keys_url).await.map_err(InternalError::Http)?;
+    if !res.is_success() {
+        return Err(InternalError::UnexpectedStatusCode(res.status).into());
+    }
+    decode_ohttp_keys(res)
+}
+
+/// Fetch the ohttp keys from the gateway an OHTTP relay forwards to, with `client`.
+///
+/// Unlike [`fetch_ohttp_keys_with_client`] this needs no HTTP CONNECT support from the relay.
+/// The keys are fetched with a plain GET of the relay's `/.well-known/ohttp-gateway`, which the
+/// relay answers with the keys of its configured gateway.
+///
+/// * `ohttp_relay`: The relay whose gateway is the payjoin directory.
+///
+/// * `payjoin_directory`: The payjoin directory the keys are for. If its URL fragment carries a
+///   `KF1` [`crate::OhttpKeysFingerprint`], the fetched keys must match it, and a malformed one
+///   is an error. Without one, the keys are only as trustworthy as the relay.
+pub async fn fetch_ohttp_keys_from_gateway_with_client(
+    client: &impl HttpClient,
+    ohttp_relay: impl IntoUrl,
+    payjoin_directory: impl IntoUrl,
+) -> Result<OhttpKeys, Error> {
+    let payjoin_directory = payjoin_directory.into_url()?;
+    let gateway_keys_url = ohttp_relay.into_url()?.join("/.well-known/ohttp-gateway")?;
+    let res = client.get(&gateway_keys_url).await.map_err(InternalError::Http)?;
+    if !res.is_success() {
+        return Err(InternalError::UnexpectedStatusCode(res.status).into());
+    }
+    let ohttp_keys = decode_ohttp_keys(res)?;
+    let fingerprint =
+        payjoin_directory.ohttp_keys_fingerprint().map_err(InternalError::InvalidFingerprint)?;
+    if let Some(expected) = fingerprint {
+        if ohttp_keys.fingerprint() != Some(expected) {
+            return Err(InternalError::FingerprintMismatch.into());
+        }
+    }
+    Ok(ohttp_keys)
+}
+
+fn decode_ohttp_keys(res: HttpResponse) -> Result<OhttpKeys, Error> {
+    OhttpKeys::decode(&res.body).map_err(|e| Error(InternalError::InvalidOhttpKeys(e.to_string())))
+}
+
+#[derive(Debug)]
+pub struct Error(InternalError);
+
+#[derive(Debug)]
+enum InternalError {
+    ParseUrl(crate::into_url::Error),
+    Http(HttpClientError),
+    #[cfg(feature = "io")]
+    Reqwest(::reqwest::Error),
+    Io(std::io::Error),
+    #[cfg(feature = "_danger-local-https")]
+    Rustls(rustls::Error),
+    #[cfg(feature = "socks")]
+    UnsupportedProxyScheme(String),
+    UnexpectedStatusCode(u16),
+    InvalidOhttpKeys(String),
+    InvalidFingerprint(crate::ohttp::ParseOhttpKeysError),
+    FingerprintMismatch,
+}
+
+impl From<url::ParseError> for Error {
+    fn from(value: url::ParseError) -> Self { Self(InternalError::ParseUrl(value.into())) }
+}
+
+macro_rules! impl_from_error {
+    ($from:ty, $to:ident) => {
+        impl From<$from> for Error {
+            fn from(value: $from) -> Self { Self(InternalError::$to(value)) }
+        }
+    };
+}
+
+impl_from_error!(crate::into_url::Error, ParseUrl);
+#[cfg(feature = "io")]
+impl_from_error!(::reqwest::Error, Reqwest);
+impl_from_error!(std::io::Error, Io);
+#[cfg(feature = "_danger-local-https")]
+impl_from_error!(rustls::Error, Rustls);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use InternalError::*;
+
+        match &self.0 {
+            Http(e) => e.fmt(f),
+            #[cfg(feature = "io")]
+            Reqwest(e) => e.fmt(f),
+            ParseUrl(e) => e.fmt(f),
+            Io(e) => e.fmt(f),
+            #[cfg(feature = "socks")]
+            UnsupportedProxyScheme(scheme) => {
+                write!(f, "Unsupported proxy scheme {}, expected socks5 or socks5h", scheme)
+            }
+            UnexpectedStatusCode(status) => {
+                write!(f, "Unexpected status code {} from payjoin directory", status)
+            }
+            InvalidOhttpKeys(e) => {
+                write!(f, "Invalid ohttp keys returned from payjoin directory: {}", e)
+            }
+            InvalidFingerprint(e) => {
+                write!(f, "Invalid ohttp keys fingerprint in the directory URL: {}", e)
+            }
+            FingerprintMismatch => {
+                write!(f, "The gateway's ohttp keys do not match the directory's fingerprint")
+            }
+            #[cfg(feature = "_danger-local-https")]
+            Rustls(e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use InternalError::*;
+
+        match &self.0 {
+            Http(e) => Some(e),
+            #[cfg(feature = "io")]
+            Reqwest(e) => Some(e),
+            ParseUrl(e) => Some(e),
+            Io(e) => Some(e),
+            #[cfg(feature = "socks")]
+            UnsupportedProxyScheme(_) => None,
+            UnexpectedStatusCode(_) => None,
+            InvalidOhttpKeys(_) => None,
+            InvalidFingerprint(e) => Some(e),
+            FingerprintMismatch => None,
+            #[cfg(feature = "_danger-local-https")]
+            Rustls(e) => Some(e),
+        }
+    }
+}
+
+impl From<InternalError> for Error {
+    fn from(value: InternalError) -> Self { Self(value) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Answers every GET with a fixed response and records the URL
+    struct StaticClient {
+        response: HttpResponse,
+        requested: std::sync::Mutex<Option<Url>>,
+    }
+
+    impl HttpClient for StaticClient {
+        fn get<'a>(&'a self, url: &'a Url) -> HttpFuture<'a> {
+            *self.requested.lock().unwrap() = Some(url.clone());
+            let response = self.response.clone();
+            Box::pin(async move { Ok(response) })
+        }
+
+        fn post<'a>(&'a self, _: &'a Request) -> HttpFuture<'a> {
+            Box::pin(async { Err(HttpClientError::new("unexpected POST")) })
+        }
+    }
+
+    fn test_keys() -> OhttpKeys {
+        OhttpKeys(
+            ohttp::KeyConfig::new(
+                1,
+                ohttp::hpke::Kem::K256Sha256,
+                vec![ohttp::SymmetricSuite::new(
+                    ohttp::hpke::Kdf::HkdfSha256,
+                    ohttp::hpke::Aead::ChaCha20Poly1305,
+                )],
+            )
+            .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn fetch_ohttp_keys_through_client() {
+        let keys = test_keys();
+        let client = StaticClient {
+            response: HttpResponse { status: 200, body: keys.encode().unwrap() },
+            requested: Default::default(),
+        };
+        let fetched = fetch_ohttp_keys_with_client(&client, "https://directory.example/")
+            .await
+            .expect("keys should be fetched");
+        assert_eq!(fetched.encode().unwrap(), keys.encode().unwrap());
+        assert_eq!(
+            client.requested.lock().unwrap().as_ref().map(Url::as_str),
+            Some("https://directory.example/ohttp-keys")
+        );
+
+        let client = StaticClient {
+            response: HttpResponse { status: 503, body: vec![] },
+            requested: Default::default(),
+        };
+        assert!(matches!(
+            fetch_ohttp_keys_with_client(&client, "https://directory.example/").await,
+            Err(Error(InternalError::UnexpectedStatusCode(503)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn fetch_ohttp_keys_from_gateway_checks_fingerprint() {
+        let keys = test_keys();
+        let client = StaticClient {
+            response: HttpResponse { status: 200, body: keys.encode().unwrap() },
+            requested: Default::default(),
+        };
+        let mut directory = Url::parse("https://directory.example/").unwrap();
+        let fetched = fetch_ohttp_keys_from_gateway_with_client(
+            &client,
+            "https://relay.example/",
+            &directory,
+        )
+        .await
+        .expect("keys without a fingerprint should be accepted");
+        assert_eq!(fetched, keys);
+        assert_eq!(
+            client.requested.lock().unwrap().as_ref().map(Url::as_str),
+            Some("https://relay.example/.well-known/ohttp-gateway")
+        );
+
+        directory.set_fragment(Some(&keys.fingerprint().unwrap().to_string()));
+        let fetched = fetch_ohttp_keys_from_gateway_with_client(
+            &client,
+            "https://relay.example/",
+            &directory,
+        )
+        .await
+        .expect("keys matching the fingerprint should be accepted");
+        assert_eq!(fetched, keys);
+
+        directory.set_fragment(Some(&test_keys().fingerprint().unwrap().to_string()));
+        assert!(matches!(
+            fetch_ohttp_keys_from_gateway_with_client(
+                &client,
+                "https://relay.example/",
+                &directory
+            )
+            .await,
+            Err(Error(InternalError::FingerprintMismatch))
+        ));
+
+        directory.set_fragment(Some("KF1invalid_bech_32"));
+        assert!(matches!(
+            fetch_ohttp_keys_from_gateway_with_client(
+                &client,
+                "https://relay.example/",
+                &directory
+            )
+            .await,
+            Err(Error(InternalError::InvalidFingerprint(_)))
+        ));
+    }
+
+    #[cfg(feature = "socks")]
+    #[test]
+    fn socks5_proxy_requires_socks5_scheme() {
+        assert!(socks5_proxy("socks5://127.0.0.1:9050").is_ok());
+        assert!(socks5_proxy("socks5h://127.0.0.1:9050").is_ok());
+        assert!(matches!(
+            socks5_proxy("http://127.0.0.1:9050"),
+            Err(Error(InternalError::UnsupportedProxyScheme(scheme))) if scheme == "http"
+        ));
+    }
+}
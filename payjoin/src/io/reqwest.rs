@@ -0,0 +1,145 @@
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Proxy};
+use url::Url;
+
+#[cfg(feature = "socks")]
+use super::InternalError;
+use super::{
+    check_ohttp_keys_with_clients, fetch_ohttp_keys_from_gateway_with_client,
+    fetch_ohttp_keys_with_client, Error, HttpClient, HttpClientError, HttpFuture, HttpResponse,
+    KeyConsistencyError,
+};
+use crate::into_url::IntoUrl;
+use crate::{OhttpKeys, Request};
+
+impl HttpClient for Client {
+    fn get<'a>(&'a self, url: &'a Url) -> HttpFuture<'a> {
+        Box::pin(async move { into_response(Client::get(self, url.clone()).send().await).await })
+    }
+
+    fn post<'a>(&'a self, request: &'a Request) -> HttpFuture<'a> {
+        Box::pin(async move {
+            into_response(
+                Client::post(self, request.url.clone())
+                    .header(CONTENT_TYPE, request.content_type)
+                    .body(request.body.clone())
+                    .send()
+                    .await,
+            )
+            .await
+        })
+    }
+}
+
+async fn into_response(
+    res: Result<reqwest::Response, reqwest::Error>,
+) -> Result<HttpResponse, HttpClientError> {
+    let res = res.map_err(HttpClientError::new)?;
+    let status = res.status().as_u16();
+    let body = res.bytes().await.map_err(HttpClientError::new)?.to_vec();
+    Ok(HttpResponse { status, body })
+}
+
+/// Fetch the ohttp keys from the specified payjoin directory via proxy.
+///
+/// * `ohttp_relay`: The http CONNNECT method proxy to request the ohttp keys from a payjoin
+///   directory.  Proxying requests for ohttp keys ensures a client IP address is never revealed to
+///   the payjoin directory.
+///
+/// * `payjoin_directory`: The payjoin directory from which to fetch the ohttp keys.  This
+///   directory stores and forwards payjoin client payloads.
+pub async fn fetch_ohttp_keys(
+    ohttp_relay: impl IntoUrl,
+    payjoin_directory: impl IntoUrl,
+) -> Result<OhttpKeys, Error> {
+    let proxy = Proxy::all(ohttp_relay.into_url()?.as_str())?;
+    let client = Client::builder().proxy(proxy).build()?;
+    fetch_ohttp_keys_with_client(&client, payjoin_directory).await
+}
+
+/// Check that the specified payjoin directory serves `expected` through each of `ohttp_relays`.
+///
+/// Run this before sending a payload encapsulated to keys from a payjoin URI or configuration.
+/// See [`super::check_ohttp_keys_with_clients`].
+pub async fn check_ohttp_keys(
+    ohttp_relays: &[Url],
+    payjoin_directory: impl IntoUrl,
+    expected: &OhttpKeys,
+) -> Result<(), KeyConsistencyError> {
+    let mut clients = Vec::with_capacity(ohttp_relays.len());
+    for relay in ohttp_relays {
+        let proxy = Proxy::all(relay.as_str()).map_err(Error::from)?;
+        clients.push((relay.clone(), Client::builder().proxy(proxy).build().map_err(Error::from)?));
+    }
+    check_ohttp_keys_with_clients(&clients, payjoin_directory, expected).await
+}
+
+/// Fetch the ohttp keys from the gateway an OHTTP relay forwards to, for relays without HTTP
+/// CONNECT support.
+///
+/// See [`super::fetch_ohttp_keys_from_gateway_with_client`].
+pub async fn fetch_ohttp_keys_from_gateway(
+    ohttp_relay: impl IntoUrl,
+    payjoin_directory: impl IntoUrl,
+) -> Result<OhttpKeys, Error> {
+    fetch_ohttp_keys_from_gateway_with_client(&Client::new(), ohttp_relay, payjoin_directory).await
+}
+
+/// Fetch the ohttp keys from the specified payjoin directory via proxy.
+///
+/// * `ohttp_relay`: The http CONNNECT method proxy to request the ohttp keys from a payjoin
+///   directory.  Proxying requests for ohttp keys ensures a client IP address is never revealed to
+///   the payjoin directory.
+///
+/// * `payjoin_directory`: The payjoin directory from which to fetch the ohttp keys.  This
+///   directory stores and forwards payjoin client payloads.
+///
+/// * `cert_der`: The DER-encoded certificate to use for local HTTPS connections.
+#[cfg(feature = "_danger-local-https")]
+pub async fn fetch_ohttp_keys_with_cert(
+    ohttp_relay: impl IntoUrl,
+    payjoin_directory: impl IntoUrl,
+    cert_der: Vec<u8>,
+) -> Result<OhttpKeys, Error> {
+    let proxy = Proxy::all(ohttp_relay.into_url()?.as_str())?;
+    let client = Client::builder()
+        .use_rustls_tls()
+        .add_root_certificate(reqwest::tls::Certificate::from_der(&cert_der)?)
+        .proxy(proxy)
+        .build()?;
+    fetch_ohttp_keys_with_client(&client, payjoin_directory).await
+}
+
+/// Fetch the ohttp keys from the specified payjoin directory through a SOCKS5 proxy.
+///
+/// * `socks5_proxy`: The SOCKS5 proxy, e.g. a local Tor daemon at `socks5h://127.0.0.1:9050`.
+///   The proxy takes the place of an OHTTP relay's CONNECT tunnel in keeping the client IP address
+///   from the payjoin directory. See [`socks5_proxy`].
+///
+/// * `payjoin_directory`: The payjoin directory from which to fetch the ohttp keys.  This
+///   directory stores and forwards payjoin client payloads.
+#[cfg(feature = "socks")]
+pub async fn fetch_ohttp_keys_via_socks5(
+    socks5_proxy: impl IntoUrl,
+    payjoin_directory: impl IntoUrl,
+) -> Result<OhttpKeys, Error> {
+    let client = Client::builder().proxy(self::socks5_proxy(socks5_proxy)?).build()?;
+    fetch_ohttp_keys_with_client(&client, payjoin_directory).await
+}
+
+/// A proxy sending all requests through the SOCKS5 proxy at `proxy`
+///
+/// A `socks5` URL is treated as `socks5h` so that host names, including `.onion` addresses, are
+/// resolved by the proxy rather than leaked to the local DNS resolver.
+#[cfg(feature = "socks")]
+pub fn socks5_proxy(proxy: impl IntoUrl) -> Result<Proxy, Error> {
+    let mut proxy = proxy.into_url()?;
+    match proxy.scheme() {
+        "socks5h" => {}
+        "socks5" => proxy
+            .set_scheme("socks5h")
+            .map_err(|_| InternalError::UnsupportedProxyScheme(proxy.scheme().to_owned()))?,
+        scheme => return Err(InternalError::UnsupportedProxyScheme(scheme.to_owned()).into()),
+    }
+    Ok(Proxy::all(proxy.as_str())?)
+}
@@ -28,14 +28,19 @@ pub mod receive;
 #[cfg(feature = "_core")]
 pub mod send;
 
+#[cfg(feature = "encrypted-sessions")]
+#[cfg_attr(docsrs, doc(cfg(feature = "encrypted-sessions")))]
+pub mod encrypted;
 #[cfg(feature = "v2")]
 pub(crate) mod hpke;
 #[cfg(feature = "v2")]
 pub use crate::hpke::{HpkeKeyPair, HpkePublicKey};
 #[cfg(feature = "v2")]
+pub(crate) mod multipart;
+#[cfg(feature = "v2")]
 pub(crate) mod ohttp;
 #[cfg(feature = "v2")]
-pub use crate::ohttp::OhttpKeys;
+pub use crate::ohttp::{OhttpKeys, OhttpKeysFingerprint, OhttpKeysStale};
 #[cfg(any(feature = "v2", feature = "directory"))]
 pub(crate) mod bech32;
 #[cfg(feature = "directory")]
@@ -44,14 +49,23 @@ pub mod directory;
 
 #[cfg(feature = "_core")]
 pub(crate) mod into_url;
-#[cfg(feature = "io")]
-#[cfg_attr(docsrs, doc(cfg(feature = "io")))]
+#[cfg(feature = "_io")]
+#[cfg_attr(docsrs, doc(cfg(feature = "_io")))]
 pub mod io;
 #[cfg(feature = "_core")]
 pub(crate) mod psbt;
+#[cfg(feature = "psbt-merge")]
+#[cfg_attr(docsrs, doc(cfg(feature = "psbt-merge")))]
+pub use psbt::merge::{merge_psbts, MergeError};
+#[cfg(feature = "_core")]
+pub use psbt::v2::{psbt_from_v2, psbt_to_v2, PsbtV2Error};
+#[cfg(feature = "v2")]
+pub mod relay;
 #[cfg(feature = "_core")]
 mod request;
 #[cfg(feature = "_core")]
+pub(crate) mod truc;
+#[cfg(feature = "_core")]
 pub use request::*;
 #[cfg(feature = "_core")]
 mod uri;
@@ -59,7 +73,7 @@ mod uri;
 #[cfg(feature = "_core")]
 pub use into_url::{Error as IntoUrlError, IntoUrl};
 #[cfg(feature = "_core")]
-pub use uri::{PjParseError, PjUri, Uri, UriExt};
+pub use uri::{PjParseError, PjUri, PjUriBuildError, PjUriBuilder, Uri, UriExt};
 #[cfg(feature = "_core")]
 pub use url::{ParseError, Url};
 #[cfg(feature = "_core")]
@@ -0,0 +1,213 @@
+//! Original PSBTs too large for a single v2 message
+//!
+//! Message A pads its plaintext to [`PADDED_PLAINTEXT_A_LENGTH`], which bounds the base64 Original
+//! PSBT a sender can post. A receiver that advertises the `MP1` parameter accepts an Original PSBT
+//! split into up to that many parts instead. Each part is an ordinary message A, so parts can't be
+//! told apart from any other message by size.
+//!
+//! The parts carry `query || '\n' || psbt` with the PSBT in its binary encoding. The first part
+//! is posted to the receiver's mailbox. Part `i` is posted to the mailbox
+//! `sha256(receiver_key || reply_key || i)`, which only the receiver can derive once it has
+//! decrypted the first part and learned the reply key.
+
+use std::{error, fmt};
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use serde::{Deserialize, Serialize};
+
+use crate::directory::ShortId;
+use crate::hpke::{HpkePublicKey, PADDED_PLAINTEXT_A_LENGTH};
+
+/// Marks a message A payload as a part. The leading NUL keeps it from being mistaken for a
+/// base64 PSBT or a cancellation.
+const PART_PREFIX: &[u8] = b"\0PjV2Part";
+
+/// `PART_PREFIX || index || count || data length`
+const PART_HEADER_LEN: usize = PART_PREFIX.len() + 1 + 1 + 2;
+
+/// Payload bytes carried by each part
+pub(crate) const PART_CAPACITY: usize = PADDED_PLAINTEXT_A_LENGTH - PART_HEADER_LEN;
+
+/// One message of a payload split into parts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Part {
+    pub index: u8,
+    pub count: u8,
+    pub data: Vec<u8>,
+}
+
+impl Part {
+    fn serialize(&self) -> Vec<u8> {
+        let mut bytes = PART_PREFIX.to_vec();
+        bytes.push(self.index);
+        bytes.push(self.count);
+        bytes.extend_from_slice(&(self.data.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    /// Parse a decrypted message A payload, or `None` if it isn't a part
+    pub(crate) fn parse(payload: &[u8]) -> Option<Result<Self, PartError>> {
+        let header = payload.strip_prefix(PART_PREFIX)?;
+        Some(Self::parse_header(header))
+    }
+
+    fn parse_header(header: &[u8]) -> Result<Self, PartError> {
+        if header.len() < 4 {
+            return Err(PartError::Malformed);
+        }
+        let (index, count) = (header[0], header[1]);
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let data = header.get(4..4 + len).ok_or(PartError::Malformed)?;
+        if index >= count {
+            return Err(PartError::Malformed);
+        }
+        Ok(Part { index, count, data: data.to_vec() })
+    }
+}
+
+/// Encode an Original PSBT and its query parameters as a payload to split into parts
+pub(crate) fn encode_payload(psbt: &[u8], query: &str) -> Vec<u8> {
+    let mut payload = query.as_bytes().to_vec();
+    payload.push(b'\n');
+    payload.extend_from_slice(psbt);
+    payload
+}
+
+/// The PSBT and query parameters of a reassembled payload
+pub(crate) fn decode_payload(payload: &[u8]) -> Result<(&[u8], &str), PartError> {
+    let split = payload.iter().position(|b| *b == b'\n').ok_or(PartError::Malformed)?;
+    let query = std::str::from_utf8(&payload[..split]).map_err(|_| PartError::Malformed)?;
+    Ok((&payload[split + 1..], query))
+}
+
+/// Split `payload` into the serialized parts to encrypt, first part first
+pub(crate) fn split(payload: &[u8], max_parts: u8) -> Result<Vec<Vec<u8>>, PartError> {
+    let count = (payload.len() + PART_CAPACITY - 1) / PART_CAPACITY;
+    if count > max_parts as usize {
+        return Err(PartError::TooManyParts { count, max: max_parts });
+    }
+    Ok(payload
+        .chunks(PART_CAPACITY)
+        .enumerate()
+        .map(|(index, data)| {
+            Part { index: index as u8, count: count as u8, data: data.to_vec() }.serialize()
+        })
+        .collect())
+}
+
+/// The mailbox part `index` is posted to
+pub(crate) fn part_mailbox(
+    receiver_key: &HpkePublicKey,
+    reply_key: &HpkePublicKey,
+    index: u8,
+) -> ShortId {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&receiver_key.to_compressed_bytes());
+    engine.input(&reply_key.to_compressed_bytes());
+    engine.input(&[index]);
+    sha256::Hash::from_engine(engine).into()
+}
+
+/// The parts of a payload received so far
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PartialPayload {
+    /// The reply key every part must be encrypted under
+    pub reply_key: HpkePublicKey,
+    parts: Vec<Option<Vec<u8>>>,
+}
+
+impl PartialPayload {
+    /// Start reassembling from the first part
+    pub(crate) fn new(first: Part, reply_key: HpkePublicKey) -> Result<Self, PartError> {
+        if first.index != 0 {
+            return Err(PartError::Malformed);
+        }
+        let mut parts = vec![None; first.count as usize];
+        parts[0] = Some(first.data);
+        Ok(Self { reply_key, parts })
+    }
+
+    pub(crate) fn insert(&mut self, part: Part) -> Result<(), PartError> {
+        if part.count as usize != self.parts.len() {
+            return Err(PartError::Malformed);
+        }
+        self.parts[part.index as usize] = Some(part.data);
+        Ok(())
+    }
+
+    /// The index of the next part to fetch
+    pub(crate) fn next_missing(&self) -> Option<u8> {
+        self.parts.iter().position(Option::is_none).map(|index| index as u8)
+    }
+
+    /// The payload once every part has been received
+    pub(crate) fn assemble(&self) -> Option<Vec<u8>> {
+        self.parts.iter().try_fold(Vec::new(), |mut payload, part| {
+            payload.extend_from_slice(part.as_ref()?);
+            Some(payload)
+        })
+    }
+}
+
+/// Error splitting or reassembling a payload sent in parts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum PartError {
+    /// A part or the reassembled payload is malformed
+    Malformed,
+    /// The payload needs more parts than the receiver accepts
+    TooManyParts { count: usize, max: u8 },
+}
+
+impl fmt::Display for PartError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PartError::Malformed => write!(f, "Malformed message part"),
+            PartError::TooManyParts { count, max } => write!(
+                f,
+                "Payload needs {} messages but the receiver accepts at most {}",
+                count, max
+            ),
+        }
+    }
+}
+
+impl error::Error for PartError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::HpkeKeyPair;
+
+    #[test]
+    fn split_and_reassemble() {
+        let payload = encode_payload(&[0xab; 3 * PART_CAPACITY / 2], "v=2");
+        assert_eq!(split(&payload, 1), Err(PartError::TooManyParts { count: 2, max: 1 }));
+        let parts = split(&payload, 2).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert!(parts.iter().all(|part| part.len() <= PADDED_PLAINTEXT_A_LENGTH));
+
+        let reply_key = HpkeKeyPair::gen_keypair().public_key().clone();
+        let mut parts = parts.iter().map(|part| Part::parse(part).unwrap().unwrap());
+        let mut partial = PartialPayload::new(parts.next().unwrap(), reply_key).unwrap();
+        assert_eq!(partial.next_missing(), Some(1));
+        assert_eq!(partial.assemble(), None);
+        partial.insert(parts.next().unwrap()).unwrap();
+        assert_eq!(partial.next_missing(), None);
+        let reassembled = partial.assemble().unwrap();
+        assert_eq!(
+            decode_payload(&reassembled).unwrap(),
+            (&[0xab; 3 * PART_CAPACITY / 2][..], "v=2")
+        );
+    }
+
+    #[test]
+    fn parse_rejects_malformed_parts() {
+        assert!(Part::parse(b"cHNidP8").is_none());
+        let part = Part { index: 2, count: 2, data: vec![1, 2, 3] }.serialize();
+        assert_eq!(Part::parse(&part), Some(Err(PartError::Malformed)));
+        let mut truncated = Part { index: 1, count: 2, data: vec![1, 2, 3] }.serialize();
+        truncated.pop();
+        assert_eq!(Part::parse(&truncated), Some(Err(PartError::Malformed)));
+    }
+}
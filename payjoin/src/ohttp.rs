@@ -2,6 +2,7 @@ use std::ops::{Deref, DerefMut};
 use std::{error, fmt};
 
 use bitcoin::bech32::{self, EncodeError};
+use bitcoin::hashes::{sha256, Hash};
 use bitcoin::key::constants::UNCOMPRESSED_PUBLIC_KEY_SIZE;
 
 use crate::directory::ENCAPSULATED_MESSAGE_BYTES;
@@ -119,6 +120,35 @@ impl error::Error for OhttpEncapsulationError {
     }
 }
 
+/// The problem type an OHTTP gateway responds with when it doesn't know the key an
+/// encapsulated request was made to. See RFC 9458 Section 5.3.
+const KEY_REJECTION_PROBLEM_TYPE: &str =
+    "https://iana.org/assignments/http-problem-types#ohttp-key";
+
+/// Whether an unencapsulated response `body` is the gateway rejecting the OHTTP key
+pub(crate) fn is_key_rejection(body: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Value>(body)
+        .map(|problem| problem["type"] == KEY_REJECTION_PROBLEM_TYPE)
+        .unwrap_or(false)
+}
+
+/// The OHTTP gateway rejected the key a request was encapsulated to.
+///
+/// Directories rotate their OHTTP keys, so keys fetched earlier eventually go stale. The request
+/// is not retried for you: fetch the directory's current keys, e.g. with `io::fetch_ohttp_keys`,
+/// update the session with them and extract the request again. Keys rejected again right after
+/// a refresh point to a misbehaving directory or relay rather than a rotation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OhttpKeysStale;
+
+impl fmt::Display for OhttpKeysStale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The OHTTP gateway rejected the OHTTP keys as unknown")
+    }
+}
+
+impl error::Error for OhttpKeysStale {}
+
 #[derive(Debug, Clone)]
 pub struct OhttpKeys(pub ohttp::KeyConfig);
 
@@ -127,6 +157,24 @@ impl OhttpKeys {
     pub fn decode(bytes: &[u8]) -> Result<Self, ohttp::Error> {
         ohttp::KeyConfig::decode(bytes).map(Self)
     }
+
+    /// Encode as `key_id || compressed_public_key`, the compact form used in payjoin URIs
+    pub(crate) fn to_compact_bytes(&self) -> Option<Vec<u8>> {
+        let bytes = self.encode().ok()?;
+        let key_id = bytes[0];
+        let pubkey = bytes.get(3..68)?;
+
+        let compressed_pubkey = bitcoin::secp256k1::PublicKey::from_slice(pubkey).ok()?.serialize();
+
+        let mut buf = vec![key_id];
+        buf.extend_from_slice(&compressed_pubkey);
+        Some(buf)
+    }
+
+    /// The fingerprint that pins these keys, e.g. when fetching them from an OHTTP gateway
+    pub fn fingerprint(&self) -> Option<OhttpKeysFingerprint> {
+        self.to_compact_bytes().map(|bytes| OhttpKeysFingerprint(sha256::Hash::hash(&bytes)))
+    }
 }
 
 const KEM_ID: &[u8] = b"\x00\x16"; // DHKEM(secp256k1, HKDF-SHA256)
@@ -135,15 +183,7 @@ const SYMMETRIC_KDF_AEAD: &[u8] = b"\x00\x01\x00\x03"; // KDF(HKDF-SHA256), AEAD
 
 impl fmt::Display for OhttpKeys {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let bytes = self.encode().map_err(|_| fmt::Error)?;
-        let key_id = bytes[0];
-        let pubkey = &bytes[3..68];
-
-        let compressed_pubkey =
-            bitcoin::secp256k1::PublicKey::from_slice(pubkey).map_err(|_| fmt::Error)?.serialize();
-
-        let mut buf = vec![key_id];
-        buf.extend_from_slice(&compressed_pubkey);
+        let buf = self.to_compact_bytes().ok_or(fmt::Error)?;
 
         let oh_hrp: bech32::Hrp = bech32::Hrp::parse("OH").unwrap();
 
@@ -206,6 +246,44 @@ impl PartialEq for OhttpKeys {
 
 impl Eq for OhttpKeys {}
 
+/// SHA256 of the compact encoding of [`OhttpKeys`]
+///
+/// Keys fetched from a relay's gateway instead of from the directory itself are only as
+/// trustworthy as the relay, unless they are checked against a fingerprint obtained out of band.
+/// It is written as a bech32 `KF1` string, which may be added to a directory URL's fragment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OhttpKeysFingerprint(sha256::Hash);
+
+impl fmt::Display for OhttpKeysFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kf_hrp: bech32::Hrp = bech32::Hrp::parse("KF").unwrap();
+
+        crate::bech32::nochecksum::encode_to_fmt(f, kf_hrp, self.0.as_byte_array()).map_err(|e| {
+            match e {
+                EncodeError::Fmt(e) => e,
+                _ => fmt::Error,
+            }
+        })
+    }
+}
+
+impl std::str::FromStr for OhttpKeysFingerprint {
+    type Err = ParseOhttpKeysError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let kf_hrp: bech32::Hrp = bech32::Hrp::parse("KF").unwrap();
+
+        let (hrp, bytes) =
+            crate::bech32::nochecksum::decode(s).map_err(ParseOhttpKeysError::DecodeBech32)?;
+        if hrp != kf_hrp {
+            return Err(ParseOhttpKeysError::InvalidFormat);
+        }
+        let hash =
+            sha256::Hash::from_slice(&bytes).map_err(|_| ParseOhttpKeysError::InvalidFormat)?;
+        Ok(Self(hash))
+    }
+}
+
 impl Deref for OhttpKeys {
     type Target = ohttp::KeyConfig;
 
@@ -285,4 +363,26 @@ mod test {
         let deserialized = OhttpKeys::from_str(serialized).unwrap();
         assert_eq!(keys.encode().unwrap(), deserialized.encode().unwrap());
     }
+
+    #[test]
+    fn test_fingerprint_roundtrip() {
+        use std::str::FromStr;
+
+        let keys =
+            OhttpKeys::from_str("OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC")
+                .expect("valid keys");
+        let fingerprint = keys.fingerprint().expect("compact encoding");
+        let serialized = fingerprint.to_string();
+        assert!(serialized.starts_with("KF1"));
+        assert_eq!(OhttpKeysFingerprint::from_str(&serialized).unwrap(), fingerprint);
+        assert!(OhttpKeysFingerprint::from_str(&keys.to_string()).is_err());
+    }
+
+    #[test]
+    fn test_is_key_rejection() {
+        let problem = br#"{"type":"https://iana.org/assignments/http-problem-types#ohttp-key", "title": "key identifier unknown"}"#;
+        assert!(is_key_rejection(problem));
+        assert!(!is_key_rejection(br#"{"type":"about:blank"}"#));
+        assert!(!is_key_rejection(&[0u8; ENCAPSULATED_MESSAGE_BYTES]));
+    }
 }
@@ -1,20 +1,199 @@
 //! Utilities for merging unique v0 PSBTs
-use bitcoin::Psbt;
-
-#[allow(dead_code)]
-/// Try to merge two PSBTs
-/// PSBTs here should not have the same unsigned tx
-/// if you do have the same unsigned tx, use `combine` instead
-/// Note: this method does not merge non inputs or outputs
-/// Note: if there are duplicate inputs, the first input will be kept
-/// Note: if there are duplicate outputs, both outputs will be kept
-pub(crate) fn merge_unsigned_tx(acc: Psbt, psbt: Psbt) -> Psbt {
-    let mut unsigned_tx = acc.unsigned_tx;
-    unsigned_tx.input.extend(psbt.unsigned_tx.input);
-    unsigned_tx.input.dedup_by_key(|input| input.previous_output);
-    unsigned_tx.output.extend(psbt.unsigned_tx.output);
-
-    Psbt::from_unsigned_tx(unsigned_tx).expect("pulling from unsigned tx above")
+use std::collections::btree_map::Entry;
+use std::fmt;
+
+use bitcoin::absolute::LockTime;
+use bitcoin::hex::DisplayHex;
+use bitcoin::psbt::raw;
+use bitcoin::transaction::Version;
+use bitcoin::{bip32, OutPoint, Psbt, ScriptBuf, Transaction};
+
+use super::{InconsistentPsbt, PsbtExt};
+
+/// Merge PSBTs with different unsigned transactions into one.
+///
+/// Inputs are concatenated in order along with their PSBT data, including UTXO information,
+/// redeem and witness scripts, derivation paths and signatures. Outputs paying the same script
+/// are combined into one output paying the sum of their values. Global xpubs, proprietary and
+/// unknown fields are unioned, and must agree wherever the same key appears in several PSBTs.
+///
+/// All PSBTs must share the transaction version and lock time, and no input may be spent by
+/// more than one of them. PSBTs with the same unsigned transaction should be combined with
+/// [`Psbt::combine`] instead.
+///
+/// Merging changes the transaction, so signatures only remain valid if their sighash type
+/// commits to neither the other inputs nor the outputs, e.g. `SINGLE|ANYONECANPAY` where the
+/// paired output is unchanged.
+pub fn merge_psbts(psbts: impl IntoIterator<Item = Psbt>) -> Result<Psbt, MergeError> {
+    let mut psbts = psbts.into_iter();
+    let mut merged = psbts.next().ok_or(InternalMergeError::NoPsbts)?.validate()?;
+    let mut merged_outputs = Vec::with_capacity(merged.outputs.len());
+    let txouts = std::mem::take(&mut merged.unsigned_tx.output);
+    for (txout, psbtout) in txouts.into_iter().zip(std::mem::take(&mut merged.outputs)) {
+        merge_output(&mut merged, &mut merged_outputs, txout, psbtout)?;
+    }
+    for psbt in psbts {
+        let psbt = psbt.validate()?;
+        check_compatible(&merged.unsigned_tx, &psbt.unsigned_tx)?;
+        for (xpub, source) in psbt.xpub {
+            match merged.xpub.entry(xpub) {
+                Entry::Vacant(entry) => {
+                    entry.insert(source);
+                }
+                Entry::Occupied(entry) if *entry.get() == source => (),
+                Entry::Occupied(_) => return Err(InternalMergeError::ConflictingXpub(xpub).into()),
+            }
+        }
+        for (key, value) in psbt.proprietary {
+            match merged.proprietary.entry(key) {
+                Entry::Vacant(entry) => {
+                    entry.insert(value);
+                }
+                Entry::Occupied(entry) if *entry.get() == value => (),
+                Entry::Occupied(entry) =>
+                    return Err(
+                        InternalMergeError::ConflictingProprietary(entry.key().clone()).into()
+                    ),
+            }
+        }
+        for (key, value) in psbt.unknown {
+            match merged.unknown.entry(key) {
+                Entry::Vacant(entry) => {
+                    entry.insert(value);
+                }
+                Entry::Occupied(entry) if *entry.get() == value => (),
+                Entry::Occupied(entry) =>
+                    return Err(InternalMergeError::ConflictingUnknown(entry.key().clone()).into()),
+            }
+        }
+        for (txin, psbtin) in psbt.unsigned_tx.input.into_iter().zip(psbt.inputs) {
+            let outpoint = txin.previous_output;
+            if merged.unsigned_tx.input.iter().any(|input| input.previous_output == outpoint) {
+                return Err(InternalMergeError::ConflictingInput(outpoint).into());
+            }
+            merged.unsigned_tx.input.push(txin);
+            merged.inputs.push(psbtin);
+        }
+        for (txout, psbtout) in psbt.unsigned_tx.output.into_iter().zip(psbt.outputs) {
+            merge_output(&mut merged, &mut merged_outputs, txout, psbtout)?;
+        }
+    }
+    merged.outputs = merged_outputs;
+    Ok(merged)
+}
+
+/// Append an output, or add its value to an existing output paying the same script
+fn merge_output(
+    merged: &mut Psbt,
+    merged_outputs: &mut Vec<bitcoin::psbt::Output>,
+    txout: bitcoin::TxOut,
+    psbtout: bitcoin::psbt::Output,
+) -> Result<(), MergeError> {
+    let existing =
+        merged.unsigned_tx.output.iter().position(|o| o.script_pubkey == txout.script_pubkey);
+    match existing {
+        Some(index) => {
+            let output = &mut merged.unsigned_tx.output[index];
+            output.value = output.value.checked_add(txout.value).ok_or_else(|| {
+                InternalMergeError::OutputValueOverflow(txout.script_pubkey.clone())
+            })?;
+            merged_outputs[index].combine(psbtout);
+        }
+        None => {
+            merged.unsigned_tx.output.push(txout);
+            merged_outputs.push(psbtout);
+        }
+    }
+    Ok(())
+}
+
+fn check_compatible(merged: &Transaction, other: &Transaction) -> Result<(), InternalMergeError> {
+    if merged.version != other.version {
+        return Err(InternalMergeError::VersionsDontMatch {
+            merged: merged.version,
+            other: other.version,
+        });
+    }
+    if merged.lock_time != other.lock_time {
+        return Err(InternalMergeError::LockTimesDontMatch {
+            merged: merged.lock_time,
+            other: other.lock_time,
+        });
+    }
+    Ok(())
+}
+
+/// Error merging PSBTs with [`merge_psbts`].
+///
+/// This is currently opaque type because we aren't sure which variants will stay.
+/// You can only display it.
+#[derive(Debug)]
+pub struct MergeError(InternalMergeError);
+
+#[derive(Debug)]
+pub(crate) enum InternalMergeError {
+    NoPsbts,
+    Inconsistent(InconsistentPsbt),
+    VersionsDontMatch { merged: Version, other: Version },
+    LockTimesDontMatch { merged: LockTime, other: LockTime },
+    ConflictingInput(OutPoint),
+    ConflictingXpub(bip32::Xpub),
+    ConflictingProprietary(raw::ProprietaryKey),
+    ConflictingUnknown(raw::Key),
+    OutputValueOverflow(ScriptBuf),
+}
+
+impl From<InternalMergeError> for MergeError {
+    fn from(value: InternalMergeError) -> Self { MergeError(value) }
+}
+
+impl From<InconsistentPsbt> for MergeError {
+    fn from(value: InconsistentPsbt) -> Self { MergeError(InternalMergeError::Inconsistent(value)) }
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use InternalMergeError::*;
+
+        match &self.0 {
+            NoPsbts => write!(f, "no PSBTs to merge"),
+            Inconsistent(e) => write!(f, "a PSBT is inconsistent: {}", e),
+            VersionsDontMatch { merged, other } =>
+                write!(f, "transaction version {} doesn't match the merged {}", other, merged),
+            LockTimesDontMatch { merged, other } =>
+                write!(f, "lock time {} doesn't match the merged {}", other, merged),
+            ConflictingInput(outpoint) =>
+                write!(f, "input {} is spent by more than one PSBT", outpoint),
+            ConflictingXpub(xpub) => write!(f, "xpub {} has conflicting key sources", xpub),
+            ConflictingProprietary(key) => write!(
+                f,
+                "proprietary key {:x} with subtype {} has conflicting values",
+                key.key.as_hex(),
+                key.subtype
+            ),
+            ConflictingUnknown(key) => write!(f, "unknown key {} has conflicting values", key),
+            OutputValueOverflow(script) =>
+                write!(f, "the merged value of outputs to {} overflows", script),
+        }
+    }
+}
+
+impl std::error::Error for MergeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use InternalMergeError::*;
+
+        match &self.0 {
+            NoPsbts => None,
+            Inconsistent(e) => Some(e),
+            VersionsDontMatch { .. } => None,
+            LockTimesDontMatch { .. } => None,
+            ConflictingInput(_) => None,
+            ConflictingXpub(_) => None,
+            ConflictingProprietary(_) => None,
+            ConflictingUnknown(_) => None,
+            OutputValueOverflow(_) => None,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -29,7 +208,7 @@ mod tests {
         Witness,
     };
 
-    use super::merge_unsigned_tx;
+    use super::*;
 
     /// Create a random p2wpkh script
     fn random_p2wpkh_script() -> ScriptBuf {
@@ -85,7 +264,7 @@ mod tests {
     fn test_merge_unsigned_txs() {
         let txs = (0..10).map(|_| create_tx(2, 3)).collect::<Vec<_>>();
         let psbts = txs.iter().map(|tx| Psbt::from_unsigned_tx(tx.clone()).unwrap());
-        let merged_psbt = psbts.reduce(merge_unsigned_tx).unwrap();
+        let merged_psbt = merge_psbts(psbts).unwrap();
 
         for tx in txs.iter() {
             assert!(merged_psbt.unsigned_tx.input.contains(&tx.input[0]));
@@ -104,7 +283,7 @@ mod tests {
         let psbts =
             vec![Psbt::from_unsigned_tx(tx_1).unwrap(), Psbt::from_unsigned_tx(tx_2).unwrap()];
 
-        let merged_psbt = psbts.into_iter().reduce(merge_unsigned_tx).unwrap();
+        let merged_psbt = merge_psbts(psbts).unwrap();
 
         assert_eq!(merged_psbt.inputs.len(), 0);
         assert_eq!(merged_psbt.outputs.len(), 0);
@@ -117,19 +296,15 @@ mod tests {
         let psbt = Psbt::from_unsigned_tx(tx.clone()).unwrap();
         let psbts = vec![psbt.clone(), psbt.clone()];
 
-        let res = psbts.into_iter().reduce(merge_unsigned_tx).unwrap();
-        let unsigned_tx = res.unsigned_tx;
-
-        assert_eq!(unsigned_tx.input.len(), 1);
-        assert_eq!(unsigned_tx.input[0].previous_output, tx.input[0].previous_output);
-        assert_eq!(unsigned_tx.output.len(), 2);
-        assert_eq!(unsigned_tx.output[0], tx.output[0]);
-        assert_eq!(unsigned_tx.output[1], tx.output[0]);
+        let err = merge_psbts(psbts).unwrap_err();
+        assert!(
+            matches!(err.0, InternalMergeError::ConflictingInput(op) if op == tx.input[0].previous_output)
+        );
     }
 
-    /// Test that we cannot merge two psbts if psbts have inputs with witness data
+    /// Test that finalized inputs keep their witness data
     #[test]
-    fn should_not_merge_signed_psbt() {
+    fn test_merge_finalized_psbts() {
         let tx_1 = create_tx(1, 1);
         let tx_2 = create_tx(1, 1);
         let mut original_psbt = Psbt::from_unsigned_tx(tx_1.clone()).unwrap();
@@ -139,8 +314,10 @@ mod tests {
         original_psbt.unsigned_tx.input[0].witness = Witness::new();
         other.inputs[0].final_script_witness = Some(Witness::new());
         let psbts = vec![original_psbt.clone(), other.clone()];
-        let merged_psbt = psbts.into_iter().reduce(merge_unsigned_tx).unwrap();
+        let merged_psbt = merge_psbts(psbts).unwrap();
 
+        assert_eq!(merged_psbt.inputs[0], original_psbt.inputs[0]);
+        assert_eq!(merged_psbt.inputs[1], other.inputs[0]);
         assert_eq!(merged_psbt.unsigned_tx.input[0], original_psbt.unsigned_tx.input[0]);
         assert_eq!(merged_psbt.unsigned_tx.input[1], other.unsigned_tx.input[0]);
         assert_eq!(merged_psbt.unsigned_tx.output[0], original_psbt.unsigned_tx.output[0]);
@@ -156,9 +333,113 @@ mod tests {
         let psbts =
             vec![Psbt::from_unsigned_tx(tx_1).unwrap(), Psbt::from_unsigned_tx(tx_2).unwrap()];
 
-        let merged_psbt = psbts.into_iter().reduce(merge_unsigned_tx).unwrap();
+        let merged_psbt = merge_psbts(psbts).unwrap();
 
         assert_eq!(merged_psbt.inputs.len(), 2);
         assert_eq!(merged_psbt.outputs.len(), 3);
     }
+
+    /// Test that input and output metadata survive the merge
+    #[test]
+    fn test_merge_preserves_metadata() {
+        let tx_1 = create_tx(1, 1);
+        let tx_2 = create_tx(1, 1);
+        let mut psbt_1 = Psbt::from_unsigned_tx(tx_1.clone()).unwrap();
+        let mut psbt_2 = Psbt::from_unsigned_tx(tx_2.clone()).unwrap();
+        psbt_1.inputs[0].witness_utxo =
+            Some(TxOut { value: Amount::from_sat(5000), script_pubkey: random_p2wpkh_script() });
+        psbt_1.inputs[0].redeem_script = Some(random_p2wpkh_script());
+        let pk = bitcoin::PrivateKey::generate(Network::Bitcoin).public_key(SECP256K1);
+        psbt_1.inputs[0].partial_sigs.insert(
+            pk,
+            bitcoin::ecdsa::Signature::from_slice(&[
+                0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x01, 0x83,
+            ])
+            .unwrap(),
+        );
+        psbt_2.inputs[0].non_witness_utxo = Some(create_tx(0, 1));
+        psbt_2.outputs[0].witness_script = Some(random_p2wpkh_script());
+
+        let merged_psbt = merge_psbts(vec![psbt_1.clone(), psbt_2.clone()]).unwrap();
+
+        assert_eq!(merged_psbt.inputs, vec![psbt_1.inputs[0].clone(), psbt_2.inputs[0].clone()]);
+        assert_eq!(merged_psbt.outputs[1], psbt_2.outputs[0]);
+    }
+
+    /// Test that outputs paying the same script are combined
+    #[test]
+    fn test_merge_duplicate_outputs() {
+        let tx_1 = create_tx(1, 2);
+        let mut tx_2 = create_tx(1, 1);
+        tx_2.output[0].script_pubkey = tx_1.output[1].script_pubkey.clone();
+
+        let psbts =
+            vec![Psbt::from_unsigned_tx(tx_1).unwrap(), Psbt::from_unsigned_tx(tx_2).unwrap()];
+        let merged_psbt = merge_psbts(psbts).unwrap();
+
+        assert_eq!(merged_psbt.unsigned_tx.output.len(), 2);
+        assert_eq!(merged_psbt.outputs.len(), 2);
+        assert_eq!(merged_psbt.unsigned_tx.output[1].value, Amount::from_sat(2000));
+    }
+
+    /// Test that incompatible transactions are not merged
+    #[test]
+    fn should_not_merge_incompatible_txs() {
+        assert!(matches!(merge_psbts(vec![]).unwrap_err().0, InternalMergeError::NoPsbts));
+
+        let tx = create_tx(1, 1);
+        let mut other_version = create_tx(1, 1);
+        other_version.version = bitcoin::transaction::Version::ONE;
+        let psbts = vec![
+            Psbt::from_unsigned_tx(tx.clone()).unwrap(),
+            Psbt::from_unsigned_tx(other_version).unwrap(),
+        ];
+        assert!(matches!(
+            merge_psbts(psbts).unwrap_err().0,
+            InternalMergeError::VersionsDontMatch { .. }
+        ));
+
+        let mut other_lock_time = create_tx(1, 1);
+        other_lock_time.lock_time = LockTime::from_height(800_000).unwrap();
+        let psbts = vec![
+            Psbt::from_unsigned_tx(tx).unwrap(),
+            Psbt::from_unsigned_tx(other_lock_time).unwrap(),
+        ];
+        assert!(matches!(
+            merge_psbts(psbts).unwrap_err().0,
+            InternalMergeError::LockTimesDontMatch { .. }
+        ));
+    }
+
+    /// Test that proprietary and unknown fields must agree across PSBTs
+    #[test]
+    fn should_not_merge_conflicting_global_fields() {
+        let proprietary_key =
+            raw::ProprietaryKey { prefix: b"payjoin".to_vec(), subtype: 0, key: vec![1] };
+        let unknown_key = raw::Key { type_value: 0xf0, key: vec![1] };
+        let mut psbt_1 = Psbt::from_unsigned_tx(create_tx(1, 1)).unwrap();
+        let mut psbt_2 = Psbt::from_unsigned_tx(create_tx(1, 1)).unwrap();
+        psbt_1.proprietary.insert(proprietary_key.clone(), vec![1]);
+        psbt_2.proprietary.insert(proprietary_key.clone(), vec![1]);
+        psbt_1.unknown.insert(unknown_key.clone(), vec![1]);
+        psbt_2.unknown.insert(unknown_key.clone(), vec![1]);
+
+        let merged_psbt = merge_psbts(vec![psbt_1.clone(), psbt_2.clone()]).unwrap();
+        assert_eq!(merged_psbt.proprietary, psbt_1.proprietary);
+        assert_eq!(merged_psbt.unknown, psbt_1.unknown);
+
+        let mut conflicting = psbt_2.clone();
+        conflicting.proprietary.insert(proprietary_key.clone(), vec![2]);
+        assert!(matches!(
+            merge_psbts(vec![psbt_1.clone(), conflicting]).unwrap_err().0,
+            InternalMergeError::ConflictingProprietary(key) if key == proprietary_key
+        ));
+
+        let mut conflicting = psbt_2;
+        conflicting.unknown.insert(unknown_key.clone(), vec![2]);
+        assert!(matches!(
+            merge_psbts(vec![psbt_1, conflicting]).unwrap_err().0,
+            InternalMergeError::ConflictingUnknown(key) if key == unknown_key
+        ));
+    }
 }
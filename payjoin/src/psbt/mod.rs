@@ -2,6 +2,7 @@
 
 #[cfg(feature = "psbt-merge")]
 pub(crate) mod merge;
+pub(crate) mod v2;
 
 use std::collections::BTreeMap;
 use std::fmt;
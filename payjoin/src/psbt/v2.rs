@@ -0,0 +1,451 @@
+//! Convert between PSBTv2 ([BIP 370](https://github.com/bitcoin/bips/blob/master/bip-0370.mediawiki))
+//! and the PSBTv0 representation of [`Psbt`]
+//!
+//! PSBTv2 replaces the global unsigned transaction with per-input and per-output fields and is
+//! otherwise identical to PSBTv0. Conversion rewrites only those fields, so every other field
+//! is parsed by [`Psbt::deserialize`] as usual.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use bitcoin::absolute::LockTime;
+use bitcoin::base64::prelude::{Engine as _, BASE64_STANDARD};
+use bitcoin::consensus::encode::{deserialize, deserialize_partial, serialize, VarInt};
+use bitcoin::psbt::Psbt;
+use bitcoin::transaction::Version;
+use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness};
+
+const MAGIC: &[u8] = b"psbt\xff";
+
+const PSBT_GLOBAL_UNSIGNED_TX: u64 = 0x00;
+const PSBT_GLOBAL_TX_VERSION: u64 = 0x02;
+const PSBT_GLOBAL_FALLBACK_LOCKTIME: u64 = 0x03;
+const PSBT_GLOBAL_INPUT_COUNT: u64 = 0x04;
+const PSBT_GLOBAL_OUTPUT_COUNT: u64 = 0x05;
+const PSBT_GLOBAL_TX_MODIFIABLE: u64 = 0x06;
+const PSBT_GLOBAL_VERSION: u64 = 0xfb;
+
+const PSBT_IN_PREVIOUS_TXID: u64 = 0x0e;
+const PSBT_IN_OUTPUT_INDEX: u64 = 0x0f;
+const PSBT_IN_SEQUENCE: u64 = 0x10;
+const PSBT_IN_REQUIRED_TIME_LOCKTIME: u64 = 0x11;
+const PSBT_IN_REQUIRED_HEIGHT_LOCKTIME: u64 = 0x12;
+
+const PSBT_OUT_AMOUNT: u64 = 0x03;
+const PSBT_OUT_SCRIPT: u64 = 0x04;
+
+/// Parse a PSBTv2 into the PSBTv0 representation used throughout this crate.
+///
+/// The transaction lock time is determined from the inputs' required lock times or the
+/// fallback lock time as specified by BIP 370.
+pub fn psbt_from_v2(bytes: &[u8]) -> Result<Psbt, PsbtV2Error> {
+    let mut reader = Reader::new(bytes)?;
+    let mut global = reader.map()?;
+    match take_u32(&mut global, PSBT_GLOBAL_VERSION, "PSBT_GLOBAL_VERSION")? {
+        Some(2) => (),
+        Some(version) => return Err(InternalPsbtV2Error::UnsupportedVersion(version).into()),
+        None => return Err(InternalPsbtV2Error::MissingField("PSBT_GLOBAL_VERSION").into()),
+    }
+    if global.take(PSBT_GLOBAL_UNSIGNED_TX).is_some() {
+        return Err(InternalPsbtV2Error::ForbiddenField("PSBT_GLOBAL_UNSIGNED_TX").into());
+    }
+    let version = take_u32(&mut global, PSBT_GLOBAL_TX_VERSION, "PSBT_GLOBAL_TX_VERSION")?
+        .ok_or(InternalPsbtV2Error::MissingField("PSBT_GLOBAL_TX_VERSION"))?;
+    let fallback_lock_time =
+        take_u32(&mut global, PSBT_GLOBAL_FALLBACK_LOCKTIME, "PSBT_GLOBAL_FALLBACK_LOCKTIME")?;
+    let input_count = take_count(&mut global, PSBT_GLOBAL_INPUT_COUNT, "PSBT_GLOBAL_INPUT_COUNT")?;
+    let output_count =
+        take_count(&mut global, PSBT_GLOBAL_OUTPUT_COUNT, "PSBT_GLOBAL_OUTPUT_COUNT")?;
+    global.take(PSBT_GLOBAL_TX_MODIFIABLE);
+
+    let mut inputs = Vec::with_capacity(input_count.min(bytes.len()));
+    let mut txins = Vec::with_capacity(inputs.capacity());
+    let mut required_lock_times = Vec::new();
+    for _ in 0..input_count {
+        let mut input = reader.map()?;
+        let txid = input
+            .take(PSBT_IN_PREVIOUS_TXID)
+            .ok_or(InternalPsbtV2Error::MissingField("PSBT_IN_PREVIOUS_TXID"))?;
+        let txid: Txid = deserialize(&txid)
+            .map_err(|_| InternalPsbtV2Error::InvalidField("PSBT_IN_PREVIOUS_TXID"))?;
+        let vout = take_u32(&mut input, PSBT_IN_OUTPUT_INDEX, "PSBT_IN_OUTPUT_INDEX")?
+            .ok_or(InternalPsbtV2Error::MissingField("PSBT_IN_OUTPUT_INDEX"))?;
+        let sequence = take_u32(&mut input, PSBT_IN_SEQUENCE, "PSBT_IN_SEQUENCE")?
+            .map_or(Sequence::MAX, Sequence);
+        let time =
+            take_u32(&mut input, PSBT_IN_REQUIRED_TIME_LOCKTIME, "PSBT_IN_REQUIRED_TIME_LOCKTIME")?;
+        let height = take_u32(
+            &mut input,
+            PSBT_IN_REQUIRED_HEIGHT_LOCKTIME,
+            "PSBT_IN_REQUIRED_HEIGHT_LOCKTIME",
+        )?;
+        if time.is_some() || height.is_some() {
+            required_lock_times.push((time, height));
+        }
+        txins.push(TxIn {
+            previous_output: OutPoint { txid, vout },
+            script_sig: ScriptBuf::new(),
+            sequence,
+            witness: Witness::new(),
+        });
+        inputs.push(input);
+    }
+
+    let mut outputs = Vec::with_capacity(output_count.min(bytes.len()));
+    let mut txouts = Vec::with_capacity(outputs.capacity());
+    for _ in 0..output_count {
+        let mut output = reader.map()?;
+        let amount = output
+            .take(PSBT_OUT_AMOUNT)
+            .ok_or(InternalPsbtV2Error::MissingField("PSBT_OUT_AMOUNT"))?;
+        let amount: i64 = deserialize(&amount)
+            .map_err(|_| InternalPsbtV2Error::InvalidField("PSBT_OUT_AMOUNT"))?;
+        let value = u64::try_from(amount)
+            .map(Amount::from_sat)
+            .map_err(|_| InternalPsbtV2Error::InvalidField("PSBT_OUT_AMOUNT"))?;
+        let script_pubkey = output
+            .take(PSBT_OUT_SCRIPT)
+            .ok_or(InternalPsbtV2Error::MissingField("PSBT_OUT_SCRIPT"))?;
+        txouts.push(TxOut { value, script_pubkey: ScriptBuf::from(script_pubkey) });
+        outputs.push(output);
+    }
+    reader.finish()?;
+
+    let unsigned_tx = Transaction {
+        version: Version(version as i32),
+        lock_time: lock_time(fallback_lock_time, &required_lock_times)?,
+        input: txins,
+        output: txouts,
+    };
+    let mut v0 = MAGIC.to_vec();
+    Pair::write(&mut v0, PSBT_GLOBAL_UNSIGNED_TX, &[], &serialize(&unsigned_tx));
+    global.write(&mut v0);
+    for map in inputs.iter().chain(&outputs) {
+        map.write(&mut v0);
+    }
+    Ok(Psbt::deserialize(&v0).map_err(InternalPsbtV2Error::Psbt)?)
+}
+
+/// Serialize `psbt` as a PSBTv2.
+///
+/// The transaction lock time becomes the fallback lock time. Inputs specify no required lock
+/// times and the transaction is not marked modifiable.
+pub fn psbt_to_v2(psbt: &Psbt) -> Vec<u8> {
+    let v0 = psbt.serialize();
+    let mut reader = Reader::new(&v0).expect("serialized PSBT starts with the magic bytes");
+    let mut global = reader.map().expect("serialized PSBT is well formed");
+    global.take(PSBT_GLOBAL_UNSIGNED_TX);
+    global.take(PSBT_GLOBAL_VERSION);
+    let tx = &psbt.unsigned_tx;
+
+    let mut v2 = MAGIC.to_vec();
+    Pair::write(&mut v2, PSBT_GLOBAL_VERSION, &[], &2u32.to_le_bytes());
+    Pair::write(&mut v2, PSBT_GLOBAL_TX_VERSION, &[], &tx.version.0.to_le_bytes());
+    Pair::write(
+        &mut v2,
+        PSBT_GLOBAL_FALLBACK_LOCKTIME,
+        &[],
+        &tx.lock_time.to_consensus_u32().to_le_bytes(),
+    );
+    Pair::write(&mut v2, PSBT_GLOBAL_INPUT_COUNT, &[], &serialize(&VarInt(tx.input.len() as u64)));
+    Pair::write(
+        &mut v2,
+        PSBT_GLOBAL_OUTPUT_COUNT,
+        &[],
+        &serialize(&VarInt(tx.output.len() as u64)),
+    );
+    global.write(&mut v2);
+    for txin in &tx.input {
+        let input = reader.map().expect("serialized PSBT has a map per input");
+        Pair::write(&mut v2, PSBT_IN_PREVIOUS_TXID, &[], &serialize(&txin.previous_output.txid));
+        Pair::write(&mut v2, PSBT_IN_OUTPUT_INDEX, &[], &txin.previous_output.vout.to_le_bytes());
+        Pair::write(&mut v2, PSBT_IN_SEQUENCE, &[], &txin.sequence.0.to_le_bytes());
+        input.write(&mut v2);
+    }
+    for txout in &tx.output {
+        let output = reader.map().expect("serialized PSBT has a map per output");
+        Pair::write(&mut v2, PSBT_OUT_AMOUNT, &[], &(txout.value.to_sat() as i64).to_le_bytes());
+        Pair::write(&mut v2, PSBT_OUT_SCRIPT, &[], txout.script_pubkey.as_bytes());
+        output.write(&mut v2);
+    }
+    v2
+}
+
+/// Whether `bytes` hold a PSBT whose global version is 2
+pub(crate) fn is_v2(bytes: &[u8]) -> bool {
+    Reader::new(bytes)
+        .and_then(|mut reader| reader.map())
+        .ok()
+        .and_then(|mut global| take_u32(&mut global, PSBT_GLOBAL_VERSION, "").ok().flatten())
+        == Some(2)
+}
+
+/// Decode a base64 PSBTv2, or return `None` if `base64` holds a PSBT of another version
+pub(crate) fn decode_base64(base64: &str) -> Option<Result<Psbt, PsbtV2Error>> {
+    let bytes = BASE64_STANDARD.decode(base64).ok()?;
+    if is_v2(&bytes) {
+        Some(psbt_from_v2(&bytes))
+    } else {
+        None
+    }
+}
+
+/// Serialize `psbt` as a PSBTv2 if `v2` is set or as a PSBTv0 otherwise
+pub(crate) fn serialize_as(psbt: &Psbt, v2: bool) -> Vec<u8> {
+    if v2 {
+        psbt_to_v2(psbt)
+    } else {
+        psbt.serialize()
+    }
+}
+
+/// Encode `psbt` as base64, as a PSBTv2 if `v2` is set or as a PSBTv0 otherwise
+pub(crate) fn encode_base64(psbt: &Psbt, v2: bool) -> String {
+    BASE64_STANDARD.encode(serialize_as(psbt, v2))
+}
+
+/// Choose the lock time as specified by BIP 370
+fn lock_time(
+    fallback: Option<u32>,
+    required: &[(Option<u32>, Option<u32>)],
+) -> Result<LockTime, PsbtV2Error> {
+    if required.is_empty() {
+        return Ok(LockTime::from_consensus(fallback.unwrap_or(0)));
+    }
+    let heights = required.iter().map(|(_, height)| *height).collect::<Option<Vec<_>>>();
+    let times = required.iter().map(|(time, _)| *time).collect::<Option<Vec<_>>>();
+    // height based lock times take precedence when every input supports them
+    let lock_time = heights
+        .or(times)
+        .and_then(|lock_times| lock_times.into_iter().max())
+        .ok_or(InternalPsbtV2Error::IncompatibleLockTimes)?;
+    Ok(LockTime::from_consensus(lock_time))
+}
+
+/// A raw PSBT key-value pair with the key type split off the key
+struct Pair {
+    key_type: u64,
+    key_data: Vec<u8>,
+    value: Vec<u8>,
+}
+
+impl Pair {
+    fn write(out: &mut Vec<u8>, key_type: u64, key_data: &[u8], value: &[u8]) {
+        let key_type = serialize(&VarInt(key_type));
+        out.extend(serialize(&VarInt((key_type.len() + key_data.len()) as u64)));
+        out.extend(key_type);
+        out.extend(key_data);
+        out.extend(serialize(&VarInt(value.len() as u64)));
+        out.extend(value);
+    }
+}
+
+/// A PSBT map, in the order the pairs were read
+struct Map(Vec<Pair>);
+
+impl Map {
+    /// Remove and return the value of the key of `key_type` without key data
+    fn take(&mut self, key_type: u64) -> Option<Vec<u8>> {
+        let index =
+            self.0.iter().position(|pair| pair.key_type == key_type && pair.key_data.is_empty())?;
+        Some(self.0.remove(index).value)
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        for pair in &self.0 {
+            Pair::write(out, pair.key_type, &pair.key_data, &pair.value);
+        }
+        out.push(0x00);
+    }
+}
+
+fn take_u32(map: &mut Map, key_type: u64, field: &'static str) -> Result<Option<u32>, PsbtV2Error> {
+    map.take(key_type)
+        .map(|value| {
+            let value: [u8; 4] =
+                value.try_into().map_err(|_| InternalPsbtV2Error::InvalidField(field))?;
+            Ok(u32::from_le_bytes(value))
+        })
+        .transpose()
+}
+
+fn take_count(map: &mut Map, key_type: u64, field: &'static str) -> Result<usize, PsbtV2Error> {
+    let value = map.take(key_type).ok_or(InternalPsbtV2Error::MissingField(field))?;
+    let count: VarInt =
+        deserialize(&value).map_err(|_| InternalPsbtV2Error::InvalidField(field))?;
+    usize::try_from(count.0).map_err(|_| InternalPsbtV2Error::InvalidField(field).into())
+}
+
+/// Reads the maps of a serialized PSBT one at a time
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Result<Self, PsbtV2Error> {
+        match bytes.strip_prefix(MAGIC) {
+            Some(bytes) => Ok(Self { bytes }),
+            None => Err(InternalPsbtV2Error::InvalidMagic.into()),
+        }
+    }
+
+    fn map(&mut self) -> Result<Map, PsbtV2Error> {
+        let mut pairs = Vec::new();
+        let mut keys = BTreeSet::new();
+        loop {
+            let key = self.bytes()?;
+            if key.is_empty() {
+                return Ok(Map(pairs));
+            }
+            let (key_type, consumed) = deserialize_partial::<VarInt>(&key)
+                .map_err(|_| InternalPsbtV2Error::UnexpectedEnd)?;
+            if !keys.insert(key.clone()) {
+                return Err(InternalPsbtV2Error::DuplicateKey(key_type.0).into());
+            }
+            let value = self.bytes()?;
+            pairs.push(Pair { key_type: key_type.0, key_data: key[consumed..].to_vec(), value });
+        }
+    }
+
+    /// Read length-prefixed bytes
+    fn bytes(&mut self) -> Result<Vec<u8>, PsbtV2Error> {
+        let (len, consumed) = deserialize_partial::<VarInt>(self.bytes)
+            .map_err(|_| InternalPsbtV2Error::UnexpectedEnd)?;
+        let rest = &self.bytes[consumed..];
+        let len = usize::try_from(len.0).map_err(|_| InternalPsbtV2Error::UnexpectedEnd)?;
+        if rest.len() < len {
+            return Err(InternalPsbtV2Error::UnexpectedEnd.into());
+        }
+        let (bytes, rest) = rest.split_at(len);
+        self.bytes = rest;
+        Ok(bytes.to_vec())
+    }
+
+    fn finish(self) -> Result<(), PsbtV2Error> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(InternalPsbtV2Error::TrailingData.into())
+        }
+    }
+}
+
+/// Error that may occur when converting a PSBTv2
+#[derive(Debug)]
+pub struct PsbtV2Error(InternalPsbtV2Error);
+
+#[derive(Debug)]
+pub(crate) enum InternalPsbtV2Error {
+    /// The PSBT does not start with the magic bytes
+    InvalidMagic,
+    /// The PSBT ends in the middle of a key-value pair
+    UnexpectedEnd,
+    /// Bytes follow the last output map
+    TrailingData,
+    /// A map contains the key of this type twice
+    DuplicateKey(u64),
+    /// The PSBT global version is not 2
+    UnsupportedVersion(u32),
+    /// A field required by PSBTv2 is missing
+    MissingField(&'static str),
+    /// A field excluded from PSBTv2 is present
+    ForbiddenField(&'static str),
+    /// A field has an invalid value
+    InvalidField(&'static str),
+    /// Some inputs require a height lock time and others a time lock time
+    IncompatibleLockTimes,
+    /// The fields shared with PSBTv0 are invalid
+    Psbt(bitcoin::psbt::Error),
+}
+
+impl From<InternalPsbtV2Error> for PsbtV2Error {
+    fn from(value: InternalPsbtV2Error) -> Self { PsbtV2Error(value) }
+}
+
+impl fmt::Display for PsbtV2Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use InternalPsbtV2Error::*;
+
+        match &self.0 {
+            InvalidMagic => write!(f, "missing PSBT magic bytes"),
+            UnexpectedEnd => write!(f, "unexpected end of PSBT"),
+            TrailingData => write!(f, "data after the last PSBT output"),
+            DuplicateKey(key_type) => write!(f, "duplicate key of type {:#04x}", key_type),
+            UnsupportedVersion(version) => write!(f, "PSBT version {} is not 2", version),
+            MissingField(field) => write!(f, "missing {}", field),
+            ForbiddenField(field) => write!(f, "{} is not allowed in PSBTv2", field),
+            InvalidField(field) => write!(f, "invalid {}", field),
+            IncompatibleLockTimes => write!(f, "inputs require incompatible lock time types"),
+            Psbt(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for PsbtV2Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use InternalPsbtV2Error::*;
+
+        match &self.0 {
+            InvalidMagic => None,
+            UnexpectedEnd => None,
+            TrailingData => None,
+            DuplicateKey(_) => None,
+            UnsupportedVersion(_) => None,
+            MissingField(_) => None,
+            ForbiddenField(_) => None,
+            InvalidField(_) => None,
+            IncompatibleLockTimes => None,
+            Psbt(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::receive::v1::test::ORIGINAL_PSBT;
+
+    #[test]
+    fn roundtrip_through_v2() {
+        let psbt = Psbt::from_str(ORIGINAL_PSBT).unwrap();
+        let v2 = psbt_to_v2(&psbt);
+        assert!(is_v2(&v2));
+        assert!(!is_v2(&psbt.serialize()));
+        assert!(matches!(
+            Psbt::deserialize(&v2),
+            Err(bitcoin::psbt::Error::Version(_)) | Err(bitcoin::psbt::Error::MustHaveUnsignedTx)
+        ));
+        assert_eq!(psbt_from_v2(&v2).unwrap(), psbt);
+        assert_eq!(decode_base64(&encode_base64(&psbt, true)).unwrap().unwrap(), psbt);
+        assert!(decode_base64(ORIGINAL_PSBT).is_none());
+    }
+
+    #[test]
+    fn chooses_lock_time() {
+        let height = |h| (None, Some(h));
+        let time = |t| (Some(t), None);
+        let both = |t, h| (Some(t), Some(h));
+        assert_eq!(lock_time(None, &[]).unwrap(), LockTime::ZERO);
+        assert_eq!(lock_time(Some(100), &[]).unwrap(), LockTime::from_consensus(100));
+        assert_eq!(
+            lock_time(Some(100), &[height(200), both(600_000_000, 300)]).unwrap(),
+            LockTime::from_consensus(300)
+        );
+        assert_eq!(
+            lock_time(None, &[time(600_000_000), both(600_000_001, 300)]).unwrap(),
+            LockTime::from_consensus(600_000_001)
+        );
+        assert!(lock_time(None, &[time(600_000_000), height(300)]).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_v2() {
+        let psbt = Psbt::from_str(ORIGINAL_PSBT).unwrap();
+        let v2 = psbt_to_v2(&psbt);
+        assert!(psbt_from_v2(&v2[..v2.len() - 1]).is_err());
+        assert!(psbt_from_v2(&[v2.as_slice(), &[0]].concat()).is_err());
+        assert!(psbt_from_v2(&psbt.serialize()).is_err());
+    }
+}
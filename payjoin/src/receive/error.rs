@@ -15,6 +15,10 @@ pub enum Error {
     #[cfg(feature = "v2")]
     /// V2-specific errors that are infeasable to reply to the sender
     V2(crate::receive::v2::SessionError),
+    #[cfg(feature = "v2")]
+    /// The sender retracted its Original PSBT. Stop processing it and release anything
+    /// reserved for the proposal.
+    Cancelled,
 }
 
 impl From<ReplyableError> for Error {
@@ -27,6 +31,8 @@ impl fmt::Display for Error {
             Error::ReplyToSender(e) => write!(f, "replyable error: {}", e),
             #[cfg(feature = "v2")]
             Error::V2(e) => write!(f, "unreplyable error: {}", e),
+            #[cfg(feature = "v2")]
+            Error::Cancelled => write!(f, "the sender cancelled the session"),
         }
     }
 }
@@ -37,6 +43,8 @@ impl error::Error for Error {
             Error::ReplyToSender(e) => e.source(),
             #[cfg(feature = "v2")]
             Error::V2(e) => e.source(),
+            #[cfg(feature = "v2")]
+            Error::Cancelled => None,
         }
     }
 }
@@ -151,6 +159,8 @@ pub(crate) enum InternalPayloadError {
     Utf8(std::string::FromUtf8Error),
     /// The payload is not a valid PSBT
     ParsePsbt(bitcoin::psbt::PsbtParseError),
+    /// The payload is not a valid PSBTv2
+    ParsePsbtV2(crate::psbt::v2::PsbtV2Error),
     /// Invalid sender parameters
     SenderParams(super::optional_parameters::Error),
     /// The raw PSBT fails bip78-specific validation.
@@ -178,6 +188,14 @@ pub(crate) enum InternalPayloadError {
     PsbtBelowFeeRate(bitcoin::FeeRate, bitcoin::FeeRate),
     /// Effective receiver feerate exceeds maximum allowed feerate
     FeeTooHigh(bitcoin::FeeRate, bitcoin::FeeRate),
+    /// The version 3 Original PSBT or the Payjoin PSBT violates the TRUC policy
+    Truc(crate::truc::TrucViolation),
+    /// The Original PSBT was split into parts that can't be reassembled
+    #[cfg(feature = "v2")]
+    Parts(crate::multipart::PartError),
+    /// The Original PSBT failed validation without a mempool
+    #[cfg(feature = "bitcoinconsensus")]
+    OriginalPsbtInvalid(super::offline::OriginalPsbtError),
 }
 
 impl JsonError for PayloadError {
@@ -187,6 +205,7 @@ impl JsonError for PayloadError {
         match &self.0 {
             Utf8(_) => serialize_json_error(ORIGINAL_PSBT_REJECTED, self),
             ParsePsbt(_) => serialize_json_error(ORIGINAL_PSBT_REJECTED, self),
+            ParsePsbtV2(_) => serialize_json_error(ORIGINAL_PSBT_REJECTED, self),
             SenderParams(e) => match e {
                 super::optional_parameters::Error::UnknownVersion { supported_versions } => {
                     let supported_versions_json =
@@ -208,6 +227,11 @@ impl JsonError for PayloadError {
             InputSeen(_) => serialize_json_error(ORIGINAL_PSBT_REJECTED, self),
             PsbtBelowFeeRate(_, _) => serialize_json_error(ORIGINAL_PSBT_REJECTED, self),
             FeeTooHigh(_, _) => serialize_json_error(NOT_ENOUGH_MONEY, self),
+            Truc(_) => serialize_json_error(ORIGINAL_PSBT_REJECTED, self),
+            #[cfg(feature = "v2")]
+            Parts(_) => serialize_json_error(ORIGINAL_PSBT_REJECTED, self),
+            #[cfg(feature = "bitcoinconsensus")]
+            OriginalPsbtInvalid(_) => serialize_json_error(ORIGINAL_PSBT_REJECTED, self),
         }
     }
 }
@@ -219,6 +243,7 @@ impl fmt::Display for PayloadError {
         match &self.0 {
             Utf8(e) => write!(f, "{}", e),
             ParsePsbt(e) => write!(f, "{}", e),
+            ParsePsbtV2(e) => write!(f, "{}", e),
             SenderParams(e) => write!(f, "{}", e),
             InconsistentPsbt(e) => write!(f, "{}", e),
             PrevTxOut(e) => write!(f, "PrevTxOut Error: {}", e),
@@ -237,6 +262,11 @@ impl fmt::Display for PayloadError {
                 "Effective receiver feerate exceeds maximum allowed feerate: {} > {}",
                 proposed_fee_rate, max_fee_rate
             ),
+            Truc(e) => write!(f, "TRUC policy violation: {}", e),
+            #[cfg(feature = "v2")]
+            Parts(e) => write!(f, "Original PSBT parts rejected: {}", e),
+            #[cfg(feature = "bitcoinconsensus")]
+            OriginalPsbtInvalid(e) => write!(f, "Original PSBT rejected: {}", e),
         }
     }
 }
@@ -247,16 +277,22 @@ impl std::error::Error for PayloadError {
         match &self.0 {
             Utf8(e) => Some(e),
             ParsePsbt(e) => Some(e),
+            ParsePsbtV2(e) => Some(e),
             SenderParams(e) => Some(e),
             InconsistentPsbt(e) => Some(e),
             PrevTxOut(e) => Some(e),
             InputWeight(e) => Some(e),
             PsbtBelowFeeRate(_, _) => None,
             FeeTooHigh(_, _) => None,
+            Truc(e) => Some(e),
             MissingPayment => None,
             OriginalPsbtNotBroadcastable => None,
             InputOwned(_) => None,
             InputSeen(_) => None,
+            #[cfg(feature = "v2")]
+            Parts(e) => Some(e),
+            #[cfg(feature = "bitcoinconsensus")]
+            OriginalPsbtInvalid(e) => Some(e),
         }
     }
 }
@@ -357,12 +393,16 @@ impl From<InternalSelectionError> for SelectionError {
 /// This is currently opaque type because we aren't sure which variants will stay.
 /// You can only display it.
 #[derive(Debug)]
-pub struct InputContributionError(InternalInputContributionError);
+pub struct InputContributionError(pub(crate) InternalInputContributionError);
 
 #[derive(Debug)]
 pub(crate) enum InternalInputContributionError {
     /// Total input value is not enough to cover additional output value
     ValueTooLow,
+    /// The expected weight of a contributed input cannot be determined
+    InputWeight(crate::psbt::InputWeightError),
+    /// The inputs make the version 3 Payjoin transaction violate the TRUC policy
+    Truc(crate::truc::TrucViolation),
 }
 
 impl fmt::Display for InputContributionError {
@@ -370,6 +410,9 @@ impl fmt::Display for InputContributionError {
         match &self.0 {
             InternalInputContributionError::ValueTooLow =>
                 write!(f, "Total input value is not enough to cover additional output value"),
+            InternalInputContributionError::InputWeight(e) =>
+                write!(f, "Can not determine expected input weight: {}", e),
+            InternalInputContributionError::Truc(e) => write!(f, "TRUC policy violation: {}", e),
         }
     }
 }
@@ -378,6 +421,8 @@ impl error::Error for InputContributionError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match &self.0 {
             InternalInputContributionError::ValueTooLow => None,
+            InternalInputContributionError::InputWeight(e) => Some(e),
+            InternalInputContributionError::Truc(e) => Some(e),
         }
     }
 }
@@ -385,3 +430,122 @@ impl error::Error for InputContributionError {
 impl From<InternalInputContributionError> for InputContributionError {
     fn from(value: InternalInputContributionError) -> Self { InputContributionError(value) }
 }
+
+/// Error that may occur when batching several senders into one transaction.
+///
+/// Batching fails as a whole, so the proposals may still be finalized individually.
+#[cfg(all(feature = "v2", feature = "psbt-merge"))]
+#[derive(Debug)]
+pub struct BatchError(pub(crate) InternalBatchError);
+
+#[cfg(all(feature = "v2", feature = "psbt-merge"))]
+#[derive(Debug)]
+pub(crate) enum InternalBatchError {
+    /// The sender of the proposal at this index did not opt in to batching
+    SenderDisallowsBatching(usize),
+    /// The fee of the proposal at this index could not be applied
+    Fee(usize, PayloadError),
+    /// The proposals could not be merged
+    Merge(crate::MergeError),
+    /// Senders reject inputs whose sequence differs from that of their own
+    MixedSequence,
+    /// The inputs or outputs of the sender at this index are out of order
+    SenderLayout(usize),
+    /// The merged transaction pays less than the minimum fee rate of the sender at this index
+    FeeRateBelowMinimum(usize, bitcoin::FeeRate, bitcoin::FeeRate),
+    /// The expected weight of the merged transaction cannot be determined
+    InputWeight(crate::psbt::InputWeightError),
+    /// The merged version 3 transaction violates the TRUC policy
+    Truc(crate::truc::TrucViolation),
+    /// The fee of the merged transaction could not be calculated
+    Psbt(bitcoin::psbt::Error),
+    /// The signed PSBT is not the batched transaction
+    UnknownTransaction,
+    /// No proposal in the batch has this index
+    UnknownSender(usize),
+    /// The sender of the proposal at this index returned scripts for an input not its own
+    ForeignInputSigned(usize, bitcoin::OutPoint),
+    /// The input spending this outpoint is not signed yet
+    MissingSignature(bitcoin::OutPoint),
+    /// The output spent by this outpoint is unknown
+    #[cfg(feature = "bitcoinconsensus")]
+    PrevTxOut(bitcoin::OutPoint, crate::psbt::PrevTxOutError),
+    /// The input spending this outpoint does not satisfy the script it spends
+    #[cfg(feature = "bitcoinconsensus")]
+    ScriptVerification(bitcoin::OutPoint, bitcoin::consensus::validation::BitcoinconsensusError),
+    /// The wallet failed to sign the batch
+    Implementation(ImplementationError),
+}
+
+#[cfg(all(feature = "v2", feature = "psbt-merge"))]
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use InternalBatchError::*;
+
+        match &self.0 {
+            SenderDisallowsBatching(index) =>
+                write!(f, "the sender of proposal {} did not opt in to batching", index),
+            Fee(index, e) => write!(f, "could not apply the fee of proposal {}: {}", index, e),
+            Merge(e) => write!(f, "could not merge the proposals: {}", e),
+            MixedSequence => write!(f, "the proposals' inputs have different sequences"),
+            SenderLayout(index) =>
+                write!(f, "the inputs or outputs of proposal {} would be out of order", index),
+            FeeRateBelowMinimum(index, fee_rate, min_fee_rate) => write!(
+                f,
+                "the batch fee rate {} is below the minimum {} of proposal {}",
+                fee_rate, min_fee_rate, index
+            ),
+            InputWeight(e) => write!(f, "can not determine expected input weight: {}", e),
+            Truc(e) => write!(f, "the batch violates the TRUC policy: {}", e),
+            Psbt(e) => write!(f, "could not calculate the batch fee: {}", e),
+            UnknownTransaction => write!(f, "the PSBT does not spend the batched transaction"),
+            UnknownSender(index) => write!(f, "there is no proposal {} in the batch", index),
+            ForeignInputSigned(index, outpoint) => write!(
+                f,
+                "the sender of proposal {} returned scripts for input {} which is not its own",
+                index, outpoint
+            ),
+            MissingSignature(outpoint) => write!(f, "input {} is not signed", outpoint),
+            #[cfg(feature = "bitcoinconsensus")]
+            PrevTxOut(outpoint, e) =>
+                write!(f, "the output spent by input {} is unknown: {}", outpoint, e),
+            #[cfg(feature = "bitcoinconsensus")]
+            ScriptVerification(outpoint, e) =>
+                write!(f, "input {} does not satisfy the script it spends: {}", outpoint, e),
+            Implementation(e) => write!(f, "Internal Server Error: {}", e),
+        }
+    }
+}
+
+#[cfg(all(feature = "v2", feature = "psbt-merge"))]
+impl error::Error for BatchError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use InternalBatchError::*;
+
+        match &self.0 {
+            SenderDisallowsBatching(_) => None,
+            Fee(_, e) => Some(e),
+            Merge(e) => Some(e),
+            MixedSequence => None,
+            SenderLayout(_) => None,
+            FeeRateBelowMinimum(..) => None,
+            InputWeight(e) => Some(e),
+            Truc(e) => Some(e),
+            Psbt(e) => Some(e),
+            UnknownTransaction => None,
+            UnknownSender(_) => None,
+            ForeignInputSigned(..) => None,
+            MissingSignature(_) => None,
+            #[cfg(feature = "bitcoinconsensus")]
+            PrevTxOut(_, e) => Some(e),
+            #[cfg(feature = "bitcoinconsensus")]
+            ScriptVerification(_, e) => Some(e),
+            Implementation(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+#[cfg(all(feature = "v2", feature = "psbt-merge"))]
+impl From<InternalBatchError> for BatchError {
+    fn from(value: InternalBatchError) -> Self { BatchError(value) }
+}
@@ -12,6 +12,11 @@
 use std::str::FromStr;
 
 use bitcoin::{psbt, AddressType, Psbt, TxIn, TxOut};
+#[cfg(all(feature = "v2", feature = "psbt-merge"))]
+#[cfg_attr(docsrs, doc(cfg(feature = "psbt-merge")))]
+pub use error::BatchError;
+#[cfg(all(feature = "v2", feature = "psbt-merge"))]
+pub(crate) use error::InternalBatchError;
 pub(crate) use error::InternalPayloadError;
 pub use error::{
     Error, ImplementationError, JsonError, OutputSubstitutionError, PayloadError, ReplyableError,
@@ -23,6 +28,8 @@ pub use crate::psbt::PsbtInputError;
 use crate::psbt::{InternalInputPair, InternalPsbtInputError, PsbtExt};
 
 mod error;
+#[cfg(feature = "bitcoinconsensus")]
+mod offline;
 pub(crate) mod optional_parameters;
 
 #[cfg(feature = "v1")]
@@ -41,11 +48,12 @@ pub mod v2;
 pub struct InputPair {
     pub(crate) txin: TxIn,
     pub(crate) psbtin: psbt::Input,
+    pub(crate) unconfirmed: bool,
 }
 
 impl InputPair {
     pub fn new(txin: TxIn, psbtin: psbt::Input) -> Result<Self, PsbtInputError> {
-        let input_pair = Self { txin, psbtin };
+        let input_pair = Self { txin, psbtin, unconfirmed: false };
         let raw = InternalInputPair::from(&input_pair);
         raw.validate_utxo()?;
         let address_type = raw.address_type().map_err(InternalPsbtInputError::AddressType)?;
@@ -55,6 +63,15 @@ impl InputPair {
         Ok(input_pair)
     }
 
+    /// Mark the input as spending an unconfirmed output.
+    ///
+    /// Unconfirmed inputs can't be contributed to a version 3 Original, since the TRUC policy
+    /// allows a transaction only one unconfirmed ancestor, which the Original may already spend.
+    pub fn unconfirmed(mut self, unconfirmed: bool) -> Self {
+        self.unconfirmed = unconfirmed;
+        self
+    }
+
     pub(crate) fn previous_txout(&self) -> TxOut {
         InternalInputPair::from(self)
             .previous_txout()
@@ -73,14 +90,18 @@ pub(crate) fn parse_payload(
     query: &str,
     supported_versions: &'static [usize],
 ) -> Result<(Psbt, Params), PayloadError> {
-    let unchecked_psbt = Psbt::from_str(&base64).map_err(InternalPayloadError::ParsePsbt)?;
+    let (unchecked_psbt, psbt_v2) = match crate::psbt::v2::decode_base64(&base64) {
+        Some(psbt) => (psbt.map_err(InternalPayloadError::ParsePsbtV2)?, true),
+        None => (Psbt::from_str(&base64).map_err(InternalPayloadError::ParsePsbt)?, false),
+    };
 
     let psbt = unchecked_psbt.validate().map_err(InternalPayloadError::InconsistentPsbt)?;
     log::debug!("Received original psbt: {:?}", psbt);
 
     let pairs = url::form_urlencoded::parse(query.as_bytes());
-    let params = Params::from_query_pairs(pairs, supported_versions)
+    let mut params = Params::from_query_pairs(pairs, supported_versions)
         .map_err(InternalPayloadError::SenderParams)?;
+    params.psbt_v2 = psbt_v2;
     log::debug!("Received request with params: {:?}", params);
 
     Ok((psbt, params))
@@ -0,0 +1,280 @@
+//! Validate an Original PSBT without access to a mempool
+//!
+//! Receivers without a node cannot ask `testmempoolaccept` whether the Original PSBT could be
+//! broadcast. These checks catch the same garbage a mempool would reject, short of knowing
+//! whether the inputs are still unspent.
+
+use std::fmt;
+
+use bitcoin::consensus::validation::BitcoinconsensusError;
+use bitcoin::opcodes::all::OP_PUSHNUM_1;
+use bitcoin::policy::MAX_STANDARD_TX_WEIGHT;
+use bitcoin::transaction::Version;
+use bitcoin::{Amount, FeeRate, OutPoint, Psbt, ScriptBuf, Weight};
+
+use super::InternalPayloadError;
+use crate::psbt::{PsbtExt, PsbtInputsError};
+use crate::truc;
+
+/// Minimum size of a standard transaction without witness data
+const MIN_STANDARD_TX_NONWITNESS_SIZE: usize = 65;
+
+/// Maximum size of a standard scriptSig
+const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1650;
+
+/// Maximum size of a standard OP_RETURN output script
+const MAX_OP_RETURN_RELAY: usize = 83;
+
+/// Maximum number of keys in a standard bare multisig output
+const MAX_STANDARD_MULTISIG_KEYS: u8 = 3;
+
+/// Check that the Original PSBT is a finalized, standard transaction whose inputs satisfy the
+/// scripts they spend and which pays at least `min_fee_rate`.
+pub(crate) fn check_original(
+    psbt: &Psbt,
+    min_fee_rate: FeeRate,
+) -> Result<(), InternalPayloadError> {
+    psbt.validate_input_utxos().map_err(OriginalPsbtError::InvalidInput)?;
+    for input in psbt.input_pairs() {
+        if input.psbtin.final_script_sig.is_none() && input.psbtin.final_script_witness.is_none() {
+            return Err(OriginalPsbtError::NotFinalized(input.txin.previous_output).into());
+        }
+    }
+
+    let tx = psbt.clone().extract_tx_unchecked_fee_rate();
+    check_standard(&tx)?;
+    if truc::is_truc(tx.version) {
+        truc::check_weight(tx.weight()).map_err(InternalPayloadError::Truc)?;
+    }
+
+    let spending_tx = bitcoin::consensus::serialize(&tx);
+    for (index, input) in psbt.input_pairs().enumerate() {
+        let txout = input.previous_txout().map_err(InternalPayloadError::PrevTxOut)?;
+        txout
+            .script_pubkey
+            .verify(index, txout.value, &spending_tx)
+            .map_err(|e| OriginalPsbtError::InvalidScript(input.txin.previous_output, e))?;
+    }
+
+    let fee = psbt.fee().map_err(OriginalPsbtError::InvalidFee)?;
+    let fee_rate = fee / tx.weight();
+    if fee_rate < min_fee_rate {
+        return Err(InternalPayloadError::PsbtBelowFeeRate(fee_rate, min_fee_rate));
+    }
+    Ok(())
+}
+
+/// Apply the relay policy of Bitcoin Core's `IsStandardTx`
+fn check_standard(tx: &bitcoin::Transaction) -> Result<(), OriginalPsbtError> {
+    use NonStandard::*;
+
+    if tx.version != Version::ONE && tx.version != Version::TWO && !truc::is_truc(tx.version) {
+        return Err(UnsupportedVersion(tx.version).into());
+    }
+    if tx.weight() > Weight::from_wu(MAX_STANDARD_TX_WEIGHT.into()) {
+        return Err(TooHeavy(tx.weight()).into());
+    }
+    if tx.base_size() < MIN_STANDARD_TX_NONWITNESS_SIZE {
+        return Err(TooSmall(tx.base_size()).into());
+    }
+    for txin in &tx.input {
+        if txin.script_sig.len() > MAX_STANDARD_SCRIPTSIG_SIZE || !txin.script_sig.is_push_only() {
+            return Err(ScriptSig(txin.previous_output).into());
+        }
+    }
+    let mut data_outputs = 0;
+    for txout in &tx.output {
+        let script = &txout.script_pubkey;
+        if script.is_op_return() {
+            if script.len() > MAX_OP_RETURN_RELAY {
+                return Err(OutputScript(script.clone()).into());
+            }
+            data_outputs += 1;
+        } else if script.is_p2pkh()
+            || script.is_p2sh()
+            || script.is_witness_program()
+            || script.is_p2pk()
+            || is_standard_multisig(script)
+        {
+            if txout.value < script.minimal_non_dust() {
+                return Err(DustOutput(txout.value).into());
+            }
+        } else {
+            return Err(OutputScript(script.clone()).into());
+        }
+    }
+    if data_outputs > 1 {
+        return Err(MultipleDataOutputs.into());
+    }
+    Ok(())
+}
+
+/// Whether `script` is a bare multisig output of at most 3 keys, the largest Bitcoin Core relays
+fn is_standard_multisig(script: &bitcoin::Script) -> bool {
+    let bytes = script.as_bytes();
+    let first_pushnum = OP_PUSHNUM_1.to_u8();
+    script.is_multisig()
+        && bytes.len() >= 2
+        && (first_pushnum..first_pushnum + MAX_STANDARD_MULTISIG_KEYS)
+            .contains(&bytes[bytes.len() - 2])
+}
+
+/// Why the Original PSBT failed offline validation
+#[derive(Debug)]
+pub(crate) enum OriginalPsbtError {
+    /// An input is missing or has inconsistent UTXO information
+    InvalidInput(PsbtInputsError),
+    /// An input has neither a final scriptSig nor a final witness
+    NotFinalized(OutPoint),
+    /// An input does not satisfy the script it spends
+    InvalidScript(OutPoint, BitcoinconsensusError),
+    /// The fee cannot be computed from the input and output amounts
+    InvalidFee(bitcoin::psbt::Error),
+    /// The transaction would not be relayed
+    NonStandard(NonStandard),
+}
+
+impl From<OriginalPsbtError> for InternalPayloadError {
+    fn from(value: OriginalPsbtError) -> Self { InternalPayloadError::OriginalPsbtInvalid(value) }
+}
+
+impl From<NonStandard> for OriginalPsbtError {
+    fn from(value: NonStandard) -> Self { OriginalPsbtError::NonStandard(value) }
+}
+
+impl fmt::Display for OriginalPsbtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use OriginalPsbtError::*;
+
+        match self {
+            InvalidInput(e) => write!(f, "{}", e),
+            NotFinalized(outpoint) => write!(f, "input {} is not finalized", outpoint),
+            InvalidScript(outpoint, e) =>
+                write!(f, "input {} does not satisfy the script it spends: {}", outpoint, e),
+            InvalidFee(e) => write!(f, "invalid fee: {}", e),
+            NonStandard(e) => write!(f, "non-standard transaction: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for OriginalPsbtError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use OriginalPsbtError::*;
+
+        match self {
+            InvalidInput(e) => Some(e),
+            NotFinalized(_) => None,
+            InvalidScript(_, e) => Some(e),
+            InvalidFee(e) => Some(e),
+            NonStandard(_) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum NonStandard {
+    UnsupportedVersion(Version),
+    TooHeavy(Weight),
+    TooSmall(usize),
+    ScriptSig(OutPoint),
+    OutputScript(ScriptBuf),
+    DustOutput(Amount),
+    MultipleDataOutputs,
+}
+
+impl fmt::Display for NonStandard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use NonStandard::*;
+
+        match self {
+            UnsupportedVersion(version) => write!(f, "unsupported version {}", version),
+            TooHeavy(weight) => write!(f, "weight {} exceeds {}", weight, MAX_STANDARD_TX_WEIGHT),
+            TooSmall(size) =>
+                write!(f, "non-witness size {} is below {}", size, MIN_STANDARD_TX_NONWITNESS_SIZE),
+            ScriptSig(outpoint) => write!(f, "input {} has a non-standard scriptSig", outpoint),
+            OutputScript(script) => write!(f, "output script {} is non-standard", script),
+            DustOutput(value) => write!(f, "output of {} is dust", value),
+            MultipleDataOutputs => write!(f, "more than one OP_RETURN output"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::receive::v1::test::ORIGINAL_PSBT;
+
+    #[test]
+    fn test_original_psbt_passes() {
+        let psbt = Psbt::from_str(ORIGINAL_PSBT).unwrap();
+        check_original(&psbt, FeeRate::BROADCAST_MIN).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_invalid_originals() {
+        let psbt = Psbt::from_str(ORIGINAL_PSBT).unwrap();
+
+        let mut unfinalized = psbt.clone();
+        unfinalized.inputs[0].final_script_sig = None;
+        unfinalized.inputs[0].final_script_witness = None;
+        assert!(matches!(
+            check_original(&unfinalized, FeeRate::ZERO),
+            Err(InternalPayloadError::OriginalPsbtInvalid(OriginalPsbtError::NotFinalized(_)))
+        ));
+
+        let mut wrong_amount = psbt.clone();
+        wrong_amount.inputs[0].witness_utxo.as_mut().unwrap().value += Amount::from_sat(1);
+        assert!(matches!(
+            check_original(&wrong_amount, FeeRate::ZERO),
+            Err(InternalPayloadError::OriginalPsbtInvalid(OriginalPsbtError::InvalidScript(..)))
+        ));
+
+        let mut dust = psbt.clone();
+        dust.unsigned_tx.output[0].value = Amount::from_sat(1);
+        assert!(matches!(
+            check_original(&dust, FeeRate::ZERO),
+            Err(InternalPayloadError::OriginalPsbtInvalid(OriginalPsbtError::NonStandard(
+                NonStandard::DustOutput(_)
+            )))
+        ));
+
+        assert!(matches!(
+            check_original(&psbt, FeeRate::from_sat_per_vb_unchecked(1000)),
+            Err(InternalPayloadError::PsbtBelowFeeRate(..))
+        ));
+    }
+
+    #[test]
+    fn test_accepts_bare_standard_outputs() {
+        use bitcoin::blockdata::script::Builder;
+        use bitcoin::opcodes::all::OP_CHECKMULTISIG;
+
+        let psbt = Psbt::from_str(ORIGINAL_PSBT).unwrap();
+        let pubkey = bitcoin::PublicKey::from_str(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        )
+        .unwrap();
+        let multisig = |keys: i64| {
+            let mut builder = Builder::new().push_int(1);
+            for _ in 0..keys {
+                builder = builder.push_key(&pubkey);
+            }
+            builder.push_int(keys).push_opcode(OP_CHECKMULTISIG).into_script()
+        };
+
+        let mut tx = psbt.unsigned_tx.clone();
+        tx.output[0].script_pubkey = ScriptBuf::new_p2pk(&pubkey);
+        check_standard(&tx).unwrap();
+
+        tx.output[0].script_pubkey = multisig(3);
+        check_standard(&tx).unwrap();
+
+        tx.output[0].script_pubkey = multisig(4);
+        assert!(matches!(
+            check_standard(&tx),
+            Err(OriginalPsbtError::NonStandard(NonStandard::OutputScript(_)))
+        ));
+    }
+}
@@ -14,6 +14,10 @@ pub(crate) struct Params {
     pub additional_fee_contribution: Option<(bitcoin::Amount, usize)>,
     // minfeerate
     pub min_fee_rate: FeeRate,
+    // batching
+    pub batching: bool,
+    // whether the Original PSBT is a PSBTv2, to respond in kind
+    pub psbt_v2: bool,
 }
 
 impl Default for Params {
@@ -23,6 +27,8 @@ impl Default for Params {
             disable_output_substitution: false,
             additional_fee_contribution: None,
             min_fee_rate: FeeRate::BROADCAST_MIN,
+            batching: false,
+            psbt_v2: false,
         }
     }
 }
@@ -84,6 +90,7 @@ impl Params {
                     },
                 ("disableoutputsubstitution", v) =>
                     params.disable_output_substitution = v == "true",
+                ("batching", v) => params.batching = v == "true",
                 _ => (),
             }
         }
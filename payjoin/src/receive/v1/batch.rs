@@ -0,0 +1,215 @@
+//! Merge the proposals of several senders into one Payjoin transaction
+//!
+//! Each sender checks the merged transaction as if it were its own Payjoin Proposal, so the
+//! merge must preserve the layout and fee rate every sender expects. The inputs of the other
+//! senders remain unsigned until they return their signatures, which only senders that opt in
+//! to batching accept.
+
+use bitcoin::{FeeRate, Psbt};
+
+use super::{
+    clear_sender_fields, sender_input_indexes, ImplementationError, PayjoinProposal,
+    ProvisionalProposal,
+};
+use crate::psbt::merge::merge_psbts;
+use crate::receive::{BatchError, InternalBatchError};
+use crate::truc;
+
+/// A sender's Payjoin Proposal and the indexes of the sender's inputs in the merged PSBT
+pub(crate) type BatchedSender = (PayjoinProposal, Vec<usize>);
+
+/// Apply fees to each of `proposals`, merge them and sign the result once.
+///
+/// Returns the merged PSBT signed by the receiver, along with a Payjoin Proposal for each sender
+/// in the order of `proposals` and the indexes of that sender's inputs in the merged PSBT.
+pub(crate) fn finalize_batch(
+    proposals: Vec<ProvisionalProposal>,
+    wallet_process_psbt: impl Fn(&Psbt) -> Result<Psbt, ImplementationError>,
+    min_fee_rate: Option<FeeRate>,
+    max_effective_fee_rate: Option<FeeRate>,
+) -> Result<(Psbt, Vec<BatchedSender>), BatchError> {
+    let mut psbts = Vec::with_capacity(proposals.len());
+    for (index, mut proposal) in proposals.iter().cloned().enumerate() {
+        if proposals.len() > 1 && !proposal.params.batching {
+            return Err(InternalBatchError::SenderDisallowsBatching(index).into());
+        }
+        let mut psbt = proposal
+            .apply_fee(min_fee_rate, max_effective_fee_rate)
+            .map_err(|e| InternalBatchError::Fee(index, e.into()))?
+            .clone();
+        // Remove now-invalid sender signatures before applying the receiver signatures, keeping
+        // the redeem script the other senders need to estimate the input's weight
+        for i in proposal.sender_input_indexes() {
+            let input = &mut psbt.inputs[i];
+            if let Some(script_sig) = &input.final_script_sig {
+                if input.redeem_script.is_none() {
+                    input.redeem_script = script_sig.redeem_script().map(ToOwned::to_owned);
+                }
+            }
+            input.final_script_sig = None;
+            input.final_script_witness = None;
+            input.tap_key_sig = None;
+        }
+        psbts.push(psbt);
+    }
+    let merged = merge_psbts(psbts).map_err(InternalBatchError::Merge)?;
+    check_layout(&merged, &proposals)?;
+    if truc::is_truc(merged.unsigned_tx.version) {
+        let originals: Vec<&Psbt> = proposals.iter().map(|p| &p.original_psbt).collect();
+        let weight =
+            truc::predicted_weight(&merged, &originals).map_err(InternalBatchError::InputWeight)?;
+        truc::check_weight(weight).map_err(InternalBatchError::Truc)?;
+    }
+
+    let psbt = wallet_process_psbt(&merged).map_err(InternalBatchError::Implementation)?;
+    if psbt.unsigned_tx.compute_txid() != merged.unsigned_tx.compute_txid() {
+        return Err(InternalBatchError::UnknownTransaction.into());
+    }
+    // Senders check the fee rate of the transaction without any sender signatures
+    let fee = psbt.fee().map_err(InternalBatchError::Psbt)?;
+    let fee_rate = fee / psbt.clone().extract_tx_unchecked_fee_rate().weight();
+    for (index, proposal) in proposals.iter().enumerate() {
+        if fee_rate < proposal.params.min_fee_rate {
+            return Err(InternalBatchError::FeeRateBelowMinimum(
+                index,
+                fee_rate,
+                proposal.params.min_fee_rate,
+            )
+            .into());
+        }
+    }
+
+    let payjoin_proposals = proposals
+        .into_iter()
+        .map(|proposal| {
+            let mut payjoin_psbt = psbt.clone();
+            let sender_inputs = sender_input_indexes(&proposal.original_psbt, &payjoin_psbt);
+            clear_sender_fields(&mut payjoin_psbt, &sender_inputs);
+            (PayjoinProposal { payjoin_psbt, params: proposal.params }, sender_inputs)
+        })
+        .collect();
+    Ok((psbt, payjoin_proposals))
+}
+
+/// Verify that every finalized input of `psbt` satisfies the script it spends.
+///
+/// libbitcoinconsensus predates taproot, so taproot key and script path spends pass unverified.
+#[cfg(feature = "bitcoinconsensus")]
+pub(crate) fn verify_scripts(psbt: &Psbt) -> Result<(), BatchError> {
+    use crate::psbt::PsbtExt;
+
+    let tx = psbt.clone().extract_tx_unchecked_fee_rate();
+    let spending_tx = bitcoin::consensus::serialize(&tx);
+    for (index, input) in psbt.input_pairs().enumerate() {
+        let outpoint = input.txin.previous_output;
+        let txout =
+            input.previous_txout().map_err(|e| InternalBatchError::PrevTxOut(outpoint, e))?;
+        txout
+            .script_pubkey
+            .verify(index, txout.value, &spending_tx)
+            .map_err(|e| InternalBatchError::ScriptVerification(outpoint, e))?;
+    }
+    Ok(())
+}
+
+/// Check that the merged transaction satisfies the layout checks of every sender.
+///
+/// Senders require every input not their own to share the sequence of their first input and
+/// their original inputs and outputs to appear in order.
+fn check_layout(merged: &Psbt, proposals: &[ProvisionalProposal]) -> Result<(), BatchError> {
+    let mut sequences = merged.unsigned_tx.input.iter().map(|txin| txin.sequence);
+    if let Some(first) = sequences.next() {
+        if sequences.any(|sequence| sequence != first) {
+            return Err(InternalBatchError::MixedSequence.into());
+        }
+    }
+    for (index, proposal) in proposals.iter().enumerate() {
+        let original = &proposal.original_psbt;
+        let inputs_in_order = sender_input_indexes(original, merged).len() == original.inputs.len();
+        let mut merged_outputs = merged.unsigned_tx.output.iter();
+        let outputs_in_order = original.unsigned_tx.output.iter().all(|original| {
+            merged_outputs.any(|merged| merged.script_pubkey == original.script_pubkey)
+        });
+        if !inputs_in_order || !outputs_in_order {
+            return Err(InternalBatchError::SenderLayout(index).into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::{Address, Network, OutPoint};
+
+    use super::*;
+    use crate::receive::v1::test::proposal_from_test_vector;
+
+    fn provisional_proposal(
+        outpoint: Option<OutPoint>,
+        batching: bool,
+    ) -> Result<ProvisionalProposal, Box<dyn std::error::Error>> {
+        let mut proposal = proposal_from_test_vector()?;
+        if let Some(outpoint) = outpoint {
+            proposal.psbt.unsigned_tx.input[0].previous_output = outpoint;
+        }
+        proposal.params.batching = batching;
+        let receiver = Address::from_str("3CZZi7aWFugaCdUCS15dgrUUViupmB8bVM")?
+            .require_network(Network::Bitcoin)?;
+        Ok(proposal
+            .assume_interactive_receiver()
+            .check_inputs_not_owned(|_| Ok(false))?
+            .check_no_inputs_seen_before(|_| Ok(false))?
+            .identify_receiver_outputs(|script| Ok(*script == receiver.script_pubkey()))?
+            .commit_outputs()
+            .commit_inputs())
+    }
+
+    #[test]
+    fn batches_senders_into_one_transaction() -> Result<(), Box<dyn std::error::Error>> {
+        let first = provisional_proposal(None, true)?;
+        let mut other_outpoint = first.original_psbt.unsigned_tx.input[0].previous_output;
+        other_outpoint.vout += 1;
+        let second = provisional_proposal(Some(other_outpoint), true)?;
+
+        let (psbt, proposals) =
+            finalize_batch(vec![first.clone(), second], |psbt| Ok(psbt.clone()), None, None)?;
+        assert_eq!(psbt.inputs.len(), 2);
+        // the payee and change outputs of both senders are shared
+        assert_eq!(psbt.outputs.len(), 2);
+        assert_eq!(proposals.len(), 2);
+        for (index, (proposal, sender_inputs)) in proposals.iter().enumerate() {
+            assert_eq!(sender_inputs, &vec![index]);
+            assert_eq!(proposal.psbt().unsigned_tx, psbt.unsigned_tx);
+            // each sender gets its own input stripped and the other sender's input intact
+            assert!(proposal.psbt().inputs[index].witness_utxo.is_none());
+            assert!(proposal.psbt().inputs[1 - index].witness_utxo.is_some());
+            assert!(proposal.psbt().inputs[1 - index].redeem_script.is_some());
+        }
+
+        let not_batching = provisional_proposal(Some(other_outpoint), false)?;
+        let error = finalize_batch(vec![first, not_batching], |psbt| Ok(psbt.clone()), None, None)
+            .expect_err("senders must opt in to batching");
+        assert!(matches!(error.0, InternalBatchError::SenderDisallowsBatching(1)));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_conflicting_senders() -> Result<(), Box<dyn std::error::Error>> {
+        let first = provisional_proposal(None, true)?;
+        let error =
+            finalize_batch(vec![first.clone(), first.clone()], |psbt| Ok(psbt.clone()), None, None)
+                .expect_err("the same input cannot be spent twice");
+        assert!(matches!(error.0, InternalBatchError::Merge(_)));
+
+        let mut other_outpoint = first.original_psbt.unsigned_tx.input[0].previous_output;
+        other_outpoint.vout += 1;
+        let mut second = provisional_proposal(Some(other_outpoint), true)?;
+        second.payjoin_psbt.unsigned_tx.input[0].sequence = bitcoin::Sequence::MAX;
+        let error = finalize_batch(vec![first, second], |psbt| Ok(psbt.clone()), None, None)
+            .expect_err("senders reject mixed sequences");
+        assert!(matches!(error.0, InternalBatchError::MixedSequence));
+        Ok(())
+    }
+}
@@ -13,13 +13,19 @@ pub trait Headers {
     fn get_header(&self, key: &str) -> Option<&str>;
 }
 
+/// Build a minimal v1 payjoin URI.
+///
+/// Use [`crate::PjUriBuilder`] to also set an amount, label, message or other BIP 21 parameters.
 pub fn build_v1_pj_uri<'a>(
     address: &bitcoin::Address,
     endpoint: impl IntoUrl,
     disable_output_substitution: bool,
 ) -> Result<crate::uri::PjUri<'a>, crate::into_url::Error> {
-    let extras =
-        crate::uri::PayjoinExtras { endpoint: endpoint.into_url()?, disable_output_substitution };
+    let extras = crate::uri::PayjoinExtras {
+        endpoint: endpoint.into_url()?,
+        disable_output_substitution,
+        extra_params: vec![],
+    };
     Ok(bitcoin_uri::Uri::with_extras(address.clone(), extras))
 }
 
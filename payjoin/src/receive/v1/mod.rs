@@ -41,7 +41,10 @@ use super::{
 };
 use crate::psbt::PsbtExt;
 use crate::receive::InternalPayloadError;
+use crate::truc;
 
+#[cfg(all(feature = "v2", feature = "psbt-merge"))]
+pub(crate) mod batch;
 #[cfg(feature = "v1")]
 mod exclusive;
 #[cfg(feature = "v1")]
@@ -89,6 +92,9 @@ impl UncheckedProposal {
     /// If a sender can make requests at will, they can learn which bitcoin the receiver owns at no cost.
     /// Broadcasting the Original PSBT after some time in the failure case makes incurs sender cost and prevents probing.
     ///
+    /// A version 3 Original must also satisfy the TRUC size limit of BIP 431. Violations are
+    /// reported as such rather than as a failed broadcast check.
+    ///
     /// Call this after checking downstream.
     pub fn check_broadcast_suitability(
         self,
@@ -105,15 +111,37 @@ impl UncheckedProposal {
                 .into());
             }
         }
-        if can_broadcast(&self.psbt.clone().extract_tx_unchecked_fee_rate())
-            .map_err(ReplyableError::Implementation)?
-        {
+        let original_tx = self.psbt.clone().extract_tx_unchecked_fee_rate();
+        if truc::is_truc(original_tx.version) {
+            truc::check_weight(original_tx.weight()).map_err(InternalPayloadError::Truc)?;
+        }
+        if can_broadcast(&original_tx).map_err(ReplyableError::Implementation)? {
             Ok(MaybeInputsOwned { psbt: self.psbt, params: self.params })
         } else {
             Err(InternalPayloadError::OriginalPsbtNotBroadcastable.into())
         }
     }
 
+    /// Check that the Original PSBT can be broadcast without access to a mempool.
+    ///
+    /// An alternative to [`Self::check_broadcast_suitability`] for receivers without a node.
+    /// The Original must be fully finalized, its inputs must satisfy the scripts of the UTXOs the
+    /// sender provided, and it must be standard and pay at least `min_fee_rate`, which defaults
+    /// to the minimum relay fee rate.
+    ///
+    /// Unlike `testmempoolaccept` this cannot tell whether the inputs are unspent, and taproot
+    /// spends are not verified since libbitcoinconsensus predates taproot.
+    #[cfg(feature = "bitcoinconsensus")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "bitcoinconsensus")))]
+    pub fn check_broadcast_suitability_offline(
+        self,
+        min_fee_rate: Option<FeeRate>,
+    ) -> Result<MaybeInputsOwned, ReplyableError> {
+        let min_fee_rate = min_fee_rate.unwrap_or(FeeRate::BROADCAST_MIN);
+        super::offline::check_original(&self.psbt, min_fee_rate)?;
+        Ok(MaybeInputsOwned { psbt: self.psbt, params: self.params })
+    }
+
     /// Call this method if the only way to initiate a Payjoin with this receiver
     /// requires manual intervention, as in most consumer wallets.
     ///
@@ -482,11 +510,17 @@ impl WantsInputs {
 
     /// Add the provided list of inputs to the transaction.
     /// Any excess input amount is added to the change_vout output indicated previously.
+    ///
+    /// A version 3 Original keeps the Payjoin transaction under the TRUC policy of BIP 431, so the
+    /// inputs must keep it within 10 kvB once signed. TRUC transactions may have only one
+    /// unconfirmed ancestor, which the Original may already spend, so inputs marked
+    /// [`InputPair::unconfirmed`] are rejected for them.
     pub fn contribute_inputs(
         self,
         inputs: impl IntoIterator<Item = InputPair>,
     ) -> Result<WantsInputs, InputContributionError> {
         let mut payjoin_psbt = self.payjoin_psbt.clone();
+        let truc = truc::is_truc(payjoin_psbt.unsigned_tx.version);
         // The payjoin proposal must not introduce mixed input sequence numbers
         let original_sequence = self
             .original_psbt
@@ -500,6 +534,13 @@ impl WantsInputs {
         let mut rng = rand::thread_rng();
         let mut receiver_input_amount = Amount::ZERO;
         for input_pair in inputs.into_iter() {
+            if truc && input_pair.unconfirmed {
+                let outpoint = input_pair.txin.previous_output;
+                return Err(InternalInputContributionError::Truc(
+                    truc::TrucViolation::UnconfirmedInput(outpoint),
+                )
+                .into());
+            }
             receiver_input_amount += input_pair.previous_txout().value;
             let index = rng.gen_range(0..=self.payjoin_psbt.unsigned_tx.input.len());
             payjoin_psbt.inputs.insert(index, input_pair.psbtin);
@@ -518,6 +559,12 @@ impl WantsInputs {
             return Err(InternalInputContributionError::ValueTooLow.into());
         }
 
+        if truc {
+            let weight = truc::predicted_weight(&payjoin_psbt, &[&self.original_psbt])
+                .map_err(InternalInputContributionError::InputWeight)?;
+            truc::check_weight(weight).map_err(InternalInputContributionError::Truc)?;
+        }
+
         Ok(WantsInputs {
             original_psbt: self.original_psbt,
             payjoin_psbt,
@@ -647,6 +694,11 @@ impl ProvisionalProposal {
             // Remove additional miner fee from the receiver's specified output
             self.payjoin_psbt.unsigned_tx.output[self.change_vout].value -= receiver_additional_fee;
         }
+        if truc::is_truc(self.payjoin_psbt.unsigned_tx.version) {
+            let weight = truc::predicted_weight(&self.payjoin_psbt, &[&self.original_psbt])
+                .map_err(InternalPayloadError::InputWeight)?;
+            truc::check_weight(weight).map_err(InternalPayloadError::Truc)?;
+        }
         Ok(&self.payjoin_psbt)
     }
 
@@ -693,48 +745,15 @@ impl ProvisionalProposal {
     fn prepare_psbt(mut self, processed_psbt: Psbt) -> PayjoinProposal {
         self.payjoin_psbt = processed_psbt;
         log::trace!("Preparing PSBT {:#?}", self.payjoin_psbt);
-        for output in self.payjoin_psbt.outputs_mut() {
-            output.bip32_derivation.clear();
-            output.tap_key_origins.clear();
-            output.tap_internal_key = None;
-        }
-        for input in self.payjoin_psbt.inputs_mut() {
-            input.bip32_derivation.clear();
-            input.tap_key_origins.clear();
-            input.tap_internal_key = None;
-            input.partial_sigs.clear();
-        }
-        for i in self.sender_input_indexes() {
-            log::trace!("Clearing sender input {}", i);
-            self.payjoin_psbt.inputs[i].non_witness_utxo = None;
-            self.payjoin_psbt.inputs[i].witness_utxo = None;
-            self.payjoin_psbt.inputs[i].final_script_sig = None;
-            self.payjoin_psbt.inputs[i].final_script_witness = None;
-            self.payjoin_psbt.inputs[i].tap_key_sig = None;
-        }
+        let sender_inputs = self.sender_input_indexes();
+        clear_sender_fields(&mut self.payjoin_psbt, &sender_inputs);
 
         PayjoinProposal { payjoin_psbt: self.payjoin_psbt, params: self.params }
     }
 
     /// Return the indexes of the sender inputs
     fn sender_input_indexes(&self) -> Vec<usize> {
-        // iterate proposal as mutable WITH the outpoint (previous_output) available too
-        let mut original_inputs = self.original_psbt.input_pairs().peekable();
-        let mut sender_input_indexes = vec![];
-        for (i, input) in self.payjoin_psbt.input_pairs().enumerate() {
-            if let Some(original) = original_inputs.peek() {
-                log::trace!(
-                    "match previous_output: {} == {}",
-                    input.txin.previous_output,
-                    original.txin.previous_output
-                );
-                if input.txin.previous_output == original.txin.previous_output {
-                    sender_input_indexes.push(i);
-                    original_inputs.next();
-                }
-            }
-        }
-        sender_input_indexes
+        sender_input_indexes(&self.original_psbt, &self.payjoin_psbt)
     }
 
     /// Return a Payjoin Proposal PSBT that the sender will find acceptable.
@@ -763,6 +782,49 @@ impl ProvisionalProposal {
     }
 }
 
+/// Clear the fields of `psbt` that the sender expects to be empty
+fn clear_sender_fields(psbt: &mut Psbt, sender_inputs: &[usize]) {
+    for output in psbt.outputs_mut() {
+        output.bip32_derivation.clear();
+        output.tap_key_origins.clear();
+        output.tap_internal_key = None;
+    }
+    for input in psbt.inputs_mut() {
+        input.bip32_derivation.clear();
+        input.tap_key_origins.clear();
+        input.tap_internal_key = None;
+        input.partial_sigs.clear();
+    }
+    for &i in sender_inputs {
+        log::trace!("Clearing sender input {}", i);
+        psbt.inputs[i].non_witness_utxo = None;
+        psbt.inputs[i].witness_utxo = None;
+        psbt.inputs[i].final_script_sig = None;
+        psbt.inputs[i].final_script_witness = None;
+        psbt.inputs[i].tap_key_sig = None;
+    }
+}
+
+/// Return the indexes in `payjoin_psbt` of the inputs of `original_psbt`
+fn sender_input_indexes(original_psbt: &Psbt, payjoin_psbt: &Psbt) -> Vec<usize> {
+    let mut original_inputs = original_psbt.input_pairs().peekable();
+    let mut sender_input_indexes = vec![];
+    for (i, input) in payjoin_psbt.input_pairs().enumerate() {
+        if let Some(original) = original_inputs.peek() {
+            log::trace!(
+                "match previous_output: {} == {}",
+                input.txin.previous_output,
+                original.txin.previous_output
+            );
+            if input.txin.previous_output == original.txin.previous_output {
+                sender_input_indexes.push(i);
+                original_inputs.next();
+            }
+        }
+    }
+    sender_input_indexes
+}
+
 /// A finalized payjoin proposal, complete with fees and receiver signatures, that the sender
 /// should find acceptable.
 #[derive(Debug, Clone)]
@@ -781,13 +843,24 @@ impl PayjoinProposal {
     }
 
     pub fn psbt(&self) -> &Psbt { &self.payjoin_psbt }
+
+    /// The Payjoin Proposal PSBT as base64, in the PSBT version of the Original PSBT
+    pub fn psbt_base64(&self) -> String {
+        crate::psbt::v2::encode_base64(&self.payjoin_psbt, self.params.psbt_v2)
+    }
+
+    /// The serialized Payjoin Proposal PSBT, in the PSBT version of the Original PSBT
+    #[cfg(feature = "v2")]
+    pub(crate) fn serialize_psbt(&self) -> Vec<u8> {
+        crate::psbt::v2::serialize_as(&self.payjoin_psbt, self.params.psbt_v2)
+    }
 }
 
 #[cfg(test)]
 pub(crate) mod test {
     use std::str::FromStr;
 
-    use bitcoin::{Address, Network};
+    use bitcoin::{Address, Network, ScriptBuf};
     use rand::rngs::StdRng;
     use rand::SeedableRng;
 
@@ -799,6 +872,8 @@ pub(crate) mod test {
     // | P2SH-P2WPKH     |  2 sat/vbyte          | 0.00000182                   | 0                       |
     pub const ORIGINAL_PSBT: &str = "cHNidP8BAHMCAAAAAY8nutGgJdyYGXWiBEb45Hoe9lWGbkxh/6bNiOJdCDuDAAAAAAD+////AtyVuAUAAAAAF6kUHehJ8GnSdBUOOv6ujXLrWmsJRDCHgIQeAAAAAAAXqRR3QJbbz0hnQ8IvQ0fptGn+votneofTAAAAAAEBIKgb1wUAAAAAF6kU3k4ekGHKWRNbA1rV5tR5kEVDVNCHAQcXFgAUx4pFclNVgo1WWAdN1SYNX8tphTABCGsCRzBEAiB8Q+A6dep+Rz92vhy26lT0AjZn4PRLi8Bf9qoB/CMk0wIgP/Rj2PWZ3gEjUkTlhDRNAQ0gXwTO7t9n+V14pZ6oljUBIQMVmsAaoNWHVMS02LfTSe0e388LNitPa1UQZyOihY+FFgABABYAFEb2Giu6c4KO5YW0pfw3lGp9jMUUAAA=";
     pub const QUERY_PARAMS: &str = "maxadditionalfeecontribution=182&additionalfeeoutputindex=0";
+    /// A Payjoin Proposal whose second input is the receiver's contribution
+    const RECEIVER_INPUT_CONTRIBUTION: &str = "cHNidP8BAJwCAAAAAo8nutGgJdyYGXWiBEb45Hoe9lWGbkxh/6bNiOJdCDuDAAAAAAD+////jye60aAl3JgZdaIERvjkeh72VYZuTGH/ps2I4l0IO4MBAAAAAP7///8CJpW4BQAAAAAXqRQd6EnwadJ0FQ46/q6NcutaawlEMIcACT0AAAAAABepFHdAltvPSGdDwi9DR+m0af6+i2d6h9MAAAAAAAEBIICEHgAAAAAAF6kUyPLL+cphRyyI5GTUazV0hF2R2NWHAQcXFgAUX4BmVeWSTJIEwtUb5TlPS/ntohABCGsCRzBEAiBnu3tA3yWlT0WBClsXXS9j69Bt+waCs9JcjWtNjtv7VgIge2VYAaBeLPDB6HGFlpqOENXMldsJezF9Gs5amvDQRDQBIQJl1jz1tBt8hNx2owTm+4Du4isx0pmdKNMNIjjaMHFfrQAAAA==";
 
     pub(crate) fn proposal_from_test_vector(
     ) -> Result<UncheckedProposal, Box<dyn std::error::Error>> {
@@ -847,6 +922,94 @@ pub(crate) mod test {
         }
     }
 
+    #[test]
+    fn truc_original_within_size_limit() {
+        let mut proposal = proposal_from_test_vector().unwrap();
+        proposal.psbt.unsigned_tx.version = truc::TRUC_VERSION;
+        assert!(proposal.clone().check_broadcast_suitability(None, |_| Ok(true)).is_ok());
+
+        // Pad the Original past the 10 kvB TRUC limit
+        let padding =
+            TxOut { value: Amount::ZERO, script_pubkey: ScriptBuf::new_op_return([0u8; 75]) };
+        for _ in 0..120 {
+            proposal.psbt.unsigned_tx.output.push(padding.clone());
+            proposal.psbt.outputs.push(Default::default());
+        }
+        match proposal.check_broadcast_suitability(None, |_| Ok(true)) {
+            Err(ReplyableError::Payload(crate::receive::PayloadError(
+                InternalPayloadError::Truc(_),
+            ))) => (),
+            _ => panic!("Expected a TRUC policy violation"),
+        }
+    }
+
+    #[test]
+    fn truc_original_rejects_unconfirmed_receiver_input() {
+        let mut proposal = proposal_from_test_vector().unwrap();
+        proposal.psbt.unsigned_tx.version = truc::TRUC_VERSION;
+        // Input contribution for the receiver, from the BIP78 test vector
+        let proposal_psbt = Psbt::from_str(RECEIVER_INPUT_CONTRIBUTION).unwrap();
+        let input = InputPair {
+            txin: proposal_psbt.unsigned_tx.input[1].clone(),
+            psbtin: proposal_psbt.inputs[1].clone(),
+            unconfirmed: false,
+        };
+        let receiver = Address::from_str("3CZZi7aWFugaCdUCS15dgrUUViupmB8bVM")
+            .unwrap()
+            .require_network(Network::Bitcoin)
+            .unwrap();
+        let wants_inputs = proposal
+            .assume_interactive_receiver()
+            .check_inputs_not_owned(|_| Ok(false))
+            .unwrap()
+            .check_no_inputs_seen_before(|_| Ok(false))
+            .unwrap()
+            .identify_receiver_outputs(|script| Ok(*script == receiver.script_pubkey()))
+            .unwrap()
+            .commit_outputs();
+
+        assert!(wants_inputs.clone().contribute_inputs(vec![input.clone()]).is_ok());
+        match wants_inputs.contribute_inputs(vec![input.clone().unconfirmed(true)]) {
+            Err(InputContributionError(InternalInputContributionError::Truc(
+                truc::TrucViolation::UnconfirmedInput(outpoint),
+            ))) => assert_eq!(outpoint, input.txin.previous_output),
+            _ => panic!("Expected an unconfirmed input to be rejected"),
+        }
+    }
+
+    #[test]
+    fn psbt_v2_original_gets_identical_proposal() {
+        let receiver = Address::from_str("3CZZi7aWFugaCdUCS15dgrUUViupmB8bVM")
+            .unwrap()
+            .require_network(Network::Bitcoin)
+            .unwrap();
+        let finalize = |base64: String| {
+            let (psbt, params) = crate::receive::parse_payload(base64, QUERY_PARAMS, &[1]).unwrap();
+            UncheckedProposal { psbt, params }
+                .assume_interactive_receiver()
+                .check_inputs_not_owned(|_| Ok(false))
+                .unwrap()
+                .check_no_inputs_seen_before(|_| Ok(false))
+                .unwrap()
+                .identify_receiver_outputs(|script| Ok(*script == receiver.script_pubkey()))
+                .unwrap()
+                .commit_outputs()
+                .commit_inputs()
+                .finalize_proposal(|psbt| Ok(psbt.clone()), None, None)
+                .unwrap()
+        };
+
+        let original = Psbt::from_str(ORIGINAL_PSBT).unwrap();
+        let v0 = finalize(ORIGINAL_PSBT.to_string());
+        let v2 = finalize(crate::psbt::v2::encode_base64(&original, true));
+        assert_eq!(v0.psbt(), v2.psbt());
+        assert_eq!(v0.psbt_base64(), v0.psbt().to_string());
+        let v2_proposal = crate::psbt::v2::decode_base64(&v2.psbt_base64())
+            .expect("a PSBTv2 Original gets a PSBTv2 Proposal")
+            .unwrap();
+        assert_eq!(&v2_proposal, v0.psbt());
+    }
+
     #[test]
     fn sender_specifies_excessive_fee_rate() {
         let mut proposal = proposal_from_test_vector().unwrap();
@@ -858,6 +1021,7 @@ pub(crate) mod test {
         let input = InputPair {
             txin: proposal_psbt.unsigned_tx.input[1].clone(),
             psbtin: proposal_psbt.inputs[1].clone(),
+            unconfirmed: false,
         };
         let mut payjoin = proposal
             .assume_interactive_receiver()
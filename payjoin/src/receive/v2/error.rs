@@ -3,7 +3,7 @@ use std::error;
 
 use super::Error::V2;
 use crate::hpke::HpkeError;
-use crate::ohttp::OhttpEncapsulationError;
+use crate::ohttp::{OhttpEncapsulationError, OhttpKeysStale};
 use crate::receive::error::Error;
 
 /// Error that may occur during a v2 session typestate change
@@ -17,6 +17,25 @@ impl From<InternalSessionError> for SessionError {
     fn from(value: InternalSessionError) -> Self { SessionError(value) }
 }
 
+impl SessionError {
+    /// The directory's OHTTP gateway rejected the session's OHTTP keys, if that is why the
+    /// request failed
+    pub fn ohttp_keys_stale(&self) -> Option<&OhttpKeysStale> {
+        match &self.0 {
+            InternalSessionError::OhttpKeysStale(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Whether the session expired, either by its own clock or because the directory no longer
+    /// keeps its mailbox
+    ///
+    /// An expired session can't receive anything more, so stop polling it.
+    pub fn is_expired(&self) -> bool {
+        matches!(&self.0, InternalSessionError::Expired(_) | InternalSessionError::MailboxExpired)
+    }
+}
+
 impl From<InternalSessionError> for Error {
     fn from(e: InternalSessionError) -> Self { V2(e.into()) }
 }
@@ -27,20 +46,37 @@ pub(crate) enum InternalSessionError {
     ParseUrl(crate::into_url::Error),
     /// The session has expired
     Expired(std::time::SystemTime),
+    /// The directory reported the session's mailbox as expired
+    MailboxExpired,
     /// OHTTP Encapsulation failed
     OhttpEncapsulation(OhttpEncapsulationError),
     /// Hybrid Public Key Encryption failed
     Hpke(HpkeError),
     /// Unexpected response size
     UnexpectedResponseSize(usize),
+    /// The OHTTP gateway rejected the OHTTP keys
+    OhttpKeysStale(OhttpKeysStale),
     /// Unexpected status code
     UnexpectedStatusCode(http::StatusCode),
+    /// The directory is not one this session publishes to
+    UnknownDirectory(url::Url),
 }
 
 impl From<crate::into_url::Error> for SessionError {
     fn from(e: crate::into_url::Error) -> Self { InternalSessionError::ParseUrl(e).into() }
 }
 
+impl InternalSessionError {
+    /// Classify a response `body` that isn't an encapsulated response
+    pub(crate) fn unexpected_response(body: &[u8]) -> Self {
+        if crate::ohttp::is_key_rejection(body) {
+            InternalSessionError::OhttpKeysStale(OhttpKeysStale)
+        } else {
+            InternalSessionError::UnexpectedResponseSize(body.len())
+        }
+    }
+}
+
 impl From<std::time::SystemTime> for Error {
     fn from(e: std::time::SystemTime) -> Self { InternalSessionError::Expired(e).into() }
 }
@@ -62,6 +98,7 @@ impl fmt::Display for SessionError {
         match &self.0 {
             ParseUrl(e) => write!(f, "URL parsing failed: {}", e),
             Expired(expiry) => write!(f, "Session expired at {:?}", expiry),
+            MailboxExpired => write!(f, "The directory expired the session's mailbox"),
             OhttpEncapsulation(e) => write!(f, "OHTTP Encapsulation Error: {}", e),
             Hpke(e) => write!(f, "Hpke decryption failed: {}", e),
             UnexpectedResponseSize(size) => write!(
@@ -70,7 +107,10 @@ impl fmt::Display for SessionError {
                 size,
                 crate::directory::ENCAPSULATED_MESSAGE_BYTES
             ),
+            OhttpKeysStale(e) => write!(f, "{}", e),
             UnexpectedStatusCode(status) => write!(f, "Unexpected status code: {}", status),
+            UnknownDirectory(directory) =>
+                write!(f, "Directory {} is not part of this session", directory),
         }
     }
 }
@@ -82,10 +122,13 @@ impl error::Error for SessionError {
         match &self.0 {
             ParseUrl(e) => Some(e),
             Expired(_) => None,
+            MailboxExpired => None,
             OhttpEncapsulation(e) => Some(e),
             Hpke(e) => Some(e),
             UnexpectedResponseSize(_) => None,
+            OhttpKeysStale(e) => Some(e),
             UnexpectedStatusCode(_) => None,
+            UnknownDirectory(_) => None,
         }
     }
 }
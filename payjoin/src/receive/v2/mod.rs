@@ -2,6 +2,7 @@
 use std::str::FromStr;
 use std::time::{Duration, SystemTime};
 
+use bitcoin::base64::prelude::{Engine as _, BASE64_STANDARD};
 use bitcoin::hashes::{sha256, Hash};
 use bitcoin::psbt::Psbt;
 use bitcoin::{Address, FeeRate, OutPoint, Script, TxOut};
@@ -17,8 +18,11 @@ use super::{
     ReplyableError, SelectionError,
 };
 use crate::hpke::{decrypt_message_a, encrypt_message_b, HpkeKeyPair, HpkePublicKey};
-use crate::ohttp::{ohttp_decapsulate, ohttp_encapsulate, OhttpEncapsulationError, OhttpKeys};
+use crate::multipart::{decode_payload, part_mailbox, Part, PartError, PartialPayload};
+use crate::ohttp::{ohttp_decapsulate, ohttp_encapsulate, OhttpKeys};
 use crate::receive::{parse_payload, InputPair};
+#[cfg(feature = "psbt-merge")]
+use crate::receive::{BatchError, InternalBatchError};
 use crate::uri::ShortId;
 use crate::{IntoUrl, IntoUrlError, Request};
 
@@ -35,9 +39,22 @@ struct SessionContext {
     directory: url::Url,
     subdirectory: Option<url::Url>,
     ohttp_keys: OhttpKeys,
+    /// Further directories the session publishes to, each with its own ohttp keys
+    #[serde(default)]
+    alternative_directories: Vec<(url::Url, OhttpKeys)>,
     expiry: SystemTime,
     s: HpkeKeyPair,
     e: Option<HpkePublicKey>,
+    /// How many messages the session accepts an Original PSBT split into
+    #[serde(default)]
+    max_messages: Option<u8>,
+    /// The parts of an Original PSBT received so far, and the directory holding the rest
+    #[serde(default)]
+    partial_original: Option<(url::Url, PartialPayload)>,
+    /// Whether the next poll for a missing part checks the session mailbox instead, so that a
+    /// sender who never posts the rest of its parts can't stall the session
+    #[serde(default)]
+    poll_session_mailbox: bool,
 }
 
 fn deserialize_address_assume_checked<'de, D>(deserializer: D) -> Result<Address, D::Error>
@@ -86,24 +103,87 @@ impl Receiver {
                 directory: directory.into_url()?,
                 subdirectory: None,
                 ohttp_keys,
+                alternative_directories: Vec::new(),
                 expiry: SystemTime::now()
                     + expire_after.unwrap_or(TWENTY_FOUR_HOURS_DEFAULT_EXPIRY),
                 s: HpkeKeyPair::gen_keypair(),
                 e: None,
+                max_messages: None,
+                partial_original: None,
+                poll_session_mailbox: false,
             },
         })
     }
 
+    /// Also publish the session's mailbox at `directory`, reached with `ohttp_keys`.
+    ///
+    /// Alternative directories are advertised in the [`Receiver::pj_uri`] so that senders may
+    /// fall back to them in order if the primary directory is unavailable. The receiver should
+    /// poll every one of [`Receiver::directories`].
+    pub fn with_alternative_directory(
+        mut self,
+        directory: impl IntoUrl,
+        ohttp_keys: OhttpKeys,
+    ) -> Result<Self, IntoUrlError> {
+        let directory = directory.into_url()?;
+        if directory != self.context.directory
+            && !self.context.alternative_directories.iter().any(|(d, _)| d == &directory)
+        {
+            self.context.alternative_directories.push((directory, ohttp_keys));
+        }
+        Ok(self)
+    }
+
+    /// Accept an Original PSBT split into up to `max_messages` messages.
+    ///
+    /// Original PSBTs with many inputs don't fit in a single message. The limit is advertised in
+    /// the [`Receiver::pj_uri`], and the remaining parts are fetched from the directory as the
+    /// receiver polls.
+    pub fn with_max_messages(mut self, max_messages: u8) -> Self {
+        self.context.max_messages = Some(max_messages);
+        self
+    }
+
+    /// Every directory this session publishes to, the primary directory first
+    pub fn directories(&self) -> Vec<Url> {
+        std::iter::once(self.context.directory.clone())
+            .chain(self.context.alternative_directories.iter().map(|(d, _)| d.clone()))
+            .collect()
+    }
+
+    /// Replace the OHTTP keys used to reach `directory` after its gateway reported them as
+    /// [`crate::OhttpKeysStale`].
+    ///
+    /// Payjoin URIs made afterwards advertise the new keys.
+    pub fn update_ohttp_keys(
+        &mut self,
+        directory: &Url,
+        ohttp_keys: OhttpKeys,
+    ) -> Result<(), SessionError> {
+        *self.ohttp_keys_for(directory)? = ohttp_keys;
+        Ok(())
+    }
+
     /// Extract an OHTTP Encapsulated HTTP GET request for the Original PSBT
     pub fn extract_req(
         &mut self,
         ohttp_relay: impl IntoUrl,
+    ) -> Result<(Request, ohttp::ClientResponse), Error> {
+        let directory = self.context.directory.clone();
+        self.extract_req_from(&directory, ohttp_relay)
+    }
+
+    /// Extract an OHTTP Encapsulated HTTP GET request for the Original PSBT from one of
+    /// [`Receiver::directories`]
+    pub fn extract_req_from(
+        &mut self,
+        directory: &Url,
+        ohttp_relay: impl IntoUrl,
     ) -> Result<(Request, ohttp::ClientResponse), Error> {
         if SystemTime::now() > self.context.expiry {
             return Err(InternalSessionError::Expired(self.context.expiry).into());
         }
-        let (body, ohttp_ctx) =
-            self.fallback_req_body().map_err(InternalSessionError::OhttpEncapsulation)?;
+        let (body, ohttp_ctx) = self.fallback_req_body(directory)?;
         let url = ohttp_relay.into_url().map_err(InternalSessionError::ParseUrl)?;
         let req = Request::new_v2(&url, &body);
         Ok((req, ohttp_ctx))
@@ -111,17 +191,62 @@ impl Receiver {
 
     /// The response can either be an UncheckedProposal or an ACCEPTED message
     /// indicating no UncheckedProposal is available yet.
+    ///
+    /// Fails with [`Error::Cancelled`] if the sender retracted its Original PSBT, in which case
+    /// any proposal made from it should be abandoned.
     pub fn process_res(
         &mut self,
         body: &[u8],
         context: ohttp::ClientResponse,
+    ) -> Result<Option<UncheckedProposal>, Error> {
+        let directory = self.context.directory.clone();
+        self.process_res_from(&directory, body, context)
+    }
+
+    /// Process the response to a request made with [`Receiver::extract_req_from`].
+    ///
+    /// A proposal found at an alternative directory is answered through that directory.
+    pub fn process_res_from(
+        &mut self,
+        directory: &Url,
+        body: &[u8],
+        context: ohttp::ClientResponse,
+    ) -> Result<Option<UncheckedProposal>, Error> {
+        let ohttp_keys = self.ohttp_keys_for(directory)?.clone();
+        let proposal = self.decapsulate_proposal(directory, body, context)?;
+        Ok(proposal.map(|mut proposal| {
+            proposal.context.directory = directory.clone();
+            proposal.context.ohttp_keys = ohttp_keys;
+            proposal
+        }))
+    }
+
+    fn ohttp_keys_for(&mut self, directory: &Url) -> Result<&mut OhttpKeys, InternalSessionError> {
+        if directory == &self.context.directory {
+            return Ok(&mut self.context.ohttp_keys);
+        }
+        self.context
+            .alternative_directories
+            .iter_mut()
+            .find(|(d, _)| d == directory)
+            .map(|(_, ohttp_keys)| ohttp_keys)
+            .ok_or_else(|| InternalSessionError::UnknownDirectory(directory.clone()))
+    }
+
+    fn decapsulate_proposal(
+        &mut self,
+        directory: &Url,
+        body: &[u8],
+        context: ohttp::ClientResponse,
     ) -> Result<Option<UncheckedProposal>, Error> {
         let response_array: &[u8; crate::directory::ENCAPSULATED_MESSAGE_BYTES] =
-            body.try_into()
-                .map_err(|_| InternalSessionError::UnexpectedResponseSize(body.len()))?;
+            body.try_into().map_err(|_| InternalSessionError::unexpected_response(body))?;
         log::trace!("decapsulating directory response");
         let response = ohttp_decapsulate(context, response_array)
             .map_err(InternalSessionError::OhttpEncapsulation)?;
+        if response.status() == http::StatusCode::GONE {
+            return Err(InternalSessionError::MailboxExpired.into());
+        }
         if response.body().is_empty() {
             log::debug!("response is empty");
             return Ok(None);
@@ -130,18 +255,38 @@ impl Receiver {
             // V1 response bodies are utf8 plaintext
             Ok(response) => Ok(Some(self.extract_proposal_from_v1(response)?)),
             // V2 response bodies are encrypted binary
-            Err(_) => Ok(Some(self.extract_proposal_from_v2(response.body().to_vec())?)),
+            Err(_) => self.extract_proposal_from_v2(directory, response.body().to_vec()),
         }
     }
 
     fn fallback_req_body(
         &mut self,
-    ) -> Result<
-        ([u8; crate::directory::ENCAPSULATED_MESSAGE_BYTES], ohttp::ClientResponse),
-        OhttpEncapsulationError,
-    > {
-        let fallback_target = subdir(&self.context.directory, &self.id());
-        ohttp_encapsulate(&mut self.context.ohttp_keys, "GET", fallback_target.as_str(), None)
+        directory: &Url,
+    ) -> Result<([u8; crate::directory::ENCAPSULATED_MESSAGE_BYTES], ohttp::ClientResponse), Error>
+    {
+        // Fetch the rest of a split Original PSBT, alternating with the session mailbox where a
+        // new Original PSBT would arrive
+        let missing_part = match &self.context.partial_original {
+            Some((parts_directory, partial)) if parts_directory == directory => partial
+                .next_missing()
+                .map(|index| part_mailbox(self.context.s.public_key(), &partial.reply_key, index)),
+            _ => None,
+        };
+        let mailbox = match missing_part {
+            Some(part_mailbox) if !self.context.poll_session_mailbox => {
+                self.context.poll_session_mailbox = true;
+                part_mailbox
+            }
+            Some(_) => {
+                self.context.poll_session_mailbox = false;
+                self.id()
+            }
+            None => self.id(),
+        };
+        let fallback_target = subdir(directory, &mailbox);
+        let ohttp_keys = self.ohttp_keys_for(directory)?;
+        Ok(ohttp_encapsulate(ohttp_keys, "GET", fallback_target.as_str(), None)
+            .map_err(InternalSessionError::OhttpEncapsulation)?)
     }
 
     fn extract_proposal_from_v1(
@@ -151,12 +296,81 @@ impl Receiver {
         self.unchecked_from_payload(response)
     }
 
-    fn extract_proposal_from_v2(&mut self, response: Vec<u8>) -> Result<UncheckedProposal, Error> {
+    fn extract_proposal_from_v2(
+        &mut self,
+        directory: &Url,
+        response: Vec<u8>,
+    ) -> Result<Option<UncheckedProposal>, Error> {
         let (payload_bytes, e) = decrypt_message_a(&response, self.context.s.secret_key().clone())?;
+        if is_cancellation(&payload_bytes) {
+            // Only the sender of the Original PSBT holds its reply key. Before an Original
+            // arrived, anyone who read the receiver key from the URI could send one.
+            match &self.context.e {
+                Some(known) if known == &e => return Err(Error::Cancelled),
+                Some(_) => log::warn!("Ignoring a cancellation from an unknown sender"),
+                None => log::warn!("Ignoring a cancellation received before any Original PSBT"),
+            }
+            return Ok(None);
+        }
+        if let Some(part) = Part::parse(&payload_bytes) {
+            return self.receive_part(directory, part, e);
+        }
+        // A complete Original PSBT supersedes one whose parts are still missing
+        self.context.partial_original = None;
         self.context.e = Some(e);
         let payload = String::from_utf8(payload_bytes)
             .map_err(|e| Error::ReplyToSender(InternalPayloadError::Utf8(e).into()))?;
-        self.unchecked_from_payload(payload).map_err(Error::ReplyToSender)
+        self.unchecked_from_payload(payload).map(Some).map_err(Error::ReplyToSender)
+    }
+
+    /// Collect a part of a split Original PSBT, returning the proposal once every part arrived
+    fn receive_part(
+        &mut self,
+        directory: &Url,
+        part: Result<Part, PartError>,
+        e: HpkePublicKey,
+    ) -> Result<Option<UncheckedProposal>, Error> {
+        // A new first part replaces parts from another sender that may never be completed
+        let is_first = matches!(&part, Ok(part) if part.index == 0);
+        match &self.context.partial_original {
+            Some((_, partial)) if partial.reply_key != e && is_first => {
+                log::warn!("Abandoning an incomplete Original PSBT for a new one");
+                self.context.partial_original = None;
+                self.context.e = Some(e.clone());
+            }
+            Some((_, partial)) if partial.reply_key != e => {
+                log::warn!("Ignoring a message part from an unknown sender");
+                return Ok(None);
+            }
+            // Errors are replied to the sender of the first part
+            None => self.context.e = Some(e.clone()),
+            Some(_) => (),
+        }
+        let part = part.map_err(reject_parts)?;
+        let max_messages = self.context.max_messages.unwrap_or(0);
+        if part.count > max_messages {
+            return Err(reject_parts(PartError::TooManyParts {
+                count: part.count as usize,
+                max: max_messages,
+            }));
+        }
+        match &mut self.context.partial_original {
+            Some((_, partial)) => partial.insert(part).map_err(reject_parts)?,
+            None => {
+                let partial = PartialPayload::new(part, e).map_err(reject_parts)?;
+                self.context.partial_original = Some((directory.clone(), partial));
+            }
+        }
+        let payload = match self.context.partial_original.as_ref().and_then(|(_, p)| p.assemble()) {
+            Some(payload) => payload,
+            None => return Ok(None),
+        };
+        self.context.partial_original = None;
+        let (psbt, query) = decode_payload(&payload).map_err(reject_parts)?;
+        let base64 = BASE64_STANDARD.encode(psbt);
+        self.unchecked_from_payload(format!("{}\n{}", base64, query))
+            .map(Some)
+            .map_err(Error::ReplyToSender)
     }
 
     fn unchecked_from_payload(
@@ -187,13 +401,35 @@ impl Receiver {
 
     /// Build a V2 Payjoin URI from the receiver's context
     pub fn pj_uri<'a>(&self) -> crate::PjUri<'a> {
-        use crate::uri::{PayjoinExtras, UrlExt};
-        let mut pj = subdir(&self.context.directory, &self.id()).clone();
+        use crate::uri::PayjoinExtras;
+        let extras = PayjoinExtras {
+            endpoint: self.pj_endpoint(),
+            disable_output_substitution: false,
+            extra_params: vec![],
+        };
+        bitcoin_uri::Uri::with_extras(self.context.address.clone(), extras)
+    }
+
+    /// Start building a V2 Payjoin URI from the receiver's context, to add an amount, label,
+    /// message or other BIP 21 parameters.
+    ///
+    /// An expiry set on the builder only changes what is advertised to the sender. It should not
+    /// be later than the session's own expiry.
+    pub fn pj_uri_builder(&self) -> crate::PjUriBuilder {
+        crate::PjUriBuilder::new(self.context.address.clone(), self.pj_endpoint())
+    }
+
+    fn pj_endpoint(&self) -> Url {
+        use crate::uri::UrlExt;
+        let mut pj = subdir(&self.context.directory, &self.id());
         pj.set_receiver_pubkey(self.context.s.public_key().clone());
         pj.set_ohttp(self.context.ohttp_keys.clone());
         pj.set_exp(self.context.expiry);
-        let extras = PayjoinExtras { endpoint: pj, disable_output_substitution: false };
-        bitcoin_uri::Uri::with_extras(self.context.address.clone(), extras)
+        pj.set_alternative_directories(&self.context.alternative_directories);
+        if let Some(max_messages) = self.context.max_messages {
+            pj.set_max_messages(max_messages);
+        }
+        pj
     }
 
     /// The per-session identifier
@@ -242,6 +478,19 @@ impl UncheckedProposal {
         Ok(MaybeInputsOwned { v1: inner, context: self.context })
     }
 
+    /// Check that the Original PSBT can be broadcast without access to a mempool.
+    ///
+    /// See [`v1::UncheckedProposal::check_broadcast_suitability_offline`].
+    #[cfg(feature = "bitcoinconsensus")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "bitcoinconsensus")))]
+    pub fn check_broadcast_suitability_offline(
+        self,
+        min_fee_rate: Option<FeeRate>,
+    ) -> Result<MaybeInputsOwned, ReplyableError> {
+        let inner = self.v1.check_broadcast_suitability_offline(min_fee_rate)?;
+        Ok(MaybeInputsOwned { v1: inner, context: self.context })
+    }
+
     /// Call this method if the only way to initiate a Payjoin with this receiver
     /// requires manual intervention, as in most consumer wallets.
     ///
@@ -252,6 +501,12 @@ impl UncheckedProposal {
         MaybeInputsOwned { v1: inner, context: self.context }
     }
 
+    /// Replace the OHTTP keys used to reach the directory after its gateway reported them as
+    /// [`crate::OhttpKeysStale`]
+    pub fn update_ohttp_keys(&mut self, ohttp_keys: OhttpKeys) {
+        self.context.ohttp_keys = ohttp_keys;
+    }
+
     /// Extract an OHTTP Encapsulated HTTP POST request to return
     /// a Receiver Error Response
     pub fn extract_err_req(
@@ -280,8 +535,7 @@ impl UncheckedProposal {
         context: ohttp::ClientResponse,
     ) -> Result<(), SessionError> {
         let response_array: &[u8; crate::directory::ENCAPSULATED_MESSAGE_BYTES] =
-            body.try_into()
-                .map_err(|_| InternalSessionError::UnexpectedResponseSize(body.len()))?;
+            body.try_into().map_err(|_| InternalSessionError::unexpected_response(body))?;
         let response = ohttp_decapsulate(context, response_array)
             .map_err(InternalSessionError::OhttpEncapsulation)?;
 
@@ -484,6 +738,15 @@ impl PayjoinProposal {
 
     pub fn psbt(&self) -> &Psbt { self.v1.psbt() }
 
+    /// The Payjoin Proposal PSBT as base64, in the PSBT version of the Original PSBT
+    pub fn psbt_base64(&self) -> String { self.v1.psbt_base64() }
+
+    /// Replace the OHTTP keys used to reach the directory after its gateway reported them as
+    /// [`crate::OhttpKeysStale`]
+    pub fn update_ohttp_keys(&mut self, ohttp_keys: OhttpKeys) {
+        self.context.ohttp_keys = ohttp_keys;
+    }
+
     pub fn extract_v2_req(
         &mut self,
         ohttp_relay: impl IntoUrl,
@@ -494,7 +757,7 @@ impl PayjoinProposal {
 
         if let Some(e) = &self.context.e {
             // Prepare v2 payload
-            let payjoin_bytes = self.v1.psbt().serialize();
+            let payjoin_bytes = self.v1.serialize_psbt();
             let sender_subdir = subdir_path_from_pubkey(e);
             target_resource = self
                 .context
@@ -505,7 +768,7 @@ impl PayjoinProposal {
             method = "POST";
         } else {
             // Prepare v2 wrapped and backwards-compatible v1 payload
-            body = self.v1.psbt().to_string().as_bytes().to_vec();
+            body = self.v1.psbt_base64().into_bytes();
             let receiver_subdir = subdir_path_from_pubkey(self.context.s.public_key());
             target_resource = self
                 .context
@@ -540,7 +803,7 @@ impl PayjoinProposal {
         ohttp_context: ohttp::ClientResponse,
     ) -> Result<(), Error> {
         let response_array: &[u8; crate::directory::ENCAPSULATED_MESSAGE_BYTES] =
-            res.try_into().map_err(|_| InternalSessionError::UnexpectedResponseSize(res.len()))?;
+            res.try_into().map_err(|_| InternalSessionError::unexpected_response(res))?;
         let res = ohttp_decapsulate(ohttp_context, response_array)
             .map_err(InternalSessionError::OhttpEncapsulation)?;
         if res.status().is_success() {
@@ -549,8 +812,216 @@ impl PayjoinProposal {
             Err(InternalSessionError::UnexpectedStatusCode(res.status()).into())
         }
     }
+
+    /// Extract an OHTTP Encapsulated HTTP GET request for the sender's feedback on this
+    /// proposal.
+    ///
+    /// Feedback is optional and only v2 senders can give it, so a receiver should not wait on
+    /// it for longer than it would otherwise.
+    pub fn extract_feedback_req(
+        &mut self,
+        ohttp_relay: impl IntoUrl,
+    ) -> Result<(Request, ohttp::ClientResponse), Error> {
+        let mailbox = subdir(&self.context.directory, &id(&self.context.s));
+        let (body, ctx) =
+            ohttp_encapsulate(&mut self.context.ohttp_keys, "GET", mailbox.as_str(), None)?;
+        let url = ohttp_relay.into_url().map_err(InternalSessionError::ParseUrl)?;
+        Ok((Request::new_v2(&url, &body), ctx))
+    }
+
+    /// Process the response to [`PayjoinProposal::extract_feedback_req`].
+    ///
+    /// Returns `None` until the sender has accepted or rejected the proposal.
+    pub fn process_feedback_res(
+        &self,
+        body: &[u8],
+        context: ohttp::ClientResponse,
+    ) -> Result<Option<SenderFeedback>, Error> {
+        let response_array: &[u8; crate::directory::ENCAPSULATED_MESSAGE_BYTES] =
+            body.try_into().map_err(|_| InternalSessionError::unexpected_response(body))?;
+        let response = ohttp_decapsulate(context, response_array)
+            .map_err(InternalSessionError::OhttpEncapsulation)?;
+        if response.status() == http::StatusCode::GONE {
+            return Err(InternalSessionError::MailboxExpired.into());
+        }
+        if response.body().is_empty() {
+            return Ok(None);
+        }
+        self.context.sender_feedback(response.body())
+    }
+}
+
+/// What the sender made of a [`PayjoinProposal`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SenderFeedback {
+    /// The sender accepted the proposal and is expected to sign and broadcast it
+    Accepted,
+    /// The sender accepted a batched proposal and returned it with its inputs signed.
+    ///
+    /// Add the signatures with [`BatchedProposal::add_sender_signatures`].
+    Signed(Psbt),
+    /// The sender rejected the proposal, so the inputs contributed to it may be released.
+    ///
+    /// `reason` names the check the proposal failed, e.g. `versions-dont-match`, or is
+    /// `cancelled` if the sender retracted its Original PSBT. It is meant for diagnostics only.
+    Rejected { reason: String },
+}
+
+/// The proposals of several senders merged into one Payjoin transaction.
+///
+/// Each sender receives the inputs of the others unsigned, which only senders that opted in to
+/// batching accept. Respond to each sender with its [`PayjoinProposal`], add the signatures they
+/// return as [`SenderFeedback::Signed`] and broadcast the transaction once all have signed.
+/// Should any sender fail to sign, broadcast the Original transactions instead.
+#[cfg(feature = "psbt-merge")]
+#[cfg_attr(docsrs, doc(cfg(feature = "psbt-merge")))]
+#[derive(Clone)]
+pub struct BatchedProposal {
+    psbt: Psbt,
+    proposals: Vec<PayjoinProposal>,
+    /// The indexes of each sender's inputs in `psbt`, in the order of `proposals`
+    sender_inputs: Vec<Vec<usize>>,
+}
+
+#[cfg(feature = "psbt-merge")]
+impl BatchedProposal {
+    /// Merge `proposals` from different sessions into one transaction signed by the receiver.
+    ///
+    /// The fees of each proposal are applied as by [`ProvisionalProposal::finalize_proposal`]
+    /// before merging, and `wallet_process_psbt` signs the merged transaction once. Fails if a
+    /// sender did not opt in to batching or the merged transaction would fail a sender's checks.
+    pub fn finalize(
+        proposals: impl IntoIterator<Item = ProvisionalProposal>,
+        wallet_process_psbt: impl Fn(&Psbt) -> Result<Psbt, ImplementationError>,
+        min_fee_rate: Option<FeeRate>,
+        max_effective_fee_rate: Option<FeeRate>,
+    ) -> Result<Self, BatchError> {
+        let (proposals, contexts): (Vec<_>, Vec<_>) =
+            proposals.into_iter().map(|proposal| (proposal.v1, proposal.context)).unzip();
+        let (psbt, proposals) = v1::batch::finalize_batch(
+            proposals,
+            wallet_process_psbt,
+            min_fee_rate,
+            max_effective_fee_rate,
+        )?;
+        let (proposals, sender_inputs) = proposals
+            .into_iter()
+            .zip(contexts)
+            .map(|((v1, sender_inputs), context)| (PayjoinProposal { v1, context }, sender_inputs))
+            .unzip();
+        Ok(Self { psbt, proposals, sender_inputs })
+    }
+
+    /// The Payjoin Proposal for each sender, in the order the proposals were batched
+    pub fn proposals_mut(&mut self) -> &mut [PayjoinProposal] { &mut self.proposals }
+
+    /// The batched transaction with the signatures collected so far
+    pub fn psbt(&self) -> &Psbt { &self.psbt }
+
+    /// Add the signatures of a sender's [`SenderFeedback::Signed`] PSBT.
+    ///
+    /// `sender` is the index of the sender's proposal in [`BatchedProposal::proposals_mut`].
+    /// Only the scripts of that sender's own inputs are taken. The PSBT is rejected if it
+    /// carries scripts for any other input that differ from those already in the batch.
+    pub fn add_sender_signatures(
+        &mut self,
+        sender: usize,
+        signed: &Psbt,
+    ) -> Result<(), BatchError> {
+        let own_inputs =
+            self.sender_inputs.get(sender).ok_or(InternalBatchError::UnknownSender(sender))?;
+        if signed.unsigned_tx.compute_txid() != self.psbt.unsigned_tx.compute_txid() {
+            return Err(InternalBatchError::UnknownTransaction.into());
+        }
+        for (index, (input, signed)) in self.psbt.inputs.iter().zip(&signed.inputs).enumerate() {
+            let foreign_script_sig = signed.final_script_sig.is_some()
+                && signed.final_script_sig != input.final_script_sig;
+            let foreign_witness = signed.final_script_witness.is_some()
+                && signed.final_script_witness != input.final_script_witness;
+            if !own_inputs.contains(&index) && (foreign_script_sig || foreign_witness) {
+                let outpoint = self.psbt.unsigned_tx.input[index].previous_output;
+                return Err(InternalBatchError::ForeignInputSigned(sender, outpoint).into());
+            }
+        }
+        for &index in own_inputs {
+            if let Some(signed) = signed.inputs.get(index) {
+                let input = &mut self.psbt.inputs[index];
+                if signed.final_script_sig.is_some() || signed.final_script_witness.is_some() {
+                    input.final_script_sig = signed.final_script_sig.clone();
+                    input.final_script_witness = signed.final_script_witness.clone();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Extract the batched transaction once every sender has signed it.
+    ///
+    /// With the `bitcoinconsensus` feature the scripts of every input are verified first.
+    pub fn extract_tx(&self) -> Result<bitcoin::Transaction, BatchError> {
+        for (txin, input) in self.psbt.unsigned_tx.input.iter().zip(&self.psbt.inputs) {
+            if input.final_script_sig.is_none() && input.final_script_witness.is_none() {
+                return Err(InternalBatchError::MissingSignature(txin.previous_output).into());
+            }
+        }
+        #[cfg(feature = "bitcoinconsensus")]
+        v1::batch::verify_scripts(&self.psbt)?;
+        Ok(self.psbt.clone().extract_tx_unchecked_fee_rate())
+    }
 }
 
+impl SessionContext {
+    /// Read the sender's feedback from the message in the session's mailbox
+    fn sender_feedback(&self, message_a: &[u8]) -> Result<Option<SenderFeedback>, Error> {
+        let sender = match &self.e {
+            Some(e) => e,
+            // v1 senders cannot give feedback
+            None => return Ok(None),
+        };
+        let (payload, e) = decrypt_message_a(message_a, self.s.secret_key().clone())?;
+        if &e != sender {
+            log::warn!("Ignoring feedback from an unknown sender");
+            return Ok(None);
+        }
+        if is_cancellation(&payload) {
+            return Ok(Some(SenderFeedback::Rejected { reason: "cancelled".to_string() }));
+        }
+        // Until the sender replies the mailbox still holds its Original PSBT
+        let json: serde_json::Value = match serde_json::from_slice(unpad(&payload)) {
+            Ok(json) => json,
+            Err(_) => return Ok(None),
+        };
+        Ok(match json.get("feedback").and_then(|f| f.as_str()) {
+            Some("ack") => match json.get("psbt").and_then(|p| p.as_str()) {
+                Some(psbt) => match Psbt::from_str(psbt) {
+                    Ok(psbt) => Some(SenderFeedback::Signed(psbt)),
+                    Err(e) => {
+                        log::warn!("Ignoring malformed signed PSBT from the sender: {}", e);
+                        None
+                    }
+                },
+                None => Some(SenderFeedback::Accepted),
+            },
+            Some("nack") => Some(SenderFeedback::Rejected {
+                reason: json.get("reason").and_then(|r| r.as_str()).unwrap_or_default().to_string(),
+            }),
+            _ => None,
+        })
+    }
+}
+
+/// Strip the zero padding of a decrypted message
+fn unpad(payload: &[u8]) -> &[u8] {
+    let len = payload.iter().rposition(|b| *b != 0).map_or(0, |last| last + 1);
+    &payload[..len]
+}
+
+fn reject_parts(e: PartError) -> Error {
+    Error::ReplyToSender(InternalPayloadError::Parts(e).into())
+}
+
+fn is_cancellation(payload: &[u8]) -> bool { unpad(payload) == crate::hpke::CANCEL_PAYLOAD }
+
 /// The subdirectory for this Payjoin receiver session.
 /// It consists of a directory URL and the session ShortID in the path.
 fn subdir(directory: &Url, id: &ShortId) -> Url {
@@ -596,9 +1067,13 @@ mod test {
         ohttp_keys: OhttpKeys(
             ohttp::KeyConfig::new(KEY_ID, KEM, Vec::from(SYMMETRIC)).expect("valid key config"),
         ),
+        alternative_directories: vec![],
         expiry: SystemTime::now() + Duration::from_secs(60),
         s: HpkeKeyPair::gen_keypair(),
         e: None,
+        max_messages: None,
+        partial_original: None,
+        poll_session_mailbox: false,
     });
 
     #[test]
@@ -624,6 +1099,243 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn sender_cancellation() -> Result<(), BoxError> {
+        use crate::hpke::{encrypt_message_a, CANCEL_PAYLOAD};
+
+        let mut receiver = Receiver { context: SHARED_CONTEXT.clone() };
+        let sender = HpkeKeyPair::gen_keypair();
+        let cancel = encrypt_message_a(
+            CANCEL_PAYLOAD.to_vec(),
+            sender.public_key(),
+            receiver.context.s.public_key(),
+        )?;
+        // A cancellation before any Original PSBT can't come from its sender and is ignored
+        assert!(receiver.extract_proposal_from_v2(&EXAMPLE_URL, cancel.clone())?.is_none());
+
+        receiver.context.e = Some(sender.public_key().clone());
+        assert!(matches!(
+            receiver.extract_proposal_from_v2(&EXAMPLE_URL, cancel.clone()),
+            Err(Error::Cancelled)
+        ));
+
+        // A cancellation under a reply key other than the Original's is ignored
+        receiver.context.e = Some(HpkeKeyPair::gen_keypair().public_key().clone());
+        assert!(receiver.extract_proposal_from_v2(&EXAMPLE_URL, cancel)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn expired_mailbox_ends_session() -> Result<(), BoxError> {
+        let config = ohttp::KeyConfig::new(KEY_ID, KEM, Vec::from(SYMMETRIC))?;
+        let server = ohttp::Server::new(config.clone())?;
+        let mut receiver = Receiver { context: SHARED_CONTEXT.clone() };
+        receiver.context.ohttp_keys = OhttpKeys(config);
+
+        // The directory answers a poll of an expired mailbox with 410 Gone
+        let (req, ohttp_ctx) = receiver.fallback_req_body(&EXAMPLE_URL)?;
+        let (_, res_ctx) = server.decapsulate(&req)?;
+        let mut bhttp_res = Vec::new();
+        bhttp::Message::response(410).write_bhttp(bhttp::Mode::KnownLength, &mut bhttp_res)?;
+        // Padded like the directory does, leaving room for the response nonce and AEAD tag
+        bhttp_res.resize(crate::directory::ENCAPSULATED_MESSAGE_BYTES - (32 + 16), 0);
+        let res = res_ctx.encapsulate(&bhttp_res)?;
+
+        match receiver.process_res(&res, ohttp_ctx) {
+            Err(Error::V2(e)) => assert!(e.is_expired()),
+            other => panic!("expected an expired session, got {:?}", other.map(|p| p.is_some())),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn original_psbt_in_parts() -> Result<(), BoxError> {
+        use crate::hpke::encrypt_message_a;
+        use crate::multipart::{encode_payload, split, PART_CAPACITY};
+        use crate::receive::v1::test::{ORIGINAL_PSBT, QUERY_PARAMS};
+        use crate::uri::UrlExt;
+
+        let mut original = Psbt::from_str(ORIGINAL_PSBT)?;
+        while original.serialize().len() <= PART_CAPACITY {
+            original.unsigned_tx.output.push(TxOut {
+                value: bitcoin::Amount::ZERO,
+                script_pubkey: bitcoin::ScriptBuf::new_op_return([0u8; 75]),
+            });
+            original.outputs.push(Default::default());
+        }
+        let payload = encode_payload(&original.serialize(), QUERY_PARAMS);
+        let parts = split(&payload, 2)?;
+        assert_eq!(parts.len(), 2);
+
+        let mut receiver = Receiver { context: SHARED_CONTEXT.clone() };
+        let sender = HpkeKeyPair::gen_keypair();
+        let receiver_key = receiver.context.s.public_key().clone();
+        let messages = parts
+            .into_iter()
+            .map(|part| encrypt_message_a(part, sender.public_key(), &receiver_key))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Parts are rejected unless the receiver advertised accepting them
+        assert!(matches!(
+            receiver.extract_proposal_from_v2(&EXAMPLE_URL, messages[0].clone()),
+            Err(Error::ReplyToSender(_))
+        ));
+
+        let mut receiver = receiver.with_max_messages(2);
+        assert_eq!(receiver.pj_uri().extras.endpoint().max_messages(), Some(2));
+        assert!(receiver.extract_proposal_from_v2(&EXAMPLE_URL, messages[0].clone())?.is_none());
+        assert_eq!(receiver.context.e.as_ref(), Some(sender.public_key()));
+        // The next poll fetches the remaining part from its own mailbox
+        let (_, partial) = receiver.context.partial_original.clone().ok_or("missing parts")?;
+        assert_eq!(partial.next_missing(), Some(1));
+        // Parts of another sender are ignored
+        let other = encrypt_message_a(
+            split(&payload, 2)?.swap_remove(1),
+            HpkeKeyPair::gen_keypair().public_key(),
+            &receiver_key,
+        )?;
+        assert!(receiver.extract_proposal_from_v2(&EXAMPLE_URL, other)?.is_none());
+
+        let proposal = receiver
+            .extract_proposal_from_v2(&EXAMPLE_URL, messages[1].clone())?
+            .ok_or("expected a proposal")?;
+        assert_eq!(proposal.v1.psbt, original);
+        assert!(receiver.context.partial_original.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn abandoned_original_psbt_parts() -> Result<(), BoxError> {
+        use crate::hpke::encrypt_message_a;
+        use crate::multipart::{encode_payload, split, PART_CAPACITY};
+        use crate::receive::v1::test::{ORIGINAL_PSBT, QUERY_PARAMS};
+
+        let mut receiver = Receiver { context: SHARED_CONTEXT.clone() }.with_max_messages(2);
+        let receiver_key = receiver.context.s.public_key().clone();
+        // The first of two parts whose second never arrives
+        let payload = encode_payload(&[0u8; PART_CAPACITY + 1], QUERY_PARAMS);
+        let abandoned = encrypt_message_a(
+            split(&payload, 2)?.swap_remove(0),
+            HpkeKeyPair::gen_keypair().public_key(),
+            &receiver_key,
+        )?;
+        assert!(receiver.extract_proposal_from_v2(&EXAMPLE_URL, abandoned)?.is_none());
+        assert!(receiver.context.partial_original.is_some());
+
+        // Polls alternate between the missing part and the session mailbox
+        receiver.fallback_req_body(&EXAMPLE_URL)?;
+        assert!(receiver.context.poll_session_mailbox);
+        receiver.fallback_req_body(&EXAMPLE_URL)?;
+        assert!(!receiver.context.poll_session_mailbox);
+
+        // An Original PSBT posted to the session mailbox is still received
+        let sender = HpkeKeyPair::gen_keypair();
+        let original = encrypt_message_a(
+            format!("{}\n{}", ORIGINAL_PSBT, QUERY_PARAMS).into_bytes(),
+            sender.public_key(),
+            &receiver_key,
+        )?;
+        let proposal = receiver
+            .extract_proposal_from_v2(&EXAMPLE_URL, original)?
+            .ok_or("expected a proposal")?;
+        assert_eq!(proposal.v1.psbt, Psbt::from_str(ORIGINAL_PSBT)?);
+        assert_eq!(receiver.context.e.as_ref(), Some(sender.public_key()));
+        assert!(receiver.context.partial_original.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn sender_feedback() -> Result<(), BoxError> {
+        use crate::hpke::encrypt_message_a;
+
+        let sender = HpkeKeyPair::gen_keypair();
+        let mut context = SHARED_CONTEXT.clone();
+        context.e = Some(sender.public_key().clone());
+        let message = |payload: &[u8]| {
+            encrypt_message_a(payload.to_vec(), sender.public_key(), context.s.public_key())
+        };
+
+        let original = message(b"cHNidP8=\nv=2")?;
+        assert_eq!(context.sender_feedback(&original)?, None);
+        let ack = message(br#"{ "feedback": "ack" }"#)?;
+        assert_eq!(context.sender_feedback(&ack)?, Some(SenderFeedback::Accepted));
+        let nack = message(br#"{ "feedback": "nack", "reason": "versions-dont-match" }"#)?;
+        assert_eq!(
+            context.sender_feedback(&nack)?,
+            Some(SenderFeedback::Rejected { reason: "versions-dont-match".to_string() })
+        );
+
+        let psbt = Psbt::from_str(crate::receive::v1::test::ORIGINAL_PSBT)?;
+        let signed = message(format!(r#"{{ "feedback": "ack", "psbt": "{}" }}"#, psbt).as_bytes())?;
+        assert_eq!(context.sender_feedback(&signed)?, Some(SenderFeedback::Signed(psbt)));
+
+        context.e = Some(HpkeKeyPair::gen_keypair().public_key().clone());
+        assert_eq!(context.sender_feedback(&ack)?, None);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "psbt-merge")]
+    fn batch_collects_sender_signatures() -> Result<(), BoxError> {
+        let signed = Psbt::from_str(crate::receive::v1::test::ORIGINAL_PSBT)?;
+        let mut unsigned = signed.clone();
+        unsigned.inputs[0].final_script_sig = None;
+        unsigned.inputs[0].final_script_witness = None;
+        let mut batch =
+            BatchedProposal { psbt: unsigned, proposals: vec![], sender_inputs: vec![vec![0]] };
+        assert!(matches!(
+            batch.extract_tx().map_err(|e| e.0),
+            Err(InternalBatchError::MissingSignature(_))
+        ));
+
+        let mut other = signed.clone();
+        other.unsigned_tx.lock_time = bitcoin::absolute::LockTime::ZERO;
+        assert!(matches!(
+            batch.add_sender_signatures(0, &other).map_err(|e| e.0),
+            Err(InternalBatchError::UnknownTransaction)
+        ));
+        assert!(matches!(
+            batch.add_sender_signatures(1, &signed).map_err(|e| e.0),
+            Err(InternalBatchError::UnknownSender(1))
+        ));
+
+        // A sender can't sign the inputs of another
+        batch.sender_inputs.push(vec![]);
+        assert!(matches!(
+            batch.add_sender_signatures(1, &signed).map_err(|e| e.0),
+            Err(InternalBatchError::ForeignInputSigned(1, _))
+        ));
+
+        batch.add_sender_signatures(0, &signed)?;
+        assert_eq!(batch.extract_tx()?, signed.clone().extract_tx_unchecked_fee_rate());
+        // Scripts of other inputs that match the batch are the ones the sender was given
+        batch.add_sender_signatures(1, &signed)?;
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(all(feature = "psbt-merge", feature = "bitcoinconsensus"))]
+    fn batch_verifies_sender_scripts() -> Result<(), BoxError> {
+        let signed = Psbt::from_str(crate::receive::v1::test::ORIGINAL_PSBT)?;
+        let mut unsigned = signed.clone();
+        unsigned.inputs[0].final_script_sig = None;
+        unsigned.inputs[0].final_script_witness = None;
+        let mut batch =
+            BatchedProposal { psbt: unsigned, proposals: vec![], sender_inputs: vec![vec![0]] };
+
+        let mut invalid = signed.clone();
+        invalid.inputs[0].final_script_witness = Some(bitcoin::Witness::from_slice(&[[0u8; 72]]));
+        batch.add_sender_signatures(0, &invalid)?;
+        assert!(matches!(
+            batch.extract_tx().map_err(|e| e.0),
+            Err(InternalBatchError::ScriptVerification(..))
+        ));
+
+        batch.add_sender_signatures(0, &signed)?;
+        assert!(batch.extract_tx().is_ok());
+        Ok(())
+    }
+
     #[test]
     fn receiver_ser_de_roundtrip() -> Result<(), serde_json::Error> {
         let session = Receiver { context: SHARED_CONTEXT.clone() };
@@ -639,4 +1351,73 @@ mod test {
         assert_ne!(uri.extras.endpoint, EXAMPLE_URL.clone());
         assert!(!uri.extras.disable_output_substitution);
     }
+
+    #[test]
+    fn test_v2_pj_uri_builder() -> Result<(), BoxError> {
+        use bitcoin::Amount;
+
+        use crate::uri::UrlExt;
+
+        let receiver = Receiver { context: SHARED_CONTEXT.clone() };
+        let expiry = SystemTime::now() + Duration::from_secs(30);
+        let uri = receiver
+            .pj_uri_builder()
+            .amount(Amount::from_sat(10_000))
+            .message("invoice 42")
+            .expiry(expiry)
+            .build()?;
+        assert_eq!(uri.amount, Some(Amount::from_sat(10_000)));
+        assert_eq!(uri.extras.endpoint().receiver_pubkey()?, *receiver.context.s.public_key());
+        assert_eq!(
+            uri.extras
+                .endpoint()
+                .exp()
+                .expect("exp is set")
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_secs(),
+            expiry.duration_since(std::time::UNIX_EPOCH)?.as_secs()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_alternative_directories() -> Result<(), BoxError> {
+        use crate::uri::UrlExt;
+
+        let alternative = Url::parse("https://alternative.example.com")?;
+        let ohttp_keys = OhttpKeys(ohttp::KeyConfig::new(2, KEM, Vec::from(SYMMETRIC))?);
+        let mut receiver = Receiver { context: SHARED_CONTEXT.clone() }
+            .with_alternative_directory(alternative.clone(), ohttp_keys.clone())?
+            .with_alternative_directory(EXAMPLE_URL.clone(), ohttp_keys.clone())?;
+        assert_eq!(receiver.directories(), vec![EXAMPLE_URL.clone(), alternative.clone()]);
+
+        let uri = receiver.pj_uri();
+        assert_eq!(
+            uri.extras.endpoint().alternative_directories()?,
+            vec![(alternative.clone(), ohttp_keys)]
+        );
+
+        assert!(receiver.extract_req_from(&alternative, &*EXAMPLE_URL).is_ok());
+        let unknown = Url::parse("https://unknown.example.com")?;
+        assert!(receiver.extract_req_from(&unknown, &*EXAMPLE_URL).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn stale_ohttp_keys() -> Result<(), BoxError> {
+        let mut receiver = Receiver { context: SHARED_CONTEXT.clone() };
+        let (_req, ctx) = receiver.extract_req(&*EXAMPLE_URL)?;
+        let problem = br#"{"type":"https://iana.org/assignments/http-problem-types#ohttp-key", "title": "key identifier unknown"}"#;
+        match receiver.process_res(problem, ctx) {
+            Err(Error::V2(e)) => assert!(e.ohttp_keys_stale().is_some()),
+            _ => panic!("expected stale OHTTP keys"),
+        }
+
+        let fresh = OhttpKeys(ohttp::KeyConfig::new(2, KEM, Vec::from(SYMMETRIC))?);
+        receiver.update_ohttp_keys(&EXAMPLE_URL, fresh.clone())?;
+        assert_eq!(receiver.context.ohttp_keys, fresh);
+        let unknown = Url::parse("https://unknown.example.com")?;
+        assert!(receiver.update_ohttp_keys(&unknown, fresh).is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,221 @@
+//! OHTTP relay selection
+//!
+//! Routing every request of a session through the same OHTTP relay lets that relay link the
+//! requests by timing, and an outage of that single relay stalls the session. A [`RelayPool`]
+//! picks a relay at random for each request and tracks [`RelayHealth`] so that callers can fail
+//! over to another relay when one is unreachable.
+//!
+//! The pool does no IO itself. Callers extract a request against [`RelayPool::choose`] (or each
+//! entry of [`RelayPool::failover_order`]), send it, and report the outcome with
+//! [`RelayPool::record_success`] or [`RelayPool::record_failure`]. Health is serializable so that
+//! it may be persisted between runs.
+
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bitcoin::secp256k1::rand::seq::SliceRandom;
+use bitcoin::secp256k1::rand::thread_rng;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// The backoff applied after the first consecutive failure of a relay.
+/// Each further consecutive failure doubles it, up to [`MAX_BACKOFF`].
+pub const BASE_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The longest a failing relay is skipped before it is tried again.
+pub const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+
+/// Observed reliability of a single OHTTP relay.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayHealth {
+    /// Number of transport failures since the last success.
+    pub consecutive_failures: u32,
+    /// Unix timestamp in seconds of the most recent failure.
+    pub last_failure: Option<u64>,
+    /// Unix timestamp in seconds of the most recent success.
+    pub last_success: Option<u64>,
+}
+
+impl RelayHealth {
+    /// How long the relay should be skipped after its last failure.
+    pub fn backoff(&self) -> Duration {
+        match self.consecutive_failures {
+            0 => Duration::ZERO,
+            n => BASE_BACKOFF.saturating_mul(1 << (n - 1).min(16)).min(MAX_BACKOFF),
+        }
+    }
+
+    /// Whether the relay's backoff has elapsed at unix time `now`.
+    pub fn is_available_at(&self, now: u64) -> bool {
+        match self.last_failure {
+            Some(last_failure) if self.consecutive_failures > 0 =>
+                now >= last_failure.saturating_add(self.backoff().as_secs()),
+            _ => true,
+        }
+    }
+
+    fn record_success(&mut self, now: u64) {
+        self.consecutive_failures = 0;
+        self.last_success = Some(now);
+    }
+
+    fn record_failure(&mut self, now: u64) {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        self.last_failure = Some(now);
+    }
+}
+
+/// A set of OHTTP relays to spread requests across.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayPool {
+    relays: Vec<(Url, RelayHealth)>,
+}
+
+impl RelayPool {
+    /// Create a pool from one or more relay URLs. Duplicate URLs are ignored.
+    pub fn new(relays: impl IntoIterator<Item = Url>) -> Result<Self, RelayPoolError> {
+        let mut pool: Vec<(Url, RelayHealth)> = Vec::new();
+        for relay in relays {
+            if !pool.iter().any(|(url, _)| url == &relay) {
+                pool.push((relay, RelayHealth::default()));
+            }
+        }
+        if pool.is_empty() {
+            return Err(RelayPoolError::Empty);
+        }
+        Ok(Self { relays: pool })
+    }
+
+    /// Restore previously recorded health for `relay`.
+    ///
+    /// Health for a relay that is not part of the pool is ignored.
+    pub fn set_health(&mut self, relay: &Url, health: RelayHealth) {
+        if let Some((_, h)) = self.relays.iter_mut().find(|(url, _)| url == relay) {
+            *h = health;
+        }
+    }
+
+    /// The recorded health of `relay`, if it is part of the pool.
+    pub fn health(&self, relay: &Url) -> Option<&RelayHealth> {
+        self.relays.iter().find(|(url, _)| url == relay).map(|(_, h)| h)
+    }
+
+    /// All relays in the pool with their recorded health.
+    pub fn relays(&self) -> impl Iterator<Item = (&Url, &RelayHealth)> {
+        self.relays.iter().map(|(url, h)| (url, h))
+    }
+
+    /// Pick a relay for the next request.
+    ///
+    /// A relay is chosen uniformly at random among those not backing off. If every relay is
+    /// backing off, the one that has been failing the least is returned.
+    pub fn choose(&self) -> Url { self.choose_at(now()) }
+
+    /// Every relay in the order they should be attempted for a single request.
+    ///
+    /// Relays that are not backing off come first in random order, followed by the rest
+    /// ordered from least to most consecutive failures.
+    pub fn failover_order(&self) -> Vec<Url> { self.failover_order_at(now()) }
+
+    /// Record that a request through `relay` received a response.
+    pub fn record_success(&mut self, relay: &Url) { self.record_success_at(relay, now()) }
+
+    /// Record that a request through `relay` failed at the transport level.
+    pub fn record_failure(&mut self, relay: &Url) { self.record_failure_at(relay, now()) }
+
+    fn choose_at(&self, now: u64) -> Url { self.failover_order_at(now).swap_remove(0) }
+
+    fn failover_order_at(&self, now: u64) -> Vec<Url> {
+        let (mut available, mut backing_off): (Vec<_>, Vec<_>) =
+            self.relays.iter().partition(|(_, h)| h.is_available_at(now));
+        available.shuffle(&mut thread_rng());
+        backing_off.sort_by_key(|(_, h)| (h.consecutive_failures, h.last_failure));
+        available.into_iter().chain(backing_off).map(|(url, _)| url.clone()).collect()
+    }
+
+    fn record_success_at(&mut self, relay: &Url, now: u64) {
+        if let Some((_, h)) = self.relays.iter_mut().find(|(url, _)| url == relay) {
+            h.record_success(now);
+        }
+    }
+
+    fn record_failure_at(&mut self, relay: &Url, now: u64) {
+        if let Some((_, h)) = self.relays.iter_mut().find(|(url, _)| url == relay) {
+            h.record_failure(now);
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+/// Error building a [`RelayPool`]
+#[derive(Debug, PartialEq, Eq)]
+pub enum RelayPoolError {
+    /// At least one relay is required
+    Empty,
+}
+
+impl fmt::Display for RelayPoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RelayPoolError::Empty => write!(f, "At least one OHTTP relay is required"),
+        }
+    }
+}
+
+impl std::error::Error for RelayPoolError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn relay(n: u8) -> Url { Url::parse(&format!("https://relay{}.example.com", n)).unwrap() }
+
+    #[test]
+    fn test_empty_pool() {
+        assert_eq!(RelayPool::new(vec![]), Err(RelayPoolError::Empty));
+    }
+
+    #[test]
+    fn test_duplicate_relays_are_ignored() {
+        let pool = RelayPool::new(vec![relay(1), relay(1), relay(2)]).unwrap();
+        assert_eq!(pool.relays().count(), 2);
+    }
+
+    #[test]
+    fn test_failing_relay_is_skipped_until_backoff_elapses() {
+        let mut pool = RelayPool::new(vec![relay(1), relay(2)]).unwrap();
+        pool.record_failure_at(&relay(1), 1000);
+        for _ in 0..32 {
+            assert_eq!(pool.choose_at(1000), relay(2));
+        }
+        assert_eq!(pool.failover_order_at(1000), vec![relay(2), relay(1)]);
+
+        let after_backoff = 1000 + BASE_BACKOFF.as_secs();
+        assert_eq!(pool.failover_order_at(after_backoff).len(), 2);
+        assert!(pool.health(&relay(1)).unwrap().is_available_at(after_backoff));
+
+        pool.record_failure_at(&relay(1), after_backoff);
+        assert_eq!(pool.health(&relay(1)).unwrap().backoff(), BASE_BACKOFF * 2);
+        pool.record_success_at(&relay(1), after_backoff + 1);
+        assert_eq!(pool.health(&relay(1)).unwrap().consecutive_failures, 0);
+    }
+
+    #[test]
+    fn test_all_relays_backing_off_prefers_fewest_failures() {
+        let mut pool = RelayPool::new(vec![relay(1), relay(2)]).unwrap();
+        pool.record_failure_at(&relay(1), 1000);
+        pool.record_failure_at(&relay(1), 1000);
+        pool.record_failure_at(&relay(2), 1000);
+        assert_eq!(pool.choose_at(1000), relay(2));
+        assert_eq!(pool.health(&relay(1)).unwrap().backoff(), BASE_BACKOFF * 2);
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let health = RelayHealth { consecutive_failures: u32::MAX, ..Default::default() };
+        assert_eq!(health.backoff(), MAX_BACKOFF);
+    }
+}
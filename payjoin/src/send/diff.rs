@@ -0,0 +1,62 @@
+use bitcoin::{Amount, OutPoint, TxOut};
+
+/// What a verified Payjoin Proposal changed relative to the Original PSBT.
+///
+/// Returned alongside the Proposal PSBT once every check has passed so that a wallet can show
+/// the user what they are about to sign.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProposalDiff {
+    pub(crate) receiver_inputs: Vec<(OutPoint, TxOut)>,
+    pub(crate) unsigned_inputs: Vec<OutPoint>,
+    pub(crate) substituted_outputs: Vec<(TxOut, TxOut)>,
+    pub(crate) receiver_outputs: Vec<TxOut>,
+    pub(crate) original_fee: Amount,
+    pub(crate) proposed_fee: Amount,
+    pub(crate) sender_fee_contribution: Amount,
+}
+
+impl ProposalDiff {
+    /// Inputs the receiver added, with the outputs they spend
+    ///
+    /// In a batched proposal these include the inputs of the other senders.
+    pub fn receiver_inputs(&self) -> &[(OutPoint, TxOut)] { &self.receiver_inputs }
+
+    /// Inputs of other senders batched into the proposal that have yet to be signed
+    pub fn unsigned_inputs(&self) -> &[OutPoint] { &self.unsigned_inputs }
+
+    /// Whether the receiver batched other senders into the proposal.
+    ///
+    /// A batched proposal can only be broadcast once every sender has signed it. Return the
+    /// signed PSBT to the receiver with
+    /// [`V2GetContext::extract_signatures_req`](crate::send::v2::V2GetContext::extract_signatures_req)
+    /// instead of broadcasting it.
+    pub fn is_batched(&self) -> bool { !self.unsigned_inputs.is_empty() }
+
+    /// Total value of the inputs the receiver added
+    pub fn receiver_input_value(&self) -> Amount {
+        self.receiver_inputs.iter().map(|(_, txout)| txout.value).sum()
+    }
+
+    /// Original outputs whose script or value changed, as `(original, proposed)` pairs
+    ///
+    /// This includes the payee output if the receiver substituted or increased it and the
+    /// sender's change output if fees were deducted from it.
+    pub fn substituted_outputs(&self) -> &[(TxOut, TxOut)] { &self.substituted_outputs }
+
+    /// Outputs the receiver added that were not in the Original PSBT
+    ///
+    /// In a batched proposal these include the outputs of the other senders.
+    pub fn receiver_outputs(&self) -> &[TxOut] { &self.receiver_outputs }
+
+    /// The absolute fee of the Original PSBT
+    pub fn original_fee(&self) -> Amount { self.original_fee }
+
+    /// The absolute fee of the Proposal PSBT
+    pub fn proposed_fee(&self) -> Amount { self.proposed_fee }
+
+    /// How much the absolute fee grew. The Proposal is rejected if the fee decreased.
+    pub fn fee_delta(&self) -> Amount { self.proposed_fee - self.original_fee }
+
+    /// The additional fee the sender pays out of their change output
+    pub fn sender_fee_contribution(&self) -> Amount { self.sender_fee_contribution }
+}
@@ -1,5 +1,7 @@
 use std::fmt::{self, Display};
 
+#[cfg(feature = "bitcoinconsensus")]
+use bitcoin::consensus::validation::BitcoinconsensusError;
 use bitcoin::locktime::absolute::LockTime;
 use bitcoin::transaction::Version;
 use bitcoin::Sequence;
@@ -29,6 +31,7 @@ pub(crate) enum InternalBuildSenderError {
     ChangeIndexPointsAtPayee,
     InputWeight(crate::psbt::InputWeightError),
     AddressType(crate::psbt::AddressTypeError),
+    Truc(crate::truc::TrucViolation),
 }
 
 impl From<InternalBuildSenderError> for BuildSenderError {
@@ -59,6 +62,7 @@ impl fmt::Display for BuildSenderError {
             ChangeIndexPointsAtPayee => write!(f, "fee output index is points at output belonging to the payee"),
             AddressType(e) => write!(f, "can not determine input address type: {}", e),
             InputWeight(e) => write!(f, "can not determine expected input weight: {}", e),
+            Truc(e) => write!(f, "the original transaction violates the TRUC policy: {}", e),
         }
     }
 }
@@ -81,6 +85,7 @@ impl std::error::Error for BuildSenderError {
             ChangeIndexPointsAtPayee => None,
             AddressType(error) => Some(error),
             InputWeight(error) => Some(error),
+            Truc(error) => Some(error),
         }
     }
 }
@@ -101,6 +106,27 @@ pub(crate) enum InternalValidationError {
     V2Encapsulation(crate::send::v2::EncapsulationError),
 }
 
+impl ValidationError {
+    /// The [`crate::send::SenderPolicy`] limit the proposal exceeded, if that is why it was
+    /// rejected
+    pub fn policy_violation(&self) -> Option<&crate::send::PolicyViolation> {
+        match &self.0 {
+            InternalValidationError::Proposal(InternalProposalError::Policy(e)) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// The directory's OHTTP gateway rejected the OHTTP keys, if that is why the response is
+    /// invalid
+    #[cfg(feature = "v2")]
+    pub fn ohttp_keys_stale(&self) -> Option<&crate::OhttpKeysStale> {
+        match &self.0 {
+            InternalValidationError::V2Encapsulation(e) => e.ohttp_keys_stale(),
+            _ => None,
+        }
+    }
+}
+
 impl From<InternalValidationError> for ValidationError {
     fn from(value: InternalValidationError) -> Self { ValidationError(value) }
 }
@@ -148,15 +174,26 @@ pub(crate) enum InternalProposalError {
     NoInputs,
     PrevTxOut(crate::psbt::PrevTxOutError),
     InputWeight(crate::psbt::InputWeightError),
-    VersionsDontMatch { proposed: Version, original: Version },
-    LockTimesDontMatch { proposed: LockTime, original: LockTime },
-    SenderTxinSequenceChanged { proposed: Sequence, original: Sequence },
+    VersionsDontMatch {
+        proposed: Version,
+        original: Version,
+    },
+    LockTimesDontMatch {
+        proposed: LockTime,
+        original: LockTime,
+    },
+    SenderTxinSequenceChanged {
+        proposed: Sequence,
+        original: Sequence,
+    },
     SenderTxinContainsFinalScriptSig,
     SenderTxinContainsFinalScriptWitness,
     TxInContainsKeyPaths,
     ContainsPartialSigs,
     ReceiverTxinNotFinalized,
     ReceiverTxinMissingUtxoInfo,
+    #[cfg(feature = "bitcoinconsensus")]
+    ReceiverTxinScriptVerification(bitcoin::OutPoint, BitcoinconsensusError),
     MixedSequence,
     MissingOrShuffledInputs,
     TxOutContainsKeyPaths,
@@ -168,7 +205,53 @@ pub(crate) enum InternalProposalError {
     PayeeTookContributedFee,
     FeeContributionPaysOutputSizeIncrease,
     FeeRateBelowMinimum,
+    Truc(crate::truc::TrucViolation),
     Psbt(bitcoin::psbt::Error),
+    #[cfg(feature = "v2")]
+    PsbtV2(crate::psbt::v2::PsbtV2Error),
+    Policy(crate::send::PolicyViolation),
+}
+
+impl InternalProposalError {
+    /// A stable name for the check the proposal failed, shared with the receiver
+    #[cfg(feature = "v2")]
+    pub(crate) fn category(&self) -> &'static str {
+        use InternalProposalError::*;
+
+        match self {
+            InvalidAddressType(_) => "invalid-address-type",
+            NoInputs => "no-inputs",
+            PrevTxOut(_) => "missing-prevout",
+            InputWeight(_) => "input-weight",
+            VersionsDontMatch { .. } => "versions-dont-match",
+            LockTimesDontMatch { .. } => "lock-times-dont-match",
+            SenderTxinSequenceChanged { .. } => "sender-sequence-changed",
+            SenderTxinContainsFinalScriptSig => "sender-input-finalized",
+            SenderTxinContainsFinalScriptWitness => "sender-input-finalized",
+            TxInContainsKeyPaths => "input-key-paths",
+            ContainsPartialSigs => "partial-sigs",
+            ReceiverTxinNotFinalized => "receiver-input-not-finalized",
+            ReceiverTxinMissingUtxoInfo => "receiver-input-missing-utxo",
+            #[cfg(feature = "bitcoinconsensus")]
+            ReceiverTxinScriptVerification(..) => "receiver-input-script-invalid",
+            MixedSequence => "mixed-sequence",
+            MissingOrShuffledInputs => "missing-or-shuffled-inputs",
+            TxOutContainsKeyPaths => "output-key-paths",
+            FeeContributionExceedsMaximum => "fee-contribution-exceeds-maximum",
+            DisallowedOutputSubstitution => "disallowed-output-substitution",
+            OutputValueDecreased => "output-value-decreased",
+            MissingOrShuffledOutputs => "missing-or-shuffled-outputs",
+            AbsoluteFeeDecreased => "absolute-fee-decreased",
+            PayeeTookContributedFee => "payee-took-contributed-fee",
+            FeeContributionPaysOutputSizeIncrease => "fee-contribution-pays-output-size-increase",
+            FeeRateBelowMinimum => "fee-rate-below-minimum",
+            Truc(_) => "truc-policy",
+            Psbt(_) => "invalid-psbt",
+            #[cfg(feature = "v2")]
+            PsbtV2(_) => "invalid-psbt",
+            Policy(_) => "sender-policy",
+        }
+    }
 }
 
 impl From<crate::psbt::AddressTypeError> for InternalProposalError {
@@ -195,6 +278,8 @@ impl fmt::Display for InternalProposalError {
             ContainsPartialSigs => write!(f, "an input in proposed transaction belonging to the sender contains partial signatures"),
             ReceiverTxinNotFinalized => write!(f, "an input in proposed transaction belonging to the receiver is not finalized"),
             ReceiverTxinMissingUtxoInfo => write!(f, "an input in proposed transaction belonging to the receiver is missing UTXO information"),
+            #[cfg(feature = "bitcoinconsensus")]
+            ReceiverTxinScriptVerification(outpoint, e) => write!(f, "the receiver input {} does not satisfy the script it spends: {}", outpoint, e),
             MixedSequence => write!(f, "inputs of proposed transaction contain mixed sequence numbers"),
             MissingOrShuffledInputs => write!(f, "proposed transaction is missing inputs of the sender or they are shuffled"),
             TxOutContainsKeyPaths => write!(f, "proposed transaction outputs contain key paths"),
@@ -206,7 +291,11 @@ impl fmt::Display for InternalProposalError {
             PayeeTookContributedFee => write!(f, "payee tried to take fee contribution for himself"),
             FeeContributionPaysOutputSizeIncrease => write!(f, "fee contribution pays for additional outputs"),
             FeeRateBelowMinimum =>  write!(f, "the fee rate of proposed transaction is below minimum"),
+            Truc(e) => write!(f, "proposed transaction violates the TRUC policy: {}", e),
             Psbt(e) => write!(f, "psbt error: {}", e),
+            #[cfg(feature = "v2")]
+            PsbtV2(e) => write!(f, "psbt error: {}", e),
+            Policy(e) => write!(f, "proposal violates the sender policy: {}", e),
         }
     }
 }
@@ -229,6 +318,8 @@ impl std::error::Error for InternalProposalError {
             ContainsPartialSigs => None,
             ReceiverTxinNotFinalized => None,
             ReceiverTxinMissingUtxoInfo => None,
+            #[cfg(feature = "bitcoinconsensus")]
+            ReceiverTxinScriptVerification(_, error) => Some(error),
             MixedSequence => None,
             MissingOrShuffledInputs => None,
             TxOutContainsKeyPaths => None,
@@ -240,11 +331,21 @@ impl std::error::Error for InternalProposalError {
             PayeeTookContributedFee => None,
             FeeContributionPaysOutputSizeIncrease => None,
             FeeRateBelowMinimum => None,
+            Truc(error) => Some(error),
             Psbt(error) => Some(error),
+            #[cfg(feature = "v2")]
+            PsbtV2(error) => Some(error),
+            Policy(error) => Some(error),
         }
     }
 }
 
+impl From<crate::send::policy::InternalPolicyViolation> for InternalProposalError {
+    fn from(value: crate::send::policy::InternalPolicyViolation) -> Self {
+        InternalProposalError::Policy(value.into())
+    }
+}
+
 /// Represent an error returned by Payjoin receiver.
 pub enum ResponseError {
     /// `WellKnown` Errors are defined in the [`BIP78::ReceiverWellKnownError`] spec.
@@ -264,6 +365,9 @@ pub enum ResponseError {
     ///
     /// [`BIP78::ReceiverWellKnownError`]: https://github.com/bitcoin/bips/blob/master/bip-0078.mediawiki#user-content-Receivers_well_known_errors
     Unrecognized { error_code: String, message: String },
+
+    /// The receiver did not respond before the sender's deadline.
+    FallbackRequired(FallbackRequired),
 }
 
 impl ResponseError {
@@ -303,6 +407,17 @@ impl ResponseError {
         }
     }
 
+    /// Why the sender rejects the receiver's response, as told to a v2 receiver
+    #[cfg(feature = "v2")]
+    pub(crate) fn rejection_reason(&self) -> &'static str {
+        match self {
+            Self::Validation(ValidationError(InternalValidationError::Proposal(e))) => e.category(),
+            Self::Validation(_) => "invalid-response",
+            Self::WellKnown(_) | Self::Unrecognized { .. } => "receiver-error",
+            Self::FallbackRequired(_) => "deadline-exceeded",
+        }
+    }
+
     /// Parse a response from the receiver.
     ///
     /// response must be valid JSON string.
@@ -324,6 +439,10 @@ impl From<InternalValidationError> for ResponseError {
     fn from(value: InternalValidationError) -> Self { Self::Validation(ValidationError(value)) }
 }
 
+impl From<FallbackRequired> for ResponseError {
+    fn from(value: FallbackRequired) -> Self { Self::FallbackRequired(value) }
+}
+
 impl From<InternalProposalError> for ResponseError {
     fn from(value: InternalProposalError) -> Self {
         ResponseError::Validation(ValidationError(InternalValidationError::Proposal(value)))
@@ -338,6 +457,7 @@ impl Display for ResponseError {
 
             // Do NOT display unrecognized errors to end users, only debug logs
             Self::Unrecognized { .. } => write!(f, "The receiver sent an unrecognized error."),
+            Self::FallbackRequired(e) => e.fmt(f),
         }
     }
 }
@@ -359,10 +479,37 @@ impl fmt::Debug for ResponseError {
                 r#"Unrecognized error: {{ "errorCode": "{}", "message": "{}" }}"#,
                 error_code, message
             ),
+            Self::FallbackRequired(e) => write!(f, "{:?}", e),
         }
     }
 }
 
+/// The sender's deadline passed before the receiver responded with a Payjoin Proposal.
+///
+/// BIP 78 senders complete the payment by broadcasting the finalized Original transaction
+/// instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FallbackRequired(pub(crate) bitcoin::Transaction);
+
+impl FallbackRequired {
+    /// The finalized Original transaction to broadcast
+    pub fn fallback_tx(&self) -> &bitcoin::Transaction { &self.0 }
+
+    pub fn into_fallback_tx(self) -> bitcoin::Transaction { self.0 }
+}
+
+impl Display for FallbackRequired {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The receiver did not respond in time. Broadcast the original transaction {}.",
+            self.0.compute_txid()
+        )
+    }
+}
+
+impl std::error::Error for FallbackRequired {}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum WellKnownError {
     Unavailable(String),
@@ -407,6 +554,19 @@ mod tests {
 
     use super::*;
 
+    #[cfg(feature = "v2")]
+    #[test]
+    fn test_rejection_reason() {
+        let rejected: ResponseError = InternalProposalError::VersionsDontMatch {
+            proposed: Version::TWO,
+            original: Version::ONE,
+        }
+        .into();
+        assert_eq!(rejected.rejection_reason(), "versions-dont-match");
+        let receiver_error = ResponseError::parse(r#"{"errorCode":"unavailable", "message":""}"#);
+        assert_eq!(receiver_error.rejection_reason(), "receiver-error");
+    }
+
     #[test]
     fn test_parse_json() {
         let known_str_error = r#"{"errorCode":"version-unsupported", "message":"custom message here", "supported": [1, 2]}"#;
@@ -10,20 +10,27 @@
 //! version 1, refer to the `send::v1` module documentation after enabling the `v1` feature.
 
 use std::str::FromStr;
+use std::time::SystemTime;
 
 use bitcoin::psbt::Psbt;
-use bitcoin::{Amount, FeeRate, Script, ScriptBuf, TxOut, Weight};
-pub use error::{BuildSenderError, ResponseError, ValidationError};
+use bitcoin::{Amount, FeeRate, OutPoint, Script, ScriptBuf, TxOut, Weight};
+pub use diff::ProposalDiff;
+pub use error::{BuildSenderError, FallbackRequired, ResponseError, ValidationError};
 pub(crate) use error::{InternalBuildSenderError, InternalProposalError, InternalValidationError};
+use policy::InternalPolicyViolation;
+pub use policy::{PolicyViolation, PolicyViolationKind, SenderPolicy};
 use url::Url;
 
 use crate::psbt::PsbtExt;
+use crate::truc;
 
 // See usize casts
 #[cfg(not(any(target_pointer_width = "32", target_pointer_width = "64")))]
 compile_error!("This crate currently only supports 32 bit and 64 bit architectures");
 
+mod diff;
 mod error;
+mod policy;
 
 #[cfg(feature = "v1")]
 #[cfg_attr(docsrs, doc(cfg(feature = "v1")))]
@@ -37,6 +44,9 @@ pub mod v2;
 
 type InternalResult<T> = Result<T, InternalProposalError>;
 
+/// The inputs the receiver added with the outputs they spend, and those yet to be signed
+type ReceiverInputs = (Vec<(OutPoint, TxOut)>, Vec<OutPoint>);
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "v2", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct AdditionalFeeContribution {
@@ -52,6 +62,10 @@ pub struct PsbtContext {
     fee_contribution: Option<AdditionalFeeContribution>,
     min_fee_rate: FeeRate,
     payee: ScriptBuf,
+    policy: SenderPolicy,
+    deadline: Option<SystemTime>,
+    allow_batching: bool,
+    unconfirmed_truc_parent: bool,
 }
 
 macro_rules! check_eq {
@@ -73,16 +87,106 @@ macro_rules! ensure {
 }
 
 impl PsbtContext {
-    fn process_proposal(self, mut proposal: Psbt) -> InternalResult<Psbt> {
+    /// Give up on the receiver once the deadline has passed
+    fn check_deadline(&self) -> Result<(), FallbackRequired> {
+        match self.deadline {
+            Some(deadline) if SystemTime::now() > deadline =>
+                Err(FallbackRequired(self.original_psbt.clone().extract_tx_unchecked_fee_rate())),
+            _ => Ok(()),
+        }
+    }
+
+    fn process_proposal(self, mut proposal: Psbt) -> InternalResult<(Psbt, ProposalDiff)> {
         self.basic_checks(&proposal)?;
-        self.check_inputs(&proposal)?;
-        let contributed_fee = self.check_outputs(&proposal)?;
+        let (receiver_inputs, unsigned_inputs) = self.check_inputs(&proposal)?;
+        #[cfg(feature = "bitcoinconsensus")]
+        self.verify_receiver_inputs(&proposal, &receiver_inputs)?;
+        let outputs = self.check_outputs(&proposal)?;
         self.restore_original_utxos(&mut proposal)?;
-        self.check_fees(&proposal, contributed_fee)?;
-        Ok(proposal)
+        let (original_fee, proposed_fee) = self.check_fees(&proposal, outputs.contributed_fee)?;
+        self.check_truc(&proposal)?;
+        let diff = ProposalDiff {
+            receiver_inputs,
+            unsigned_inputs,
+            substituted_outputs: outputs.substituted,
+            receiver_outputs: outputs.added,
+            original_fee,
+            proposed_fee,
+            sender_fee_contribution: outputs.contributed_fee,
+        };
+        self.check_policy(&proposal, &diff)?;
+        Ok((proposal, diff))
     }
 
-    fn check_fees(&self, proposal: &Psbt, contributed_fee: Amount) -> InternalResult<()> {
+    /// Enforce the sender's own [`SenderPolicy`] once the proposal satisfies BIP 78
+    fn check_policy(&self, proposal: &Psbt, diff: &ProposalDiff) -> InternalResult<()> {
+        let policy = &self.policy;
+        if let Some(max) = policy.max_receiver_inputs {
+            let proposed = diff.receiver_inputs.len();
+            if proposed > max {
+                return Err(InternalPolicyViolation::TooManyReceiverInputs { max, proposed }.into());
+            }
+        }
+        if policy.reject_dust_outputs {
+            for output in &diff.receiver_outputs {
+                let dust_limit = output.script_pubkey.minimal_non_dust();
+                if output.value < dust_limit {
+                    return Err(InternalPolicyViolation::ReceiverDustOutput {
+                        value: output.value,
+                        dust_limit,
+                    }
+                    .into());
+                }
+            }
+        }
+        if let Some(max) = policy.max_fee_increase {
+            let proposed = diff.fee_delta();
+            if proposed > max {
+                return Err(
+                    InternalPolicyViolation::FeeIncreaseExceedsMaximum { max, proposed }.into()
+                );
+            }
+        }
+        if policy.require_matching_script_type {
+            let sender_types = self
+                .original_psbt
+                .input_pairs()
+                .map(|input| input.address_type())
+                .collect::<Result<Vec<_>, _>>()?;
+            for input in proposal.input_pairs() {
+                let outpoint = input.txin.previous_output;
+                if diff.receiver_inputs.iter().any(|(op, _)| *op == outpoint) {
+                    let script_type = input.address_type()?;
+                    if !sender_types.contains(&script_type) {
+                        return Err(InternalPolicyViolation::ScriptTypeMismatch {
+                            outpoint,
+                            script_type,
+                        }
+                        .into());
+                    }
+                }
+            }
+        }
+        if policy.require_confirmed_inputs {
+            let is_confirmed = policy
+                .input_confirmed
+                .as_ref()
+                .ok_or(InternalPolicyViolation::ConfirmationCheckMissing)?;
+            for (outpoint, _) in &diff.receiver_inputs {
+                if !is_confirmed(outpoint) {
+                    return Err(InternalPolicyViolation::UnconfirmedReceiverInput(*outpoint).into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the original and proposed absolute fees
+    fn check_fees(
+        &self,
+        proposal: &Psbt,
+        contributed_fee: Amount,
+    ) -> InternalResult<(Amount, Amount)> {
         let proposed_fee = proposal.fee().map_err(InternalProposalError::Psbt)?;
         let original_fee = self.original_psbt.fee().map_err(InternalProposalError::Psbt)?;
         ensure!(original_fee <= proposed_fee, AbsoluteFeeDecreased);
@@ -124,7 +228,7 @@ impl PsbtContext {
             let proposed_weight = proposal.clone().extract_tx_unchecked_fee_rate().weight();
             ensure!(proposed_fee / proposed_weight >= self.min_fee_rate, FeeRateBelowMinimum);
         }
-        Ok(())
+        Ok((original_fee, proposed_fee))
     }
 
     /// Check that the version and lock time are the same as in the original PSBT.
@@ -142,8 +246,28 @@ impl PsbtContext {
         Ok(())
     }
 
-    fn check_inputs(&self, proposal: &Psbt) -> InternalResult<()> {
+    /// Check that a version 3 proposal stays within the TRUC size limit once the sender signs it,
+    /// the lower limit of a child if the Original spends an unconfirmed TRUC parent
+    fn check_truc(&self, proposal: &Psbt) -> InternalResult<()> {
+        if truc::is_truc(proposal.unsigned_tx.version) {
+            let weight = truc::predicted_weight(proposal, &[&self.original_psbt])
+                .map_err(InternalProposalError::InputWeight)?;
+            if self.unconfirmed_truc_parent {
+                truc::check_child_weight(weight)
+            } else {
+                truc::check_weight(weight)
+            }
+            .map_err(InternalProposalError::Truc)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the inputs the receiver added along with the outputs they spend, and those of
+    /// them that other senders batched into the proposal have yet to sign
+    fn check_inputs(&self, proposal: &Psbt) -> InternalResult<ReceiverInputs> {
         let mut original_inputs = self.original_psbt.input_pairs().peekable();
+        let mut receiver_inputs = Vec::new();
+        let mut unsigned_inputs = Vec::new();
 
         for proposed in proposal.input_pairs() {
             ensure!(proposed.psbtin.bip32_derivation.is_empty(), TxInContainsKeyPaths);
@@ -175,12 +299,14 @@ impl PsbtContext {
                         .input_pairs()
                         .next()
                         .ok_or(InternalProposalError::NoInputs)?;
-                    // Verify the PSBT input is finalized
-                    ensure!(
-                        proposed.psbtin.final_script_sig.is_some()
-                            || proposed.psbtin.final_script_witness.is_some(),
-                        ReceiverTxinNotFinalized
-                    );
+                    // Verify the PSBT input is finalized, unless it belongs to another sender
+                    // batched into the same transaction
+                    if proposed.psbtin.final_script_sig.is_none()
+                        && proposed.psbtin.final_script_witness.is_none()
+                    {
+                        ensure!(self.allow_batching, ReceiverTxinNotFinalized);
+                        unsigned_inputs.push(proposed.txin.previous_output);
+                    }
                     // Verify that non_witness_utxo or witness_utxo are filled in.
                     ensure!(
                         proposed.psbtin.witness_utxo.is_some()
@@ -188,10 +314,51 @@ impl PsbtContext {
                         ReceiverTxinMissingUtxoInfo
                     );
                     ensure!(proposed.txin.sequence == original.txin.sequence, MixedSequence);
+                    let txout =
+                        proposed.previous_txout().map_err(InternalProposalError::PrevTxOut)?;
+                    receiver_inputs.push((proposed.txin.previous_output, txout.clone()));
                 }
             }
         }
         ensure!(original_inputs.peek().is_none(), MissingOrShuffledInputs);
+        Ok((receiver_inputs, unsigned_inputs))
+    }
+
+    /// Verify that the receiver's finalized inputs satisfy the scripts they spend.
+    ///
+    /// libbitcoinconsensus predates taproot, so taproot key and script path spends pass
+    /// unverified.
+    #[cfg(feature = "bitcoinconsensus")]
+    fn verify_receiver_inputs(
+        &self,
+        proposal: &Psbt,
+        receiver_inputs: &[(OutPoint, TxOut)],
+    ) -> InternalResult<()> {
+        let mut tx = proposal.unsigned_tx.clone();
+        for (txin, psbtin) in tx.input.iter_mut().zip(&proposal.inputs) {
+            if let Some(script_sig) = &psbtin.final_script_sig {
+                txin.script_sig = script_sig.clone();
+            }
+            if let Some(witness) = &psbtin.final_script_witness {
+                txin.witness = witness.clone();
+            }
+        }
+        // The sender's inputs are unsigned but neither legacy nor segwit v0 signatures commit
+        // to the unlocking data of other inputs.
+        let spending_tx = bitcoin::consensus::serialize(&tx);
+        for (index, (txin, psbtin)) in tx.input.iter().zip(&proposal.inputs).enumerate() {
+            // Other senders batched into the proposal have yet to sign their inputs
+            if psbtin.final_script_sig.is_none() && psbtin.final_script_witness.is_none() {
+                continue;
+            }
+            if let Some((outpoint, txout)) =
+                receiver_inputs.iter().find(|(outpoint, _)| *outpoint == txin.previous_output)
+            {
+                txout.script_pubkey.verify(index, txout.value, &spending_tx).map_err(|e| {
+                    InternalProposalError::ReceiverTxinScriptVerification(*outpoint, e)
+                })?;
+            }
+        }
         Ok(())
     }
 
@@ -218,10 +385,10 @@ impl PsbtContext {
         Ok(())
     }
 
-    fn check_outputs(&self, proposal: &Psbt) -> InternalResult<Amount> {
+    fn check_outputs(&self, proposal: &Psbt) -> InternalResult<OutputChanges> {
         let mut original_outputs =
             self.original_psbt.unsigned_tx.output.iter().enumerate().peekable();
-        let mut contributed_fee = Amount::ZERO;
+        let mut changes = OutputChanges::default();
 
         for (proposed_txout, proposed_psbtout) in
             proposal.unsigned_tx.output.iter().zip(&proposal.outputs)
@@ -239,10 +406,12 @@ impl PsbtContext {
                     && *original_output_index == fee_contrib_idx =>
                 {
                     if proposed_txout.value < original_output.value {
-                        contributed_fee = original_output.value - proposed_txout.value;
+                        let contributed_fee = original_output.value - proposed_txout.value;
                         ensure!(contributed_fee <= max_fee_contrib, FeeContributionExceedsMaximum);
                         // The remaining fee checks are done in later in `check_fees`
+                        changes.contributed_fee = contributed_fee;
                     }
+                    changes.record(original_output, proposed_txout);
                     original_outputs.next();
                 }
                 // payee output
@@ -255,6 +424,7 @@ impl PsbtContext {
                                 && proposed_txout.value >= original_output.value),
                         DisallowedOutputSubstitution
                     );
+                    changes.record(original_output, proposed_txout);
                     original_outputs.next();
                 }
                 // our output
@@ -262,15 +432,32 @@ impl PsbtContext {
                     if proposed_txout.script_pubkey == original_output.script_pubkey =>
                 {
                     ensure!(proposed_txout.value >= original_output.value, OutputValueDecreased);
+                    changes.record(original_output, proposed_txout);
                     original_outputs.next();
                 }
                 // additional output
-                _ => (),
+                _ => changes.added.push(proposed_txout.clone()),
             }
         }
 
         ensure!(original_outputs.peek().is_none(), MissingOrShuffledOutputs);
-        Ok(contributed_fee)
+        Ok(changes)
+    }
+}
+
+/// Output changes collected by [`PsbtContext::check_outputs`]
+#[derive(Default)]
+struct OutputChanges {
+    contributed_fee: Amount,
+    substituted: Vec<(TxOut, TxOut)>,
+    added: Vec<TxOut>,
+}
+
+impl OutputChanges {
+    fn record(&mut self, original: &TxOut, proposed: &TxOut) {
+        if original != proposed {
+            self.substituted.push((original.clone(), proposed.clone()));
+        }
     }
 }
 
@@ -447,7 +634,7 @@ pub(crate) mod test {
     use crate::send::AdditionalFeeContribution;
 
     pub(crate) const ORIGINAL_PSBT: &str = "cHNidP8BAHMCAAAAAY8nutGgJdyYGXWiBEb45Hoe9lWGbkxh/6bNiOJdCDuDAAAAAAD+////AtyVuAUAAAAAF6kUHehJ8GnSdBUOOv6ujXLrWmsJRDCHgIQeAAAAAAAXqRR3QJbbz0hnQ8IvQ0fptGn+votneofTAAAAAAEBIKgb1wUAAAAAF6kU3k4ekGHKWRNbA1rV5tR5kEVDVNCHAQcXFgAUx4pFclNVgo1WWAdN1SYNX8tphTABCGsCRzBEAiB8Q+A6dep+Rz92vhy26lT0AjZn4PRLi8Bf9qoB/CMk0wIgP/Rj2PWZ3gEjUkTlhDRNAQ0gXwTO7t9n+V14pZ6oljUBIQMVmsAaoNWHVMS02LfTSe0e388LNitPa1UQZyOihY+FFgABABYAFEb2Giu6c4KO5YW0pfw3lGp9jMUUAAA=";
-    const PAYJOIN_PROPOSAL: &str = "cHNidP8BAJwCAAAAAo8nutGgJdyYGXWiBEb45Hoe9lWGbkxh/6bNiOJdCDuDAAAAAAD+////jye60aAl3JgZdaIERvjkeh72VYZuTGH/ps2I4l0IO4MBAAAAAP7///8CJpW4BQAAAAAXqRQd6EnwadJ0FQ46/q6NcutaawlEMIcACT0AAAAAABepFHdAltvPSGdDwi9DR+m0af6+i2d6h9MAAAAAAQEgqBvXBQAAAAAXqRTeTh6QYcpZE1sDWtXm1HmQRUNU0IcBBBYAFMeKRXJTVYKNVlgHTdUmDV/LaYUwIgYDFZrAGqDVh1TEtNi300ntHt/PCzYrT2tVEGcjooWPhRYYSFzWUDEAAIABAACAAAAAgAEAAAAAAAAAAAEBIICEHgAAAAAAF6kUyPLL+cphRyyI5GTUazV0hF2R2NWHAQcXFgAUX4BmVeWSTJIEwtUb5TlPS/ntohABCGsCRzBEAiBnu3tA3yWlT0WBClsXXS9j69Bt+waCs9JcjWtNjtv7VgIge2VYAaBeLPDB6HGFlpqOENXMldsJezF9Gs5amvDQRDQBIQJl1jz1tBt8hNx2owTm+4Du4isx0pmdKNMNIjjaMHFfrQABABYAFEb2Giu6c4KO5YW0pfw3lGp9jMUUIgICygvBWB5prpfx61y1HDAwo37kYP3YRJBvAjtunBAur3wYSFzWUDEAAIABAACAAAAAgAEAAAABAAAAAAA=";
+    pub(crate) const PAYJOIN_PROPOSAL: &str = "cHNidP8BAJwCAAAAAo8nutGgJdyYGXWiBEb45Hoe9lWGbkxh/6bNiOJdCDuDAAAAAAD+////jye60aAl3JgZdaIERvjkeh72VYZuTGH/ps2I4l0IO4MBAAAAAP7///8CJpW4BQAAAAAXqRQd6EnwadJ0FQ46/q6NcutaawlEMIcACT0AAAAAABepFHdAltvPSGdDwi9DR+m0af6+i2d6h9MAAAAAAQEgqBvXBQAAAAAXqRTeTh6QYcpZE1sDWtXm1HmQRUNU0IcBBBYAFMeKRXJTVYKNVlgHTdUmDV/LaYUwIgYDFZrAGqDVh1TEtNi300ntHt/PCzYrT2tVEGcjooWPhRYYSFzWUDEAAIABAACAAAAAgAEAAAAAAAAAAAEBIICEHgAAAAAAF6kUyPLL+cphRyyI5GTUazV0hF2R2NWHAQcXFgAUX4BmVeWSTJIEwtUb5TlPS/ntohABCGsCRzBEAiBnu3tA3yWlT0WBClsXXS9j69Bt+waCs9JcjWtNjtv7VgIge2VYAaBeLPDB6HGFlpqOENXMldsJezF9Gs5amvDQRDQBIQJl1jz1tBt8hNx2owTm+4Du4isx0pmdKNMNIjjaMHFfrQABABYAFEb2Giu6c4KO5YW0pfw3lGp9jMUUIgICygvBWB5prpfx61y1HDAwo37kYP3YRJBvAjtunBAur3wYSFzWUDEAAIABAACAAAAAgAEAAAABAAAAAAA=";
 
     pub(crate) fn create_psbt_context() -> super::PsbtContext {
         let original_psbt = Psbt::from_str(ORIGINAL_PSBT).unwrap();
@@ -462,7 +649,24 @@ pub(crate) mod test {
             }),
             min_fee_rate: FeeRate::ZERO,
             payee,
+            policy: super::SenderPolicy::default(),
+            deadline: None,
+            allow_batching: false,
+            unconfirmed_truc_parent: false,
+        }
+    }
+
+    /// The proposal of the test vectors, stripped of what the receiver must not send
+    pub(crate) fn proposal_from_test_vector() -> Psbt {
+        let mut proposal = Psbt::from_str(PAYJOIN_PROPOSAL).unwrap();
+        for output in proposal.outputs_mut() {
+            output.bip32_derivation.clear();
         }
+        for input in proposal.inputs_mut() {
+            input.bip32_derivation.clear();
+        }
+        proposal.inputs_mut()[0].witness_utxo = None;
+        proposal
     }
 
     #[test]
@@ -479,7 +683,208 @@ pub(crate) mod test {
             input.bip32_derivation.clear();
         }
         proposal.inputs_mut()[0].witness_utxo = None;
-        ctx.process_proposal(proposal).unwrap();
+        let (_, diff) = ctx.process_proposal(proposal).unwrap();
+        assert_eq!(diff.receiver_inputs().len(), 1);
+        assert_eq!(diff.receiver_input_value(), bitcoin::Amount::from_sat(2_000_000));
+        assert!(diff.receiver_outputs().is_empty());
+        // the payee output grew by the receiver's input and the change output paid the fee
+        assert_eq!(diff.substituted_outputs().len(), 2);
+        assert_eq!(diff.sender_fee_contribution(), bitcoin::Amount::from_sat(182));
+        assert_eq!(diff.fee_delta(), diff.sender_fee_contribution());
+    }
+
+    #[test]
+    #[cfg(feature = "bitcoinconsensus")]
+    fn test_invalid_receiver_witness() {
+        let ctx = create_psbt_context();
+        let mut proposal = proposal_from_test_vector();
+        // Claim a different amount than the one the receiver's signature commits to
+        let receiver_utxo = proposal.inputs_mut()[1].witness_utxo.as_mut().unwrap();
+        receiver_utxo.value += bitcoin::Amount::from_sat(1);
+        match ctx.process_proposal(proposal) {
+            Err(super::InternalProposalError::ReceiverTxinScriptVerification(..)) => (),
+            other => panic!("expected script verification error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_truc_child_size_limit() {
+        use super::InternalProposalError;
+
+        let mut proposal = Psbt::from_str(PAYJOIN_PROPOSAL).unwrap();
+        proposal.unsigned_tx.version = crate::truc::TRUC_VERSION;
+        // Pad the proposal past the 1 kvB limit of a TRUC child
+        let padding = bitcoin::TxOut {
+            value: bitcoin::Amount::ZERO,
+            script_pubkey: bitcoin::ScriptBuf::new_op_return([0u8; 75]),
+        };
+        for _ in 0..12 {
+            proposal.unsigned_tx.output.push(padding.clone());
+            proposal.outputs.push(Default::default());
+        }
+
+        assert!(create_psbt_context().check_truc(&proposal).is_ok());
+        let ctx = super::PsbtContext { unconfirmed_truc_parent: true, ..create_psbt_context() };
+        match ctx.check_truc(&proposal) {
+            Err(InternalProposalError::Truc(crate::truc::TrucViolation::TooLarge {
+                max, ..
+            })) => assert_eq!(max, crate::truc::MAX_TRUC_CHILD_VSIZE),
+            other => panic!("expected a TRUC size violation, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_sender_policy() {
+        use super::{
+            InternalPolicyViolation, InternalProposalError, PolicyViolationKind, SenderPolicy,
+        };
+
+        let violation = |policy: SenderPolicy| {
+            let ctx = super::PsbtContext { policy, ..create_psbt_context() };
+            match ctx.process_proposal(proposal_from_test_vector()) {
+                Err(InternalProposalError::Policy(violation)) => Some(violation),
+                Err(e) => panic!("unexpected error: {}", e),
+                Ok(_) => None,
+            }
+        };
+        let kind = |policy: SenderPolicy| violation(policy).map(|violation| violation.kind());
+        let violation = |policy: SenderPolicy| violation(policy).map(|violation| violation.0);
+
+        assert_eq!(
+            violation(SenderPolicy::default().max_receiver_inputs(0)),
+            Some(InternalPolicyViolation::TooManyReceiverInputs { max: 0, proposed: 1 })
+        );
+        assert_eq!(
+            violation(SenderPolicy::default().max_fee_increase(bitcoin::Amount::from_sat(100))),
+            Some(InternalPolicyViolation::FeeIncreaseExceedsMaximum {
+                max: bitcoin::Amount::from_sat(100),
+                proposed: bitcoin::Amount::from_sat(182),
+            })
+        );
+        assert!(matches!(
+            violation(SenderPolicy::default().require_confirmed_inputs(|_| false)),
+            Some(InternalPolicyViolation::UnconfirmedReceiverInput(_))
+        ));
+        let mut restored = SenderPolicy::default().require_confirmed_inputs(|_| true);
+        restored.input_confirmed = None;
+        assert_eq!(
+            violation(restored.clone()),
+            Some(InternalPolicyViolation::ConfirmationCheckMissing)
+        );
+
+        assert_eq!(
+            kind(SenderPolicy::default().max_receiver_inputs(0)),
+            Some(PolicyViolationKind::TooManyReceiverInputs)
+        );
+        assert_eq!(
+            kind(SenderPolicy::default().max_fee_increase(bitcoin::Amount::from_sat(100))),
+            Some(PolicyViolationKind::FeeIncreaseExceedsMaximum)
+        );
+        assert_eq!(
+            kind(SenderPolicy::default().require_confirmed_inputs(|_| false)),
+            Some(PolicyViolationKind::UnconfirmedReceiverInput)
+        );
+        assert_eq!(kind(restored), Some(PolicyViolationKind::ConfirmationCheckMissing));
+        assert_eq!(
+            violation(
+                SenderPolicy::default()
+                    .max_receiver_inputs(1)
+                    .reject_dust_outputs(true)
+                    .require_matching_script_type(true)
+                    .require_confirmed_inputs(|_| true)
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_sender_policy_receiver_dust_output() {
+        use super::{InternalPolicyViolation, InternalProposalError, ProposalDiff, SenderPolicy};
+
+        let ctx = super::PsbtContext {
+            policy: SenderPolicy::default().reject_dust_outputs(true),
+            ..create_psbt_context()
+        };
+        let proposal = Psbt::from_str(PAYJOIN_PROPOSAL).unwrap();
+        let script_pubkey = proposal.unsigned_tx.output[1].script_pubkey.clone();
+        let dust_limit = script_pubkey.minimal_non_dust();
+        let diff = |value| ProposalDiff {
+            receiver_inputs: vec![],
+            unsigned_inputs: vec![],
+            substituted_outputs: vec![],
+            receiver_outputs: vec![bitcoin::TxOut { value, script_pubkey: script_pubkey.clone() }],
+            original_fee: bitcoin::Amount::ZERO,
+            proposed_fee: bitcoin::Amount::ZERO,
+            sender_fee_contribution: bitcoin::Amount::ZERO,
+        };
+
+        let value = dust_limit - bitcoin::Amount::from_sat(1);
+        match ctx.check_policy(&proposal, &diff(value)) {
+            Err(InternalProposalError::Policy(violation)) => {
+                assert_eq!(violation.kind(), super::PolicyViolationKind::ReceiverDustOutput);
+                assert_eq!(
+                    violation.0,
+                    InternalPolicyViolation::ReceiverDustOutput { value, dust_limit }
+                )
+            }
+            other => panic!("expected a dust output violation, got {:?}", other.err()),
+        }
+        assert!(ctx.check_policy(&proposal, &diff(dust_limit)).is_ok());
+    }
+
+    #[test]
+    fn test_sender_policy_script_type_mismatch() {
+        use bitcoin::hashes::Hash;
+        use bitcoin::AddressType;
+
+        use super::{InternalPolicyViolation, InternalProposalError, SenderPolicy};
+
+        let proposal = proposal_from_test_vector();
+        let receiver_outpoint = proposal.unsigned_tx.input[1].previous_output;
+
+        // Make the sender's input native segwit so the receiver's nested segwit input stands out
+        let mut ctx = super::PsbtContext {
+            policy: SenderPolicy::default().require_matching_script_type(true),
+            ..create_psbt_context()
+        };
+        let utxo = ctx.original_psbt.inputs[0].witness_utxo.as_mut().unwrap();
+        utxo.script_pubkey =
+            bitcoin::ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::from_byte_array([1; 20]));
+        ctx.original_psbt.inputs[0].final_script_sig = None;
+
+        match ctx.process_proposal(proposal) {
+            Err(InternalProposalError::Policy(violation)) => {
+                assert_eq!(violation.kind(), super::PolicyViolationKind::ScriptTypeMismatch);
+                assert_eq!(
+                    violation.0,
+                    InternalPolicyViolation::ScriptTypeMismatch {
+                        outpoint: receiver_outpoint,
+                        script_type: AddressType::P2sh,
+                    }
+                )
+            }
+            other => panic!("expected a script type mismatch, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_batched_proposal() {
+        use super::InternalProposalError;
+
+        let mut proposal = proposal_from_test_vector();
+        // Another sender batched into the proposal has yet to sign this input
+        let script_sig = proposal.inputs[1].final_script_sig.take().unwrap();
+        proposal.inputs_mut()[1].redeem_script = script_sig.redeem_script().map(ToOwned::to_owned);
+        proposal.inputs_mut()[1].final_script_witness = None;
+
+        match create_psbt_context().process_proposal(proposal.clone()) {
+            Err(InternalProposalError::ReceiverTxinNotFinalized) => (),
+            other => panic!("expected unfinalized input error, got {:?}", other.err()),
+        }
+        let ctx = super::PsbtContext { allow_batching: true, ..create_psbt_context() };
+        let (_, diff) = ctx.process_proposal(proposal).unwrap();
+        assert!(diff.is_batched());
+        assert_eq!(diff.unsigned_inputs(), &[diff.receiver_inputs()[0].0]);
     }
 
     #[test]
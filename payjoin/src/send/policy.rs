@@ -0,0 +1,190 @@
+use std::fmt;
+use std::sync::Arc;
+
+use bitcoin::{AddressType, Amount, OutPoint};
+
+/// Callback reporting whether a receiver input spends a confirmed output
+type ConfirmationCheck = Arc<dyn Fn(&OutPoint) -> bool + Send + Sync>;
+
+/// Limits the sender places on a Payjoin Proposal on top of the BIP 78 checks.
+///
+/// The default policy enforces nothing beyond BIP 78.
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "v2", derive(serde::Serialize, serde::Deserialize))]
+pub struct SenderPolicy {
+    pub(crate) max_receiver_inputs: Option<usize>,
+    pub(crate) reject_dust_outputs: bool,
+    pub(crate) max_fee_increase: Option<Amount>,
+    pub(crate) require_matching_script_type: bool,
+    /// Persisted so that a restored session can't silently drop the requirement along with the
+    /// callback
+    #[cfg_attr(feature = "v2", serde(default))]
+    pub(crate) require_confirmed_inputs: bool,
+    #[cfg_attr(feature = "v2", serde(skip))]
+    pub(crate) input_confirmed: Option<ConfirmationCheck>,
+}
+
+impl SenderPolicy {
+    /// Reject proposals in which the receiver adds more than `max` inputs
+    pub fn max_receiver_inputs(mut self, max: usize) -> Self {
+        self.max_receiver_inputs = Some(max);
+        self
+    }
+
+    /// Reject proposals in which the receiver adds an output below the dust limit
+    pub fn reject_dust_outputs(mut self, reject: bool) -> Self {
+        self.reject_dust_outputs = reject;
+        self
+    }
+
+    /// Reject proposals whose absolute fee exceeds the Original PSBT's by more than `max`
+    pub fn max_fee_increase(mut self, max: Amount) -> Self {
+        self.max_fee_increase = Some(max);
+        self
+    }
+
+    /// Reject receiver inputs whose script type is not among those of the sender's inputs
+    ///
+    /// Mixed input types make it easier to tell which inputs belong to the receiver.
+    pub fn require_matching_script_type(mut self, require: bool) -> Self {
+        self.require_matching_script_type = require;
+        self
+    }
+
+    /// Reject receiver inputs for which `is_confirmed` returns false
+    ///
+    /// The callback is not persisted along with a v2 [`crate::send::v2::Sender`]. Set it again
+    /// with [`crate::send::v2::Sender::with_policy`] after restoring a session. Until then every
+    /// proposal is rejected with a [`PolicyViolation::kind`] of
+    /// [`PolicyViolationKind::ConfirmationCheckMissing`].
+    pub fn require_confirmed_inputs(
+        mut self,
+        is_confirmed: impl Fn(&OutPoint) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.require_confirmed_inputs = true;
+        self.input_confirmed = Some(Arc::new(is_confirmed));
+        self
+    }
+}
+
+impl fmt::Debug for SenderPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SenderPolicy")
+            .field("max_receiver_inputs", &self.max_receiver_inputs)
+            .field("reject_dust_outputs", &self.reject_dust_outputs)
+            .field("max_fee_increase", &self.max_fee_increase)
+            .field("require_matching_script_type", &self.require_matching_script_type)
+            .field("require_confirmed_inputs", &self.require_confirmed_inputs)
+            .field("input_confirmed", &self.input_confirmed.is_some())
+            .finish()
+    }
+}
+
+impl PartialEq for SenderPolicy {
+    fn eq(&self, other: &Self) -> bool {
+        self.max_receiver_inputs == other.max_receiver_inputs
+            && self.reject_dust_outputs == other.reject_dust_outputs
+            && self.max_fee_increase == other.max_fee_increase
+            && self.require_matching_script_type == other.require_matching_script_type
+            && self.require_confirmed_inputs == other.require_confirmed_inputs
+            && match (&self.input_confirmed, &other.input_confirmed) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                (None, None) => true,
+                _ => false,
+            }
+    }
+}
+
+impl Eq for SenderPolicy {}
+
+/// A Payjoin Proposal that passed the BIP 78 checks but violates the [`SenderPolicy`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyViolation(pub(crate) InternalPolicyViolation);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum InternalPolicyViolation {
+    /// The receiver added more inputs than allowed
+    TooManyReceiverInputs { max: usize, proposed: usize },
+    /// The receiver added an output below the dust limit
+    ReceiverDustOutput { value: Amount, dust_limit: Amount },
+    /// The absolute fee grew by more than allowed
+    FeeIncreaseExceedsMaximum { max: Amount, proposed: Amount },
+    /// A receiver input's script type differs from the sender's inputs
+    ScriptTypeMismatch { outpoint: OutPoint, script_type: AddressType },
+    /// A receiver input spends an output the confirmation check rejected
+    UnconfirmedReceiverInput(OutPoint),
+    /// Confirmed inputs are required but the confirmation check was not set again after the
+    /// session was restored
+    ConfirmationCheckMissing,
+}
+
+/// Which [`SenderPolicy`] limit a Payjoin Proposal violates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PolicyViolationKind {
+    /// The receiver added more inputs than [`SenderPolicy::max_receiver_inputs`] allows
+    TooManyReceiverInputs,
+    /// The receiver added an output below the dust limit, see
+    /// [`SenderPolicy::reject_dust_outputs`]
+    ReceiverDustOutput,
+    /// The absolute fee grew by more than [`SenderPolicy::max_fee_increase`] allows
+    FeeIncreaseExceedsMaximum,
+    /// A receiver input's script type differs from the sender's inputs, see
+    /// [`SenderPolicy::require_matching_script_type`]
+    ScriptTypeMismatch,
+    /// A receiver input spends an output the confirmation check rejected, see
+    /// [`SenderPolicy::require_confirmed_inputs`]
+    UnconfirmedReceiverInput,
+    /// Confirmed inputs are required but the confirmation check was not set again after the
+    /// session was restored
+    ConfirmationCheckMissing,
+}
+
+impl PolicyViolation {
+    /// Which limit of the [`SenderPolicy`] the proposal violates
+    pub fn kind(&self) -> PolicyViolationKind {
+        use InternalPolicyViolation::*;
+
+        match self.0 {
+            TooManyReceiverInputs { .. } => PolicyViolationKind::TooManyReceiverInputs,
+            ReceiverDustOutput { .. } => PolicyViolationKind::ReceiverDustOutput,
+            FeeIncreaseExceedsMaximum { .. } => PolicyViolationKind::FeeIncreaseExceedsMaximum,
+            ScriptTypeMismatch { .. } => PolicyViolationKind::ScriptTypeMismatch,
+            UnconfirmedReceiverInput(_) => PolicyViolationKind::UnconfirmedReceiverInput,
+            ConfirmationCheckMissing => PolicyViolationKind::ConfirmationCheckMissing,
+        }
+    }
+}
+
+impl From<InternalPolicyViolation> for PolicyViolation {
+    fn from(value: InternalPolicyViolation) -> Self { PolicyViolation(value) }
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use InternalPolicyViolation::*;
+
+        match &self.0 {
+            TooManyReceiverInputs { max, proposed } =>
+                write!(f, "receiver added {} inputs, more than the allowed {}", proposed, max),
+            ReceiverDustOutput { value, dust_limit } => write!(
+                f,
+                "receiver added an output of {} below the dust limit of {}",
+                value, dust_limit
+            ),
+            FeeIncreaseExceedsMaximum { max, proposed } =>
+                write!(f, "fee increased by {}, more than the allowed {}", proposed, max),
+            ScriptTypeMismatch { outpoint, script_type } => write!(
+                f,
+                "receiver input {} is {} which differs from the sender's inputs",
+                outpoint, script_type
+            ),
+            UnconfirmedReceiverInput(outpoint) =>
+                write!(f, "receiver input {} is not confirmed", outpoint),
+            ConfirmationCheckMissing =>
+                write!(f, "confirmed receiver inputs are required but no confirmation check is set"),
+        }
+    }
+}
+
+impl std::error::Error for PolicyViolation {}
@@ -29,7 +29,7 @@ use url::Url;
 use super::*;
 use crate::psbt::PsbtExt;
 use crate::request::Request;
-use crate::PjUri;
+use crate::{truc, PjUri};
 
 #[derive(Clone)]
 pub struct SenderBuilder<'a> {
@@ -44,6 +44,11 @@ pub struct SenderBuilder<'a> {
     /// be just lowered in the request to match the change amount.
     pub(crate) clamp_fee_contribution: bool,
     pub(crate) min_fee_rate: FeeRate,
+    pub(crate) policy: SenderPolicy,
+    pub(crate) deadline: Option<SystemTime>,
+    pub(crate) allow_batching: bool,
+    pub(crate) psbt_v2: bool,
+    pub(crate) unconfirmed_truc_parent: bool,
 }
 
 impl<'a> SenderBuilder<'a> {
@@ -60,6 +65,11 @@ impl<'a> SenderBuilder<'a> {
             fee_contribution: None,
             clamp_fee_contribution: false,
             min_fee_rate: FeeRate::ZERO,
+            policy: SenderPolicy::default(),
+            deadline: None,
+            allow_batching: false,
+            psbt_v2: false,
+            unconfirmed_truc_parent: false,
         }
     }
 
@@ -74,6 +84,49 @@ impl<'a> SenderBuilder<'a> {
         self
     }
 
+    /// Enforce the sender's own limits on the proposal on top of the BIP 78 checks.
+    pub fn policy(mut self, policy: SenderPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Stop waiting for the receiver at `deadline`.
+    ///
+    /// Responses processed after the deadline fail with [`ResponseError::FallbackRequired`],
+    /// which holds the Original transaction to broadcast instead.
+    pub fn deadline(mut self, deadline: SystemTime) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Send the Original PSBT as a PSBTv2 ([BIP 370](https://github.com/bitcoin/bips/blob/master/bip-0370.mediawiki)).
+    ///
+    /// Receivers respond in the PSBT version of the Original PSBT. Proposals of either version
+    /// are returned as a [`Psbt`], which [`crate::psbt_to_v2`] converts back.
+    pub fn psbt_v2(mut self, psbt_v2: bool) -> Self {
+        self.psbt_v2 = psbt_v2;
+        self
+    }
+
+    /// State whether the version 3 Original spends an unconfirmed TRUC parent.
+    ///
+    /// BIP 431 limits such a child to 1 kvB, so the Original and the Payjoin Proposal are held
+    /// to that limit instead of 10 kvB. The receiver can't know whether the sender's inputs are
+    /// confirmed.
+    pub fn spends_unconfirmed_truc_parent(mut self, spends: bool) -> Self {
+        self.unconfirmed_truc_parent = spends;
+        self
+    }
+
+    /// Accept proposals batching other senders, whose inputs are not yet signed.
+    ///
+    /// Only v2 senders can return their signatures to the receiver.
+    #[cfg(feature = "v2")]
+    pub(crate) fn allow_batching(mut self, allow: bool) -> Self {
+        self.allow_batching = allow;
+        self
+    }
+
     // Calculate the recommended fee contribution for an Original PSBT.
     //
     // BIP 78 recommends contributing `originalPSBTFeeRate * vsize(sender_input_type)`.
@@ -190,6 +243,16 @@ impl<'a> SenderBuilder<'a> {
         let payee = self.uri.address.script_pubkey();
 
         check_single_payee(&psbt, &payee, self.uri.amount)?;
+        if truc::is_truc(psbt.unsigned_tx.version) {
+            let weight = truc::predicted_weight(&psbt, &[])
+                .map_err(InternalBuildSenderError::InputWeight)?;
+            if self.unconfirmed_truc_parent {
+                truc::check_child_weight(weight)
+            } else {
+                truc::check_weight(weight)
+            }
+            .map_err(InternalBuildSenderError::Truc)?;
+        }
         let fee_contribution = determine_fee_contribution(
             &psbt,
             &payee,
@@ -205,6 +268,11 @@ impl<'a> SenderBuilder<'a> {
             fee_contribution,
             payee,
             min_fee_rate: self.min_fee_rate,
+            policy: self.policy,
+            deadline: self.deadline,
+            allow_batching: self.allow_batching,
+            psbt_v2: self.psbt_v2,
+            unconfirmed_truc_parent: self.unconfirmed_truc_parent,
         })
     }
 }
@@ -223,6 +291,21 @@ pub struct Sender {
     pub(crate) min_fee_rate: FeeRate,
     /// Script of the person being paid
     pub(crate) payee: ScriptBuf,
+    /// The sender's own limits on the proposal
+    #[cfg_attr(feature = "v2", serde(default))]
+    pub(crate) policy: SenderPolicy,
+    /// When to give up on the receiver and broadcast the Original
+    #[cfg_attr(feature = "v2", serde(default))]
+    pub(crate) deadline: Option<SystemTime>,
+    /// Whether to accept proposals batching other senders
+    #[cfg_attr(feature = "v2", serde(default))]
+    pub(crate) allow_batching: bool,
+    /// Whether to send the Original PSBT as a PSBTv2
+    #[cfg_attr(feature = "v2", serde(default))]
+    pub(crate) psbt_v2: bool,
+    /// Whether the Original spends an unconfirmed TRUC parent
+    #[cfg_attr(feature = "v2", serde(default))]
+    pub(crate) unconfirmed_truc_parent: bool,
 }
 
 impl Sender {
@@ -235,7 +318,7 @@ impl Sender {
             self.min_fee_rate,
             "1", // payjoin version
         )?;
-        let body = self.psbt.to_string().as_bytes().to_vec();
+        let body = crate::psbt::v2::encode_base64(&self.psbt, self.psbt_v2).into_bytes();
         Ok((
             Request::new_v1(&url, &body),
             V1Context {
@@ -245,6 +328,11 @@ impl Sender {
                     fee_contribution: self.fee_contribution,
                     payee: self.payee.clone(),
                     min_fee_rate: self.min_fee_rate,
+                    policy: self.policy.clone(),
+                    deadline: self.deadline,
+                    // a v1 sender has no way to return its signatures to the receiver
+                    allow_batching: false,
+                    unconfirmed_truc_parent: self.unconfirmed_truc_parent,
                 },
             },
         ))
@@ -266,15 +354,23 @@ impl V1Context {
     /// Decodes and validates the response.
     ///
     /// Call this method with response from receiver to continue BIP78 flow. If the response is
-    /// valid you will get appropriate PSBT that you should sign and broadcast.
+    /// valid you will get appropriate PSBT that you should sign and broadcast, along with a
+    /// [`ProposalDiff`] describing how it differs from the Original PSBT.
+    ///
+    /// Once the sender's deadline has passed this fails with
+    /// [`ResponseError::FallbackRequired`] regardless of the response.
     #[inline]
     pub fn process_response(
         self,
         response: &mut impl std::io::Read,
-    ) -> Result<Psbt, ResponseError> {
+    ) -> Result<(Psbt, ProposalDiff), ResponseError> {
+        self.psbt_context.check_deadline()?;
         let mut res_str = String::new();
         response.read_to_string(&mut res_str).map_err(InternalValidationError::Io)?;
-        let proposal = Psbt::from_str(&res_str).map_err(|_| ResponseError::parse(&res_str))?;
+        let proposal = match crate::psbt::v2::decode_base64(&res_str) {
+            Some(proposal) => proposal.map_err(|_| ResponseError::parse(&res_str))?,
+            None => Psbt::from_str(&res_str).map_err(|_| ResponseError::parse(&res_str))?,
+        };
         self.psbt_context.process_proposal(proposal).map_err(Into::into)
     }
 }
@@ -311,4 +407,26 @@ mod test {
             _ => panic!("Expected unrecognized JSON error"),
         }
     }
+
+    #[test]
+    fn fallback_required_after_deadline() {
+        let mut ctx = create_v1_context();
+        ctx.psbt_context.deadline =
+            Some(std::time::SystemTime::now() - std::time::Duration::from_secs(1));
+        let original_tx = ctx.psbt_context.original_psbt.clone().extract_tx_unchecked_fee_rate();
+        match ctx.process_response(&mut crate::send::test::PAYJOIN_PROPOSAL.as_bytes()) {
+            Err(ResponseError::FallbackRequired(fallback)) =>
+                assert_eq!(fallback.into_fallback_tx(), original_tx),
+            _ => panic!("Expected FallbackRequired"),
+        }
+    }
+
+    #[test]
+    fn accepts_psbt_v2_proposal() {
+        let proposal = crate::send::test::proposal_from_test_vector();
+        let v0 = create_v1_context().process_response(&mut proposal.to_string().as_bytes());
+        let v2 = crate::psbt::v2::encode_base64(&proposal, true);
+        let v2 = create_v1_context().process_response(&mut v2.as_bytes());
+        assert_eq!(v0.unwrap(), v2.unwrap());
+    }
 }
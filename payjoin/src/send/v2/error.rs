@@ -18,6 +18,7 @@ pub(crate) enum InternalCreateRequestError {
     ParseReceiverPubkey(ParseReceiverPubkeyParamError),
     MissingOhttpConfig,
     Expired(std::time::SystemTime),
+    Parts(crate::multipart::PartError),
 }
 
 impl fmt::Display for CreateRequestError {
@@ -32,6 +33,7 @@ impl fmt::Display for CreateRequestError {
             MissingOhttpConfig =>
                 write!(f, "no ohttp configuration with which to make a v2 request available"),
             Expired(expiry) => write!(f, "session expired at {:?}", expiry),
+            Parts(e) => write!(f, "cannot split the Original PSBT: {}", e),
         }
     }
 }
@@ -47,6 +49,7 @@ impl std::error::Error for CreateRequestError {
             ParseReceiverPubkey(error) => Some(error),
             MissingOhttpConfig => None,
             Expired(_) => None,
+            Parts(error) => Some(error),
         }
     }
 }
@@ -75,6 +78,8 @@ pub struct EncapsulationError(InternalEncapsulationError);
 pub(crate) enum InternalEncapsulationError {
     /// The response size is not the expected size.
     InvalidSize(usize),
+    /// The OHTTP gateway rejected the OHTTP keys.
+    OhttpKeysStale(crate::ohttp::OhttpKeysStale),
     /// The status code is not the expected status code.
     UnexpectedStatusCode(http::StatusCode),
     /// The HPKE failed.
@@ -83,12 +88,34 @@ pub(crate) enum InternalEncapsulationError {
     Ohttp(crate::ohttp::OhttpEncapsulationError),
 }
 
+impl EncapsulationError {
+    /// The directory's OHTTP gateway rejected the OHTTP keys, if that is why the request failed
+    pub fn ohttp_keys_stale(&self) -> Option<&crate::ohttp::OhttpKeysStale> {
+        match &self.0 {
+            InternalEncapsulationError::OhttpKeysStale(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl InternalEncapsulationError {
+    /// Classify a `response` that isn't an encapsulated response
+    pub(crate) fn unexpected_response(response: &[u8]) -> Self {
+        if crate::ohttp::is_key_rejection(response) {
+            InternalEncapsulationError::OhttpKeysStale(crate::ohttp::OhttpKeysStale)
+        } else {
+            InternalEncapsulationError::InvalidSize(response.len())
+        }
+    }
+}
+
 impl fmt::Display for EncapsulationError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use InternalEncapsulationError::*;
 
         match &self.0 {
             InvalidSize(size) => write!(f, "invalid size: {}", size),
+            OhttpKeysStale(error) => write!(f, "{}", error),
             UnexpectedStatusCode(status) => write!(f, "unexpected status code: {}", status),
             Ohttp(error) => write!(f, "OHTTP encapsulation error: {}", error),
             Hpke(error) => write!(f, "HPKE error: {}", error),
@@ -102,6 +129,7 @@ impl std::error::Error for EncapsulationError {
 
         match &self.0 {
             InvalidSize(_) => None,
+            OhttpKeysStale(error) => Some(error),
             UnexpectedStatusCode(_) => None,
             Ohttp(error) => Some(error),
             Hpke(error) => Some(error),
@@ -29,11 +29,11 @@ use url::Url;
 
 use super::error::BuildSenderError;
 use super::*;
-use crate::hpke::{decrypt_message_b, encrypt_message_a, HpkeSecretKey};
+use crate::hpke::{decrypt_message_b, encrypt_message_a, HpkeSecretKey, PADDED_PLAINTEXT_A_LENGTH};
 use crate::ohttp::{ohttp_decapsulate, ohttp_encapsulate};
 use crate::send::v1;
 use crate::uri::{ShortId, UrlExt};
-use crate::{HpkeKeyPair, HpkePublicKey, IntoUrl, PjUri, Request};
+use crate::{HpkeKeyPair, HpkePublicKey, IntoUrl, OhttpKeys, PjUri, Request};
 
 mod error;
 
@@ -57,6 +57,39 @@ impl<'a> SenderBuilder<'a> {
         Self(self.0.always_disable_output_substitution(disable))
     }
 
+    /// Enforce the sender's own limits on the proposal on top of the BIP 78 checks.
+    pub fn policy(self, policy: SenderPolicy) -> Self { Self(self.0.policy(policy)) }
+
+    /// Stop polling for the receiver's proposal at `deadline`.
+    ///
+    /// [`V2GetContext::process_response`] fails with [`ResponseError::FallbackRequired`] once
+    /// the deadline has passed, holding the Original transaction to broadcast instead.
+    pub fn deadline(self, deadline: std::time::SystemTime) -> Self {
+        Self(self.0.deadline(deadline))
+    }
+
+    /// Let the receiver batch this payment with those of other senders into one transaction.
+    ///
+    /// A batched proposal holds the inputs of the other senders unsigned, which BIP 78 senders
+    /// reject, so receivers only batch senders that opt in. Check
+    /// [`ProposalDiff::is_batched`] and return the signed proposal with
+    /// [`V2GetContext::extract_signatures_req`] instead of broadcasting it.
+    pub fn allow_batching(self, allow: bool) -> Self { Self(self.0.allow_batching(allow)) }
+
+    /// Send the Original PSBT as a PSBTv2 ([BIP 370](https://github.com/bitcoin/bips/blob/master/bip-0370.mediawiki)).
+    ///
+    /// Receivers respond in the PSBT version of the Original PSBT. Proposals of either version
+    /// are returned as a [`Psbt`], which [`crate::psbt_to_v2`] converts back.
+    pub fn psbt_v2(self, psbt_v2: bool) -> Self { Self(self.0.psbt_v2(psbt_v2)) }
+
+    /// State whether the version 3 Original spends an unconfirmed TRUC parent.
+    ///
+    /// BIP 431 limits such a child to 1 kvB, so the Original and the Payjoin Proposal are held
+    /// to that limit instead of 10 kvB.
+    pub fn spends_unconfirmed_truc_parent(self, spends: bool) -> Self {
+        Self(self.0.spends_unconfirmed_truc_parent(spends))
+    }
+
     // Calculate the recommended fee contribution for an Original PSBT.
     //
     // BIP 78 recommends contributing `originalPSBTFeeRate * vsize(sender_input_type)`.
@@ -149,12 +182,7 @@ impl Sender {
         }
         let rs = self.extract_rs_pubkey()?;
         let url = self.v1.endpoint.clone();
-        let body = serialize_v2_body(
-            &self.v1.psbt,
-            self.v1.disable_output_substitution,
-            self.v1.fee_contribution,
-            self.v1.min_fee_rate,
-        )?;
+        let body = self.original_payloads()?.swap_remove(0);
         let hpke_ctx = HpkeContext::new(rs, &self.reply_key);
         let body = encrypt_message_a(
             body,
@@ -177,6 +205,10 @@ impl Sender {
                     fee_contribution: self.v1.fee_contribution,
                     payee: self.v1.payee.clone(),
                     min_fee_rate: self.v1.min_fee_rate,
+                    policy: self.v1.policy.clone(),
+                    deadline: self.v1.deadline,
+                    allow_batching: self.v1.allow_batching,
+                    unconfirmed_truc_parent: self.v1.unconfirmed_truc_parent,
                 },
                 hpke_ctx,
                 ohttp_ctx,
@@ -184,6 +216,120 @@ impl Sender {
         ))
     }
 
+    /// Extract the requests posting the rest of an Original PSBT too large for a single message.
+    ///
+    /// Receivers that advertise it accept an Original PSBT split into several messages. Post
+    /// these requests before the one from [`Sender::extract_v2`], which tells the receiver to
+    /// fetch them. There are none if the Original PSBT fits in a single message.
+    pub fn extract_v2_continuations(
+        &self,
+        ohttp_relay: impl IntoUrl,
+    ) -> Result<Vec<(Request, V2MessageContext)>, CreateRequestError> {
+        let ohttp_relay = ohttp_relay.into_url()?;
+        let rs = self.extract_rs_pubkey()?;
+        let hpke_ctx = HpkeContext::new(rs, &self.reply_key);
+        self.original_payloads()?
+            .into_iter()
+            .enumerate()
+            .skip(1)
+            .map(|(index, payload)| {
+                let mailbox = crate::multipart::part_mailbox(
+                    &hpke_ctx.receiver,
+                    hpke_ctx.reply_pair.public_key(),
+                    index as u8,
+                );
+                let mut endpoint = self
+                    .v1
+                    .endpoint
+                    .join(&mailbox.to_string())
+                    .map_err(|e| InternalCreateRequestError::Url(e.into()))?;
+                endpoint.set_fragment(self.v1.endpoint.fragment());
+                post_to_mailbox(&endpoint, &hpke_ctx, payload, &ohttp_relay)
+            })
+            .collect()
+    }
+
+    /// The message A payloads carrying the Original PSBT, the one for the receiver's mailbox
+    /// first
+    ///
+    /// An Original PSBT too large for a single message is split into parts if the receiver
+    /// accepts them.
+    fn original_payloads(&self) -> Result<Vec<Vec<u8>>, CreateRequestError> {
+        let query = serialize_v2_query(
+            self.v1.disable_output_substitution,
+            self.v1.fee_contribution,
+            self.v1.min_fee_rate,
+            self.v1.allow_batching,
+        )?;
+        let base64 = crate::psbt::v2::encode_base64(&self.v1.psbt, self.v1.psbt_v2);
+        let body = format!("{}\n{}", base64, query).into_bytes();
+        let max_messages = match self.v1.endpoint.max_messages() {
+            Some(max_messages) if body.len() > PADDED_PLAINTEXT_A_LENGTH => max_messages,
+            // Encryption reports a body that is too large
+            _ => return Ok(vec![body]),
+        };
+        let psbt = crate::psbt::v2::serialize_as(&self.v1.psbt, self.v1.psbt_v2);
+        let payload = crate::multipart::encode_payload(&psbt, &query);
+        Ok(crate::multipart::split(&payload, max_messages)
+            .map_err(InternalCreateRequestError::Parts)?)
+    }
+
+    /// One [`Sender`] per alternative directory advertised by the receiver, in the order they
+    /// should be tried should posting through this one fail.
+    ///
+    /// Each alternative addresses the receiver's mailbox at that directory using the ohttp keys
+    /// the receiver provided for it. Malformed alternatives are skipped.
+    pub fn alternatives(&self) -> Vec<Sender> {
+        let alternatives = match self.v1.endpoint.alternative_directories() {
+            Ok(alternatives) => alternatives,
+            Err(e) => {
+                log::warn!("Ignoring malformed alternative directories: {}", e);
+                return Vec::new();
+            }
+        };
+        let rs = match self.extract_rs_pubkey() {
+            Ok(rs) => rs,
+            Err(_) => return Vec::new(),
+        };
+        let mailbox: ShortId = sha256::Hash::hash(&rs.to_compressed_bytes()).into();
+        alternatives
+            .into_iter()
+            .filter_map(|(directory, ohttp_keys)| {
+                // address the mailbox the same way the receiver polls it
+                let mut endpoint = directory;
+                endpoint.path_segments_mut().ok()?.push(&mailbox.to_string());
+                endpoint.set_receiver_pubkey(rs.clone());
+                endpoint.set_ohttp(ohttp_keys);
+                if let Ok(expiry) = self.v1.endpoint.exp() {
+                    endpoint.set_exp(expiry);
+                }
+                let mut sender = self.clone();
+                sender.v1.endpoint = endpoint;
+                Some(sender)
+            })
+            .collect()
+    }
+
+    /// Extract a request retracting the Original PSBT from the receiver's mailbox.
+    ///
+    /// The cancellation is encrypted to the receiver under the same reply key as the Original
+    /// PSBT and replaces it in the directory. A receiver that already fetched the Original
+    /// learns of the cancellation the next time it polls. If the Original was posted to one of
+    /// [`Sender::alternatives`], cancel through that [`Sender`] as well.
+    pub fn extract_cancel_req(
+        &self,
+        ohttp_relay: impl IntoUrl,
+    ) -> Result<(Request, V2MessageContext), CreateRequestError> {
+        let rs = self.extract_rs_pubkey()?;
+        let hpke_ctx = HpkeContext::new(rs, &self.reply_key);
+        post_to_mailbox(
+            &self.v1.endpoint,
+            &hpke_ctx,
+            crate::hpke::CANCEL_PAYLOAD.to_vec(),
+            ohttp_relay,
+        )
+    }
+
     fn extract_rs_pubkey(
         &self,
     ) -> Result<HpkePublicKey, crate::uri::url_ext::ParseReceiverPubkeyParamError> {
@@ -191,18 +337,50 @@ impl Sender {
     }
 
     pub fn endpoint(&self) -> &Url { self.v1.endpoint() }
+
+    /// Whether the receiver advertised the OHTTP keys and public key a v2 request needs, in a
+    /// URI that hasn't expired
+    ///
+    /// Receivers that didn't can only be paid with [`Self::extract_v1`].
+    pub fn is_v2(&self) -> bool {
+        let expired =
+            self.v1.endpoint.exp().map_or(false, |expiry| std::time::SystemTime::now() > expiry);
+        !expired && self.v1.endpoint.ohttp().is_ok() && self.extract_rs_pubkey().is_ok()
+    }
+
+    /// The OHTTP keys the receiver advertised in the payjoin URI
+    ///
+    /// A directory could give each receiver unique keys to link their sessions. Check these
+    /// against the keys the directory serves through independent relays before sending, e.g. with
+    /// `io::check_ohttp_keys`.
+    pub fn ohttp_keys(&self) -> Option<OhttpKeys> { self.v1.endpoint.ohttp().ok() }
+
+    /// Replace the OHTTP keys the receiver advertised after the directory's gateway reported
+    /// them as [`crate::OhttpKeysStale`]
+    pub fn update_ohttp_keys(&mut self, ohttp_keys: OhttpKeys) {
+        self.v1.endpoint.set_ohttp(ohttp_keys);
+    }
+
+    /// Replace the [`SenderPolicy`] the proposal is checked against.
+    ///
+    /// The confirmation check of a policy is not persisted, so set the policy again after
+    /// restoring a session.
+    pub fn with_policy(mut self, policy: SenderPolicy) -> Self {
+        self.v1.policy = policy;
+        self
+    }
 }
 
-fn serialize_v2_body(
-    psbt: &Psbt,
+fn serialize_v2_query(
     disable_output_substitution: bool,
     fee_contribution: Option<AdditionalFeeContribution>,
     min_fee_rate: FeeRate,
-) -> Result<Vec<u8>, CreateRequestError> {
+    allow_batching: bool,
+) -> Result<String, CreateRequestError> {
     // Grug say localhost base be discarded anyway. no big brain needed.
     let base_url = Url::parse("http://localhost").expect("invalid URL");
 
-    let placeholder_url = serialize_url(
+    let mut placeholder_url = serialize_url(
         base_url,
         disable_output_substitution,
         fee_contribution,
@@ -210,9 +388,10 @@ fn serialize_v2_body(
         "2", // payjoin version
     )
     .map_err(|e| InternalCreateRequestError::Url(e.into()))?;
-    let query_params = placeholder_url.query().unwrap_or_default();
-    let base64 = psbt.to_string();
-    Ok(format!("{}\n{}", base64, query_params).into_bytes())
+    if allow_batching {
+        placeholder_url.query_pairs_mut().append_pair("batching", "true");
+    }
+    Ok(placeholder_url.query().unwrap_or_default().to_owned())
 }
 
 pub struct V2PostContext {
@@ -227,7 +406,7 @@ impl V2PostContext {
     pub fn process_response(self, response: &[u8]) -> Result<V2GetContext, EncapsulationError> {
         let response_array: &[u8; crate::directory::ENCAPSULATED_MESSAGE_BYTES] = response
             .try_into()
-            .map_err(|_| InternalEncapsulationError::InvalidSize(response.len()))?;
+            .map_err(|_| InternalEncapsulationError::unexpected_response(response))?;
         let response = ohttp_decapsulate(self.ohttp_ctx, response_array)
             .map_err(InternalEncapsulationError::Ohttp)?;
         match response.status() {
@@ -244,6 +423,42 @@ impl V2PostContext {
     }
 }
 
+/// Encrypt `payload` to the receiver and post it to its mailbox in place of the Original PSBT
+fn post_to_mailbox(
+    endpoint: &Url,
+    hpke_ctx: &HpkeContext,
+    payload: Vec<u8>,
+    ohttp_relay: impl IntoUrl,
+) -> Result<(Request, V2MessageContext), CreateRequestError> {
+    let body = encrypt_message_a(payload, hpke_ctx.reply_pair.public_key(), &hpke_ctx.receiver)
+        .map_err(InternalCreateRequestError::Hpke)?;
+    let mut ohttp = endpoint.ohttp().map_err(|_| InternalCreateRequestError::MissingOhttpConfig)?;
+    let (body, ohttp_ctx) = ohttp_encapsulate(&mut ohttp, "POST", endpoint.as_str(), Some(&body))
+        .map_err(InternalCreateRequestError::OhttpEncapsulation)?;
+    let url = ohttp_relay.into_url().map_err(InternalCreateRequestError::Url)?;
+    Ok((Request::new_v2(&url, &body), V2MessageContext { ohttp_ctx }))
+}
+
+/// Context to process the directory's response to a message posted to the receiver's mailbox
+pub struct V2MessageContext {
+    ohttp_ctx: ohttp::ClientResponse,
+}
+
+impl V2MessageContext {
+    /// Check that the directory accepted the message
+    pub fn process_response(self, response: &[u8]) -> Result<(), EncapsulationError> {
+        let response_array: &[u8; crate::directory::ENCAPSULATED_MESSAGE_BYTES] = response
+            .try_into()
+            .map_err(|_| InternalEncapsulationError::unexpected_response(response))?;
+        let response = ohttp_decapsulate(self.ohttp_ctx, response_array)
+            .map_err(InternalEncapsulationError::Ohttp)?;
+        match response.status() {
+            http::StatusCode::OK => Ok(()),
+            _ => Err(InternalEncapsulationError::UnexpectedStatusCode(response.status()))?,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct V2GetContext {
     /// The payjoin directory subdirectory to send the request to.
@@ -253,6 +468,12 @@ pub struct V2GetContext {
 }
 
 impl V2GetContext {
+    /// Replace the OHTTP keys used to poll the directory after its gateway reported them as
+    /// [`crate::OhttpKeysStale`]
+    pub fn update_ohttp_keys(&mut self, ohttp_keys: OhttpKeys) {
+        self.endpoint.set_ohttp(ohttp_keys);
+    }
+
     pub fn extract_req(
         &self,
         ohttp_relay: impl IntoUrl,
@@ -285,10 +506,11 @@ impl V2GetContext {
         &self,
         response: &[u8],
         ohttp_ctx: ohttp::ClientResponse,
-    ) -> Result<Option<Psbt>, ResponseError> {
+    ) -> Result<Option<(Psbt, ProposalDiff)>, ResponseError> {
+        self.psbt_ctx.check_deadline()?;
         let response_array: &[u8; crate::directory::ENCAPSULATED_MESSAGE_BYTES] = response
             .try_into()
-            .map_err(|_| InternalEncapsulationError::InvalidSize(response.len()))?;
+            .map_err(|_| InternalEncapsulationError::unexpected_response(response))?;
 
         let response = ohttp_decapsulate(ohttp_ctx, response_array)
             .map_err(InternalEncapsulationError::Ohttp)?;
@@ -304,9 +526,55 @@ impl V2GetContext {
         )
         .map_err(InternalEncapsulationError::Hpke)?;
 
-        let proposal = Psbt::deserialize(&psbt).map_err(InternalProposalError::Psbt)?;
-        let processed_proposal = self.psbt_ctx.clone().process_proposal(proposal)?;
-        Ok(Some(processed_proposal))
+        let proposal = if crate::psbt::v2::is_v2(&psbt) {
+            crate::psbt::v2::psbt_from_v2(&psbt).map_err(InternalProposalError::PsbtV2)?
+        } else {
+            Psbt::deserialize(&psbt).map_err(InternalProposalError::Psbt)?
+        };
+        Ok(Some(self.psbt_ctx.clone().process_proposal(proposal)?))
+    }
+
+    /// Extract a request telling the receiver that its Payjoin Proposal was accepted.
+    ///
+    /// Sending feedback is optional. It lets the receiver stop waiting on the sender.
+    pub fn extract_ack_req(
+        &self,
+        ohttp_relay: impl IntoUrl,
+    ) -> Result<(Request, V2MessageContext), CreateRequestError> {
+        let payload = serde_json::json!({ "feedback": "ack" }).to_string().into_bytes();
+        post_to_mailbox(&self.endpoint, &self.hpke_ctx, payload, ohttp_relay)
+    }
+
+    /// Extract a request telling the receiver that its Payjoin Proposal was rejected because
+    /// of `error`.
+    ///
+    /// Only the category of the failed check is shared, e.g. `versions-dont-match`, so the
+    /// receiver can diagnose incompatibilities and release the inputs it contributed early.
+    pub fn extract_nack_req(
+        &self,
+        ohttp_relay: impl IntoUrl,
+        error: &ResponseError,
+    ) -> Result<(Request, V2MessageContext), CreateRequestError> {
+        let payload = serde_json::json!({ "feedback": "nack", "reason": error.rejection_reason() })
+            .to_string()
+            .into_bytes();
+        post_to_mailbox(&self.endpoint, &self.hpke_ctx, payload, ohttp_relay)
+    }
+
+    /// Extract a request returning a batched Payjoin Proposal, `signed` by the sender, to the
+    /// receiver.
+    ///
+    /// The receiver broadcasts the transaction once every sender in the batch has signed it, so
+    /// this takes the place of both the acknowledgement and the broadcast.
+    pub fn extract_signatures_req(
+        &self,
+        ohttp_relay: impl IntoUrl,
+        signed: &Psbt,
+    ) -> Result<(Request, V2MessageContext), CreateRequestError> {
+        let payload = serde_json::json!({ "feedback": "ack", "psbt": signed.to_string() })
+            .to_string()
+            .into_bytes();
+        post_to_mailbox(&self.endpoint, &self.hpke_ctx, payload, ohttp_relay)
     }
 }
 
@@ -341,12 +609,170 @@ mod test {
                 fee_contribution: None,
                 min_fee_rate: FeeRate::ZERO,
                 payee: ScriptBuf::from(vec![0x00]),
+                policy: SenderPolicy::default().max_receiver_inputs(1).reject_dust_outputs(true),
+                deadline: Some(std::time::SystemTime::now()),
+                allow_batching: true,
+                psbt_v2: true,
+                unconfirmed_truc_parent: true,
             },
             reply_key: HpkeKeyPair::gen_keypair().0,
         };
+        assert!(!req_ctx.is_v2());
         let serialized = serde_json::to_string(&req_ctx)?;
         let deserialized = serde_json::from_str(&serialized)?;
         assert!(req_ctx == deserialized);
+
+        // The confirmation requirement survives even though its callback can't
+        let mut req_ctx = req_ctx;
+        req_ctx.v1.policy = SenderPolicy::default().require_confirmed_inputs(|_| true);
+        let deserialized: Sender = serde_json::from_str(&serde_json::to_string(&req_ctx)?)?;
+        assert!(deserialized.v1.policy.require_confirmed_inputs);
+        assert!(deserialized.v1.policy.input_confirmed.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn alternatives_address_the_same_mailbox() -> Result<(), payjoin_test_utils::BoxError> {
+        use super::*;
+        use crate::send::test::ORIGINAL_PSBT;
+        use crate::OhttpKeys;
+
+        let receiver = HpkeKeyPair::gen_keypair();
+        let mailbox: ShortId =
+            sha256::Hash::hash(&receiver.public_key().to_compressed_bytes()).into();
+        let ohttp_keys =
+            OhttpKeys::from_str("OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC")?;
+        let alternative = Url::parse("https://alternative.example.com")?;
+        let mut endpoint = Url::parse("https://primary.example.com")?;
+        endpoint.path_segments_mut().unwrap().push(&mailbox.to_string());
+        endpoint.set_receiver_pubkey(receiver.public_key().clone());
+        endpoint.set_ohttp(ohttp_keys.clone());
+        endpoint.set_alternative_directories(&[(alternative.clone(), ohttp_keys.clone())]);
+
+        let sender = Sender {
+            v1: v1::Sender {
+                psbt: Psbt::from_str(ORIGINAL_PSBT)?,
+                endpoint,
+                disable_output_substitution: false,
+                fee_contribution: None,
+                min_fee_rate: FeeRate::ZERO,
+                payee: ScriptBuf::from(vec![0x00]),
+                policy: SenderPolicy::default(),
+                deadline: None,
+                allow_batching: false,
+                psbt_v2: false,
+                unconfirmed_truc_parent: false,
+            },
+            reply_key: HpkeKeyPair::gen_keypair().0,
+        };
+        assert!(sender.is_v2());
+        // An expired URI can only be paid over v1
+        let mut expired = sender.clone();
+        expired
+            .v1
+            .endpoint
+            .set_exp(std::time::SystemTime::now() - std::time::Duration::from_secs(1));
+        assert!(!expired.is_v2());
+        let alternatives = sender.alternatives();
+        assert_eq!(alternatives.len(), 1);
+        let endpoint = alternatives[0].endpoint();
+        assert_eq!(endpoint.host_str(), alternative.host_str());
+        assert_eq!(endpoint.path(), sender.endpoint().path());
+        assert_eq!(endpoint.receiver_pubkey()?, *receiver.public_key());
+        assert!(alternatives[0].alternatives().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn large_original_psbt_in_parts() -> Result<(), payjoin_test_utils::BoxError> {
+        use super::*;
+        use crate::send::test::ORIGINAL_PSBT;
+
+        let mut psbt = Psbt::from_str(ORIGINAL_PSBT)?;
+        for _ in 0..100 {
+            psbt.unsigned_tx.output.push(bitcoin::TxOut {
+                value: bitcoin::Amount::ZERO,
+                script_pubkey: ScriptBuf::new_op_return([0u8; 75]),
+            });
+            psbt.outputs.push(Default::default());
+        }
+        let receiver = HpkeKeyPair::gen_keypair();
+        let mut endpoint = Url::parse("https://directory.example.com/mailbox")?;
+        endpoint.set_receiver_pubkey(receiver.public_key().clone());
+        endpoint.set_ohttp(OhttpKeys::from_str(
+            "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC",
+        )?);
+        let mut sender = Sender {
+            v1: v1::Sender {
+                psbt,
+                endpoint,
+                disable_output_substitution: false,
+                fee_contribution: None,
+                min_fee_rate: FeeRate::ZERO,
+                payee: ScriptBuf::from(vec![0x00]),
+                policy: SenderPolicy::default(),
+                deadline: None,
+                allow_batching: false,
+                psbt_v2: false,
+                unconfirmed_truc_parent: false,
+            },
+            reply_key: HpkeKeyPair::gen_keypair().0,
+        };
+        let relay = Url::parse("https://relay.example.com")?;
+        // Too large for a single message
+        assert!(sender.extract_v2(relay.clone()).is_err());
+
+        sender.v1.endpoint.set_max_messages(1);
+        assert!(sender.extract_v2(relay.clone()).is_err());
+
+        sender.v1.endpoint.set_max_messages(4);
+        assert!(sender.extract_v2(relay.clone()).is_ok());
+        let continuations = sender.extract_v2_continuations(relay)?;
+        assert_eq!(continuations.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn stale_ohttp_keys() -> Result<(), payjoin_test_utils::BoxError> {
+        use ohttp::hpke::{Aead, Kdf, Kem};
+        use ohttp::SymmetricSuite;
+
+        use super::*;
+        use crate::send::test::ORIGINAL_PSBT;
+
+        let receiver = HpkeKeyPair::gen_keypair();
+        let ohttp_keys =
+            OhttpKeys::from_str("OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC")?;
+        let mut endpoint = Url::parse("https://directory.example.com/mailbox")?;
+        endpoint.set_receiver_pubkey(receiver.public_key().clone());
+        endpoint.set_ohttp(ohttp_keys);
+        let mut sender = Sender {
+            v1: v1::Sender {
+                psbt: Psbt::from_str(ORIGINAL_PSBT)?,
+                endpoint,
+                disable_output_substitution: false,
+                fee_contribution: None,
+                min_fee_rate: FeeRate::ZERO,
+                payee: ScriptBuf::from(vec![0x00]),
+                policy: SenderPolicy::default(),
+                deadline: None,
+                allow_batching: false,
+                psbt_v2: false,
+                unconfirmed_truc_parent: false,
+            },
+            reply_key: HpkeKeyPair::gen_keypair().0,
+        };
+        let relay = Url::parse("https://relay.example.com")?;
+        let (_req, ctx) = sender.extract_v2(relay.clone())?;
+        let problem = br#"{"type":"https://iana.org/assignments/http-problem-types#ohttp-key", "title": "key identifier unknown"}"#;
+        let error = ctx.process_response(problem).err().ok_or("expected stale OHTTP keys")?;
+        assert!(error.ohttp_keys_stale().is_some());
+
+        let symmetric = vec![SymmetricSuite::new(Kdf::HkdfSha256, Aead::ChaCha20Poly1305)];
+        let fresh = OhttpKeys(ohttp::KeyConfig::new(2, Kem::K256Sha256, symmetric)?);
+        sender.update_ohttp_keys(fresh.clone());
+        assert_eq!(sender.endpoint().ohttp().ok(), Some(fresh));
+        assert!(sender.extract_v2(relay).is_ok());
         Ok(())
     }
 }
@@ -0,0 +1,155 @@
+//! Topologically Restricted Until Confirmation (TRUC) transactions
+//!
+//! [BIP 431](https://github.com/bitcoin/bips/blob/master/bip-0431.mediawiki) lets version 3
+//! transactions opt in to a stricter relay policy. A TRUC transaction may not exceed 10 kvB, may
+//! have at most one unconfirmed ancestor, which must itself be TRUC, and may not exceed 1 kvB
+//! while it has one.
+//!
+//! Payjoin participants can't tell which of each other's inputs are confirmed. Each enforces the
+//! limits that follow from what it knows about its own inputs: a sender whose Original spends an
+//! unconfirmed TRUC parent caps the Payjoin transaction at 1 kvB, and a receiver contributes only
+//! confirmed inputs to a TRUC Original, which may already spend an unconfirmed parent. Mempool
+//! checks like `testmempoolaccept` catch the rest.
+
+use std::fmt;
+
+use bitcoin::transaction::Version;
+use bitcoin::{OutPoint, Psbt, Weight};
+
+use crate::psbt::{InputWeightError, PsbtExt};
+
+/// The transaction version that opts in to the TRUC policy
+pub(crate) const TRUC_VERSION: Version = Version(3);
+
+/// The maximum virtual size of a TRUC transaction
+pub(crate) const MAX_TRUC_VSIZE: u64 = 10_000;
+
+/// The maximum virtual size of a TRUC transaction spending an unconfirmed TRUC parent
+pub(crate) const MAX_TRUC_CHILD_VSIZE: u64 = 1_000;
+
+pub(crate) fn is_truc(version: Version) -> bool { version == TRUC_VERSION }
+
+/// Check that a TRUC transaction of `weight` is within the TRUC size limit
+pub(crate) fn check_weight(weight: Weight) -> Result<(), TrucViolation> {
+    check_vsize(weight, MAX_TRUC_VSIZE)
+}
+
+/// Check that a TRUC transaction of `weight` spending an unconfirmed TRUC parent is within the
+/// size limit of such children
+pub(crate) fn check_child_weight(weight: Weight) -> Result<(), TrucViolation> {
+    check_vsize(weight, MAX_TRUC_CHILD_VSIZE)
+}
+
+fn check_vsize(weight: Weight, max: u64) -> Result<(), TrucViolation> {
+    let vsize = weight.to_vbytes_ceil();
+    if vsize > max {
+        return Err(TrucViolation::TooLarge { vsize, max });
+    }
+    Ok(())
+}
+
+/// Predict the weight of `psbt` once every input is signed.
+///
+/// Inputs without final scripts take them from the first of `signed` spending the same outpoint,
+/// or else are assumed to be signed like [`crate::psbt::InternalInputPair::expected_input_weight`]
+/// predicts.
+pub(crate) fn predicted_weight(psbt: &Psbt, signed: &[&Psbt]) -> Result<Weight, InputWeightError> {
+    let mut tx = psbt.unsigned_tx.clone();
+    let mut unsigned_weight = Weight::ZERO;
+    for (input, txin) in psbt.input_pairs().zip(tx.input.iter_mut()) {
+        let finalized = |psbtin: &bitcoin::psbt::Input| {
+            psbtin.final_script_sig.is_some() || psbtin.final_script_witness.is_some()
+        };
+        let signed_input = Some(input.psbtin).filter(|psbtin| finalized(psbtin)).or_else(|| {
+            signed.iter().find_map(|psbt| {
+                psbt.input_pairs()
+                    .find(|signed| signed.txin.previous_output == txin.previous_output)
+                    .map(|signed| signed.psbtin)
+                    .filter(|psbtin| finalized(psbtin))
+            })
+        });
+        match signed_input {
+            Some(psbtin) => {
+                txin.script_sig = psbtin.final_script_sig.clone().unwrap_or_default();
+                txin.witness = psbtin.final_script_witness.clone().unwrap_or_default();
+            }
+            None => {
+                let unsigned = Weight::from_non_witness_data_size(txin.base_size() as u64);
+                unsigned_weight += input.expected_input_weight()? - unsigned;
+            }
+        }
+    }
+    // Assume predicted inputs spend segwit outputs, which need the segwit marker and flag
+    if unsigned_weight > Weight::ZERO && tx.input.iter().all(|txin| txin.witness.is_empty()) {
+        unsigned_weight += Weight::from_wu(2);
+    }
+    Ok(tx.weight() + unsigned_weight)
+}
+
+/// A TRUC transaction that relay policy would reject
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum TrucViolation {
+    /// The transaction exceeds [`MAX_TRUC_VSIZE`], or [`MAX_TRUC_CHILD_VSIZE`] while it spends
+    /// an unconfirmed TRUC parent
+    TooLarge { vsize: u64, max: u64 },
+    /// The receiver contributed an input spending an unconfirmed output, which would give the
+    /// transaction a second unconfirmed ancestor if the Original already spends one
+    UnconfirmedInput(OutPoint),
+}
+
+impl fmt::Display for TrucViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrucViolation::TooLarge { vsize, max } =>
+                write!(f, "TRUC transaction of {} vB exceeds the {} vB limit", vsize, max),
+            TrucViolation::UnconfirmedInput(outpoint) => write!(
+                f,
+                "unconfirmed input {} may exceed the TRUC limit of one unconfirmed ancestor",
+                outpoint
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TrucViolation {}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::receive::v1::test::ORIGINAL_PSBT;
+
+    #[test]
+    fn predicts_weight_of_unsigned_inputs() {
+        let original = Psbt::from_str(ORIGINAL_PSBT).unwrap();
+        let signed_weight = original.clone().extract_tx_unchecked_fee_rate().weight();
+        assert_eq!(predicted_weight(&original, &[]).unwrap(), signed_weight);
+
+        // Unsigned inputs take their final scripts from the signed Original
+        let mut unsigned = original.clone();
+        let script_sig = unsigned.inputs[0].final_script_sig.take().unwrap();
+        unsigned.inputs[0].final_script_witness = None;
+        assert_eq!(predicted_weight(&unsigned, &[&original]).unwrap(), signed_weight);
+        // or else are predicted conservatively
+        unsigned.inputs[0].redeem_script = script_sig.redeem_script().map(ToOwned::to_owned);
+        assert!(predicted_weight(&unsigned, &[]).unwrap() >= signed_weight);
+    }
+
+    #[test]
+    fn limits_vsize() {
+        assert!(check_weight(Weight::from_vb_unchecked(MAX_TRUC_VSIZE)).is_ok());
+        assert_eq!(
+            check_weight(Weight::from_vb_unchecked(MAX_TRUC_VSIZE) + Weight::from_wu(1)),
+            Err(TrucViolation::TooLarge { vsize: MAX_TRUC_VSIZE + 1, max: MAX_TRUC_VSIZE })
+        );
+        assert!(check_child_weight(Weight::from_vb_unchecked(MAX_TRUC_CHILD_VSIZE)).is_ok());
+        assert_eq!(
+            check_child_weight(Weight::from_vb_unchecked(MAX_TRUC_CHILD_VSIZE + 1)),
+            Err(TrucViolation::TooLarge {
+                vsize: MAX_TRUC_CHILD_VSIZE + 1,
+                max: MAX_TRUC_CHILD_VSIZE
+            })
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use bitcoin::{Address, Amount};
+use url::Url;
+
+use super::error::{InternalPjUriBuildError, PjUriBuildError};
+use super::{is_secure_endpoint, PayjoinExtras, PjUri};
+
+/// BIP 21 parameters a passthrough parameter may not shadow
+const RESERVED_PARAMS: &[&str] = &["amount", "label", "message", "pj", "pjos"];
+
+/// Whether `key` consists only of the unreserved characters BIP 21 allows in parameter names
+fn is_valid_param_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c))
+}
+
+/// Build a [`PjUri`] for either a v1 endpoint or a v2 directory mailbox.
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use payjoin::bitcoin::{Address, Amount};
+/// # use payjoin::{PjUriBuilder, Url};
+/// let address = Address::from_str("tb1q6d3a2w975yny0asuvd9a67ner4nks58ff0q8g4")
+///     .unwrap()
+///     .assume_checked();
+/// let uri = PjUriBuilder::new(address, Url::parse("https://example.com/pj").unwrap())
+///     .amount(Amount::from_sat(50_000))
+///     .label("coffee")
+///     .extra_param("lightning", "LNBC1")
+///     .build()
+///     .unwrap();
+/// assert_eq!(uri.amount, Some(Amount::from_sat(50_000)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct PjUriBuilder {
+    address: Address,
+    endpoint: Url,
+    amount: Option<Amount>,
+    label: Option<String>,
+    message: Option<String>,
+    disable_output_substitution: bool,
+    #[cfg(feature = "v2")]
+    expiry: Option<std::time::SystemTime>,
+    #[cfg(feature = "v2")]
+    ohttp_keys_fingerprint: Option<crate::OhttpKeysFingerprint>,
+    extra_params: Vec<(String, String)>,
+}
+
+impl PjUriBuilder {
+    /// Start building a URI paying `address` with payjoin `endpoint`.
+    ///
+    /// For v2, `endpoint` is the receiver's mailbox URL including its fragment parameters, as
+    /// prepared by [`crate::receive::v2::Receiver::pj_uri_builder`].
+    pub fn new(address: Address, endpoint: Url) -> Self {
+        Self {
+            address,
+            endpoint,
+            amount: None,
+            label: None,
+            message: None,
+            disable_output_substitution: false,
+            #[cfg(feature = "v2")]
+            expiry: None,
+            #[cfg(feature = "v2")]
+            ohttp_keys_fingerprint: None,
+            extra_params: Vec::new(),
+        }
+    }
+
+    /// Request a specific amount
+    pub fn amount(mut self, amount: Amount) -> Self {
+        self.amount = Some(amount);
+        self
+    }
+
+    /// Label the address for the sender, e.g. the receiver's name
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// A note describing the payment for the sender
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    /// Set `pjos=1` to forbid the receiver from substituting outputs
+    pub fn pjos(mut self, disable_output_substitution: bool) -> Self {
+        self.disable_output_substitution = disable_output_substitution;
+        self
+    }
+
+    /// Override when the v2 session stops accepting requests
+    ///
+    /// Only valid for v2 endpoints, which carry the receiver's public key.
+    #[cfg(feature = "v2")]
+    pub fn expiry(mut self, expiry: std::time::SystemTime) -> Self {
+        self.expiry = Some(expiry);
+        self
+    }
+
+    /// Pin the directory's OHTTP keys with a `KF1` fingerprint in the endpoint's fragment
+    ///
+    /// Senders that fetch the keys through an OHTTP gateway instead of from the directory check
+    /// them against it.
+    #[cfg(feature = "v2")]
+    pub fn ohttp_keys_fingerprint(mut self, fingerprint: crate::OhttpKeysFingerprint) -> Self {
+        self.ohttp_keys_fingerprint = Some(fingerprint);
+        self
+    }
+
+    /// Pass an additional BIP 21 parameter through to the URI, e.g. `lightning=`
+    pub fn extra_param(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.extra_params.push((key.into(), value.into()));
+        self
+    }
+
+    /// Validate the parameters and build the URI
+    pub fn build<'a>(self) -> Result<PjUri<'a>, PjUriBuildError> {
+        if !is_secure_endpoint(&self.endpoint) {
+            return Err(InternalPjUriBuildError::UnsecureEndpoint.into());
+        }
+        if let Some(amount) = self.amount {
+            if amount == Amount::ZERO || amount > Amount::MAX_MONEY {
+                return Err(InternalPjUriBuildError::InvalidAmount(amount).into());
+            }
+        }
+        for (i, (key, _)) in self.extra_params.iter().enumerate() {
+            if !is_valid_param_key(key) {
+                return Err(InternalPjUriBuildError::InvalidParamKey(key.clone()).into());
+            }
+            let lowercase = key.to_lowercase();
+            if RESERVED_PARAMS.contains(&lowercase.as_str()) || lowercase.starts_with("req-") {
+                return Err(InternalPjUriBuildError::ReservedParam(key.clone()).into());
+            }
+            if self.extra_params[..i].iter().any(|(k, _)| k.eq_ignore_ascii_case(key)) {
+                return Err(InternalPjUriBuildError::DuplicateParam(key.clone()).into());
+            }
+        }
+
+        #[allow(unused_mut)]
+        let mut endpoint = self.endpoint;
+        #[cfg(feature = "v2")]
+        if let Some(expiry) = self.expiry {
+            use super::UrlExt;
+            if endpoint.receiver_pubkey().is_err() {
+                return Err(InternalPjUriBuildError::ExpiryWithoutV2Endpoint.into());
+            }
+            if expiry < std::time::SystemTime::now() {
+                return Err(InternalPjUriBuildError::Expired(expiry).into());
+            }
+            endpoint.set_exp(expiry);
+        }
+        #[cfg(feature = "v2")]
+        if let Some(fingerprint) = self.ohttp_keys_fingerprint {
+            use super::UrlExt;
+            endpoint.set_ohttp_keys_fingerprint(fingerprint);
+        }
+
+        let extras = PayjoinExtras {
+            endpoint,
+            disable_output_substitution: self.disable_output_substitution,
+            extra_params: self.extra_params,
+        };
+        let mut uri = bitcoin_uri::Uri::with_extras(self.address, extras);
+        uri.amount = self.amount;
+        uri.label = self.label.map(Into::into);
+        uri.message = self.message.map(Into::into);
+        Ok(uri)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::{Uri, UriExt};
+
+    fn builder() -> PjUriBuilder {
+        let address = Address::from_str("tb1q6d3a2w975yny0asuvd9a67ner4nks58ff0q8g4")
+            .unwrap()
+            .assume_checked();
+        PjUriBuilder::new(address, Url::parse("https://example.com/pj").unwrap())
+    }
+
+    #[test]
+    fn test_build_roundtrip() {
+        let uri = builder()
+            .amount(Amount::from_sat(50_000))
+            .label("Alice")
+            .message("coffee & cake")
+            .pjos(true)
+            .extra_param("lightning", "LNBC1")
+            .build()
+            .unwrap();
+        let serialized = uri.to_string();
+        assert!(serialized.contains("&lightning=LNBC1"));
+
+        let parsed =
+            Uri::from_str(&serialized).unwrap().assume_checked().check_pj_supported().unwrap();
+        assert_eq!(parsed.amount, Some(Amount::from_sat(50_000)));
+        assert!(parsed.extras.is_output_substitution_disabled());
+        assert_eq!(parsed.extras.extra_params(), &[("lightning".to_owned(), "LNBC1".to_owned())]);
+        assert_eq!(parsed.to_string(), serialized);
+    }
+
+    #[test]
+    fn test_invalid_combinations() {
+        assert!(builder().amount(Amount::ZERO).build().is_err());
+        assert!(builder().extra_param("amount", "1").build().is_err());
+        assert!(builder().extra_param("req-foo", "1").build().is_err());
+        assert!(builder().extra_param("a=b", "1").build().is_err());
+        assert!(builder().extra_param("x", "1").extra_param("X", "2").build().is_err());
+        assert!(PjUriBuilder::new(builder().address, Url::parse("http://example.com").unwrap())
+            .build()
+            .is_err());
+        #[cfg(feature = "v2")]
+        assert!(builder()
+            .expiry(std::time::SystemTime::now() + std::time::Duration::from_secs(60))
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_invalid_param_key() {
+        assert!(builder().extra_param("x-custom.param_1~", "1").build().is_ok());
+        for key in ["", "a b", "a%20b", "a+b", "a/b", "ä"] {
+            let error = builder().extra_param(key, "1").build().unwrap_err();
+            assert_eq!(error.to_string(), format!("Invalid parameter name '{}'", key));
+        }
+    }
+
+    #[cfg(feature = "v2")]
+    #[test]
+    fn test_ohttp_keys_fingerprint() {
+        let fingerprint = crate::OhttpKeys::from_str(
+            "OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC",
+        )
+        .unwrap()
+        .fingerprint()
+        .unwrap();
+        let uri = builder().ohttp_keys_fingerprint(fingerprint).build().unwrap();
+        assert_eq!(uri.extras.endpoint().fragment(), Some(fingerprint.to_string().as_str()));
+
+        let parsed =
+            Uri::from_str(&uri.to_string()).unwrap().assume_checked().check_pj_supported().unwrap();
+        assert_eq!(parsed.extras.endpoint(), uri.extras.endpoint());
+    }
+}
@@ -32,3 +32,44 @@ impl std::fmt::Display for PjParseError {
         }
     }
 }
+
+/// Error building a [`crate::PjUri`] with [`crate::PjUriBuilder`]
+#[derive(Debug)]
+pub struct PjUriBuildError(InternalPjUriBuildError);
+
+#[derive(Debug)]
+pub(crate) enum InternalPjUriBuildError {
+    UnsecureEndpoint,
+    InvalidAmount(bitcoin::Amount),
+    InvalidParamKey(String),
+    ReservedParam(String),
+    DuplicateParam(String),
+    #[cfg(feature = "v2")]
+    ExpiryWithoutV2Endpoint,
+    #[cfg(feature = "v2")]
+    Expired(std::time::SystemTime),
+}
+
+impl From<InternalPjUriBuildError> for PjUriBuildError {
+    fn from(value: InternalPjUriBuildError) -> Self { PjUriBuildError(value) }
+}
+
+impl std::fmt::Display for PjUriBuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use InternalPjUriBuildError::*;
+        match &self.0 {
+            UnsecureEndpoint => write!(f, "Endpoint scheme is not secure (https or onion)"),
+            InvalidAmount(amount) => write!(f, "Invalid amount {}", amount),
+            InvalidParamKey(key) => write!(f, "Invalid parameter name '{}'", key),
+            ReservedParam(key) => write!(f, "Parameter '{}' may not be passed through", key),
+            DuplicateParam(key) => write!(f, "Multiple instances of parameter '{}'", key),
+            #[cfg(feature = "v2")]
+            ExpiryWithoutV2Endpoint =>
+                write!(f, "Expiry requires a v2 endpoint with a receiver public key"),
+            #[cfg(feature = "v2")]
+            Expired(expiry) => write!(f, "Expiry {:?} is in the past", expiry),
+        }
+    }
+}
+
+impl std::error::Error for PjUriBuildError {}
@@ -1,7 +1,8 @@
 use std::borrow::Cow;
 
 use bitcoin::address::NetworkChecked;
-pub use error::PjParseError;
+pub use builder::PjUriBuilder;
+pub use error::{PjParseError, PjUriBuildError};
 use url::Url;
 
 #[cfg(feature = "v2")]
@@ -10,6 +11,7 @@ use crate::uri::error::InternalPjParseError;
 #[cfg(feature = "v2")]
 pub(crate) use crate::uri::url_ext::UrlExt;
 
+mod builder;
 pub mod error;
 #[cfg(feature = "v2")]
 pub(crate) mod url_ext;
@@ -33,10 +35,21 @@ impl MaybePayjoinExtras {
 pub struct PayjoinExtras {
     pub(crate) endpoint: Url,
     pub(crate) disable_output_substitution: bool,
+    /// BIP 21 parameters unrelated to payjoin, passed through as-is
+    pub(crate) extra_params: Vec<(String, String)>,
 }
 
 impl PayjoinExtras {
     pub fn endpoint(&self) -> &Url { &self.endpoint }
+
+    /// BIP 21 parameters unrelated to payjoin, such as `lightning=`
+    pub fn extra_params(&self) -> &[(String, String)] { &self.extra_params }
+}
+
+/// Whether a payjoin endpoint may be used, i.e. it is https or an http onion service
+pub(crate) fn is_secure_endpoint(endpoint: &Url) -> bool {
+    endpoint.scheme() == "https"
+        || endpoint.scheme() == "http" && endpoint.domain().unwrap_or_default().ends_with(".onion")
 }
 
 pub type Uri<'a, NetworkValidation> = bitcoin_uri::Uri<'a, NetworkValidation, MaybePayjoinExtras>;
@@ -96,10 +109,11 @@ impl bitcoin_uri::de::DeserializeParams<'_> for MaybePayjoinExtras {
 pub struct DeserializationState {
     pj: Option<Url>,
     pjos: Option<bool>,
+    extra_params: Vec<(String, String)>,
 }
 
 impl bitcoin_uri::SerializeParams for &MaybePayjoinExtras {
-    type Key = &'static str;
+    type Key = String;
     type Value = String;
     type Iterator = std::vec::IntoIter<(Self::Key, Self::Value)>;
 
@@ -112,7 +126,7 @@ impl bitcoin_uri::SerializeParams for &MaybePayjoinExtras {
 }
 
 impl bitcoin_uri::SerializeParams for &PayjoinExtras {
-    type Key = &'static str;
+    type Key = String;
     type Value = String;
     type Iterator = std::vec::IntoIter<(Self::Key, Self::Value)>;
 
@@ -127,11 +141,15 @@ impl bitcoin_uri::SerializeParams for &PayjoinExtras {
             .replacen(scheme, &scheme.to_uppercase(), 1)
             .replacen(host, &host.to_uppercase(), 1);
 
-        vec![
-            ("pjos", if self.disable_output_substitution { "1" } else { "0" }.to_string()),
-            ("pj", endpoint_str),
-        ]
-        .into_iter()
+        let mut params = vec![
+            (
+                "pjos".to_owned(),
+                if self.disable_output_substitution { "1" } else { "0" }.to_owned(),
+            ),
+            ("pj".to_owned(), endpoint_str),
+        ];
+        params.extend(self.extra_params.iter().cloned());
+        params.into_iter()
     }
 }
 
@@ -166,7 +184,13 @@ impl bitcoin_uri::de::DeserializationState<'_> for DeserializationState {
                 Ok(bitcoin_uri::de::ParamKind::Known)
             }
             "pjos" => Err(InternalPjParseError::DuplicateParams("pjos").into()),
-            _ => Ok(bitcoin_uri::de::ParamKind::Unknown),
+            _ => {
+                // keep unrelated parameters so that they survive re-serialization
+                if let Ok(value) = Cow::try_from(value) {
+                    self.extra_params.push((key.to_owned(), value.into_owned()));
+                }
+                Ok(bitcoin_uri::de::ParamKind::Unknown)
+            }
         }
     }
 
@@ -177,19 +201,16 @@ impl bitcoin_uri::de::DeserializationState<'_> for DeserializationState {
         match (self.pj, self.pjos) {
             (None, None) => Ok(MaybePayjoinExtras::Unsupported),
             (None, Some(_)) => Err(InternalPjParseError::MissingEndpoint.into()),
-            (Some(endpoint), pjos) => {
-                if endpoint.scheme() == "https"
-                    || endpoint.scheme() == "http"
-                        && endpoint.domain().unwrap_or_default().ends_with(".onion")
-                {
+            (Some(endpoint), pjos) =>
+                if is_secure_endpoint(&endpoint) {
                     Ok(MaybePayjoinExtras::Supported(PayjoinExtras {
                         endpoint,
                         disable_output_substitution: pjos.unwrap_or(false),
+                        extra_params: self.extra_params,
                     }))
                 } else {
                     Err(InternalPjParseError::UnsecureEndpoint.into())
-                }
-            }
+                },
         }
     }
 }
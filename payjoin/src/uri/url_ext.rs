@@ -6,7 +6,9 @@ use bitcoin::consensus::Encodable;
 use url::Url;
 
 use crate::hpke::HpkePublicKey;
-use crate::ohttp::OhttpKeys;
+#[cfg(feature = "_io")]
+use crate::ohttp::ParseOhttpKeysError;
+use crate::ohttp::{OhttpKeys, OhttpKeysFingerprint};
 
 /// Parse and set fragment parameters from `&pj=` URI parameter URLs
 pub(crate) trait UrlExt {
@@ -16,6 +18,15 @@ pub(crate) trait UrlExt {
     fn set_ohttp(&mut self, ohttp: OhttpKeys);
     fn exp(&self) -> Result<std::time::SystemTime, ParseExpParamError>;
     fn set_exp(&mut self, exp: std::time::SystemTime);
+    fn alternative_directories(
+        &self,
+    ) -> Result<Vec<(Url, OhttpKeys)>, ParseAlternativeDirectoryParamError>;
+    fn set_alternative_directories(&mut self, alternatives: &[(Url, OhttpKeys)]);
+    fn max_messages(&self) -> Option<u8>;
+    fn set_max_messages(&mut self, max_messages: u8);
+    #[cfg(feature = "_io")]
+    fn ohttp_keys_fingerprint(&self) -> Result<Option<OhttpKeysFingerprint>, ParseOhttpKeysError>;
+    fn set_ohttp_keys_fingerprint(&mut self, fingerprint: OhttpKeysFingerprint);
 }
 
 impl UrlExt for Url {
@@ -95,8 +106,99 @@ impl UrlExt for Url {
 
         set_param(self, "EX1", &exp_str)
     }
+
+    /// Retrieve the alternative directories and their ohttp keys from the URL fragment
+    ///
+    /// Each alternative is a separate `AD1` parameter holding `key_id || compressed_public_key`
+    /// followed by the directory URL.
+    fn alternative_directories(
+        &self,
+    ) -> Result<Vec<(Url, OhttpKeys)>, ParseAlternativeDirectoryParamError> {
+        let ad_hrp: Hrp = Hrp::parse("AD").unwrap();
+        get_params(self, "AD1")
+            .map(|value| {
+                let (hrp, bytes) = crate::bech32::nochecksum::decode(value)
+                    .map_err(ParseAlternativeDirectoryParamError::DecodeBech32)?;
+                if hrp != ad_hrp {
+                    return Err(ParseAlternativeDirectoryParamError::InvalidHrp(hrp));
+                }
+                if bytes.len() < COMPACT_OHTTP_KEYS_LEN {
+                    return Err(ParseAlternativeDirectoryParamError::InvalidOhttpKeys(
+                        crate::ohttp::ParseOhttpKeysError::InvalidFormat,
+                    ));
+                }
+                let (ohttp_bytes, url_bytes) = bytes.split_at(COMPACT_OHTTP_KEYS_LEN);
+                let ohttp_keys = OhttpKeys::try_from(ohttp_bytes)
+                    .map_err(ParseAlternativeDirectoryParamError::InvalidOhttpKeys)?;
+                let directory = std::str::from_utf8(url_bytes)
+                    .ok()
+                    .and_then(|url| Url::parse(url).ok())
+                    .ok_or(ParseAlternativeDirectoryParamError::InvalidDirectory)?;
+                Ok((directory, ohttp_keys))
+            })
+            .collect()
+    }
+
+    /// Replace the alternative directories in the URL fragment
+    fn set_alternative_directories(&mut self, alternatives: &[(Url, OhttpKeys)]) {
+        while get_param(self, "AD1", |_| Some(())).is_some() {
+            remove_param(self, "AD1");
+        }
+        let ad_hrp: Hrp = Hrp::parse("AD").unwrap();
+        for (directory, ohttp_keys) in alternatives {
+            let mut bytes =
+                ohttp_keys.to_compact_bytes().expect("ohttp keys should always encode compactly");
+            bytes.extend_from_slice(directory.as_str().as_bytes());
+            let ad_str = crate::bech32::nochecksum::encode(ad_hrp, &bytes)
+                .expect("encoding alternative directory bytes should never fail");
+            append_param(self, &ad_str);
+        }
+    }
+
+    /// Retrieve the number of messages the receiver accepts an Original PSBT split into from
+    /// the URL fragment
+    ///
+    /// A missing or malformed parameter means the Original PSBT must fit in a single message.
+    fn max_messages(&self) -> Option<u8> {
+        let value = get_param(self, "MP1", |v| Some(v.to_owned()))?;
+        let (hrp, bytes) = crate::bech32::nochecksum::decode(&value).ok()?;
+        if hrp != Hrp::parse("MP").unwrap() {
+            return None;
+        }
+        match bytes[..] {
+            [max_messages] => Some(max_messages),
+            _ => None,
+        }
+    }
+
+    /// Set the number of messages the receiver accepts an Original PSBT split into in the URL
+    /// fragment
+    fn set_max_messages(&mut self, max_messages: u8) {
+        let mp_hrp: Hrp = Hrp::parse("MP").unwrap();
+        let mp_str = crate::bech32::nochecksum::encode(mp_hrp, &[max_messages])
+            .expect("encoding a single byte should never fail");
+        set_param(self, "MP1", &mp_str)
+    }
+
+    /// Retrieve the fingerprint OHTTP keys for this directory must match from the URL fragment
+    ///
+    /// A missing fingerprint is `None`, while a malformed one is an error.
+    #[cfg(feature = "_io")]
+    fn ohttp_keys_fingerprint(&self) -> Result<Option<OhttpKeysFingerprint>, ParseOhttpKeysError> {
+        get_param(self, "KF1", |value| Some(value.to_owned()))
+            .map(|value| OhttpKeysFingerprint::from_str(&value))
+            .transpose()
+    }
+
+    /// Set the fingerprint OHTTP keys for this directory must match in the URL fragment
+    fn set_ohttp_keys_fingerprint(&mut self, fingerprint: OhttpKeysFingerprint) {
+        set_param(self, "KF1", &fingerprint.to_string())
+    }
 }
 
+/// Length of `key_id || compressed_public_key`
+const COMPACT_OHTTP_KEYS_LEN: usize = 34;
+
 fn get_param<F, T>(url: &Url, prefix: &str, parse: F) -> Option<T>
 where
     F: Fn(&str) -> Option<T>,
@@ -111,22 +213,35 @@ where
     None
 }
 
+fn get_params<'a>(url: &'a Url, prefix: &'a str) -> impl Iterator<Item = &'a str> {
+    url.fragment()
+        .into_iter()
+        .flat_map(|fragment| fragment.split('+'))
+        .filter(move |param| param.starts_with(prefix))
+}
+
 fn set_param(url: &mut Url, prefix: &str, param: &str) {
-    let fragment = url.fragment().unwrap_or("");
-    let mut fragment = fragment.to_string();
-    if let Some(start) = fragment.find(prefix) {
-        let end = fragment[start..].find('+').map_or(fragment.len(), |i| start + i);
-        fragment.replace_range(start..end, "");
-        if fragment.ends_with('+') {
-            fragment.pop();
-        }
+    remove_param(url, prefix);
+    append_param(url, param);
+}
+
+/// Remove the first parameter starting with `prefix`
+fn remove_param(url: &mut Url, prefix: &str) {
+    let fragment = url.fragment().unwrap_or("").to_string();
+    let mut params: Vec<&str> = fragment.split('+').filter(|param| !param.is_empty()).collect();
+    if let Some(index) = params.iter().position(|param| param.starts_with(prefix)) {
+        params.remove(index);
     }
+    let fragment = params.join("+");
+    url.set_fragment(if fragment.is_empty() { None } else { Some(&fragment) });
+}
 
+fn append_param(url: &mut Url, param: &str) {
+    let mut fragment = url.fragment().unwrap_or("").to_string();
     if !fragment.is_empty() {
         fragment.push('+');
     }
     fragment.push_str(param);
-
     url.set_fragment(if fragment.is_empty() { None } else { Some(&fragment) });
 }
 
@@ -173,6 +288,43 @@ impl std::fmt::Display for ParseExpParamError {
     }
 }
 
+#[cfg(feature = "v2")]
+#[derive(Debug)]
+pub(crate) enum ParseAlternativeDirectoryParamError {
+    InvalidHrp(bitcoin::bech32::Hrp),
+    DecodeBech32(bitcoin::bech32::primitives::decode::CheckedHrpstringError),
+    InvalidOhttpKeys(crate::ohttp::ParseOhttpKeysError),
+    InvalidDirectory,
+}
+
+#[cfg(feature = "v2")]
+impl std::fmt::Display for ParseAlternativeDirectoryParamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use ParseAlternativeDirectoryParamError::*;
+
+        match &self {
+            InvalidHrp(h) => write!(f, "incorrect hrp for alternative directory: {}", h),
+            DecodeBech32(d) => write!(f, "alternative directory is not valid bech32: {}", d),
+            InvalidOhttpKeys(o) => write!(f, "invalid alternative directory ohttp keys: {}", o),
+            InvalidDirectory => write!(f, "alternative directory is not a valid url"),
+        }
+    }
+}
+
+#[cfg(feature = "v2")]
+impl std::error::Error for ParseAlternativeDirectoryParamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        use ParseAlternativeDirectoryParamError::*;
+
+        match &self {
+            InvalidHrp(_) => None,
+            DecodeBech32(error) => Some(error),
+            InvalidOhttpKeys(error) => Some(error),
+            InvalidDirectory => None,
+        }
+    }
+}
+
 #[cfg(feature = "v2")]
 #[derive(Debug)]
 pub(crate) enum ParseReceiverPubkeyParamError {
@@ -279,6 +431,83 @@ mod tests {
         assert!(matches!(invalid_timestamp_exp_url.exp(), Err(ParseExpParamError::InvalidExp(_))))
     }
 
+    #[test]
+    fn test_alternative_directories_get_set() {
+        let mut url = Url::parse("https://example.com").unwrap();
+        let ohttp_keys =
+            OhttpKeys::from_str("OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC")
+                .unwrap();
+        url.set_ohttp(ohttp_keys.clone());
+        assert!(url.alternative_directories().unwrap().is_empty());
+
+        let alternatives = vec![
+            (Url::parse("https://directory.example.com/").unwrap(), ohttp_keys.clone()),
+            (Url::parse("https://Other.example.com/CaseSensitive").unwrap(), ohttp_keys.clone()),
+        ];
+        url.set_alternative_directories(&alternatives);
+        url.set_exp(std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1720547781));
+        assert_eq!(url.alternative_directories().unwrap(), alternatives);
+        assert_eq!(url.ohttp().unwrap(), ohttp_keys);
+        assert!(url.fragment().unwrap().starts_with("OH1"));
+
+        url.set_alternative_directories(&alternatives[1..]);
+        assert_eq!(url.alternative_directories().unwrap(), alternatives[1..]);
+        assert_eq!(
+            url.exp().unwrap().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
+            1720547781
+        );
+
+        url.set_alternative_directories(&[]);
+        assert!(url.alternative_directories().unwrap().is_empty());
+        assert_eq!(url.fragment().unwrap().matches('+').count(), 1);
+    }
+
+    #[test]
+    fn test_remove_param_matches_whole_params() {
+        let mut url = Url::parse("https://example.com#XAD1QQ+AD1ZZ+EX1QQ").unwrap();
+        remove_param(&mut url, "AD1");
+        assert_eq!(url.fragment(), Some("XAD1QQ+EX1QQ"));
+        remove_param(&mut url, "AD1");
+        assert_eq!(url.fragment(), Some("XAD1QQ+EX1QQ"));
+        remove_param(&mut url, "XAD1");
+        remove_param(&mut url, "EX1");
+        assert_eq!(url.fragment(), None);
+    }
+
+    #[test]
+    fn test_max_messages_get_set() {
+        let mut url = Url::parse("https://example.com").unwrap();
+        assert_eq!(url.max_messages(), None);
+
+        url.set_max_messages(16);
+        assert_eq!(url.max_messages(), Some(16));
+        url.set_max_messages(255);
+        assert_eq!(url.max_messages(), Some(255));
+
+        url.set_fragment(Some("MP1QQQQQQ"));
+        assert_eq!(url.max_messages(), None);
+    }
+
+    #[cfg(feature = "_io")]
+    #[test]
+    fn test_ohttp_keys_fingerprint_get_set() {
+        let mut url = Url::parse("https://example.com").unwrap();
+        assert!(matches!(url.ohttp_keys_fingerprint(), Ok(None)));
+
+        let fingerprint =
+            OhttpKeys::from_str("OH1QYPM5JXYNS754Y4R45QWE336QFX6ZR8DQGVQCULVZTV20TFVEYDMFQC")
+                .unwrap()
+                .fingerprint()
+                .unwrap();
+        url.set_ohttp_keys_fingerprint(fingerprint);
+        url.set_max_messages(16);
+        assert_eq!(url.ohttp_keys_fingerprint().unwrap(), Some(fingerprint));
+        assert_eq!(url.max_messages(), Some(16));
+
+        url.set_fragment(Some("KF1QQQQQQ"));
+        assert!(url.ohttp_keys_fingerprint().is_err());
+    }
+
     #[test]
     fn test_valid_v2_url_fragment_on_bip21() {
         let uri = "bitcoin:12c6DSiU4Rq3P4ZxziKxzrL5LmMBrzjrJX?amount=0.01\
@@ -99,7 +99,8 @@ mod integration {
             // **********************
             // Inside the Sender:
             // Sender checks, signs, finalizes, extracts, and broadcasts
-            let checked_payjoin_proposal_psbt = ctx.process_response(&mut response.as_bytes())?;
+            let (checked_payjoin_proposal_psbt, _) =
+                ctx.process_response(&mut response.as_bytes())?;
             let payjoin_tx = extract_pj_tx(&sender, checked_payjoin_proposal_psbt)?;
             sender.send_raw_transaction(&payjoin_tx)?;
 
@@ -357,7 +358,7 @@ mod integration {
                     .send()
                     .await?;
                 log::info!("Response: {:#?}", &response);
-                let checked_payjoin_proposal_psbt = send_ctx
+                let (checked_payjoin_proposal_psbt, _) = send_ctx
                     .process_response(&response.bytes().await?, ohttp_ctx)?
                     .expect("psbt should exist");
                 let payjoin_tx = extract_pj_tx(&sender, checked_payjoin_proposal_psbt)?;
@@ -412,7 +413,8 @@ mod integration {
             // **********************
             // Inside the Sender:
             // Sender checks, signs, finalizes, extracts, and broadcasts
-            let checked_payjoin_proposal_psbt = ctx.process_response(&mut response.as_bytes())?;
+            let (checked_payjoin_proposal_psbt, _) =
+                ctx.process_response(&mut response.as_bytes())?;
             let payjoin_tx = extract_pj_tx(&sender, checked_payjoin_proposal_psbt)?;
             sender.send_raw_transaction(&payjoin_tx)?;
 
@@ -528,7 +530,7 @@ mod integration {
                 assert!(response.status().is_success(), "error response: {}", response.status());
 
                 let res = response.bytes().await?.to_vec();
-                let checked_payjoin_proposal_psbt =
+                let (checked_payjoin_proposal_psbt, _) =
                     send_ctx.process_response(&mut res.as_slice())?;
                 let payjoin_tx = extract_pj_tx(&sender, checked_payjoin_proposal_psbt)?;
                 sender.send_raw_transaction(&payjoin_tx)?;
@@ -730,7 +732,8 @@ mod integration {
             // **********************
             // Inside the Sender:
             // Sender checks, signs, finalizes, extracts, and broadcasts
-            let checked_payjoin_proposal_psbt = ctx.process_response(&mut response.as_bytes())?;
+            let (checked_payjoin_proposal_psbt, _) =
+                ctx.process_response(&mut response.as_bytes())?;
             let payjoin_tx = extract_pj_tx(&sender, checked_payjoin_proposal_psbt)?;
             sender.send_raw_transaction(&payjoin_tx)?;
 
@@ -815,7 +818,8 @@ mod integration {
             // **********************
             // Inside the Sender:
             // Sender checks, signs, finalizes, extracts, and broadcasts
-            let checked_payjoin_proposal_psbt = ctx.process_response(&mut response.as_bytes())?;
+            let (checked_payjoin_proposal_psbt, _) =
+                ctx.process_response(&mut response.as_bytes())?;
             let payjoin_tx = extract_pj_tx(&sender, checked_payjoin_proposal_psbt)?;
             sender.send_raw_transaction(&payjoin_tx)?;
 